@@ -34,7 +34,7 @@ where
         let this = std::mem::ManuallyDrop::new(this);
 
         T::try_from(&this).map_err(|e| {
-            serde::de::Error::custom(&format!("could not convert from js value: {:?}", e))
+            serde::de::Error::custom(format!("could not convert from js value: {:?}", e))
         })
     }
 }
@@ -47,7 +47,7 @@ macro_rules! serde_impl {
             where
                 S: Serializer,
             {
-                crate::as_js_value::AsJsValue::<Self>::serialize(&self, serializer)
+                $crate::as_js_value::AsJsValue::<Self>::serialize(&self, serializer)
             }
         }
 
@@ -56,7 +56,7 @@ macro_rules! serde_impl {
             where
                 D: Deserializer<'de>,
             {
-                crate::as_js_value::AsJsValue::<Self>::deserialize(deserializer)
+                $crate::as_js_value::AsJsValue::<Self>::deserialize(deserializer)
             }
         }
     };
@@ -71,9 +71,9 @@ macro_rules! wrapper_impl {
             }
         }
 
-        impl Into<$to> for $from {
-            fn into(self) -> $to {
-                self.0
+        impl From<$from> for $to {
+            fn from(s: $from) -> Self {
+                s.0
             }
         }
     };
@@ -52,9 +52,9 @@ impl From<Compression> for qrcloak_core::payload::Compression {
     }
 }
 
-impl Into<Compression> for qrcloak_core::payload::Compression {
-    fn into(self) -> Compression {
-        match self {
+impl From<qrcloak_core::payload::Compression> for Compression {
+    fn from(val: qrcloak_core::payload::Compression) -> Self {
+        match val {
             qrcloak_core::payload::Compression::NoCompression => Compression::NoCompression,
             qrcloak_core::payload::Compression::Gzip(gzip) => {
                 Compression::Gzip { gzip: gzip.into() }
@@ -79,9 +79,9 @@ impl From<Decompression> for qrcloak_core::payload::Decompression {
     }
 }
 
-impl Into<Decompression> for qrcloak_core::payload::Decompression {
-    fn into(self) -> Decompression {
-        match self {
+impl From<qrcloak_core::payload::Decompression> for Decompression {
+    fn from(val: qrcloak_core::payload::Decompression) -> Self {
+        match val {
             qrcloak_core::payload::Decompression::NoCompression => Decompression::NoCompression,
             qrcloak_core::payload::Decompression::Gzip(gzip) => {
                 Decompression::Gzip { gzip: gzip.into() }
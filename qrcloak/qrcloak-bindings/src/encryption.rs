@@ -37,9 +37,9 @@ pub enum ParsingError {
     Recipient(String),
 }
 
-impl Into<JsValue> for ParsingError {
-    fn into(self) -> JsValue {
-        JsValue::from(self.to_string())
+impl From<ParsingError> for JsValue {
+    fn from(val: ParsingError) -> Self {
+        JsValue::from(val.to_string())
     }
 }
 
@@ -113,6 +113,7 @@ uniffi_object_clone!(AgeIdentity);
 
 #[derive(Tsify, Serialize, Deserialize, Enum)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
+#[allow(clippy::enum_variant_names)]
 pub enum Encryption {
     NoEncryption,
     AgePassphrase { passphrase: Passphrase },
@@ -118,7 +118,7 @@ impl PayloadMerger {
     }
 
     pub fn merge(&self, payloads: Payloads) -> MergeResult {
-        self.0.clone().merge(payloads).into()
+        self.0.clone().merge(payloads)
     }
 }
 
@@ -180,8 +180,8 @@ pub enum GenericError {
     GenerationError(#[from] qrcloak_core::payload::PayloadGenerationError),
 }
 
-impl Into<JsValue> for GenericError {
-    fn into(self) -> JsValue {
-        JsValue::from(self.to_string())
+impl From<GenericError> for JsValue {
+    fn from(val: GenericError) -> Self {
+        JsValue::from(val.to_string())
     }
 }
@@ -64,9 +64,9 @@ impl IntoIterator for Payloads {
     }
 }
 
-impl Into<Vec<Payload>> for Payloads {
-    fn into(self) -> Vec<Payload> {
-        self.0
+impl From<Payloads> for Vec<Payload> {
+    fn from(val: Payloads) -> Self {
+        val.0
     }
 }
 
@@ -0,0 +1,946 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{self, BufReader},
+    num::NonZeroU32,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use age::{secrecy::SecretString, x25519};
+use clap::Parser;
+use miette::Context;
+use qrcloak_core::{
+    config::{CompressionKind, Ecl, Splits},
+    generate::{Generator, ImageRenderer, Symbol},
+    payload::{AgeKeyEncryption, AgePassphrase, Encryption, PayloadGenerator},
+};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config,
+    env::get_env,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    qrcode::{generate_splits, numbered_path, output_paths},
+    report::{self, ErrorReport, Event, ItemStatus, Summary},
+    secrets::parse_recipients,
+};
+
+#[derive(Parser, Debug)]
+pub struct BatchArgs {
+    /// A TOML or JSON file that lists the QR Codes to generate
+    manifest: PathBuf,
+
+    /// Generate all items, even those that have not changed since the last run
+    #[arg(long)]
+    force: bool,
+
+    /// The number of items to generate at once [default: the number of CPUs]
+    #[arg(short, long)]
+    jobs: Option<usize>,
+}
+
+/// The items of a manifest, under `items` in JSON or as `[[items]]` tables in TOML.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    items: Vec<BatchItem>,
+}
+
+/// One QR Code, or several if the payload is split. Paths are relative to the manifest.
+///
+/// Compression and the settings of the symbols that are left out are taken from the
+/// configuration.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct BatchItem {
+    /// The name in the report, which defaults to the output path
+    #[serde(default, skip_serializing)]
+    name: Option<String>,
+
+    /// The data as text
+    text: Option<String>,
+
+    /// A file with the data
+    file: Option<PathBuf>,
+
+    /// Public keys to encrypt to
+    #[serde(default)]
+    recipients: Vec<String>,
+
+    /// Files with public keys to encrypt to
+    #[serde(default)]
+    recipients_file: Vec<PathBuf>,
+
+    /// Recipient groups of the configuration to encrypt to
+    #[serde(default)]
+    groups: Vec<String>,
+
+    /// The environment variable with a passphrase to encrypt with
+    passphrase_env: Option<String>,
+
+    /// The settings below default to those of the configuration
+    compression: Option<CompressionKind>,
+
+    splits: Option<Splits>,
+
+    ecl: Option<Ecl>,
+
+    structured_append: Option<bool>,
+
+    /// The number of pixels per module, 4 by default
+    scale: Option<NonZeroU32>,
+
+    /// The width of the light border in modules, 1 by default
+    border: Option<u32>,
+
+    /// The format of the images, which defaults to the one of the output extension
+    format: Option<ImageFormat>,
+
+    /// The image, where `{index}` is replaced by the number of each part
+    output: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum ImageFormat {
+    Png,
+    Svg,
+}
+
+/// What was generated for an item by an earlier run, stored next to the manifest.
+///
+/// The hash covers the item and its data, salted with a random salt of the item. The
+/// salt keeps equal items from having equal hashes, but a guess at the data can still
+/// be checked against the hash, so the state must not be published.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ItemState {
+    sha256: String,
+    outputs: Vec<PathBuf>,
+    /// The public keys the item is encrypted to
+    #[serde(default)]
+    pub(crate) recipients: Vec<String>,
+    /// The salt of the hash and of the passphrase digest in it
+    salt: String,
+}
+
+#[derive(Debug)]
+pub(crate) enum Outcome {
+    Created(ItemState),
+    Unchanged(ItemState),
+}
+
+pub(crate) type Reports = Vec<(String, miette::Result<Outcome>)>;
+
+impl BatchArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        report_items(self.run()?)
+    }
+
+    fn run(&self) -> miette::Result<Reports> {
+        generate(&self.manifest, self.jobs, &|_| self.force)
+    }
+}
+
+/// Reports the outcome of each item and fails if any of them failed, or returns the
+/// outputs that were created.
+pub(crate) fn report_items(reports: Reports) -> miette::Result<Summary> {
+    let total = reports.len();
+    let mut summary = Summary::default();
+    let mut failed = Vec::new();
+    for (name, result) in reports {
+        let (status, outputs, error) = match result {
+            Ok(Outcome::Created(state)) => (ItemStatus::Created, state.outputs, None),
+            Ok(Outcome::Unchanged(state)) => (ItemStatus::Unchanged, state.outputs, None),
+            Err(err) => {
+                let error = ErrorReport::new(&err);
+                failed.push(error.kind);
+                (ItemStatus::Failed, Vec::new(), Some(error))
+            }
+        };
+
+        let outputs: Vec<String> = outputs
+            .iter()
+            .map(|output| output.display().to_string())
+            .collect();
+        if status == ItemStatus::Created {
+            summary.outputs.extend(outputs.iter().cloned());
+        }
+
+        report::emit(Event::Item {
+            name: &name,
+            status,
+            outputs,
+            error,
+        });
+    }
+
+    if let Some(&kind) = failed.first() {
+        let kind = if failed.iter().all(|&other| other == kind) {
+            kind
+        } else {
+            ErrorKind::Other
+        };
+
+        return Err(
+            CliError::new(kind, format!("{} of {total} items failed", failed.len())).into(),
+        );
+    }
+
+    Ok(summary)
+}
+
+/// Generates the items of the manifest and returns the outcome for each one, in the
+/// order of the manifest. Items that have not changed are skipped, unless `force` is
+/// true for their state of the last run.
+pub(crate) fn generate(
+    manifest_path: &Path,
+    jobs: Option<usize>,
+    force: &(dyn Fn(&ItemState) -> bool + Sync),
+) -> miette::Result<Reports> {
+    let manifest = read_manifest(manifest_path)?;
+    let base = manifest_path.parent().unwrap_or(Path::new(""));
+
+    let config = config::get()?;
+    let mut seen: Vec<OutputPattern> = Vec::new();
+    for item in manifest.items.iter() {
+        let splits = item.splits.or(config.render.splits).unwrap_or_default();
+        let patterns = item.output_patterns(base, splits);
+        if patterns
+            .iter()
+            .any(|pattern| seen.iter().any(|other| pattern.overlaps(other)))
+        {
+            return Err(CliError::new(
+                ErrorKind::Usage,
+                format!("More than one item writes to {}", item.output.display()),
+            )
+            .into());
+        }
+        seen.extend(patterns);
+    }
+
+    let state_path = state_path(manifest_path);
+    let previous = read_state(&state_path)?;
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.unwrap_or(0))
+        .build()
+        .into_diagnostic()?;
+
+    let reports: Reports = pool.install(|| {
+        manifest
+            .items
+            .par_iter()
+            .map(|item| {
+                let previous = previous.get(&item.key());
+                let force = previous.is_some_and(force);
+                (item.name(), item.process(base, previous, force))
+            })
+            .collect()
+    });
+
+    // Items that failed or are no longer in the manifest are generated again next time
+    let state: BTreeMap<String, ItemState> = manifest
+        .items
+        .iter()
+        .zip(reports.iter())
+        .filter_map(|(item, (_, result))| match result {
+            Ok(Outcome::Created(state) | Outcome::Unchanged(state)) => {
+                Some((item.key(), state.clone()))
+            }
+            Err(_) => None,
+        })
+        .collect();
+
+    // Parts of an earlier run that are no longer written, because the item has fewer parts
+    let outputs: HashSet<&PathBuf> = state.values().flat_map(|state| &state.outputs).collect();
+    for key in state.keys() {
+        let stale = previous
+            .get(key)
+            .into_iter()
+            .flat_map(|previous| &previous.outputs)
+            .filter(|output| !outputs.contains(output));
+        for output in stale {
+            match std::fs::remove_file(output) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => {
+                    return Err(err)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Unable to remove {}", output.display()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let json = serde_json::to_string_pretty(&state).into_diagnostic()?;
+    std::fs::write(&state_path, json)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to write {}", state_path.display()))?;
+
+    Ok(reports)
+}
+
+fn read_manifest(path: &Path) -> miette::Result<Manifest> {
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+    let manifest = if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        serde_json::from_str(&contents).into_diagnostic()
+    } else {
+        toml::from_str(&contents).into_diagnostic()
+    };
+
+    manifest.wrap_err_with(|| format!("Invalid manifest {}", path.display()))
+}
+
+// The state of manifest.toml is kept in manifest.toml.state.json
+fn state_path(manifest: &Path) -> PathBuf {
+    let mut name = manifest.file_name().unwrap_or_default().to_os_string();
+    name.push(".state.json");
+    manifest.with_file_name(name)
+}
+
+fn read_state(path: &Path) -> miette::Result<BTreeMap<String, ItemState>> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let contents = std::fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+    // A broken state only means that everything is generated again
+    Ok(serde_json::from_str(&contents).unwrap_or_default())
+}
+
+impl BatchItem {
+    fn key(&self) -> String {
+        self.output.display().to_string()
+    }
+
+    fn name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| self.key())
+    }
+
+    // The paths the item may write to, relative to the working directory
+    fn output_patterns(&self, base: &Path, splits: Splits) -> Vec<OutputPattern> {
+        let path = normalize(&base.join(&self.output));
+        let pattern = |path: &Path| {
+            let path = path.to_string_lossy();
+            let (prefix, suffix) = path.split_once("{index}").unwrap_or((&*path, ""));
+            OutputPattern::Numbered {
+                prefix: prefix.to_string(),
+                suffix: suffix.to_string(),
+            }
+        };
+
+        if path.to_string_lossy().contains("{index}") {
+            return vec![pattern(&path)];
+        }
+
+        let single = OutputPattern::Single(path.to_string_lossy().into_owned());
+        let numbered = pattern(&numbered_path(&path, "{index}"));
+        match splits {
+            Splits::Count(1) => vec![single],
+            Splits::Count(_) => vec![numbered],
+            Splits::Auto => vec![single, numbered],
+        }
+    }
+
+    fn process(
+        &self,
+        base: &Path,
+        previous: Option<&ItemState>,
+        force: bool,
+    ) -> miette::Result<Outcome> {
+        let data = match (&self.text, &self.file) {
+            (Some(text), None) => text.as_bytes().to_vec(),
+            (None, Some(file)) => {
+                let path = base.join(file);
+                std::fs::read(&path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Unable to read {}", path.display()))?
+            }
+            _ => {
+                return Err(CliError::new(
+                    ErrorKind::Usage,
+                    "Expected exactly one of 'text' and 'file'",
+                )
+                .into())
+            }
+        };
+
+        let recipients = self.recipients(base)?;
+        let keys: Vec<String> = recipients.iter().map(ToString::to_string).collect();
+
+        let config = config::get()?;
+        let item = BatchItem {
+            compression: self.compression.or(config.defaults.compression),
+            splits: self.splits.or(config.render.splits),
+            ecl: self.ecl.or(config.render.ecl),
+            structured_append: self.structured_append.or(config.render.structured_append),
+            ..self.clone()
+        };
+
+        let passphrase = match (&self.passphrase_env, recipients.is_empty()) {
+            (Some(_), false) => {
+                return Err(CliError::new(
+                    ErrorKind::Usage,
+                    "Expected either recipients or a passphrase, but not both",
+                )
+                .into())
+            }
+            (Some(name), true) => {
+                let passphrase: String = get_env(name)?;
+                Some(AgePassphrase::new(SecretString::new(passphrase)))
+            }
+            (None, _) => None,
+        };
+
+        // The salt is kept, so that the hash only changes with the item. A changed
+        // passphrase is noticed through its digest with the same salt.
+        let salt = previous
+            .map(|previous| previous.salt.clone())
+            .unwrap_or_else(AgePassphrase::generate_salt);
+
+        let mut hasher = Sha256::new();
+        hasher.update(&salt);
+        hasher.update(serde_json::to_vec(&(&item, &keys)).into_diagnostic()?);
+        if let Some(passphrase) = &passphrase {
+            hasher.update(passphrase.digest(&salt));
+        }
+        hasher.update(&data);
+        let sha256 = format!("{:x}", hasher.finalize());
+
+        if let Some(previous) = previous.filter(|_| !force) {
+            if previous.sha256 == sha256 && previous.outputs.iter().all(|output| output.exists()) {
+                return Ok(Outcome::Unchanged(previous.clone()));
+            }
+        }
+
+        let encryption = match passphrase {
+            Some(passphrase) => Encryption::AgePassphrase(passphrase),
+            None if recipients.is_empty() => Encryption::NoEncryption,
+            None => Encryption::AgeKey(AgeKeyEncryption::new(recipients)),
+        };
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(encryption)
+            .with_compression(item.compression.unwrap_or_default().into())
+            .generate(data.into())
+            .into_diagnostic()?;
+
+        let generator = Generator::default()
+            .with_ecl(item.ecl.unwrap_or_default().into())
+            .with_structured_append(item.structured_append.unwrap_or_default());
+
+        let symbols = generate_splits(
+            item.splits.unwrap_or_default(),
+            &generator,
+            payload,
+            Symbol::clone,
+        )?;
+
+        let outputs = output_paths(&[base.join(&self.output)], symbols.len())?;
+        for (symbol, output) in symbols.iter().zip(outputs.iter()) {
+            if let Some(parent) = output.parent() {
+                std::fs::create_dir_all(parent).into_diagnostic()?;
+            }
+
+            self.write_image(symbol, output)
+                .wrap_err_with(|| format!("Unable to write {}", output.display()))?;
+        }
+
+        Ok(Outcome::Created(ItemState {
+            sha256,
+            outputs,
+            recipients: keys,
+            salt,
+        }))
+    }
+
+    fn write_image(&self, symbol: &Symbol, output: &Path) -> miette::Result<()> {
+        let mut renderer = ImageRenderer::default();
+        if let Some(scale) = self.scale {
+            renderer = renderer.with_scale(scale.get());
+        }
+        if let Some(border) = self.border {
+            renderer = renderer.with_border(border);
+        }
+
+        let format = self.format.or_else(|| {
+            let svg = output
+                .extension()
+                .is_some_and(|extension| extension == "svg");
+            svg.then_some(ImageFormat::Svg)
+        });
+
+        // Other formats than these are guessed from the extension by the image crate
+        match format {
+            Some(ImageFormat::Svg) => {
+                std::fs::write(output, renderer.render_svg(symbol)).into_diagnostic()
+            }
+            Some(ImageFormat::Png) => renderer
+                .render(symbol)
+                .save_with_format(output, image::ImageFormat::Png)
+                .into_diagnostic(),
+            None => renderer.render(symbol).save(output).into_diagnostic(),
+        }
+    }
+
+    // The public keys of the item together with those of its recipients files and groups
+    fn recipients(&self, base: &Path) -> miette::Result<Vec<x25519::Recipient>> {
+        let mut recipients = self
+            .recipients
+            .iter()
+            .map(|recipient| {
+                x25519::Recipient::from_str(recipient).map_err(|err| {
+                    CliError::new(
+                        ErrorKind::Usage,
+                        format!("Invalid recipient '{recipient}': {err}"),
+                    )
+                    .into()
+                })
+            })
+            .collect::<miette::Result<Vec<_>>>()?;
+
+        for file in self.recipients_file.iter() {
+            let path = base.join(file);
+            let reader = std::fs::File::open(&path)
+                .map(BufReader::new)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+            let keys = parse_recipients(reader)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Invalid recipients file {}", path.display()))?;
+            recipients.extend(keys);
+        }
+
+        for group in self.groups.iter() {
+            recipients.extend(config::get()?.group(group).into_diagnostic()?);
+        }
+
+        Ok(recipients)
+    }
+}
+
+/// A path an item may write to, or all paths with a part number between the prefix and
+/// the suffix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OutputPattern {
+    Single(String),
+    Numbered { prefix: String, suffix: String },
+}
+
+impl OutputPattern {
+    fn overlaps(&self, other: &OutputPattern) -> bool {
+        match (self, other) {
+            (OutputPattern::Single(path), OutputPattern::Single(other)) => path == other,
+            (OutputPattern::Single(path), OutputPattern::Numbered { prefix, suffix })
+            | (OutputPattern::Numbered { prefix, suffix }, OutputPattern::Single(path)) => path
+                .strip_prefix(prefix.as_str())
+                .and_then(|rest| rest.strip_suffix(suffix.as_str()))
+                .is_some_and(|index| {
+                    index
+                        .parse::<usize>()
+                        .is_ok_and(|number| number > 0 && number.to_string() == index)
+                }),
+            (
+                OutputPattern::Numbered { prefix, suffix },
+                OutputPattern::Numbered {
+                    prefix: other_prefix,
+                    suffix: other_suffix,
+                },
+            ) => {
+                // Both take the same path when the longer prefix and suffix only add digits
+                let only_digits = |rest: &str| rest.bytes().all(|byte| byte.is_ascii_digit());
+                let (short, long) = sorted_by_len(prefix, other_prefix);
+                let prefixes = long.strip_prefix(short).is_some_and(only_digits);
+                let (short, long) = sorted_by_len(suffix, other_suffix);
+                let suffixes = long.strip_suffix(short).is_some_and(only_digits);
+                prefixes && suffixes
+            }
+        }
+    }
+}
+
+fn sorted_by_len<'a>(a: &'a str, b: &'a str) -> (&'a str, &'a str) {
+    if a.len() <= b.len() {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+// Leaves out `.` and resolves `..` without looking at the file system
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use qrcloak_core::payload::{AgeKeyDecryption, Decryption, PayloadExtractor, PayloadMerger};
+    use tempfile::TempDir;
+
+    use crate::payload::read_payloads;
+
+    use super::*;
+
+    fn run(manifest: &Path, extra: &[&str]) -> Vec<(String, miette::Result<Outcome>)> {
+        BatchArgs::parse_from(
+            ["cmd"]
+                .into_iter()
+                .chain(extra.iter().copied())
+                .chain([manifest.to_str().unwrap()]),
+        )
+        .run()
+        .expect("should run")
+    }
+
+    fn created(reports: &[(String, miette::Result<Outcome>)]) -> Vec<bool> {
+        reports
+            .iter()
+            .map(|(_, result)| matches!(result, Ok(Outcome::Created(_))))
+            .collect()
+    }
+
+    #[test]
+    fn test_batch() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let identity = x25519::Identity::generate();
+        let recipient = identity.to_public();
+
+        std::fs::write(dir.path().join("secret.txt"), "from a file").unwrap();
+        std::fs::write(
+            dir.path().join("team.txt"),
+            format!("# team\n{recipient}\n"),
+        )
+        .unwrap();
+
+        let manifest = dir.path().join("codes.toml");
+        let write_manifest = |text: &str| {
+            std::fs::write(
+                &manifest,
+                format!(
+                    r#"
+[[items]]
+name = "first"
+text = "{text}"
+recipients = ["{}"]
+compression = "gzip"
+splits = 2
+ecl = "low"
+output = "out/first-{{index}}.png"
+
+[[items]]
+file = "secret.txt"
+recipients-file = ["team.txt"]
+output = "out/second.png"
+"#,
+                    recipient
+                ),
+            )
+            .unwrap();
+        };
+
+        write_manifest("hello world");
+        let reports = run(&manifest, &[]);
+        assert_eq!(reports[0].0, "first");
+        assert_eq!(reports[1].0, "out/second.png");
+        assert_eq!(created(&reports), [true, true]);
+
+        let outputs = ["first-1.png", "first-2.png", "second.png"]
+            .map(|name| dir.path().join("out").join(name));
+        let payloads = read_payloads(&outputs)
+            .expect("should read")
+            .into_iter()
+            .map(|(_, payload)| payload);
+
+        let decryption = Decryption::AgeKey(AgeKeyDecryption::from(vec![identity]));
+        let mut data: Vec<String> = PayloadMerger::default()
+            .merge(payloads)
+            .complete
+            .into_iter()
+            .map(|payload| {
+                let data = PayloadExtractor::default()
+                    .with_decryption(decryption.clone())
+                    .with_decompression(payload.compression().into())
+                    .extract(payload)
+                    .expect("should extract");
+                String::from_utf8(data.to_vec()).unwrap()
+            })
+            .collect();
+        data.sort();
+        assert_eq!(data, ["from a file", "hello world"]);
+
+        // Nothing changed, unless forced
+        assert_eq!(created(&run(&manifest, &[])), [false, false]);
+        assert_eq!(created(&run(&manifest, &["--force"])), [true, true]);
+
+        // Only the changed item and the one with a missing output are generated again
+        write_manifest("goodbye");
+        std::fs::remove_file(&outputs[2]).unwrap();
+        assert_eq!(created(&run(&manifest, &[])), [true, true]);
+        assert_eq!(created(&run(&manifest, &[])), [false, false]);
+    }
+
+    #[test]
+    fn test_batch_passphrase_and_render_options() {
+        // Only read by this test
+        const PASSPHRASE_ENV: &str = "QRCLOAK_TEST_BATCH_PASSPHRASE";
+
+        let dir = TempDir::new().expect("should create temp dir");
+        let manifest = dir.path().join("codes.toml");
+        std::fs::write(
+            &manifest,
+            format!(
+                r#"
+[[items]]
+text = "secret"
+passphrase-env = "{PASSPHRASE_ENV}"
+scale = 2
+border = 4
+output = "secret.svg"
+
+[[items]]
+text = "plain"
+scale = 3
+border = 0
+format = "png"
+output = "plain.img"
+"#
+            ),
+        )
+        .unwrap();
+
+        std::env::set_var(PASSPHRASE_ENV, "correct horse");
+        assert_eq!(created(&run(&manifest, &[])), [true, true]);
+
+        let svg = std::fs::read_to_string(dir.path().join("secret.svg")).unwrap();
+        let width: u32 = svg
+            .split(r#"width=""#)
+            .nth(1)
+            .and_then(|rest| rest.split('"').next())
+            .and_then(|width| width.parse().ok())
+            .expect("should have a width");
+        // Versions grow by 4 modules from 21, and the border adds 8
+        assert_eq!((width / 2 - 8 - 21) % 4, 0);
+
+        let plain = image::load_from_memory_with_format(
+            &std::fs::read(dir.path().join("plain.img")).unwrap(),
+            image::ImageFormat::Png,
+        )
+        .expect("should be a png");
+        assert_eq!((plain.width() / 3 - 21) % 4, 0);
+        assert_eq!(plain.width() % 3, 0);
+
+        // The state tells that the passphrase changed without containing it
+        assert_eq!(created(&run(&manifest, &[])), [false, false]);
+        std::env::set_var(PASSPHRASE_ENV, "battery staple");
+        assert_eq!(created(&run(&manifest, &[])), [true, false]);
+        assert_eq!(created(&run(&manifest, &[])), [false, false]);
+
+        let state = std::fs::read_to_string(state_path(&manifest)).unwrap();
+        assert!(!state.contains("battery staple"));
+        let state = read_state(&state_path(&manifest)).unwrap();
+        assert_ne!(state["secret.svg"].salt, state["plain.img"].salt);
+
+        // A changed render option generates the item again
+        let contents = std::fs::read_to_string(&manifest).unwrap();
+        std::fs::write(&manifest, contents.replace("border = 0", "border = 1")).unwrap();
+        assert_eq!(created(&run(&manifest, &[])), [false, true]);
+    }
+
+    #[test]
+    fn test_batch_errors() {
+        let dir = TempDir::new().expect("should create temp dir");
+
+        let manifest = dir.path().join("codes.json");
+        std::fs::write(
+            &manifest,
+            r#"{"items": [
+                {"text": "plain", "output": "plain.png"},
+                {"text": "broken", "recipients": ["age1invalid"], "output": "broken.png"},
+                {"output": "empty.png"}
+            ]}"#,
+        )
+        .unwrap();
+
+        let reports = run(&manifest, &["--jobs", "2"]);
+        assert_eq!(created(&reports), [true, false, false]);
+        assert!(reports[1]
+            .1
+            .as_ref()
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid recipient 'age1invalid'"));
+        assert!(dir.path().join("plain.png").exists());
+
+        // The failed items are not remembered
+        let state = read_state(&state_path(&manifest)).unwrap();
+        assert_eq!(state.keys().collect::<Vec<_>>(), ["plain.png"]);
+
+        let error = BatchArgs::parse_from(["cmd", manifest.to_str().unwrap()])
+            .handle()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "2 of 3 items failed");
+        assert_eq!(ErrorKind::of_report(&error), ErrorKind::Other);
+
+        // Items that all fail the same way keep the kind of their error
+        std::fs::write(
+            &manifest,
+            r#"{"items": [{"file": "missing.txt", "output": "missing.png"}]}"#,
+        )
+        .unwrap();
+        let error = BatchArgs::parse_from(["cmd", manifest.to_str().unwrap()])
+            .handle()
+            .unwrap_err();
+        assert_eq!(ErrorKind::of_report(&error), ErrorKind::Io);
+
+        std::fs::write(
+            &manifest,
+            r#"{"items": [{"text": "a", "recipients": ["age1invalid"], "output": "a.png"}]}"#,
+        )
+        .unwrap();
+        let error = BatchArgs::parse_from(["cmd", manifest.to_str().unwrap()])
+            .handle()
+            .unwrap_err();
+        assert_eq!(ErrorKind::of_report(&error), ErrorKind::Usage);
+
+        std::fs::write(
+            &manifest,
+            r#"{"items": [{"text": "a", "output": "a.png", "ecl": "none"}]}"#,
+        )
+        .unwrap();
+        assert!(BatchArgs::parse_from(["cmd", manifest.to_str().unwrap()])
+            .run()
+            .is_err());
+    }
+
+    #[test]
+    fn test_batch_overlapping_outputs() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let manifest = dir.path().join("codes.json");
+
+        let overlapping = [
+            (r#""x.png""#, r#""./x.png""#),
+            (r#""out/x.png""#, r#""out/../out/x.png""#),
+            (r#""a-{index}.png""#, r#""a-1.png""#),
+            (r#""a-{index}.png", "splits": 2"#, r#""a.png", "splits": 3"#),
+            (r#""a.png", "splits": "auto""#, r#""a-12.png""#),
+            (r#""a-{index}.png""#, r#""a-1{index}.png""#),
+        ];
+        for (first, second) in overlapping {
+            std::fs::write(
+                &manifest,
+                format!(
+                    r#"{{"items": [
+                        {{"text": "a", "output": {first}}},
+                        {{"text": "b", "output": {second}}}
+                    ]}}"#
+                ),
+            )
+            .unwrap();
+
+            let error = BatchArgs::parse_from(["cmd", manifest.to_str().unwrap()])
+                .run()
+                .unwrap_err();
+            assert_eq!(ErrorKind::of_report(&error), ErrorKind::Usage, "{second}");
+            assert!(error
+                .to_string()
+                .starts_with("More than one item writes to"));
+        }
+
+        let separate = [
+            (r#""a.png""#, r#""a-1.png""#),
+            (r#""a-{index}.png""#, r#""a-0.png""#),
+            (r#""a-{index}.png""#, r#""a-{index}.svg""#),
+            (
+                r#""a-{index}.png", "splits": 2"#,
+                r#""b-{index}.png", "splits": 2"#,
+            ),
+        ];
+        for (first, second) in separate {
+            std::fs::write(
+                &manifest,
+                format!(
+                    r#"{{"items": [
+                        {{"text": "a", "output": {first}}},
+                        {{"text": "b", "output": {second}}}
+                    ]}}"#
+                ),
+            )
+            .unwrap();
+
+            assert_eq!(
+                created(&run(&manifest, &["--force"])),
+                [true, true],
+                "{second}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_batch_removes_stale_outputs() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let manifest = dir.path().join("codes.toml");
+        let write_manifest = |splits: u32| {
+            std::fs::write(
+                &manifest,
+                format!(
+                    r#"
+[[items]]
+text = "hello world"
+splits = {splits}
+output = "out-{{index}}.png"
+"#
+                ),
+            )
+            .unwrap();
+        };
+        let outputs = ["out-1.png", "out-2.png", "out-3.png"].map(|name| dir.path().join(name));
+
+        write_manifest(3);
+        assert_eq!(created(&run(&manifest, &[])), [true]);
+        assert!(outputs.iter().all(|output| output.exists()));
+
+        write_manifest(2);
+        assert_eq!(created(&run(&manifest, &[])), [true]);
+        assert!(outputs[0].exists() && outputs[1].exists());
+        assert!(!outputs[2].exists());
+
+        // Also when forced, and when the outputs are already gone
+        write_manifest(1);
+        std::fs::remove_file(&outputs[1]).unwrap();
+        assert_eq!(created(&run(&manifest, &["--force"])), [true]);
+        assert!(outputs[0].exists());
+        assert!(!outputs[1].exists());
+    }
+}
@@ -0,0 +1,30 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use clap::Args;
+use qrcloak_core::payload::Compression;
+
+pub use qrcloak_core::config::CompressionKind;
+
+use crate::config;
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct CompressionOptions {
+    /// Compress the data before encrypting it [default: none]
+    #[arg(long, value_enum)]
+    pub compression: Option<CompressionKind>,
+}
+
+impl CompressionOptions {
+    /// Returns the given compression, or the one of the configuration if none is given.
+    pub fn compression(&self) -> miette::Result<Compression> {
+        let kind = match self.compression {
+            Some(kind) => kind,
+            None => config::get()?.defaults.compression.unwrap_or_default(),
+        };
+
+        Ok(kind.into())
+    }
+}
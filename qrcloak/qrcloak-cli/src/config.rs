@@ -0,0 +1,48 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::sync::OnceLock;
+
+use miette::Context;
+use qrcloak_core::config::Config;
+
+use crate::error::IntoCliDiagnostic;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// Returns the configuration for the working directory, which is only read once a
+/// command needs it, so that a broken file does not fail the commands that do not.
+///
+/// Tests use an empty configuration.
+pub fn get() -> miette::Result<&'static Config> {
+    if let Some(config) = CONFIG.get() {
+        return Ok(config);
+    }
+
+    let config = if cfg!(test) {
+        Config::default()
+    } else {
+        let dir = std::env::current_dir().into_diagnostic()?;
+        Config::discover(&dir)
+            .into_diagnostic()
+            .wrap_err("Unable to load the configuration")?
+    };
+
+    Ok(CONFIG.get_or_init(|| config))
+}
+
+/// Returns whether a flag is set, given the flag and its negation, of which the last one
+/// on the command line wins, or the configured value if neither is given.
+pub fn flag(
+    set: bool,
+    unset: bool,
+    configured: impl FnOnce(&Config) -> Option<bool>,
+) -> miette::Result<bool> {
+    match (set, unset) {
+        (true, _) => Ok(true),
+        (_, true) => Ok(false),
+        _ => Ok(configured(get()?).unwrap_or_default()),
+    }
+}
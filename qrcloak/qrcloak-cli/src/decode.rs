@@ -0,0 +1,343 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use miette::Context;
+use qrcloak_core::{
+    format::PartialPayload,
+    payload::{Decompression, PayloadExtractor, PayloadMerger},
+};
+
+use crate::{
+    decryption::DecryptionOptions,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    payload::read_payloads,
+    report::{self, Event, Summary},
+    DataOutputOptions, FileOrStdout,
+};
+
+#[derive(Parser, Debug)]
+pub struct DecodeArgs {
+    /// Files with encoded payloads, or images and PDFs with QR Codes (use '-' for stdin)
+    #[arg(default_value = "-")]
+    inputs: Vec<PathBuf>,
+
+    #[command(flatten)]
+    decryption: DecryptionOptions,
+
+    #[command(flatten)]
+    data_output: DataOutputOptions,
+
+    /// Write each payload to its own file 'payload-N' in this directory
+    #[arg(long, value_name = "DIR", conflicts_with = "output")]
+    output_dir: Option<PathBuf>,
+
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+impl DecodeArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let decryption = self.decryption.decryption()?;
+        let payloads = read_payloads(&self.inputs)?
+            .into_iter()
+            .map(|(_, payload)| payload);
+
+        let merged = PayloadMerger::default().merge(payloads);
+
+        if let Some(dir) = &self.output_dir {
+            std::fs::create_dir_all(dir)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Unable to create {}", dir.display()))?;
+        }
+
+        let mut writer = match self.output_dir {
+            Some(_) => None,
+            None => Some(self.output.try_get_writer().into_diagnostic()?),
+        };
+
+        let mut summary = Summary::default();
+        if self.output_dir.is_none() {
+            summary = summary.with_output(&self.output);
+        }
+
+        let mut decoded = 0;
+        let mut problems = Vec::new();
+        for (number, payload) in merged.complete.into_iter().enumerate() {
+            let extractor = PayloadExtractor::default()
+                .with_decryption(decryption.clone())
+                .with_decompression(Decompression::from(payload.compression()));
+
+            let data = match extractor.extract(payload) {
+                Ok(data) => data,
+                Err(err) => {
+                    let kind = ErrorKind::of(&err);
+                    report::warning(
+                        kind,
+                        format!("Unable to decode payload {}: {err}", number + 1),
+                    );
+                    problems.push(kind);
+                    continue;
+                }
+            };
+
+            match (&mut writer, &self.output_dir) {
+                (Some(writer), _) => {
+                    self.data_output.write(writer, &data).into_diagnostic()?;
+                    report::emit(Event::Decoded {
+                        payload: number + 1,
+                        path: None,
+                    });
+                }
+                (None, Some(dir)) => {
+                    let path = dir.join(format!("payload-{}", number + 1));
+                    let mut file = std::fs::File::create(&path)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Unable to create {}", path.display()))?;
+                    self.data_output.write(&mut file, &data).into_diagnostic()?;
+                    report::emit(Event::Decoded {
+                        payload: number + 1,
+                        path: Some(&path),
+                    });
+                    summary = summary.with_output(path.display());
+                }
+                (None, None) => unreachable!("there is either an output or a directory"),
+            }
+
+            decoded += 1;
+        }
+
+        let mut incomplete: Vec<_> = merged.incomplete.partials().iter().collect();
+        incomplete.sort_by_key(|(index, _)| *index);
+
+        for (_, parts) in incomplete.iter() {
+            report::warning(ErrorKind::IncompleteGroup, describe_incomplete(parts));
+            problems.push(ErrorKind::IncompleteGroup);
+        }
+
+        for partial in merged.incomplete.misconfigured() {
+            let index = partial.index();
+            report::warning(
+                ErrorKind::Decode,
+                format!(
+                    "Payload part {} of {} in group {} does not match the other parts of its group",
+                    index.index() + 1,
+                    index.size(),
+                    index.id()
+                ),
+            );
+            problems.push(ErrorKind::Decode);
+        }
+
+        if let Some(&kind) = problems.first() {
+            // Mixed problems have no single class beyond the payloads being undecodable
+            let kind = if problems.iter().all(|&other| other == kind) {
+                kind
+            } else {
+                ErrorKind::Decode
+            };
+
+            return Err(CliError::new(
+                kind,
+                format!(
+                    "Unable to decode {} of the payloads, see the messages above",
+                    problems.len()
+                ),
+            )
+            .into());
+        }
+
+        if decoded == 0 {
+            return Err(CliError::new(ErrorKind::Decode, "No qrcloak payloads found").into());
+        }
+
+        Ok(summary.with_payloads(decoded))
+    }
+}
+
+// Names the group of the parts and the 1-based positions of the parts that are missing
+fn describe_incomplete(parts: &[Option<PartialPayload>]) -> String {
+    let id = parts
+        .iter()
+        .flatten()
+        .next()
+        .map(|part| part.index().id().to_string())
+        .unwrap_or_else(|| "?".to_string());
+
+    let missing = parts
+        .iter()
+        .enumerate()
+        .filter(|(_, part)| part.is_none())
+        .map(|(i, _)| (i + 1).to_string())
+        .collect::<Vec<_>>();
+
+    format!(
+        "Payload group {id} is missing {} {} of {}",
+        if missing.len() == 1 { "part" } else { "parts" },
+        missing.join(", "),
+        parts.len()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use age::x25519;
+    use qrcloak_core::{
+        format::Payload,
+        payload::{
+            AgeKeyEncryption, Compression, Encoder, EncodingOpts, Encryption, GzipCompression,
+            PayloadGenerator, PayloadSplitter,
+        },
+    };
+    use tempfile::TempDir;
+
+    use crate::secrets::format_identity;
+
+    use super::*;
+
+    fn identity() -> &'static x25519::Identity {
+        static IDENTITY: OnceLock<x25519::Identity> = OnceLock::new();
+        IDENTITY.get_or_init(x25519::Identity::generate)
+    }
+
+    fn generate(text: &str, splits: u32) -> Vec<Payload> {
+        let payload = PayloadGenerator::default()
+            .with_encryption(Encryption::AgeKey(AgeKeyEncryption::new(vec![
+                identity().to_public()
+            ])))
+            .with_compression(Compression::Gzip(GzipCompression))
+            .generate(text.as_bytes().to_vec().into())
+            .expect("should generate");
+
+        if splits == 1 {
+            return vec![Payload::Complete(payload)];
+        }
+
+        PayloadSplitter::default()
+            .with_splits(splits)
+            .split(payload)
+            .map(Payload::from)
+            .collect()
+    }
+
+    fn write(dir: &TempDir, name: &str, payloads: Vec<Payload>) -> PathBuf {
+        let encoded = Encoder::default()
+            .with_encoding(EncodingOpts::Json {
+                pretty: false,
+                merge: true,
+            })
+            .encode(payloads)
+            .expect("should encode");
+
+        let path = dir.path().join(name);
+        std::fs::write(&path, &encoded[0]).unwrap();
+        path
+    }
+
+    fn args(dir: &TempDir, inputs: &[PathBuf], extra: &[&str]) -> DecodeArgs {
+        let key_file = dir.path().join("key.txt");
+        std::fs::write(&key_file, format_identity(identity())).unwrap();
+
+        DecodeArgs::parse_from(
+            ["cmd", "--identity", key_file.to_str().unwrap()]
+                .into_iter()
+                .chain(extra.iter().copied())
+                .chain(inputs.iter().map(|input| input.to_str().unwrap())),
+        )
+    }
+
+    #[test]
+    fn test_decode_mixed_inputs() {
+        let dir = TempDir::new().expect("should create temp dir");
+
+        let mut first = generate("hello world", 3);
+        let second = generate("goodbye", 1);
+
+        // The parts of the group are spread over two files, together with a complete payload
+        let tail = first.split_off(1);
+        let inputs = [
+            write(&dir, "a.json", first.into_iter().chain(second).collect()),
+            write(&dir, "b.json", tail),
+        ];
+
+        let mut args = args(&dir, &inputs, &["--as-text"]);
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+        args.handle().expect("should decode");
+
+        let output = String::from_utf8(output.into_inner()).expect("should be utf-8");
+        let mut lines: Vec<&str> = output.lines().collect();
+        lines.sort();
+        assert_eq!(lines, ["goodbye", "hello world"]);
+    }
+
+    #[test]
+    fn test_decode_output_dir() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let input = write(
+            &dir,
+            "payloads.json",
+            generate("hello", 1)
+                .into_iter()
+                .chain(generate("world", 2))
+                .collect(),
+        );
+
+        let output_dir = dir.path().join("out");
+        args(
+            &dir,
+            &[input],
+            &["--output-dir", output_dir.to_str().unwrap()],
+        )
+        .handle()
+        .expect("should decode");
+
+        let mut contents: Vec<String> = std::fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, ["hello", "world"]);
+        assert!(output_dir.join("payload-1").exists());
+        assert!(output_dir.join("payload-2").exists());
+    }
+
+    #[test]
+    fn test_decode_incomplete() {
+        let dir = TempDir::new().expect("should create temp dir");
+
+        let mut parts = generate("hello world", 4);
+        parts.remove(3);
+        parts.remove(1);
+        let input = write(&dir, "parts.json", parts.clone());
+
+        let mut args = args(&dir, &[input], &[]);
+        args.output = FileOrStdout::new_testing();
+        let error = args.handle().unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Unable to decode 1 of the payloads"));
+        assert_eq!(ErrorKind::of_report(&error), ErrorKind::IncompleteGroup);
+
+        let parts: Vec<Option<PartialPayload>> = [Some(0), None, Some(2), None]
+            .into_iter()
+            .map(|i| {
+                i.map(|i: usize| match &parts[i / 2] {
+                    Payload::Partial(partial) => partial.clone(),
+                    _ => panic!("should be partial"),
+                })
+            })
+            .collect();
+        let id = parts[0].as_ref().unwrap().index().id();
+        assert_eq!(
+            describe_incomplete(&parts),
+            format!("Payload group {id} is missing parts 2, 4 of 4")
+        );
+    }
+}
@@ -3,16 +3,20 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use std::fmt::Debug;
+use std::{fmt::Debug, path::PathBuf};
 
 use age::{secrecy::SecretString, x25519};
-use clap::{Args, CommandFactory, FromArgMatches, Parser};
+use clap::Args;
 use qrcloak_core::payload::{AgeKeyDecryption, AgePassphrase, Decryption};
 
-use crate::env::get_env;
+use crate::{
+    env::get_env,
+    secrets::{prompt_passphrase, read_identity_file},
+};
 
-#[derive(Parser, Debug)]
-struct DecryptionArgsInner {
+/// How the payloads are decrypted, which is resolved when the command runs.
+#[derive(Args, Debug, Clone, Default)]
+pub struct DecryptionOptions {
     #[arg(
         long,
         help = "Read private key from $AGE_PRIVATE_KEY environment variable",
@@ -20,93 +24,57 @@ struct DecryptionArgsInner {
     )]
     age_key: bool,
 
+    #[arg(
+        short,
+        long,
+        value_name = "PATH",
+        conflicts_with_all = ["age_passphrase", "passphrase_prompt"],
+        help = "Decrypt with the private keys in the identity file (can be repeated)"
+    )]
+    identity: Vec<PathBuf>,
+
     #[arg(
         long,
         help = "Read passphrase from $AGE_PASSPHRASE environment variable",
         group = "decryption"
     )]
     age_passphrase: bool,
-}
-
-#[derive(Clone, Debug)]
-pub struct DecryptionOptions(pub Decryption);
-
-impl Parser for DecryptionOptions {}
-
-impl CommandFactory for DecryptionOptions {
-    fn command() -> clap::Command {
-        DecryptionArgsInner::command()
-    }
-    fn command_for_update() -> clap::Command {
-        DecryptionArgsInner::command_for_update()
-    }
-}
 
-impl Args for DecryptionOptions {
-    fn augment_args(cmd: clap::Command) -> clap::Command {
-        DecryptionArgsInner::augment_args(cmd)
-    }
-
-    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
-        DecryptionArgsInner::augment_args_for_update(cmd)
-    }
+    #[arg(
+        long,
+        help = "Prompt for the passphrase on the terminal",
+        group = "decryption"
+    )]
+    passphrase_prompt: bool,
 }
 
-impl TryFrom<DecryptionArgsInner> for DecryptionOptions {
-    type Error = clap::Error;
+impl DecryptionOptions {
+    /// Returns the decryption of the arguments, which is none if none is given.
+    pub fn decryption(&self) -> miette::Result<Decryption> {
+        let mut identities = Vec::new();
 
-    fn try_from(inner: DecryptionArgsInner) -> Result<DecryptionOptions, Self::Error> {
-        if inner.age_key {
+        if self.age_key {
             let key: x25519::Identity = get_env("AGE_PRIVATE_KEY")?;
+            identities.push(key);
+        }
+        for path in self.identity.iter() {
+            identities.extend(read_identity_file(path)?);
+        }
 
-            Ok(DecryptionOptions(Decryption::AgeKey(
-                AgeKeyDecryption::new(vec![key]),
-            )))
-        } else if inner.age_passphrase {
+        if !identities.is_empty() {
+            Ok(Decryption::AgeKey(AgeKeyDecryption::new(identities)))
+        } else if self.age_passphrase {
             let passphrase = get_env("AGE_PASSPHRASE")?;
 
-            Ok(DecryptionOptions(Decryption::AgePassphrase(
-                AgePassphrase::new(SecretString::new(passphrase)),
+            Ok(Decryption::AgePassphrase(AgePassphrase::new(
+                SecretString::new(passphrase),
+            )))
+        } else if self.passphrase_prompt {
+            Ok(Decryption::AgePassphrase(AgePassphrase::new(
+                prompt_passphrase(false)?,
             )))
         } else {
-            Ok(DecryptionOptions(Decryption::NoEncryption))
+            Ok(Decryption::NoEncryption)
         }
     }
 }
-
-impl From<&DecryptionOptions> for DecryptionArgsInner {
-    fn from(args: &DecryptionOptions) -> Self {
-        match args.0 {
-            Decryption::AgeKey(_) => DecryptionArgsInner {
-                age_key: true,
-                age_passphrase: false,
-            },
-            Decryption::AgePassphrase(_) => DecryptionArgsInner {
-                age_key: false,
-                age_passphrase: true,
-            },
-            Decryption::NoEncryption => DecryptionArgsInner {
-                age_key: false,
-                age_passphrase: false,
-            },
-        }
-    }
-}
-
-impl FromArgMatches for DecryptionOptions {
-    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
-        let inner = DecryptionArgsInner::from_arg_matches(matches)?;
-
-        Self::try_from(inner)
-    }
-
-    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
-        let mut inner = DecryptionArgsInner::from(&*self);
-
-        inner.update_from_arg_matches(matches)?;
-
-        *self = Self::try_from(inner)?;
-
-        Ok(())
-    }
-}
@@ -3,17 +3,22 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use std::str::FromStr;
+use std::{path::PathBuf, str::FromStr};
 
-use age::secrecy::SecretString;
-use clap::{Args, FromArgMatches};
+use age::{secrecy::SecretString, x25519::Recipient};
+use clap::Args;
 use qrcloak_core::payload::{AgeKeyEncryption, AgePassphrase, Encryption};
 
-use crate::env::get_env;
+use crate::{
+    config,
+    env::get_env,
+    error::IntoCliDiagnostic,
+    secrets::{prompt_passphrase, read_recipients_file},
+};
 
-#[derive(Args, Debug)]
-#[group(required = false, multiple = false)]
-pub struct EncryptionArgs {
+/// How the data is encrypted, which is resolved when the command runs.
+#[derive(Args, Debug, Clone, Default)]
+pub struct EncryptionOptions {
     #[arg(
         long,
         action,
@@ -21,28 +26,56 @@ pub struct EncryptionArgs {
     )]
     age_key: bool,
 
+    #[arg(
+        short,
+        long,
+        value_name = "RECIPIENT",
+        help = "Encrypt to the given public key (can be repeated)"
+    )]
+    recipient: Vec<Recipient>,
+
+    #[arg(
+        short = 'R',
+        long,
+        value_name = "PATH",
+        help = "Encrypt to the public keys listed in the file (can be repeated)"
+    )]
+    recipients_file: Vec<PathBuf>,
+
+    #[arg(
+        short,
+        long,
+        value_name = "NAME",
+        help = "Encrypt to a recipient group of the configuration (can be repeated)"
+    )]
+    group: Vec<String>,
+
     #[arg(
         long,
         action,
+        conflicts_with_all = ["age_key", "recipient", "recipients_file", "group", "passphrase_prompt"],
         help = "Read passphrase from $AGE_PASSPHRASE environment variable"
     )]
     age_passphrase: bool,
-}
-
-#[derive(Debug, Clone)]
-pub struct EncryptionOptions(pub Encryption);
 
-impl Args for EncryptionOptions {
-    fn augment_args(cmd: clap::Command) -> clap::Command {
-        EncryptionArgs::augment_args(cmd)
-    }
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["age_key", "recipient", "recipients_file", "group"],
+        help = "Prompt for a passphrase on the terminal"
+    )]
+    passphrase_prompt: bool,
 
-    fn augment_args_for_update(cmd: clap::Command) -> clap::Command {
-        EncryptionArgs::augment_args_for_update(cmd)
-    }
+    #[arg(
+        long,
+        action,
+        conflicts_with_all = ["age_key", "recipient", "recipients_file", "group", "age_passphrase", "passphrase_prompt"],
+        help = "Do not encrypt, even if the configuration has default recipients"
+    )]
+    no_encryption: bool,
 }
 
-struct Recipients(Vec<age::x25519::Recipient>);
+struct Recipients(Vec<Recipient>);
 
 impl FromStr for Recipients {
     type Err = &'static str;
@@ -51,68 +84,52 @@ impl FromStr for Recipients {
         value
             .split(',')
             .try_fold(Recipients(Vec::new()), |mut acc, s| {
-                acc.0.push(age::x25519::Recipient::from_str(s)?);
+                acc.0.push(Recipient::from_str(s)?);
 
                 Ok(acc)
             })
     }
 }
 
-impl From<&EncryptionOptions> for EncryptionArgs {
-    fn from(args: &EncryptionOptions) -> Self {
-        match args.0 {
-            Encryption::AgeKey(_) => EncryptionArgs {
-                age_key: true,
-                age_passphrase: false,
-            },
-            Encryption::AgePassphrase(_) => EncryptionArgs {
-                age_key: false,
-                age_passphrase: true,
-            },
-            Encryption::NoEncryption => EncryptionArgs {
-                age_key: false,
-                age_passphrase: false,
-            },
-        }
-    }
-}
-
-impl TryFrom<EncryptionArgs> for EncryptionOptions {
-    type Error = clap::Error;
+impl EncryptionOptions {
+    /// Returns the encryption of the arguments, or the default recipients of the
+    /// configuration if none is given.
+    pub fn encryption(&self) -> miette::Result<Encryption> {
+        let mut recipients: Vec<Recipient> = self.recipient.clone();
 
-    fn try_from(args: EncryptionArgs) -> Result<Self, Self::Error> {
-        if args.age_key {
-            let reciptiens: Recipients = get_env("AGE_KEY")?;
+        if self.age_key {
+            let env_recipients: Recipients = get_env("AGE_KEY")?;
+            recipients.extend(env_recipients.0);
+        }
+        for path in self.recipients_file.iter() {
+            recipients.extend(read_recipients_file(path)?);
+        }
+        for group in self.group.iter() {
+            recipients.extend(config::get()?.group(group).into_diagnostic()?);
+        }
 
-            Ok(EncryptionOptions(Encryption::AgeKey(
-                AgeKeyEncryption::new(reciptiens.0),
-            )))
-        } else if args.age_passphrase {
+        if !recipients.is_empty() {
+            Ok(Encryption::AgeKey(AgeKeyEncryption::new(recipients)))
+        } else if self.age_passphrase {
             let passphrase: String = get_env("AGE_PASSPHRASE")?;
 
-            Ok(EncryptionOptions(Encryption::AgePassphrase(
-                AgePassphrase::new(SecretString::new(passphrase)),
+            Ok(Encryption::AgePassphrase(AgePassphrase::new(
+                SecretString::new(passphrase),
+            )))
+        } else if self.passphrase_prompt {
+            Ok(Encryption::AgePassphrase(AgePassphrase::new(
+                prompt_passphrase(true)?,
             )))
+        } else if self.no_encryption {
+            Ok(Encryption::NoEncryption)
         } else {
-            Ok(EncryptionOptions(Encryption::NoEncryption))
-        }
-    }
-}
-
-impl FromArgMatches for EncryptionOptions {
-    fn from_arg_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
-        let inner = EncryptionArgs::from_arg_matches(matches)?;
-
-        inner.try_into()
-    }
-
-    fn update_from_arg_matches(&mut self, matches: &clap::ArgMatches) -> Result<(), clap::Error> {
-        let mut inner: EncryptionArgs = (&*self).into();
-
-        inner.update_from_arg_matches(matches)?;
+            let recipients = config::get()?.default_recipients().into_diagnostic()?;
 
-        *self = inner.try_into()?;
-
-        Ok(())
+            if recipients.is_empty() {
+                Ok(Encryption::NoEncryption)
+            } else {
+                Ok(Encryption::AgeKey(AgeKeyEncryption::new(recipients)))
+            }
+        }
     }
 }
@@ -3,31 +3,15 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use std::{convert, fmt::Display, str::FromStr};
+use std::{fmt::Display, str::FromStr};
 
-pub fn get_env<T: FromStr<Err = impl Display>>(name: &str) -> Result<T, clap::Error> {
-    std::env::var(name)
-        .map_err(|_| {
-            let mut error = clap::Error::new(clap::error::ErrorKind::MissingRequiredArgument);
-            error.insert(
-                clap::error::ContextKind::InvalidArg,
-                clap::error::ContextValue::Strings(vec![format!("env: {name}=")]),
-            );
-            error
-        })
-        .map(|v| {
-            T::from_str(&v).map_err(|err| {
-                let mut error = clap::Error::new(clap::error::ErrorKind::InvalidValue);
-                error.insert(
-                    clap::error::ContextKind::InvalidArg,
-                    clap::error::ContextValue::String(name.to_string()),
-                );
-                error.insert(
-                    clap::error::ContextKind::InvalidValue,
-                    clap::error::ContextValue::String(format!("{err}")),
-                );
-                error
-            })
-        })
-        .and_then(convert::identity)
+use crate::error::{CliError, ErrorKind};
+
+/// Reads and parses an environment variable that an argument asks for.
+pub fn get_env<T: FromStr<Err = impl Display>>(name: &str) -> miette::Result<T> {
+    let value = std::env::var(name)
+        .map_err(|_| CliError::new(ErrorKind::Usage, format!("${name} is not set")))?;
+
+    T::from_str(&value)
+        .map_err(|err| CliError::new(ErrorKind::Usage, format!("${name} is invalid: {err}")).into())
 }
@@ -0,0 +1,321 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{error::Error, fmt::Display, io};
+
+use miette::Diagnostic;
+use qrcloak_core::payload::{
+    DecodingError, DecompressionError, DecryptionError, PayloadExtractionError, PayloadRekeyError,
+};
+use serde::Serialize;
+
+/// Exit codes of the process, shown in the help.
+pub const EXIT_CODES: &str = "\
+Exit codes:
+  0  Success
+  1  Any other error
+  2  Invalid arguments or environment variables (usage)
+  3  Reading or writing a file failed (io)
+  4  The input does not contain valid payloads (decode)
+  5  Parts of a split payload are missing (incomplete-group)
+  6  The payload is encrypted or compressed differently than expected (spec-mismatch)
+  7  No identity or passphrase can decrypt the payload (no-matching-key)";
+
+/// The class of an error, which decides the exit code of the process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ErrorKind {
+    Other,
+    Usage,
+    Io,
+    Decode,
+    IncompleteGroup,
+    SpecMismatch,
+    NoMatchingKey,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Usage => 2,
+            ErrorKind::Io => 3,
+            ErrorKind::Decode => 4,
+            ErrorKind::IncompleteGroup => 5,
+            ErrorKind::SpecMismatch => 6,
+            ErrorKind::NoMatchingKey => 7,
+        }
+    }
+
+    /// What an error of the kind means, in the words of [`EXIT_CODES`].
+    pub fn description(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "the command failed",
+            ErrorKind::Usage => "invalid arguments or environment variables",
+            ErrorKind::Io => "reading or writing a file failed",
+            ErrorKind::Decode => "the input does not contain valid payloads",
+            ErrorKind::IncompleteGroup => "parts of a split payload are missing",
+            ErrorKind::SpecMismatch => {
+                "the payload is encrypted or compressed differently than expected"
+            }
+            ErrorKind::NoMatchingKey => "no identity or passphrase can decrypt the payload",
+        }
+    }
+
+    /// Returns the kind of the first error in the chain that has one.
+    pub fn of(error: &(dyn Error + 'static)) -> ErrorKind {
+        let mut next = Some(error);
+        while let Some(error) = next {
+            if let Some(kind) = Self::classify(error) {
+                return kind;
+            }
+            next = error.source();
+        }
+
+        ErrorKind::Other
+    }
+
+    /// Like [`ErrorKind::of`], for the chain of a report including its context.
+    pub fn of_report(report: &miette::Report) -> ErrorKind {
+        report
+            .chain()
+            .find_map(Self::classify)
+            .unwrap_or(ErrorKind::Other)
+    }
+
+    fn classify(error: &(dyn Error + 'static)) -> Option<ErrorKind> {
+        if let Some(error) = error.downcast_ref::<CliError>() {
+            return Some(error.kind);
+        }
+
+        if error.is::<clap::Error>() {
+            return Some(ErrorKind::Usage);
+        }
+
+        if let Some(error) = error.downcast_ref::<PayloadExtractionError>() {
+            return match error {
+                PayloadExtractionError::DecryptionError(error) => Some(Self::decryption(error)),
+                PayloadExtractionError::DecompressionError(_) => Some(ErrorKind::SpecMismatch),
+            };
+        }
+
+        if let Some(error) = error.downcast_ref::<PayloadRekeyError>() {
+            return match error {
+                PayloadRekeyError::DecryptionError(error) => Some(Self::decryption(error)),
+                PayloadRekeyError::EncryptionError(_) => None,
+                PayloadRekeyError::Incomplete { .. } => Some(ErrorKind::IncompleteGroup),
+                PayloadRekeyError::InvalidIndex { .. } | PayloadRekeyError::TooShort { .. } => {
+                    Some(ErrorKind::Decode)
+                }
+            };
+        }
+
+        if let Some(error) = error.downcast_ref::<DecryptionError>() {
+            return Some(Self::decryption(error));
+        }
+
+        if let Some(error) = error.downcast_ref::<age::DecryptError>() {
+            return Some(Self::age(error));
+        }
+
+        if error.is::<DecompressionError>() {
+            return Some(ErrorKind::SpecMismatch);
+        }
+
+        // Inputs from arguments or stdin are parsed into payloads
+        if let Some(error) = error.downcast_ref::<clap_stdin::StdinError>() {
+            return match error {
+                clap_stdin::StdinError::StdIn(_) => Some(ErrorKind::Io),
+                clap_stdin::StdinError::FromStr(_) => Some(ErrorKind::Decode),
+                clap_stdin::StdinError::StdInRepeatedUse => None,
+            };
+        }
+
+        if error.is::<DecodingError>() || error.is::<serde_json::Error>() {
+            return Some(ErrorKind::Decode);
+        }
+
+        if error.is::<io::Error>() {
+            return Some(ErrorKind::Io);
+        }
+
+        None
+    }
+
+    fn decryption(error: &DecryptionError) -> ErrorKind {
+        match error {
+            DecryptionError::SpecMismtach { .. } => ErrorKind::SpecMismatch,
+            DecryptionError::Age(error) => Self::age(error),
+        }
+    }
+
+    fn age(error: &age::DecryptError) -> ErrorKind {
+        match error {
+            // A wrong passphrase fails to decrypt the file key
+            age::DecryptError::NoMatchingKeys
+            | age::DecryptError::KeyDecryptionFailed
+            | age::DecryptError::DecryptionFailed => ErrorKind::NoMatchingKey,
+            // Payloads are decrypted in memory, so reading only fails for a corrupt
+            // or truncated ciphertext
+            age::DecryptError::Io(error)
+                if matches!(
+                    error.kind(),
+                    io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                ) =>
+            {
+                ErrorKind::Decode
+            }
+            age::DecryptError::Io(_) => ErrorKind::Io,
+            _ => ErrorKind::Decode,
+        }
+    }
+}
+
+/// An error together with its kind.
+///
+/// The errors of the commands themselves are only their message. Other errors are
+/// wrapped to keep their kind, the wrapper then displays what the kind means and has
+/// the error as its source.
+#[derive(Debug)]
+pub struct CliError {
+    kind: ErrorKind,
+    message: String,
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
+}
+
+impl CliError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn wrap(kind: ErrorKind, error: impl Into<Box<dyn Error + Send + Sync + 'static>>) -> Self {
+        Self {
+            kind,
+            message: kind.description().to_string(),
+            source: Some(error.into()),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for CliError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_deref()
+            .map(|error| error as &(dyn Error + 'static))
+    }
+}
+
+impl Diagnostic for CliError {}
+
+/// Like [`miette::IntoDiagnostic`], but keeps the [`ErrorKind`] of the error for the
+/// exit code and the JSON output. The errors converted by miette only ever have
+/// [`ErrorKind::Other`], so the commands use this one.
+pub trait IntoCliDiagnostic<T> {
+    fn into_diagnostic(self) -> miette::Result<T>;
+}
+
+impl<T, E: Error + Send + Sync + 'static> IntoCliDiagnostic<T> for Result<T, E> {
+    fn into_diagnostic(self) -> miette::Result<T> {
+        self.map_err(|error| CliError::wrap(ErrorKind::of(&error), error).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Context;
+    use qrcloak_core::payload::{
+        AgeKeyDecryption, AgeKeyEncryption, AgePassphrase, Compression, Decompression, Decryption,
+        Encryption, GzipCompression, PayloadExtractor, PayloadGenerator,
+    };
+
+    use super::*;
+
+    fn extract(encryption: Encryption, extractor: PayloadExtractor) -> ErrorKind {
+        let payload = PayloadGenerator::default()
+            .with_encryption(encryption)
+            .with_compression(Compression::Gzip(GzipCompression))
+            .generate(b"hello world".to_vec().into())
+            .expect("should generate");
+
+        let error = extractor
+            .extract(payload)
+            .map(|_| ())
+            .map_err(|error| CliError::wrap(ErrorKind::of(&error), error))
+            .expect_err("should fail");
+
+        error.kind()
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let identity = age::x25519::Identity::generate();
+        let other = age::x25519::Identity::generate();
+        let keys = || Encryption::AgeKey(AgeKeyEncryption::new(vec![identity.to_public()]));
+
+        let gzip =
+            PayloadExtractor::default().with_decompression(Decompression::Gzip(GzipCompression));
+
+        assert_eq!(
+            extract(
+                keys(),
+                gzip.clone()
+                    .with_decryption(Decryption::AgeKey(AgeKeyDecryption::new(vec![other])))
+            ),
+            ErrorKind::NoMatchingKey
+        );
+
+        assert_eq!(
+            extract(
+                Encryption::AgePassphrase(AgePassphrase::new("correct".to_string().into())),
+                gzip.clone()
+                    .with_decryption(Decryption::AgePassphrase(AgePassphrase::new(
+                        "wrong".to_string().into()
+                    )))
+            ),
+            ErrorKind::NoMatchingKey
+        );
+
+        assert_eq!(extract(keys(), gzip.clone()), ErrorKind::SpecMismatch);
+
+        assert_eq!(
+            extract(Encryption::NoEncryption, PayloadExtractor::default()),
+            ErrorKind::SpecMismatch
+        );
+
+        // The kind survives the conversion into a report and added context
+        let report = std::fs::read("/nonexistent/qrcloak")
+            .into_diagnostic()
+            .wrap_err("Unable to read")
+            .unwrap_err();
+        assert_eq!(ErrorKind::of_report(&report), ErrorKind::Io);
+
+        let report = serde_json::from_str::<serde_json::Value>("{")
+            .into_diagnostic()
+            .unwrap_err();
+        assert_eq!(ErrorKind::of_report(&report), ErrorKind::Decode);
+
+        // The wrapped error follows what its kind means
+        let report = std::fs::read("/nonexistent/qrcloak")
+            .into_diagnostic()
+            .unwrap_err();
+        let chain: Vec<_> = report.chain().collect();
+        assert_eq!(chain[0].to_string(), ErrorKind::Io.description());
+        assert!(chain[1].is::<io::Error>());
+    }
+}
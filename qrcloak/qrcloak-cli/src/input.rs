@@ -3,7 +3,7 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use std::str::FromStr;
+use std::{io::Read, str::FromStr};
 
 use clap::Args;
 use clap_stdin::{FileOrStdin, StdinError};
@@ -41,3 +41,32 @@ where
         }
     }
 }
+
+/// Input of arbitrary bytes, files are read as is without trimming.
+#[derive(Args, Debug, Clone)]
+#[group(required = true, multiple = false)]
+pub struct BinaryInput {
+    #[arg(long, help = "Read data from argument")]
+    pub text: Option<String>,
+
+    #[arg(
+        long,
+        value_name = "FILENAME",
+        help = "Read data from file (use '-' for stdin)"
+    )]
+    pub file: Option<FileOrStdin>,
+}
+
+impl BinaryInput {
+    pub fn contents(self) -> Result<Vec<u8>, StdinError> {
+        if let Some(text) = self.text {
+            Ok(text.into_bytes())
+        } else if let Some(file) = self.file {
+            let mut data = Vec::new();
+            file.into_reader()?.read_to_end(&mut data)?;
+            Ok(data)
+        } else {
+            panic!("ArgGroup is required so at least one of the args should be set")
+        }
+    }
+}
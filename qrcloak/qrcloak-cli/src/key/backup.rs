@@ -0,0 +1,112 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use clap_stdin::FileOrStdin;
+use image::{imageops, GrayImage, Luma};
+use qrcloak_core::{
+    generate::{Generator, Symbol},
+    payload::{Encryption, PayloadGenerator},
+};
+
+use crate::{
+    encryption::EncryptionOptions,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    qrcode::{ecl_or_configured, generate_splits, splits_or_configured, Ecl, Splits},
+    report::Summary,
+    secrets::format_identity,
+};
+
+use super::read_identities;
+
+// Pixels per module on the sheet and the space around each symbol
+const SCALE: u32 = 2;
+const MARGIN: u32 = 32;
+
+#[derive(Parser, Debug)]
+pub struct KeyBackupArgs {
+    /// The identity file to back up (use '-' for stdin)
+    identity: FileOrStdin,
+
+    #[command(flatten)]
+    encryption: EncryptionOptions,
+
+    /// Split the payload into this many parts, or with 'auto' into as few as fit into a QR Code each
+    /// [default: 1]
+    #[arg(short, long, value_name = "N|auto")]
+    splits: Option<Splits>,
+
+    /// The error correction level of the QR Codes [default: high]
+    #[arg(long, value_enum)]
+    ecl: Option<Ecl>,
+
+    /// The image to write the sheet to
+    #[arg(short, long)]
+    output: PathBuf,
+}
+
+impl KeyBackupArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let encryption = self.encryption.encryption()?;
+        if let Encryption::NoEncryption = encryption {
+            return Err(CliError::new(
+                ErrorKind::Usage,
+                "The backup of an identity must be encrypted, e.g. with --passphrase-prompt",
+            )
+            .into());
+        }
+
+        // Only the keys are kept, so that the restored file is a valid identity file
+        let identities = read_identities(&self.identity)?;
+        let contents: String = identities.iter().map(format_identity).collect();
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(encryption)
+            .generate(contents.into_bytes().into())
+            .into_diagnostic()?;
+
+        let generator = Generator::default()
+            .with_ecl(ecl_or_configured(self.ecl)?)
+            .with_uniform_version(true);
+
+        let images = generate_splits(
+            splits_or_configured(self.splits)?,
+            &generator,
+            payload,
+            Symbol::to_image,
+        )?;
+
+        sheet(&images).save(&self.output).into_diagnostic()?;
+
+        Ok(Summary::default().with_output(self.output.display()))
+    }
+}
+
+// Stacks the symbols on a white sheet, enlarged for printing
+fn sheet(images: &[GrayImage]) -> GrayImage {
+    let width = images.iter().map(|image| image.width()).max().unwrap_or(0) * SCALE;
+    let height = images
+        .iter()
+        .map(|image| image.height() * SCALE + MARGIN)
+        .sum::<u32>();
+
+    let mut sheet = GrayImage::from_pixel(width + 2 * MARGIN, height + MARGIN, Luma([255]));
+
+    let mut y = MARGIN;
+    for image in images {
+        let image = imageops::resize(
+            image,
+            image.width() * SCALE,
+            image.height() * SCALE,
+            imageops::FilterType::Nearest,
+        );
+        imageops::overlay(&mut sheet, &image, i64::from(MARGIN), i64::from(y));
+        y += image.height() + MARGIN;
+    }
+
+    sheet
+}
@@ -0,0 +1,113 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::io::Write;
+
+use age::x25519;
+use clap::Parser;
+use miette::Context;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+use crate::{
+    error::IntoCliDiagnostic,
+    report::{self, Event, Summary},
+    secrets::format_identity,
+    FileOrStdout,
+};
+
+#[derive(Parser, Debug)]
+pub struct KeyGenerateArgs {
+    /// Write the identity to a new file, readable only by its owner, instead of stdout
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+impl KeyGenerateArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let identity = x25519::Identity::generate();
+
+        let created = OffsetDateTime::now_utc()
+            .replace_nanosecond(0)
+            .into_diagnostic()?
+            .format(&Rfc3339)
+            .into_diagnostic()?;
+
+        let mut writer = self
+            .output
+            .try_get_private_writer()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Unable to create {}", self.output))?;
+
+        writeln!(writer, "# created: {created}").into_diagnostic()?;
+        write!(writer, "{}", format_identity(&identity)).into_diagnostic()?;
+
+        // The public key is in the file, but needed right away to share it
+        if let FileOrStdout::File(_) = self.output {
+            report::emit(Event::PublicKey {
+                key: identity.to_public().to_string(),
+            });
+        }
+
+        Ok(Summary::default().with_output(self.output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::secrets::{parse_identities, read_identity_file};
+
+    use super::*;
+
+    #[test]
+    fn test_generate_key() {
+        let mut args = KeyGenerateArgs::parse_from(["cmd"]);
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle().unwrap();
+
+        let output = String::from_utf8(output.into_inner()).expect("should be utf-8");
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("# created: "));
+
+        let identities = parse_identities(output.as_bytes()).expect("should parse");
+        assert_eq!(identities.len(), 1);
+        assert_eq!(
+            lines[1],
+            format!("# public key: {}", identities[0].to_public())
+        );
+    }
+
+    #[test]
+    fn test_generate_key_file() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("key.txt");
+
+        KeyGenerateArgs::parse_from(["cmd", "-o", path.to_str().unwrap()])
+            .handle()
+            .unwrap();
+        assert_eq!(read_identity_file(&path).unwrap().len(), 1);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        // An existing identity is never overwritten
+        let before = std::fs::read(&path).unwrap();
+        assert!(
+            KeyGenerateArgs::parse_from(["cmd", "-o", path.to_str().unwrap()])
+                .handle()
+                .is_err()
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), before);
+    }
+}
@@ -0,0 +1,42 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::io::BufReader;
+
+use age::x25519;
+use clap::Subcommand;
+use clap_stdin::FileOrStdin;
+use miette::Context;
+
+mod backup;
+mod generate;
+mod public;
+
+pub use backup::KeyBackupArgs;
+pub use generate::KeyGenerateArgs;
+pub use public::KeyPublicArgs;
+
+use crate::{error::IntoCliDiagnostic, secrets::parse_identities};
+
+#[derive(Subcommand, Debug)]
+pub enum KeyCommand {
+    /// Generate a new identity, like age-keygen
+    Generate(KeyGenerateArgs),
+    /// Print the public keys of the identities in an identity file
+    Public(KeyPublicArgs),
+    /// Write an encrypted backup of an identity file as a printable sheet of QR Codes
+    ///
+    /// Restore it with `qrcode extract`, passing the same encryption options
+    /// for decryption and the identity file as output.
+    Backup(KeyBackupArgs),
+}
+
+fn read_identities(input: &FileOrStdin) -> miette::Result<Vec<x25519::Identity>> {
+    let reader = input.into_reader().into_diagnostic()?;
+
+    parse_identities(BufReader::new(reader))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read identities from {:?}", input.source))
+}
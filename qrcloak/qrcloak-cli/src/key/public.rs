@@ -0,0 +1,76 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::io::Write;
+
+use clap::Parser;
+use clap_stdin::FileOrStdin;
+
+use crate::{error::IntoCliDiagnostic, report::Summary, FileOrStdout};
+
+use super::read_identities;
+
+#[derive(Parser, Debug)]
+pub struct KeyPublicArgs {
+    /// The identity file (use '-' for stdin)
+    #[arg(default_value = "-")]
+    identity: FileOrStdin,
+
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+impl KeyPublicArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let identities = read_identities(&self.identity)?;
+
+        let mut writer = self.output.try_get_writer().into_diagnostic()?;
+
+        for identity in identities {
+            writeln!(writer, "{}", identity.to_public()).into_diagnostic()?;
+        }
+
+        Ok(Summary::default().with_output(self.output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age::x25519;
+    use tempfile::TempDir;
+
+    use crate::secrets::format_identity;
+
+    use super::*;
+
+    #[test]
+    fn test_public_keys() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("keys.txt");
+
+        let identities = [x25519::Identity::generate(), x25519::Identity::generate()];
+        std::fs::write(
+            &path,
+            identities.iter().map(format_identity).collect::<String>(),
+        )
+        .unwrap();
+
+        let mut args = KeyPublicArgs::parse_from(["cmd", path.to_str().unwrap()]);
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle().unwrap();
+
+        assert_eq!(
+            String::from_utf8(output.into_inner()).unwrap(),
+            format!(
+                "{}\n{}\n",
+                identities[0].to_public(),
+                identities[1].to_public()
+            )
+        );
+    }
+}
@@ -6,21 +6,31 @@
 use std::{
     fmt::Debug,
     io::{self},
+    process::ExitCode,
 };
 
 mod output;
 use clap::{CommandFactory, Parser, Subcommand};
 use clap_complete::{generate, Shell};
 
-pub use output::FileOrStdout;
+pub use output::{DataOutputOptions, FileOrStdout};
 
+use batch::BatchArgs;
+use decode::DecodeArgs;
+use key::KeyCommand;
 use payload::PayloadCommand;
 
+use error::{CliError, ErrorKind};
 use qrcode::QrCodeCommand;
+use report::{ErrorReport, Event, OutputFormat, Summary};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, after_long_help = error::EXIT_CODES)]
 struct Cli {
+    /// How messages and errors are written to stderr, 'json' writes one object per line
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output_format: OutputFormat,
+
     #[command(subcommand)]
     command: Command,
 }
@@ -30,6 +40,15 @@ enum Command {
     Payload(PayloadArgs),
     #[command(name = "qrcode")]
     QrCode(QrCodeArgs),
+    Key(KeyArgs),
+    /// Merge, decrypt and decompress payloads from any number of files into their data
+    Decode(DecodeArgs),
+    /// Generate the QR Codes listed in a manifest, skipping those that have not changed
+    ///
+    /// What was generated is kept in <MANIFEST>.state.json next to the manifest. It holds
+    /// salted hashes of the data, against which guesses at the data can still be checked,
+    /// so do not publish it.
+    Batch(BatchArgs),
 
     #[command(hide = true)]
     Completion(CompletionArgs),
@@ -52,22 +71,75 @@ struct QrCodeArgs {
     inner: QrCodeCommand,
 }
 
+#[derive(Parser, Debug)]
+struct KeyArgs {
+    #[command(subcommand)]
+    command: KeyCommand,
+}
+
+mod batch;
+pub mod compression;
+mod config;
+mod decode;
 pub mod decryption;
 
 pub mod encryption;
 pub mod env;
+pub mod error;
 pub mod input;
+mod key;
 mod payload;
 mod qrcode;
+pub mod report;
+pub mod secrets;
 
-fn main() -> miette::Result<()> {
+fn main() -> ExitCode {
     miette::set_panic_hook();
-    miette::set_hook(Box::new(|_| {
+    let _ = miette::set_hook(Box::new(|_| {
         Box::new(miette::MietteHandlerOpts::new().build())
-    }))?;
+    }));
+
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(error) => {
+            // Help and version go to stdout, usage errors are reported like all others
+            // if JSON is asked for
+            let format = OutputFormat::from_args(std::env::args_os());
+            if !error.use_stderr() || format == OutputFormat::Text {
+                error.exit();
+            }
+
+            report::init(format);
+            return finish(Err(CliError::wrap(ErrorKind::Usage, error).into()));
+        }
+    };
+
+    report::init(cli.output_format);
+
+    finish(run(cli))
+}
 
-    let cli = Cli::parse();
+fn finish(result: miette::Result<Summary>) -> ExitCode {
+    match result {
+        Ok(summary) => {
+            report::emit(Event::Ok(summary));
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            let error_report = ErrorReport::new(&error);
+            let exit_code = error_report.exit_code;
+
+            match report::format() {
+                OutputFormat::Text => eprintln!("{error:?}"),
+                OutputFormat::Json => report::emit(Event::Error(error_report)),
+            }
 
+            ExitCode::from(exit_code)
+        }
+    }
+}
+
+fn run(cli: Cli) -> miette::Result<Summary> {
     match cli.command {
         Command::Completion(args) => {
             let cmd = &mut Cli::command();
@@ -77,16 +149,28 @@ fn main() -> miette::Result<()> {
                 cmd.get_name().to_string(),
                 &mut io::stdout(),
             );
+
+            Ok(Summary::default())
         }
         Command::Payload(args) => match args.command {
-            PayloadCommand::Generate(args) => args.handle()?,
-            PayloadCommand::Extract(args) => args.handle()?,
-            PayloadCommand::Merge(args) => args.handle()?,
+            PayloadCommand::Generate(args) => args.handle(),
+            PayloadCommand::Extract(args) => args.handle(),
+            PayloadCommand::Merge(args) => args.handle(),
+            PayloadCommand::Inspect(args) => args.handle(),
+            PayloadCommand::Rekey(args) => args.handle(),
         },
         Command::QrCode(args) => match args.inner {
-            QrCodeCommand::Generate(args) => args.handle()?,
+            QrCodeCommand::Generate(args) => args.handle(),
+            QrCodeCommand::Extract(args) => args.handle(),
+            QrCodeCommand::Scan(args) => args.handle(),
+            QrCodeCommand::Show(args) => args.handle(),
         },
+        Command::Key(args) => match args.command {
+            KeyCommand::Generate(args) => args.handle(),
+            KeyCommand::Public(args) => args.handle(),
+            KeyCommand::Backup(args) => args.handle(),
+        },
+        Command::Decode(args) => args.handle(),
+        Command::Batch(args) => args.handle(),
     }
-
-    Ok(())
 }
@@ -10,6 +10,8 @@ use std::{
     str::FromStr,
 };
 
+use clap::Args;
+
 #[cfg(test)]
 use std::sync::{Arc, Mutex, Weak};
 
@@ -31,8 +33,7 @@ impl FileOrStdout {
         match self {
             FileOrStdout::InMem(v) => {
                 let v = Arc::into_inner(v).expect("should have only one reference");
-                let v = v.into_inner().expect("should not be locked");
-                v
+                v.into_inner().expect("should not be locked")
             }
             _ => panic!("should be in-mem"),
         }
@@ -54,6 +55,22 @@ impl FileOrStdout {
     pub fn try_get_writer(&self) -> Result<FileOrStdoutWriter, io::Error> {
         FileOrStdoutWriter::try_from(self.clone())
     }
+
+    /// Like [`FileOrStdout::try_get_writer`], but refuses to overwrite an existing file
+    /// and creates it readable only by its owner, for writing secrets.
+    pub fn try_get_private_writer(&self) -> Result<FileOrStdoutWriter, io::Error> {
+        let FileOrStdout::File(path) = self else {
+            return self.try_get_writer();
+        };
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        Ok(FileOrStdoutWriter::File(options.open(path)?))
+    }
 }
 
 impl TryFrom<FileOrStdout> for FileOrStdoutWriter {
@@ -83,13 +100,12 @@ impl Write for FileOrStdoutWriter {
             FileOrStdoutWriter::Stdout(stdout) => stdout.write(buf),
             #[cfg(test)]
             FileOrStdoutWriter::InMem(v) => {
-                let v = v.upgrade().ok_or(io::Error::new(
-                    io::ErrorKind::Other,
-                    "unable to upgrade arc",
-                ))?;
-                let mut v = v.lock().map_err(|e| {
-                    io::Error::new(io::ErrorKind::Other, format!("unable to lock arc: {}", e))
-                })?;
+                let v = v
+                    .upgrade()
+                    .ok_or(io::Error::other("unable to upgrade arc"))?;
+                let mut v = v
+                    .lock()
+                    .map_err(|e| io::Error::other(format!("unable to lock arc: {}", e)))?;
                 v.write(buf)
             }
         }
@@ -116,3 +132,23 @@ impl FromStr for FileOrStdout {
         }
     }
 }
+
+/// How extracted data is written to the output.
+#[derive(Args, Debug, Clone, Default)]
+pub struct DataOutputOptions {
+    /// Write the data as text with a trailing newline instead of the exact bytes
+    ///
+    /// The flag is not called --text, as that one already reads the input from an argument.
+    #[arg(long)]
+    pub as_text: bool,
+}
+
+impl DataOutputOptions {
+    pub fn write(&self, writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+        if self.as_text {
+            writeln!(writer, "{}", String::from_utf8_lossy(data))
+        } else {
+            writer.write_all(data)
+        }
+    }
+}
@@ -8,14 +8,15 @@ use std::str::FromStr;
 use clap::Parser;
 use qrcloak_core::{
     format::CompletePayload,
-    payload::{OneOrMany, PayloadExtractor},
+    payload::{Decompression, OneOrMany, PayloadExtractor},
 };
-use std::io::Write;
 
-use miette::IntoDiagnostic;
 use serde::de::Error;
 
-use crate::{decryption::DecryptionOptions, input::Input, FileOrStdout};
+use crate::{
+    decryption::DecryptionOptions, error::IntoCliDiagnostic, input::Input, report::Summary,
+    DataOutputOptions, FileOrStdout,
+};
 
 #[derive(Parser, Debug)]
 pub struct PayloadExtractArgs {
@@ -25,6 +26,9 @@ pub struct PayloadExtractArgs {
     #[command(flatten)]
     decryption: DecryptionOptions,
 
+    #[command(flatten)]
+    data_output: DataOutputOptions,
+
     #[arg(default_value_t = FileOrStdout::Stdout)]
     output: FileOrStdout,
 }
@@ -54,18 +58,21 @@ impl FromStr for PayloadExtractText {
 }
 
 impl PayloadExtractArgs {
-    pub fn handle(self) -> miette::Result<()> {
+    pub fn handle(self) -> miette::Result<Summary> {
         let payload = self.input.contents().into_diagnostic()?.0;
 
         let data = PayloadExtractor::default()
-            .with_decryption(self.decryption.0)
+            .with_decryption(self.decryption.decryption()?)
+            .with_decompression(Decompression::from(payload.compression()))
             .extract(payload)
             .into_diagnostic()?;
 
         let mut writer = self.output.try_get_writer().into_diagnostic()?;
 
-        writeln!(writer, "{}", String::from_utf8_lossy(&data)).into_diagnostic()?;
+        self.data_output
+            .write(&mut writer, &data)
+            .into_diagnostic()?;
 
-        Ok(())
+        Ok(Summary::default().with_payloads(1).with_output(self.output))
     }
 }
@@ -8,13 +8,15 @@ use std::vec;
 
 use clap::Parser;
 
-use miette::IntoDiagnostic;
 use qrcloak_core::format::Payload;
 use qrcloak_core::payload::{Encoder, EncodingOpts, PayloadGenerator, PayloadSplitter};
 
+use crate::config;
 use crate::encryption::EncryptionOptions;
-use crate::input::Input;
+use crate::input::BinaryInput;
+use crate::report::Summary;
 use crate::FileOrStdout;
+use crate::{compression::CompressionOptions, error::IntoCliDiagnostic};
 
 #[derive(Parser, Debug)]
 pub struct PayloadGenerateArgs {
@@ -25,21 +27,30 @@ pub struct PayloadGenerateArgs {
     encryption: EncryptionOptions,
 
     #[command(flatten)]
-    input: Input<String>,
+    compression: CompressionOptions,
 
-    #[arg(long)]
+    #[command(flatten)]
+    input: BinaryInput,
+
+    /// Pretty print the encoded payloads [default: as configured]
+    #[arg(long, overrides_with = "no_pretty")]
     pretty: bool,
 
+    /// Do not pretty print the encoded payloads, even if configured
+    #[arg(long, overrides_with = "pretty")]
+    no_pretty: bool,
+
     #[arg(default_value_t = FileOrStdout::Stdout)]
     output: FileOrStdout,
 }
 
 impl PayloadGenerateArgs {
-    pub fn handle(self) -> miette::Result<()> {
+    pub fn handle(self) -> miette::Result<Summary> {
         let input = self.input.contents().into_diagnostic()?;
 
         let payloads = PayloadGenerator::default()
-            .with_encryption(self.encryption.0)
+            .with_encryption(self.encryption.encryption()?)
+            .with_compression(self.compression.compression()?)
             .generate(input.into())
             .into_diagnostic()?;
 
@@ -52,10 +63,11 @@ impl PayloadGenerateArgs {
         } else {
             vec![Payload::from(payloads)]
         };
+        let count = payloads.len();
 
         let encoded_payloads = Encoder::default()
             .with_encoding(EncodingOpts::Json {
-                pretty: self.pretty,
+                pretty: config::flag(self.pretty, self.no_pretty, |config| config.defaults.pretty)?,
                 merge: true,
             })
             .encode(payloads)
@@ -65,13 +77,18 @@ impl PayloadGenerateArgs {
 
         writeln!(writer, "{}", encoded_payloads[0]).into_diagnostic()?;
 
-        Ok(())
+        Ok(Summary::default()
+            .with_payloads(count)
+            .with_output(self.output))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use qrcloak_core::payload::{Decoder, PayloadExtractor, PayloadMerger};
+    use qrcloak_core::{
+        format::CompressionSpec,
+        payload::{Decoder, Decompression, PayloadExtractor, PayloadMerger},
+    };
 
     use super::*;
 
@@ -106,4 +123,34 @@ mod tests {
 
         assert_eq!(&*extracted, b"hello world");
     }
+
+    #[test]
+    fn test_generate_compressed_payload() {
+        let text = "hello world ".repeat(100);
+
+        let mut args =
+            PayloadGenerateArgs::parse_from(["cmd", "--compression", "gzip", "--text", &text]);
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle().unwrap();
+
+        let mut payloads = Decoder::default()
+            .decode(&output.into_inner())
+            .expect("should decode");
+
+        let Some(Payload::Complete(complete)) = payloads.pop() else {
+            panic!("should have one complete payload");
+        };
+
+        assert_eq!(complete.compression(), &CompressionSpec::Gzip);
+
+        let extracted = PayloadExtractor::default()
+            .with_decompression(Decompression::from(complete.compression()))
+            .extract(complete)
+            .expect("should extract");
+
+        assert_eq!(&*extracted, text.as_bytes());
+    }
 }
@@ -0,0 +1,248 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{io::Write, path::PathBuf};
+
+use clap::Parser;
+use qrcloak_core::{
+    format::{CompressionSpec, EncryptionSpec},
+    payload::{PayloadInfo, PayloadKind},
+};
+use serde::Serialize;
+
+use crate::{
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    report::Summary,
+    FileOrStdout,
+};
+
+use super::read_payloads;
+
+#[derive(Parser, Debug)]
+pub struct PayloadInspectArgs {
+    /// Files with encoded payloads, or images and PDFs with QR Codes (use '-' for stdin)
+    #[arg(default_value = "-")]
+    inputs: Vec<PathBuf>,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+#[derive(Serialize)]
+struct Inspection {
+    source: String,
+    #[serde(flatten)]
+    info: PayloadInfo,
+}
+
+impl PayloadInspectArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let inspections: Vec<Inspection> = read_payloads(&self.inputs)?
+            .into_iter()
+            .map(|(source, payload)| Inspection {
+                source,
+                info: PayloadInfo::from(&payload),
+            })
+            .collect();
+
+        if inspections.is_empty() {
+            return Err(CliError::new(ErrorKind::Decode, "No qrcloak payloads found").into());
+        }
+
+        let mut writer = self.output.try_get_writer().into_diagnostic()?;
+        let summary = Summary::default()
+            .with_payloads(inspections.len())
+            .with_output(&self.output);
+
+        if self.json {
+            let json = serde_json::to_string_pretty(&inspections).into_diagnostic()?;
+            writeln!(writer, "{json}").into_diagnostic()?;
+
+            return Ok(summary);
+        }
+
+        for (i, inspection) in inspections.iter().enumerate() {
+            if i > 0 {
+                writeln!(writer).into_diagnostic()?;
+            }
+            write_inspection(&mut writer, inspection).into_diagnostic()?;
+        }
+
+        Ok(summary)
+    }
+}
+
+fn write_inspection(writer: &mut impl Write, inspection: &Inspection) -> std::io::Result<()> {
+    let info = &inspection.info;
+
+    match (info.kind, info.index) {
+        (PayloadKind::Head | PayloadKind::Tail, Some(index)) => writeln!(
+            writer,
+            "{}: partial payload {} of {} in group {}",
+            inspection.source,
+            index.index() + 1,
+            index.size(),
+            index.id()
+        )?,
+        _ => writeln!(writer, "{}: complete payload", inspection.source)?,
+    }
+
+    if let Some(encryption) = &info.encryption {
+        let encryption = match encryption {
+            EncryptionSpec::NoEncryption => "none",
+            EncryptionSpec::AgePassphrase => "age passphrase",
+            EncryptionSpec::AgeKey => "age key",
+        };
+        writeln!(writer, "  encryption:  {encryption}")?;
+    }
+
+    if let Some(compression) = &info.compression {
+        let compression = match compression {
+            CompressionSpec::NoCompression => "none",
+            CompressionSpec::Gzip => "gzip",
+        };
+        writeln!(writer, "  compression: {compression}")?;
+    }
+
+    writeln!(writer, "  data:        {} bytes", info.data_length)?;
+
+    let Some(age) = &info.age else {
+        return Ok(());
+    };
+
+    let recipients = age
+        .recipients
+        .iter()
+        .map(|stanzas| format!("{} {}", stanzas.count, stanzas.stanza_type))
+        .collect::<Vec<_>>()
+        .join(", ");
+    writeln!(writer, "  recipients:  {recipients}")?;
+
+    if let Some(work_factor) = age.scrypt_work_factor {
+        writeln!(writer, "  work factor: 2^{work_factor}")?;
+    }
+
+    match age.ciphertext_length {
+        Some(length) => writeln!(writer, "  ciphertext:  {length} bytes")?,
+        None => writeln!(writer, "  ciphertext:  header continues in the next part")?,
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use age::x25519;
+    use qrcloak_core::{
+        format::Payload,
+        generate::Generator,
+        payload::{
+            AgeKeyEncryption, Encoder, EncodingOpts, Encryption, PayloadGenerator, PayloadSplitter,
+        },
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn payloads() -> Vec<Payload> {
+        let recipients = vec![
+            x25519::Identity::generate().to_public(),
+            x25519::Identity::generate().to_public(),
+        ];
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(Encryption::AgeKey(AgeKeyEncryption::new(recipients)))
+            .generate("hello world".into())
+            .expect("should generate");
+
+        PayloadSplitter::default()
+            .with_splits(2)
+            .split(payload)
+            .map(Payload::from)
+            .collect()
+    }
+
+    fn run(inputs: &[PathBuf], extra: &[&str]) -> String {
+        let mut args = PayloadInspectArgs::parse_from(
+            ["cmd"]
+                .into_iter()
+                .chain(extra.iter().copied())
+                .chain(inputs.iter().map(|input| input.to_str().unwrap())),
+        );
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle().unwrap();
+
+        String::from_utf8(output.into_inner()).expect("should be utf-8")
+    }
+
+    #[test]
+    fn test_inspect_json_payloads() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("payloads.json");
+
+        let encoded = Encoder::default()
+            .with_encoding(EncodingOpts::Json {
+                pretty: false,
+                merge: true,
+            })
+            .encode(payloads())
+            .expect("should encode");
+        std::fs::write(&path, &encoded[0]).unwrap();
+
+        let report = run(std::slice::from_ref(&path), &[]);
+        let lines: Vec<&str> = report.lines().collect();
+        assert!(lines[0].starts_with(&format!(
+            "{}: partial payload 1 of 2 in group ",
+            path.display()
+        )));
+        assert_eq!(lines[1], "  encryption:  age key");
+        assert_eq!(lines[2], "  compression: none");
+        assert_eq!(lines[4], "  recipients:  2 X25519");
+        assert!(report.contains("partial payload 2 of 2"));
+
+        let json: serde_json::Value =
+            serde_json::from_str(&run(&[path], &["--json"])).expect("should be json");
+        assert_eq!(json[0]["kind"], "head");
+        assert_eq!(json[0]["encryption"], "AgeKey");
+        assert_eq!(json[0]["age"]["recipients"][0]["type"], "X25519");
+        assert_eq!(json[0]["age"]["recipients"][0]["count"], 2);
+        assert_eq!(json[1]["kind"], "tail");
+        assert_eq!(json[1]["index"]["index"], 1);
+        assert!(json[1].get("encryption").is_none());
+    }
+
+    #[test]
+    fn test_inspect_image() {
+        let dir = TempDir::new().expect("should create temp dir");
+
+        let images = Generator::default()
+            .generate(payloads())
+            .expect("should generate");
+
+        let paths: Vec<PathBuf> = images
+            .into_iter()
+            .enumerate()
+            .map(|(i, image)| {
+                let path = dir.path().join(format!("{i}.png"));
+                image.save(&path).expect("should save");
+                path
+            })
+            .collect();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&run(&paths, &["--json"])).expect("should be json");
+        assert_eq!(json.as_array().unwrap().len(), 2);
+        assert_eq!(json[0]["source"], paths[0].display().to_string());
+        assert_eq!(json[0]["kind"], "head");
+        assert_eq!(json[1]["kind"], "tail");
+    }
+}
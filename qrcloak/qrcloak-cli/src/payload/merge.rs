@@ -7,7 +7,7 @@ use std::str::FromStr;
 
 use clap::Parser;
 
-use miette::{miette, Context, IntoDiagnostic};
+use miette::Context;
 use qrcloak_core::{
     format::PartialPayload,
     payload::{Encoder, EncodingOpts, OneOrMany, PayloadMerger},
@@ -16,7 +16,12 @@ use serde::de::Error;
 
 use std::io::Write;
 
-use crate::{input::Input, FileOrStdout};
+use crate::{
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    input::Input,
+    report::Summary,
+    FileOrStdout,
+};
 
 #[derive(Parser, Debug)]
 pub struct PayloadMergeArgs {
@@ -31,7 +36,7 @@ pub struct PayloadMergeArgs {
 }
 
 impl PayloadMergeArgs {
-    pub fn handle(self) -> miette::Result<()> {
+    pub fn handle(self) -> miette::Result<Summary> {
         let payloads = self
             .input
             .contents()
@@ -42,7 +47,11 @@ impl PayloadMergeArgs {
 
         // TODO: create good error message
         if merge_result.complete.len() != 1 {
-            return Err(miette!("Merge result should have one complete payload"));
+            return Err(CliError::new(
+                ErrorKind::IncompleteGroup,
+                "Merge result should have one complete payload",
+            )
+            .into());
         }
 
         let encoded_payload = Encoder::default()
@@ -57,7 +66,7 @@ impl PayloadMergeArgs {
 
         writeln!(writer, "{}", encoded_payload[0]).into_diagnostic()?;
 
-        Ok(())
+        Ok(Summary::default().with_payloads(1).with_output(self.output))
     }
 }
 
@@ -3,19 +3,95 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
 use clap::Subcommand;
+use miette::Context;
+use qrcloak_core::{format::Payload, payload::Decoder};
+
+use crate::{
+    error::IntoCliDiagnostic,
+    qrcode::{decode_images, extractor, is_image},
+};
 
 pub use self::{
-    extract::PayloadExtractArgs, generate::PayloadGenerateArgs, merge::PayloadMergeArgs,
+    extract::PayloadExtractArgs, generate::PayloadGenerateArgs, inspect::PayloadInspectArgs,
+    merge::PayloadMergeArgs, rekey::PayloadRekeyArgs,
 };
 
 mod extract;
 mod generate;
+mod inspect;
 mod merge;
+mod rekey;
 
 #[derive(Subcommand, Debug)]
 pub enum PayloadCommand {
     Generate(PayloadGenerateArgs),
     Extract(PayloadExtractArgs),
     Merge(PayloadMergeArgs),
+    /// Show what payloads contain, such as their encryption, without decrypting them
+    Inspect(PayloadInspectArgs),
+    /// Encrypt payloads again for other recipients, keeping their compression and parts
+    Rekey(PayloadRekeyArgs),
+}
+
+/// Reads the payloads from files with encoded payloads or images of QR Codes, each
+/// together with a description of where it came from. A path of '-' reads stdin.
+pub(crate) fn read_payloads(inputs: &[PathBuf]) -> miette::Result<Vec<(String, Payload)>> {
+    let mut payloads = Vec::new();
+    for input in inputs {
+        let (name, bytes) = read_input(input)?;
+        payloads.extend(load_payloads(&name, &bytes)?);
+    }
+
+    Ok(payloads)
+}
+
+fn read_input(path: &Path) -> miette::Result<(String, Vec<u8>)> {
+    let mut bytes = Vec::new();
+
+    if path.as_os_str() == "-" {
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .into_diagnostic()
+            .wrap_err("Unable to read stdin")?;
+
+        return Ok(("stdin".to_string(), bytes));
+    }
+
+    let bytes = std::fs::read(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+    Ok((path.display().to_string(), bytes))
+}
+
+// Returns the payloads in the data together with a description of where they came from
+fn load_payloads(name: &str, bytes: &[u8]) -> miette::Result<Vec<(String, Payload)>> {
+    if !is_image(bytes) {
+        let payloads = Decoder::default()
+            .decode(bytes)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Unable to decode payloads from {name}"))?;
+
+        return Ok(payloads
+            .into_iter()
+            .map(|payload| (name.to_string(), payload))
+            .collect());
+    }
+
+    let extractor = extractor();
+
+    let mut payloads = Vec::new();
+    for (source, image) in decode_images(name, bytes)? {
+        let found =
+            extractor.extract_payloads(image.width() as usize, image.height() as usize, &*image);
+        payloads.extend(found.into_iter().map(|payload| (source.clone(), payload)));
+    }
+
+    Ok(payloads)
 }
@@ -0,0 +1,565 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use qrcloak_core::{
+    format::Payload,
+    generate::Symbol,
+    payload::{
+        AgeKeyDecryption, AgePassphrase, Decryption, Encoder, EncodingOpts, Encryption,
+        PayloadRekeyer,
+    },
+};
+
+use crate::{
+    batch::{self, Outcome},
+    config,
+    encryption::EncryptionOptions,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    qrcode::{is_image, output_paths, SymbolOptions},
+    report::{self, Summary},
+    secrets::{prompt_passphrase, read_identity_file},
+    FileOrStdout,
+};
+
+use super::{load_payloads, read_input};
+
+#[derive(Parser, Debug)]
+pub struct PayloadRekeyArgs {
+    /// Files with encoded payloads, or images and PDFs with QR Codes (use '-' for stdin)
+    #[arg(default_value = "-", conflicts_with = "manifest")]
+    inputs: Vec<PathBuf>,
+
+    /// Decrypt with the private keys in the identity file (can be repeated)
+    #[arg(short, long = "identity", value_name = "PATH")]
+    identities: Vec<PathBuf>,
+
+    /// Prompt for the passphrase the payloads are encrypted with
+    #[arg(long, conflicts_with = "identities")]
+    old_passphrase_prompt: bool,
+
+    #[command(flatten)]
+    encryption: EncryptionOptions,
+
+    /// Generate the items of a batch manifest again that are encrypted to one of the
+    /// identities, with the recipients the manifest has now
+    #[arg(long, value_name = "PATH", requires = "identities")]
+    manifest: Option<PathBuf>,
+
+    /// Pretty print the encoded payloads [default: as configured]
+    #[arg(long, conflicts_with = "manifest", overrides_with = "no_pretty")]
+    pretty: bool,
+
+    /// Do not pretty print the encoded payloads, even if configured
+    #[arg(long, conflicts_with = "manifest", overrides_with = "pretty")]
+    no_pretty: bool,
+
+    /// Write the payloads of all inputs as a single JSON document, which is an object
+    /// only if the one input was an object
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout, conflicts_with = "manifest")]
+    output: FileOrStdout,
+
+    /// Write each input to this directory under its file name and in its form: JSON
+    /// as an object or an array, and images as PNG files with one QR Code per payload
+    #[arg(long, value_name = "DIR", conflicts_with_all = ["manifest", "output"])]
+    output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    symbol: SymbolOptions,
+}
+
+// How the payloads of an input were encoded, which their rekeyed payloads keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Form {
+    Object,
+    Array,
+    Images,
+}
+
+impl Form {
+    fn detect(bytes: &[u8]) -> Self {
+        if is_image(bytes) {
+            Form::Images
+        } else if bytes.trim_ascii_start().starts_with(b"[") {
+            Form::Array
+        } else {
+            Form::Object
+        }
+    }
+}
+
+// An input with the number of payloads that were read from it
+struct Input {
+    path: PathBuf,
+    form: Form,
+    count: usize,
+}
+
+impl Input {
+    // The file name of the input, where stdin is named with the given extension
+    fn file_name(&self, extension: &str) -> PathBuf {
+        match self.path.file_name() {
+            Some(name) if self.path.as_os_str() != "-" => PathBuf::from(name),
+            _ => PathBuf::from("stdin").with_extension(extension),
+        }
+    }
+}
+
+impl PayloadRekeyArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let mut identities = Vec::new();
+        for path in self.identities.iter() {
+            identities.extend(read_identity_file(path)?);
+        }
+
+        if let Some(manifest) = &self.manifest {
+            let old: HashSet<String> = identities
+                .iter()
+                .map(|identity| identity.to_public().to_string())
+                .collect();
+
+            return rekey_manifest(manifest, &old);
+        }
+
+        let decryption = if !identities.is_empty() {
+            Decryption::AgeKey(AgeKeyDecryption::new(identities))
+        } else if self.old_passphrase_prompt {
+            Decryption::AgePassphrase(AgePassphrase::new(prompt_passphrase(false)?))
+        } else {
+            Decryption::NoEncryption
+        };
+
+        let encryption = self.encryption.encryption()?;
+        if let Encryption::NoEncryption = encryption {
+            return Err(CliError::new(
+                ErrorKind::Usage,
+                "The payloads must be encrypted again, e.g. with --recipient",
+            )
+            .into());
+        }
+
+        let mut inputs = Vec::new();
+        let mut payloads = Vec::new();
+        for path in self.inputs.iter() {
+            let (name, bytes) = read_input(path)?;
+            let loaded = load_payloads(&name, &bytes)?;
+
+            inputs.push(Input {
+                path: path.clone(),
+                form: Form::detect(&bytes),
+                count: loaded.len(),
+            });
+            payloads.extend(loaded.into_iter().map(|(_, payload)| payload));
+        }
+
+        let mut rekeyed = PayloadRekeyer::default()
+            .with_decryption(decryption)
+            .with_encryption(encryption)
+            .rekey_all(payloads)
+            .into_diagnostic()?
+            .into_iter();
+        let count = rekeyed.len();
+
+        let pretty = config::flag(self.pretty, self.no_pretty, |config| config.defaults.pretty)?;
+        let summary = Summary::default().with_payloads(count);
+
+        let Some(dir) = &self.output_dir else {
+            let merge = !matches!(
+                inputs.as_slice(),
+                [Input {
+                    form: Form::Object,
+                    ..
+                }]
+            );
+            let encoded = encode(rekeyed, pretty, merge)?;
+
+            let mut writer = self.output.try_get_writer().into_diagnostic()?;
+            writeln!(writer, "{encoded}").into_diagnostic()?;
+
+            return Ok(summary.with_output(self.output));
+        };
+
+        std::fs::create_dir_all(dir).into_diagnostic()?;
+
+        let mut written = HashSet::new();
+        let mut summary = summary;
+        for input in inputs {
+            let payloads: Vec<Payload> = rekeyed.by_ref().take(input.count).collect();
+
+            let paths = match input.form {
+                Form::Images => {
+                    let template = dir.join(input.file_name("png")).with_extension("png");
+                    let images = self
+                        .symbol
+                        .generator()?
+                        .generate_with(payloads, Symbol::to_image)
+                        .into_diagnostic()?;
+
+                    let paths = output_paths(&[template], images.len())?;
+                    for (image, path) in images.iter().zip(paths.iter()) {
+                        check_unique(&mut written, path)?;
+                        image.save(path).into_diagnostic()?;
+                    }
+                    paths
+                }
+                Form::Object | Form::Array => {
+                    let path = dir.join(input.file_name("json"));
+                    check_unique(&mut written, &path)?;
+
+                    let encoded = encode(payloads, pretty, input.form == Form::Array)?;
+                    std::fs::write(&path, format!("{encoded}\n")).into_diagnostic()?;
+                    vec![path]
+                }
+            };
+
+            for path in paths {
+                summary = summary.with_output(path.display());
+            }
+        }
+
+        Ok(summary)
+    }
+}
+
+fn encode(
+    payloads: impl IntoIterator<Item = Payload>,
+    pretty: bool,
+    merge: bool,
+) -> miette::Result<String> {
+    let encoded = Encoder::default()
+        .with_encoding(EncodingOpts::Json { pretty, merge })
+        .encode(payloads)
+        .into_diagnostic()?;
+
+    Ok(encoded.join("\n"))
+}
+
+// Two inputs with the same file name would overwrite each other in the output directory
+fn check_unique(written: &mut HashSet<PathBuf>, path: &Path) -> miette::Result<()> {
+    if !written.insert(path.to_path_buf()) {
+        return Err(CliError::new(
+            ErrorKind::Usage,
+            format!("More than one input would be written to {}", path.display()),
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Generates the items again that were encrypted to one of the old public keys
+fn rekey_manifest(manifest: &std::path::Path, old: &HashSet<String>) -> miette::Result<Summary> {
+    let affected = |state: &batch::ItemState| state.recipients.iter().any(|key| old.contains(key));
+
+    let reports = batch::generate(manifest, None, &affected)?;
+
+    for (name, result) in reports.iter() {
+        if let Ok(Outcome::Created(state)) = result {
+            if affected(state) {
+                report::warning(
+                    ErrorKind::Other,
+                    format!("{name}: still encrypted to one of the old keys"),
+                );
+            }
+        }
+    }
+
+    batch::report_items(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
+
+    use age::x25519;
+    use qrcloak_core::{
+        generate::Generator,
+        payload::{
+            AgeKeyEncryption, Compression, Decoder, Decompression, GzipCompression,
+            PayloadExtractor, PayloadGenerator, PayloadMerger, PayloadSplitter,
+        },
+    };
+    use tempfile::TempDir;
+
+    use crate::{batch::BatchArgs, secrets::format_identity};
+
+    use super::*;
+
+    fn identities() -> &'static [x25519::Identity; 2] {
+        static IDENTITIES: OnceLock<[x25519::Identity; 2]> = OnceLock::new();
+        IDENTITIES.get_or_init(|| [x25519::Identity::generate(), x25519::Identity::generate()])
+    }
+
+    fn key_file(dir: &TempDir, name: &str, identity: &x25519::Identity) -> PathBuf {
+        let path = dir.path().join(name);
+        std::fs::write(&path, format_identity(identity)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_rekey_payloads() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let [old, new] = identities();
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(Encryption::AgeKey(AgeKeyEncryption::new(vec![
+                old.to_public()
+            ])))
+            .with_compression(Compression::Gzip(GzipCompression))
+            .generate("hello world".into())
+            .unwrap();
+        let parts: Vec<Payload> = PayloadSplitter::default()
+            .with_splits(2)
+            .split(payload)
+            .map(Payload::from)
+            .collect();
+
+        let input = dir.path().join("payloads.json");
+        let encoded = Encoder::default()
+            .with_encoding(EncodingOpts::Json {
+                pretty: false,
+                merge: true,
+            })
+            .encode(parts)
+            .unwrap();
+        std::fs::write(&input, &encoded[0]).unwrap();
+
+        let old_file = key_file(&dir, "old.txt", old);
+        let mut args = PayloadRekeyArgs::parse_from([
+            "cmd",
+            "-i",
+            old_file.to_str().unwrap(),
+            "-r",
+            &new.to_public().to_string(),
+            input.to_str().unwrap(),
+        ]);
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+        args.handle().expect("should rekey");
+
+        let payloads = Decoder::default()
+            .decode(&output.into_inner())
+            .expect("should decode");
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads
+            .iter()
+            .all(|payload| matches!(payload, Payload::Partial(_))));
+
+        let merged = PayloadMerger::default().merge(payloads).complete;
+        let data = PayloadExtractor::default()
+            .with_decryption(Decryption::AgeKey(AgeKeyDecryption::new(vec![new.clone()])))
+            .with_decompression(Decompression::Gzip(GzipCompression))
+            .extract(merged.into_iter().next().unwrap())
+            .expect("should decrypt with the new key");
+        assert_eq!(data, "hello world");
+
+        // Rekeying without new recipients would leave the payloads unencrypted
+        let mut args = PayloadRekeyArgs::parse_from([
+            "cmd",
+            "-i",
+            old_file.to_str().unwrap(),
+            "--no-encryption",
+            input.to_str().unwrap(),
+        ]);
+        args.output = FileOrStdout::new_testing();
+        assert!(args.handle().is_err());
+    }
+
+    #[test]
+    fn test_rekey_keeps_form() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let [old, new] = identities();
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(Encryption::AgeKey(AgeKeyEncryption::new(vec![
+                old.to_public()
+            ])))
+            .generate("hello world".into())
+            .unwrap();
+        let parts: Vec<Payload> = PayloadSplitter::default()
+            .with_splits(2)
+            .split(payload.clone())
+            .map(Payload::from)
+            .collect();
+
+        let encode = |payloads: Vec<Payload>, merge: bool| {
+            Encoder::default()
+                .with_encoding(EncodingOpts::Json {
+                    pretty: false,
+                    merge,
+                })
+                .encode(payloads)
+                .unwrap()
+                .remove(0)
+        };
+
+        let object = dir.path().join("object.json");
+        std::fs::write(&object, encode(vec![payload.clone().into()], false)).unwrap();
+        let array = dir.path().join("array.json");
+        std::fs::write(&array, encode(parts.clone(), true)).unwrap();
+        let image = dir.path().join("code.png");
+        Generator::default()
+            .generate_with([payload], Symbol::to_image)
+            .unwrap()[0]
+            .save(&image)
+            .unwrap();
+
+        let old_file = key_file(&dir, "old.txt", old);
+        let out = dir.path().join("out");
+        PayloadRekeyArgs::parse_from([
+            "cmd",
+            "-i",
+            old_file.to_str().unwrap(),
+            "-r",
+            &new.to_public().to_string(),
+            "--output-dir",
+            out.to_str().unwrap(),
+            object.to_str().unwrap(),
+            array.to_str().unwrap(),
+            image.to_str().unwrap(),
+        ])
+        .handle()
+        .expect("should rekey");
+
+        let decryption = Decryption::AgeKey(AgeKeyDecryption::new(vec![new.clone()]));
+        let extract = |payloads: Vec<Payload>| {
+            let merged = PayloadMerger::default().merge(payloads).complete;
+            assert_eq!(merged.len(), 1);
+            PayloadExtractor::default()
+                .with_decryption(decryption.clone())
+                .extract(merged.into_iter().next().unwrap())
+                .expect("should decrypt with the new key")
+        };
+
+        // An object stays an object, and an array of parts stays an array of as many parts
+        let rekeyed = std::fs::read_to_string(out.join("object.json")).unwrap();
+        assert!(rekeyed.starts_with('{'));
+        assert_eq!(
+            extract(Decoder::default().decode(rekeyed.as_bytes()).unwrap()),
+            "hello world"
+        );
+
+        let rekeyed = std::fs::read_to_string(out.join("array.json")).unwrap();
+        assert!(rekeyed.starts_with('['));
+        let payloads = Decoder::default().decode(rekeyed.as_bytes()).unwrap();
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads
+            .iter()
+            .all(|payload| matches!(payload, Payload::Partial(_))));
+        assert_eq!(extract(payloads), "hello world");
+
+        // An image is written back as a PNG image with a QR Code
+        let bytes = std::fs::read(out.join("code.png")).unwrap();
+        let payloads: Vec<Payload> = load_payloads("code.png", &bytes)
+            .unwrap()
+            .into_iter()
+            .map(|(_, payload)| payload)
+            .collect();
+        assert_eq!(extract(payloads), "hello world");
+
+        // A single output of a single object is an object as well
+        let mut args = PayloadRekeyArgs::parse_from([
+            "cmd",
+            "-i",
+            old_file.to_str().unwrap(),
+            "-r",
+            &new.to_public().to_string(),
+            object.to_str().unwrap(),
+        ]);
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+        args.handle().expect("should rekey");
+        assert!(output.into_inner().starts_with(b"{"));
+
+        // Inputs with the same file name cannot both be written to the directory
+        let other = dir.path().join("other");
+        std::fs::create_dir(&other).unwrap();
+        std::fs::copy(&object, other.join("object.json")).unwrap();
+        let result = PayloadRekeyArgs::parse_from([
+            "cmd",
+            "-i",
+            old_file.to_str().unwrap(),
+            "-r",
+            &new.to_public().to_string(),
+            "--output-dir",
+            out.to_str().unwrap(),
+            object.to_str().unwrap(),
+            other.join("object.json").to_str().unwrap(),
+        ])
+        .handle();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_rekey_manifest() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let [old, new] = identities();
+
+        let write_manifest = |first: &x25519::Identity| {
+            let manifest = dir.path().join("codes.toml");
+            std::fs::write(
+                &manifest,
+                format!(
+                    r#"
+                    [[items]]
+                    text = "first"
+                    recipients = ["{}"]
+                    output = "first.png"
+
+                    [[items]]
+                    text = "second"
+                    recipients = ["{}"]
+                    output = "second.png"
+                    "#,
+                    first.to_public(),
+                    new.to_public()
+                ),
+            )
+            .unwrap();
+            manifest
+        };
+
+        let manifest = write_manifest(old);
+        BatchArgs::parse_from(["cmd", manifest.to_str().unwrap()])
+            .handle()
+            .expect("should generate");
+
+        let state = std::fs::read_to_string(dir.path().join("codes.toml.state.json")).unwrap();
+        assert!(state.contains(&old.to_public().to_string()));
+
+        // Only the item that is encrypted to the old key is generated again
+        let old_keys = HashSet::from([old.to_public().to_string()]);
+        let reports = batch::generate(&manifest, None, &|state: &batch::ItemState| {
+            state.recipients.iter().any(|key| old_keys.contains(key))
+        })
+        .expect("should generate");
+        let created: Vec<bool> = reports
+            .iter()
+            .map(|(_, result)| matches!(result, Ok(Outcome::Created(_))))
+            .collect();
+        assert_eq!(created, [true, false]);
+
+        let manifest = write_manifest(new);
+        let old_file = key_file(&dir, "old.txt", old);
+
+        // Nothing is encrypted to the old key anymore
+        PayloadRekeyArgs::parse_from([
+            "cmd",
+            "-i",
+            old_file.to_str().unwrap(),
+            "--manifest",
+            manifest.to_str().unwrap(),
+        ])
+        .handle()
+        .expect("should rekey");
+        let state = std::fs::read_to_string(dir.path().join("codes.toml.state.json")).unwrap();
+        assert!(!state.contains(&old.to_public().to_string()));
+    }
+}
@@ -0,0 +1,493 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use clap::Parser;
+use image::GrayImage;
+use lopdf::{Document, Object, Stream};
+use miette::{miette, Context};
+use qrcloak_core::{
+    extract::{DetectorChain, Extractor, Preprocessor, QuircDetector, RqrrDetector, SymbolStatus},
+    payload::{Decompression, Encoder, EncodingOpts, PayloadExtractor, PayloadMerger},
+};
+
+use crate::{
+    decryption::DecryptionOptions,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    report::{self, Summary},
+    DataOutputOptions, FileOrStdout,
+};
+
+#[derive(Parser, Debug)]
+pub struct QrCodeExtractArgs {
+    /// Images (PNG, JPEG) or PDF documents with embedded images to scan
+    #[arg(required = true)]
+    files: Vec<PathBuf>,
+
+    #[command(flatten)]
+    decryption: DecryptionOptions,
+
+    #[command(flatten)]
+    data_output: DataOutputOptions,
+
+    /// Print the scanned payloads as JSON instead of merging and decrypting them
+    #[arg(long)]
+    json: bool,
+
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+impl QrCodeExtractArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let decryption = self.decryption.decryption()?;
+        let extractor = extractor();
+
+        let mut payloads = Vec::new();
+        for path in self.files.iter() {
+            for (source, image) in load_images(path)? {
+                let report = extractor.extract_report(
+                    image.width() as usize,
+                    image.height() as usize,
+                    &*image,
+                );
+
+                for symbol in report.failures() {
+                    let SymbolStatus::Failed { error } = &symbol.status else {
+                        continue;
+                    };
+                    let message = match symbol.center() {
+                        Some(center) => format!(
+                            "{source}: skipping symbol at ({}, {}): {error}",
+                            center.x, center.y
+                        ),
+                        None => format!("{source}: skipping symbol: {error}"),
+                    };
+                    report::warning(ErrorKind::Decode, message);
+                }
+
+                payloads.extend(report.into_payloads());
+            }
+        }
+
+        let mut writer = self.output.try_get_writer().into_diagnostic()?;
+        let summary = Summary::default().with_output(&self.output);
+
+        if self.json {
+            let summary = summary.with_payloads(payloads.len());
+            let encoded = Encoder::default()
+                .with_encoding(EncodingOpts::Json {
+                    pretty: true,
+                    merge: true,
+                })
+                .encode(payloads)
+                .into_diagnostic()?;
+
+            writeln!(writer, "{}", encoded[0]).into_diagnostic()?;
+
+            return Ok(summary);
+        }
+
+        let merge_result = PayloadMerger::default().merge(payloads);
+
+        for (index, parts) in merge_result.incomplete.partials() {
+            let found = parts.iter().flatten().count();
+            let message = format!(
+                "Found only {found} of {} parts of payload {index:?}",
+                parts.len()
+            );
+
+            if merge_result.complete.is_empty() {
+                return Err(CliError::new(ErrorKind::IncompleteGroup, message).into());
+            }
+            report::warning(ErrorKind::IncompleteGroup, message);
+        }
+
+        if merge_result.complete.is_empty() {
+            return Err(CliError::new(ErrorKind::Decode, "No qrcloak payloads found").into());
+        }
+
+        let summary = summary.with_payloads(merge_result.complete.len());
+        for payload in merge_result.complete {
+            let data = PayloadExtractor::default()
+                .with_decryption(decryption.clone())
+                .with_decompression(Decompression::from(payload.compression()))
+                .extract(payload)
+                .into_diagnostic()?;
+
+            self.data_output
+                .write(&mut writer, &data)
+                .into_diagnostic()?;
+        }
+
+        Ok(summary)
+    }
+}
+
+// Returns the extractor for photos and scans, which may need preprocessing
+pub(crate) fn extractor() -> Extractor<DetectorChain> {
+    // rqrr gets another chance at the symbols that quirc samples wrongly
+    let detector = DetectorChain::default()
+        .with_detector(QuircDetector)
+        .with_detector(RqrrDetector);
+
+    Extractor::default()
+        .with_preprocessor(Some(Preprocessor::default()))
+        .with_detector(detector)
+}
+
+// Returns the images in the file together with a description of where they came from
+fn load_images(path: &Path) -> miette::Result<Vec<(String, GrayImage)>> {
+    let bytes = std::fs::read(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+    decode_images(&path.display().to_string(), &bytes)
+}
+
+/// Returns whether the data is a PDF document or an image in a known format.
+pub(crate) fn is_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"%PDF") || image::guess_format(bytes).is_ok()
+}
+
+/// Decodes an image or the images embedded in a PDF document, each together with a
+/// description of where it came from.
+pub(crate) fn decode_images(name: &str, bytes: &[u8]) -> miette::Result<Vec<(String, GrayImage)>> {
+    if !bytes.starts_with(b"%PDF") {
+        let image = image::load_from_memory(bytes)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Unable to load image {name}"))?;
+
+        return Ok(vec![(name.to_string(), image.to_luma8())]);
+    }
+
+    let document = Document::load_mem(bytes)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to load PDF {name}"))?;
+
+    let mut images = Vec::new();
+    let mut seen = HashSet::new();
+    for (number, page_id) in document.get_pages() {
+        let source = format!("{name} page {number}");
+
+        let (resources, resource_ids) = document.get_page_resources(page_id);
+        let resources = resources.into_iter().chain(
+            resource_ids
+                .into_iter()
+                .filter_map(|id| document.get_dictionary(id).ok()),
+        );
+
+        for resources in resources {
+            let Some(xobjects) = resources
+                .get(b"XObject")
+                .and_then(|xobjects| document.dereference(xobjects))
+                .and_then(|(_, xobjects)| xobjects.as_dict())
+                .ok()
+            else {
+                continue;
+            };
+
+            for (_, xobject) in xobjects.iter() {
+                let Ok((id, Object::Stream(stream))) = document.dereference(xobject) else {
+                    continue;
+                };
+
+                // Images shared between pages only need to be scanned once
+                if id.is_some_and(|id| !seen.insert(id))
+                    || stream
+                        .dict
+                        .get(b"Subtype")
+                        .and_then(Object::as_name_str)
+                        .ok()
+                        != Some("Image")
+                {
+                    continue;
+                }
+
+                match decode_pdf_image(&document, stream) {
+                    Ok(image) => images.push((source.clone(), image)),
+                    Err(error) => report::warning(
+                        ErrorKind::Decode,
+                        format!("{source}: skipping image: {error}"),
+                    ),
+                }
+            }
+        }
+    }
+
+    Ok(images)
+}
+
+fn decode_pdf_image(document: &Document, stream: &Stream) -> miette::Result<GrayImage> {
+    let filters = stream.filters().unwrap_or_default();
+
+    let samples = match filters.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        [] => stream.content.clone(),
+        ["DCTDecode"] => {
+            return Ok(image::load_from_memory_with_format(
+                &stream.content,
+                image::ImageFormat::Jpeg,
+            )
+            .into_diagnostic()?
+            .to_luma8())
+        }
+        ["FlateDecode"] | ["LZWDecode"] => {
+            // lopdf refuses to decompress image streams, as they are usually
+            // handed to a decoder as is, which does not hold for raw samples
+            let mut stream = stream.clone();
+            stream.dict.remove(b"Subtype");
+            stream.decompressed_content().into_diagnostic()?
+        }
+        _ => return Err(miette!("unsupported filters {filters:?}")),
+    };
+
+    let get = |key: &[u8]| {
+        stream
+            .dict
+            .get(key)
+            .and_then(Object::as_i64)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("missing {}", String::from_utf8_lossy(key)))
+    };
+    let (width, height) = (get(b"Width")? as u32, get(b"Height")? as u32);
+    let bits = get(b"BitsPerComponent")?;
+    let components = color_components(document, stream)?;
+
+    let pixels: Vec<u8> = match (bits, components) {
+        (1, 1) => {
+            let stride = (width as usize).div_ceil(8);
+            (0..height as usize)
+                .flat_map(|y| {
+                    let row = samples.get(y * stride..(y + 1) * stride);
+                    (0..width as usize).map(move |x| {
+                        let byte = row.map_or(0, |row| row[x / 8]);
+                        if byte >> (7 - x % 8) & 1 == 1 {
+                            255
+                        } else {
+                            0
+                        }
+                    })
+                })
+                .collect()
+        }
+        (8, 1) => samples,
+        (8, 3) => samples
+            .chunks_exact(3)
+            .map(|rgb| luma(rgb[0], rgb[1], rgb[2]))
+            .collect(),
+        (8, 4) => samples
+            .chunks_exact(4)
+            .map(|cmyk| {
+                let ink = |c: u8| ((255 - u32::from(c)) * (255 - u32::from(cmyk[3])) / 255) as u8;
+                luma(ink(cmyk[0]), ink(cmyk[1]), ink(cmyk[2]))
+            })
+            .collect(),
+        _ => {
+            return Err(miette!(
+                "unsupported {bits} bit samples with {components} components"
+            ))
+        }
+    };
+
+    GrayImage::from_raw(width, height, pixels).ok_or_else(|| miette!("truncated image data"))
+}
+
+fn color_components(document: &Document, stream: &Stream) -> miette::Result<usize> {
+    let color_space = stream
+        .dict
+        .get(b"ColorSpace")
+        .and_then(|color_space| document.dereference(color_space))
+        .map(|(_, color_space)| color_space)
+        .into_diagnostic()
+        .wrap_err("missing ColorSpace")?;
+
+    if let Ok(array) = color_space.as_array() {
+        // ICC profiles declare the number of components in their stream
+        if array.first().and_then(|name| name.as_name_str().ok()) == Some("ICCBased") {
+            let profile = array
+                .get(1)
+                .ok_or_else(|| miette!("missing ICC profile"))
+                .and_then(|profile| document.dereference(profile).into_diagnostic())?
+                .1;
+            let components = profile
+                .as_stream()
+                .and_then(|profile| profile.dict.get(b"N"))
+                .and_then(Object::as_i64)
+                .into_diagnostic()?;
+
+            return Ok(components as usize);
+        }
+    }
+
+    match color_space.as_name_str() {
+        Ok("DeviceGray") | Ok("CalGray") => Ok(1),
+        Ok("DeviceRGB") | Ok("CalRGB") => Ok(3),
+        Ok("DeviceCMYK") => Ok(4),
+        _ => Err(miette!("unsupported color space {color_space:?}")),
+    }
+}
+
+fn luma(r: u8, g: u8, b: u8) -> u8 {
+    ((299 * u32::from(r) + 587 * u32::from(g) + 114 * u32::from(b)) / 1000) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::ImageFormat;
+    use lopdf::dictionary;
+    use qrcloak_core::{
+        format::Payload,
+        generate::Generator,
+        payload::{PayloadGenerator, PayloadSplitter},
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn generate(splits: u32) -> Vec<GrayImage> {
+        let payload = PayloadGenerator::default()
+            .generate("hello world".into())
+            .expect("should build");
+
+        let payloads = PayloadSplitter::default()
+            .with_splits(splits)
+            .split(payload)
+            .map(Payload::from);
+
+        Generator::default()
+            .generate(payloads)
+            .expect("should generate")
+            .into_iter()
+            .map(|image| {
+                image::imageops::resize(
+                    &image,
+                    image.width() * 4,
+                    image.height() * 4,
+                    image::imageops::FilterType::Nearest,
+                )
+            })
+            .collect()
+    }
+
+    fn run(files: &[PathBuf], extra: &[&str]) -> String {
+        let mut args = QrCodeExtractArgs::parse_from(
+            ["cmd"]
+                .into_iter()
+                .chain(extra.iter().copied())
+                .chain(files.iter().map(|file| file.to_str().unwrap())),
+        );
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle().unwrap();
+
+        String::from_utf8(output.into_inner()).expect("should be utf-8")
+    }
+
+    #[test]
+    fn test_extract_images() {
+        let dir = TempDir::new().expect("should create temp dir");
+
+        let files: Vec<PathBuf> = generate(2)
+            .into_iter()
+            .zip(["first.png", "second.jpg"])
+            .map(|(image, name)| {
+                let path = dir.path().join(name);
+                image.save(&path).expect("should save");
+                path
+            })
+            .collect();
+
+        assert_eq!(run(&files, &[]), "hello world");
+        assert_eq!(run(&files, &["--as-text"]), "hello world\n");
+
+        let json = run(&files, &["--json"]);
+        let payloads: Vec<Payload> = serde_json::from_str(&json).expect("should be json");
+        assert_eq!(payloads.len(), 2);
+        assert!(payloads
+            .iter()
+            .all(|payload| matches!(payload, Payload::Partial(_))));
+
+        let mut args = QrCodeExtractArgs::parse_from(["cmd", files[0].to_str().unwrap()]);
+        args.output = FileOrStdout::new_testing();
+        assert!(args.handle().is_err());
+    }
+
+    #[test]
+    fn test_extract_pdf() {
+        let images = generate(2);
+
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+
+        // One page with raw compressed samples and one with a JPEG
+        let mut page_ids = Vec::new();
+        for (i, image) in images.iter().enumerate() {
+            let mut dict = dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Image",
+                "Width" => image.width(),
+                "Height" => image.height(),
+                "ColorSpace" => "DeviceGray",
+                "BitsPerComponent" => 8,
+            };
+
+            let stream = if i == 0 {
+                let mut stream = Stream::new(dict, image.to_vec());
+                stream.compress().expect("should compress");
+                stream
+            } else {
+                let mut jpeg = Vec::new();
+                image
+                    .write_to(&mut Cursor::new(&mut jpeg), ImageFormat::Jpeg)
+                    .expect("should encode");
+                dict.set("Filter", "DCTDecode");
+                Stream::new(dict, jpeg).with_compression(false)
+            };
+            let image_id = document.add_object(stream);
+
+            page_ids.push(
+                document
+                    .add_object(dictionary! {
+                        "Type" => "Page",
+                        "Parent" => pages_id,
+                        "Resources" => dictionary! {
+                            "XObject" => dictionary! { "Im0" => image_id },
+                        },
+                    })
+                    .into(),
+            );
+        }
+
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => page_ids,
+                "Count" => 2,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = document.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+        });
+        document.trailer.set("Root", catalog_id);
+
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("codes.pdf");
+        document.save(&path).expect("should save");
+
+        assert_eq!(run(&[path], &[]), "hello world");
+    }
+}
@@ -0,0 +1,299 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+};
+
+use image::GrayImage;
+use miette::{miette, Context};
+
+use crate::error::IntoCliDiagnostic;
+
+const Y4M_MAGIC: &[u8] = b"YUV4MPEG2 ";
+const JPEG_SOI: &[u8] = &[0xff, 0xd8];
+
+/// Reads the frames of a video or a directory of images as grayscale images.
+pub enum FrameReader {
+    /// Uncompressed YUV frames, of which only the luma plane is needed.
+    Y4m {
+        reader: BufReader<File>,
+        width: usize,
+        height: usize,
+        chroma: usize,
+    },
+    /// Concatenated JPEG images.
+    Mjpeg { data: Vec<u8>, offset: usize },
+    /// Image files in the order of their names.
+    Images(std::vec::IntoIter<PathBuf>),
+}
+
+impl FrameReader {
+    pub fn open(path: &Path) -> miette::Result<Self> {
+        if path.is_dir() {
+            let mut paths = std::fs::read_dir(path)
+                .into_diagnostic()?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<Vec<_>, _>>()
+                .into_diagnostic()?;
+            paths.retain(|path| path.is_file());
+            paths.sort();
+
+            return Ok(FrameReader::Images(paths.into_iter()));
+        }
+
+        let mut reader = BufReader::new(File::open(path).into_diagnostic()?);
+        let magic = reader.fill_buf().into_diagnostic()?;
+
+        if magic.starts_with(Y4M_MAGIC) {
+            let mut header = String::new();
+            reader.read_line(&mut header).into_diagnostic()?;
+            let (width, height, chroma) = parse_y4m_header(header.trim_end())?;
+
+            Ok(FrameReader::Y4m {
+                reader,
+                width,
+                height,
+                chroma,
+            })
+        } else if magic.starts_with(JPEG_SOI) {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data).into_diagnostic()?;
+
+            Ok(FrameReader::Mjpeg { data, offset: 0 })
+        } else {
+            Err(miette!(
+                "{} is neither a Y4M nor an MJPEG video",
+                path.display()
+            ))
+        }
+    }
+
+    fn next_y4m(
+        reader: &mut BufReader<File>,
+        width: usize,
+        height: usize,
+        chroma: usize,
+    ) -> miette::Result<Option<GrayImage>> {
+        let mut header = String::new();
+        if reader.read_line(&mut header).into_diagnostic()? == 0 {
+            return Ok(None);
+        }
+        if !header.starts_with("FRAME") {
+            return Err(miette!("Invalid Y4M frame header {:?}", header.trim_end()));
+        }
+
+        let mut luma = vec![0; width * height];
+        reader.read_exact(&mut luma).into_diagnostic()?;
+        std::io::copy(
+            &mut reader.by_ref().take(chroma as u64),
+            &mut std::io::sink(),
+        )
+        .into_diagnostic()?;
+
+        GrayImage::from_raw(width as u32, height as u32, luma)
+            .map(Some)
+            .ok_or_else(|| miette!("Invalid Y4M frame size"))
+    }
+}
+
+impl Iterator for FrameReader {
+    type Item = miette::Result<GrayImage>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            FrameReader::Y4m {
+                reader,
+                width,
+                height,
+                chroma,
+            } => Self::next_y4m(reader, *width, *height, *chroma).transpose(),
+            FrameReader::Mjpeg { data, offset } => {
+                if *offset >= data.len() {
+                    return None;
+                }
+
+                let frame = match jpeg_length(&data[*offset..]) {
+                    Ok(length) => {
+                        let frame = &data[*offset..*offset + length];
+                        *offset += length;
+                        frame
+                    }
+                    Err(error) => {
+                        *offset = data.len();
+                        return Some(Err(error));
+                    }
+                };
+
+                Some(
+                    image::load_from_memory_with_format(frame, image::ImageFormat::Jpeg)
+                        .map(|image| image.to_luma8())
+                        .into_diagnostic(),
+                )
+            }
+            FrameReader::Images(paths) => {
+                let path = paths.next()?;
+                Some(
+                    image::open(&path)
+                        .map(|image| image.to_luma8())
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Unable to load image {}", path.display())),
+                )
+            }
+        }
+    }
+}
+
+// Returns the width, height and the number of chroma bytes per frame.
+fn parse_y4m_header(header: &str) -> miette::Result<(usize, usize, usize)> {
+    let (mut width, mut height, mut colorspace) = (None, None, "420jpeg");
+
+    for param in header.split(' ').skip(1) {
+        let (tag, value) = param.split_at(1);
+        match tag {
+            "W" => width = value.parse::<usize>().ok(),
+            "H" => height = value.parse::<usize>().ok(),
+            "C" => colorspace = value,
+            _ => {}
+        }
+    }
+
+    let (Some(width), Some(height)) = (width, height) else {
+        return Err(miette!("Y4M header is missing the frame size"));
+    };
+
+    let (half_width, half_height) = (width.div_ceil(2), height.div_ceil(2));
+    let chroma = match colorspace {
+        "mono" => 0,
+        "420" | "420jpeg" | "420paldv" | "420mpeg2" => 2 * half_width * half_height,
+        "411" => 2 * width.div_ceil(4) * height,
+        "422" => 2 * half_width * height,
+        "444" => 2 * width * height,
+        "444alpha" => 3 * width * height,
+        _ => return Err(miette!("Unsupported Y4M color space {colorspace}")),
+    };
+
+    Ok((width, height, chroma))
+}
+
+// Returns the length of the JPEG image at the start of the data, up to and including its
+// end of image marker.
+fn jpeg_length(data: &[u8]) -> miette::Result<usize> {
+    if !data.starts_with(JPEG_SOI) {
+        return Err(miette!("Invalid MJPEG frame"));
+    }
+
+    let truncated = || miette!("Truncated MJPEG frame");
+    let mut offset = 2;
+    loop {
+        let marker = *data.get(offset + 1).ok_or_else(truncated)?;
+        if data[offset] != 0xff {
+            return Err(miette!("Invalid JPEG marker"));
+        }
+
+        match marker {
+            // Fill bytes before a marker
+            0xff => offset += 1,
+            // End of image
+            0xd9 => return Ok(offset + 2),
+            // Markers without a segment
+            0x01 | 0xd0..=0xd7 => offset += 2,
+            _ => {
+                let length = data.get(offset + 2..offset + 4).ok_or_else(truncated)?;
+                offset += 2 + usize::from(u16::from_be_bytes([length[0], length[1]]));
+
+                // The entropy coded data after the start of scan runs up to the next
+                // marker, bytes of 0xff in it are followed by a zero byte
+                if marker == 0xda {
+                    loop {
+                        match (data.get(offset), data.get(offset + 1)) {
+                            (Some(0xff), Some(0x00 | 0xd0..=0xd7)) => offset += 2,
+                            (Some(0xff), Some(_)) => break,
+                            (Some(_), _) => offset += 1,
+                            (None, _) => return Err(truncated()),
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn gradient(width: u32, height: u32, shift: u32) -> GrayImage {
+        GrayImage::from_fn(width, height, |x, y| {
+            image::Luma([((x * 16 + y * 4 + shift) % 256) as u8])
+        })
+    }
+
+    #[test]
+    fn test_y4m() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("video.y4m");
+
+        // Odd sizes round the subsampled chroma planes up
+        let frames = [gradient(5, 3, 0), gradient(5, 3, 100)];
+        let mut file = File::create(&path).unwrap();
+        writeln!(
+            file,
+            "YUV4MPEG2 W5 H3 F25:1 Ip A1:1 C420jpeg XYSCSS=420JPEG"
+        )
+        .unwrap();
+        for frame in frames.iter() {
+            file.write_all(b"FRAME\n").unwrap();
+            file.write_all(frame).unwrap();
+            file.write_all(&[128; 2 * 3 * 2]).unwrap();
+        }
+        drop(file);
+
+        let read = FrameReader::open(&path)
+            .unwrap()
+            .collect::<miette::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(read, frames);
+    }
+
+    #[test]
+    fn test_mjpeg() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("video.mjpeg");
+
+        let mut data = Vec::new();
+        let mut lengths = Vec::new();
+        for shift in [0, 100, 200] {
+            let mut frame = Vec::new();
+            gradient(64, 48, shift)
+                .write_to(&mut Cursor::new(&mut frame), image::ImageFormat::Jpeg)
+                .unwrap();
+            lengths.push(frame.len());
+            data.extend(frame);
+        }
+        assert_eq!(jpeg_length(&data).unwrap(), lengths[0]);
+        assert_eq!(jpeg_length(&data[lengths[0]..]).unwrap(), lengths[1]);
+
+        std::fs::write(&path, &data).unwrap();
+        let frames = FrameReader::open(&path)
+            .unwrap()
+            .collect::<miette::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.iter().all(|frame| frame.dimensions() == (64, 48)));
+
+        // A stream cut off in the middle of a frame
+        std::fs::write(&path, &data[..data.len() - 10]).unwrap();
+        let frames: Vec<_> = FrameReader::open(&path).unwrap().collect();
+        assert_eq!(frames.len(), 3);
+        assert!(frames[2].is_err());
+    }
+}
@@ -3,17 +3,20 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use clap::Parser;
-use miette::IntoDiagnostic;
-use qrcloak_core::{
-    format::Payload,
-    generate::Generator,
-    payload::{PayloadGenerator, PayloadSplitter},
+use qrcloak_core::{generate::Symbol, payload::PayloadGenerator};
+
+use crate::{
+    compression::CompressionOptions,
+    encryption::EncryptionOptions,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    input::BinaryInput,
+    report::Summary,
 };
 
-use crate::{encryption::EncryptionOptions, input::Input};
+use super::options::{generate_splits, output_paths, splits_or_configured, Splits, SymbolOptions};
 
 #[derive(Parser, Debug)]
 pub struct QrCodeGenerateArgs {
@@ -21,13 +24,25 @@ pub struct QrCodeGenerateArgs {
     encryption: EncryptionOptions,
 
     #[command(flatten)]
-    input: Input<String>,
+    compression: CompressionOptions,
+
+    #[command(flatten)]
+    input: BinaryInput,
+
+    #[command(flatten)]
+    symbol: SymbolOptions,
 
+    /// Split the payload into this many parts, or with 'auto' into as few as fit into a QR Code each
+    /// [default: the number of output paths, or as configured]
+    #[arg(short, long, value_name = "N|auto")]
+    splits: Option<Splits>,
+
+    /// The image of each part, or a single path where '{index}' is replaced by the number of the part
     #[arg(required = true)]
     output: Vec<PathBuf>,
 }
 
-fn ensure_parent(path: &PathBuf) -> miette::Result<()> {
+fn ensure_parent(path: &Path) -> miette::Result<()> {
     if let Some(parent) = path.parent() {
         std::fs::create_dir_all(parent).into_diagnostic()?;
     }
@@ -35,38 +50,42 @@ fn ensure_parent(path: &PathBuf) -> miette::Result<()> {
 }
 
 impl QrCodeGenerateArgs {
-    pub fn handle(self) -> miette::Result<()> {
+    pub fn handle(self) -> miette::Result<Summary> {
         let input = self.input.contents().into_diagnostic()?;
 
-        let splits = if self.output.len() > 1 {
-            Some(self.output.len() as u32)
-        } else {
-            None
+        let splits = match (self.splits, self.output.len()) {
+            (None, outputs) if outputs > 1 => Splits::Count(outputs as u32),
+            (splits, _) => splits_or_configured(splits)?,
         };
 
-        let payloads = PayloadGenerator::default()
-            .with_encryption(self.encryption.0)
+        if let (Splits::Count(count), true) = (splits, self.output.len() > 1) {
+            if count as usize != self.output.len() {
+                return Err(CliError::new(
+                    ErrorKind::Usage,
+                    format!("Got {} output paths for {count} parts", self.output.len()),
+                )
+                .into());
+            }
+        }
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(self.encryption.encryption()?)
+            .with_compression(self.compression.compression()?)
             .generate(input.into())
             .into_diagnostic()?;
 
-        let payloads: Vec<Payload> = if let Some(splits) = splits {
-            PayloadSplitter::default()
-                .with_splits(splits)
-                .split(payloads)
-                .map(Payload::from)
-                .collect()
-        } else {
-            vec![Payload::from(payloads)]
-        };
+        let images = generate_splits(splits, &self.symbol.generator()?, payload, Symbol::to_image)?;
 
-        let images = Generator::default().generate(payloads).into_diagnostic()?;
+        let paths = output_paths(&self.output, images.len())?;
 
-        for (image, path) in images.into_iter().zip(self.output.into_iter()) {
+        let mut summary = Summary::default().with_payloads(1);
+        for (image, path) in images.into_iter().zip(paths) {
             ensure_parent(&path)?;
 
-            image.save(path).into_diagnostic()?;
+            image.save(&path).into_diagnostic()?;
+            summary = summary.with_output(path.display());
         }
 
-        Ok(())
+        Ok(summary)
     }
 }
@@ -5,11 +5,30 @@
 
 use clap::Subcommand;
 
+mod extract;
+mod frames;
 mod generate;
+mod options;
+mod scan;
+mod show;
 
+pub use extract::QrCodeExtractArgs;
+pub(crate) use extract::{decode_images, extractor, is_image};
 pub use generate::QrCodeGenerateArgs;
+pub(crate) use options::{
+    ecl_or_configured, generate_splits, numbered_path, output_paths, splits_or_configured, Ecl,
+    Splits, SymbolOptions,
+};
+pub use scan::QrCodeScanArgs;
+pub use show::QrCodeShowArgs;
 
 #[derive(Subcommand, Debug)]
 pub enum QrCodeCommand {
     Generate(QrCodeGenerateArgs),
+    /// Scan images and PDFs for QR Codes and print the payload they hold
+    Extract(QrCodeExtractArgs),
+    /// Scan the frames of a video or a directory of images for QR Codes spread across frames
+    Scan(QrCodeScanArgs),
+    /// Display QR Codes in the terminal
+    Show(QrCodeShowArgs),
 }
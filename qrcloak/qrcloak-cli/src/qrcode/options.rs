@@ -0,0 +1,182 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use clap::Args;
+use qrcloak_core::{
+    format::{CompletePayload, Payload},
+    generate::{Generator, QrCodeEcc, Symbol},
+    payload::PayloadSplitter,
+};
+
+pub use qrcloak_core::config::{Ecl, Splits};
+
+use crate::{
+    config,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+};
+
+/// Returns the error correction level, or the one of the configuration if none is given.
+pub fn ecl_or_configured(ecl: Option<Ecl>) -> miette::Result<QrCodeEcc> {
+    let ecl = match ecl {
+        Some(ecl) => ecl,
+        None => config::get()?.render.ecl.unwrap_or_default(),
+    };
+
+    Ok(ecl.into())
+}
+
+/// How the symbols are generated.
+#[derive(Args, Debug, Clone, Default)]
+pub struct SymbolOptions {
+    /// The error correction level of the QR Codes [default: high]
+    #[arg(long, value_enum)]
+    pub ecl: Option<Ecl>,
+
+    /// Use QR Structured Append headers when splitting into multiple parts
+    /// [default: as configured]
+    #[arg(long, overrides_with = "no_structured_append")]
+    pub structured_append: bool,
+
+    /// Do not use QR Structured Append headers, even if configured
+    #[arg(long, overrides_with = "structured_append")]
+    pub no_structured_append: bool,
+}
+
+impl SymbolOptions {
+    pub fn generator(&self) -> miette::Result<Generator> {
+        let structured_append = config::flag(
+            self.structured_append,
+            self.no_structured_append,
+            |config| config.render.structured_append,
+        )?;
+
+        Ok(Generator::default()
+            .with_ecl(ecl_or_configured(self.ecl)?)
+            .with_structured_append(structured_append))
+    }
+}
+
+/// Returns the given number of parts, or the one of the configuration if none is given.
+pub fn splits_or_configured(splits: Option<Splits>) -> miette::Result<Splits> {
+    match splits {
+        Some(splits) => Ok(splits),
+        None => Ok(config::get()?.render.splits.unwrap_or_default()),
+    }
+}
+
+/// Splits the payload and passes the symbol of each part to the given function.
+pub fn generate_splits<T>(
+    splits: Splits,
+    generator: &Generator,
+    payload: CompletePayload,
+    render: impl FnMut(&Symbol) -> T,
+) -> miette::Result<Vec<T>> {
+    let payloads: Vec<Payload> = match splits {
+        Splits::Auto => {
+            return generator
+                .generate_split_with(payload, render)
+                .into_diagnostic()
+        }
+        Splits::Count(1) => vec![Payload::from(payload)],
+        Splits::Count(splits) => PayloadSplitter::default()
+            .with_splits(splits)
+            .split(payload)
+            .map(Payload::from)
+            .collect(),
+    };
+
+    generator.generate_with(payloads, render).into_diagnostic()
+}
+
+/// Returns a path for each of the parts. A single path is used as a template, where
+/// `{index}` is replaced by the number of the part, starting at one. Without it, the
+/// number is appended to the file name if there is more than one part.
+pub fn output_paths(outputs: &[PathBuf], parts: usize) -> miette::Result<Vec<PathBuf>> {
+    let [template] = outputs else {
+        if outputs.len() != parts {
+            return Err(CliError::new(
+                ErrorKind::Usage,
+                format!(
+                    "Got {} output paths for {parts} parts, use a single path with '{{index}}' instead",
+                    outputs.len()
+                ),
+            )
+            .into());
+        }
+        return Ok(outputs.to_vec());
+    };
+
+    let Some(template) = template.to_str().filter(|path| path.contains("{index}")) else {
+        if parts == 1 {
+            return Ok(vec![template.clone()]);
+        }
+
+        return Ok((1..=parts)
+            .map(|index| numbered_path(template, index))
+            .collect());
+    };
+
+    Ok((1..=parts)
+        .map(|index| PathBuf::from(template.replace("{index}", &index.to_string())))
+        .collect())
+}
+
+// Turns out.png into out-1.png
+pub(crate) fn numbered_path(path: &Path, index: impl Display) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{index}"),
+    };
+
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splits() {
+        assert_eq!("auto".parse(), Ok(Splits::Auto));
+        assert_eq!("3".parse(), Ok(Splits::Count(3)));
+        assert!("0".parse::<Splits>().is_err());
+        assert!("some".parse::<Splits>().is_err());
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let paths = |outputs: &[&str], parts| {
+            output_paths(
+                &outputs.iter().map(PathBuf::from).collect::<Vec<_>>(),
+                parts,
+            )
+            .map(|paths| {
+                paths
+                    .into_iter()
+                    .map(|path| path.display().to_string())
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        assert_eq!(paths(&["out.png"], 1).unwrap(), ["out.png"]);
+        assert_eq!(
+            paths(&["dir/out.png"], 2).unwrap(),
+            ["dir/out-1.png", "dir/out-2.png"]
+        );
+        assert_eq!(
+            paths(&["part{index}/code.png"], 2).unwrap(),
+            ["part1/code.png", "part2/code.png"]
+        );
+        assert_eq!(paths(&["a.png", "b.png"], 2).unwrap(), ["a.png", "b.png"]);
+        assert!(paths(&["a.png", "b.png"], 3).is_err());
+    }
+}
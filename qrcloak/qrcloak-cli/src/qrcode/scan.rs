@@ -0,0 +1,226 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::path::PathBuf;
+
+use clap::Parser;
+use miette::Context;
+use qrcloak_core::{
+    extract::FrameScanner,
+    payload::{Decompression, PayloadExtractor},
+};
+
+use crate::{
+    decryption::DecryptionOptions,
+    error::{CliError, ErrorKind, IntoCliDiagnostic},
+    report::{self, Summary},
+    DataOutputOptions, FileOrStdout,
+};
+
+use super::{extract::extractor, frames::FrameReader};
+
+#[derive(Parser, Debug)]
+pub struct QrCodeScanArgs {
+    /// A Y4M or MJPEG video, or a directory of images that are scanned in the order of their names
+    source: PathBuf,
+
+    #[command(flatten)]
+    decryption: DecryptionOptions,
+
+    #[command(flatten)]
+    data_output: DataOutputOptions,
+
+    /// Keep scanning for more payloads after the first one is complete
+    #[arg(long)]
+    all: bool,
+
+    #[arg(short, long, default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+impl QrCodeScanArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let decryption = self.decryption.decryption()?;
+        let mut scanner = FrameScanner::default().with_extractor(extractor());
+
+        let mut writer = self.output.try_get_writer().into_diagnostic()?;
+
+        for (number, frame) in FrameReader::open(&self.source)?.enumerate() {
+            let frame = frame.wrap_err_with(|| format!("Unable to read frame {number}"))?;
+
+            let update =
+                scanner.push_frame(frame.width() as usize, frame.height() as usize, &*frame);
+
+            if !update.payloads.is_empty() {
+                for (index, parts) in scanner.incomplete().partials() {
+                    let found = parts.iter().flatten().count();
+                    report::warning(
+                        ErrorKind::IncompleteGroup,
+                        format!(
+                            "frame {number}: found {found} of {} parts of payload {index:?}",
+                            parts.len()
+                        ),
+                    );
+                }
+            }
+
+            for payload in update.complete {
+                let data = PayloadExtractor::default()
+                    .with_decryption(decryption.clone())
+                    .with_decompression(Decompression::from(payload.compression()))
+                    .extract(payload)
+                    .into_diagnostic()?;
+
+                self.data_output
+                    .write(&mut writer, &data)
+                    .into_diagnostic()?;
+            }
+
+            if !self.all && !scanner.completed().is_empty() {
+                break;
+            }
+        }
+
+        if !scanner.completed().is_empty() {
+            return Ok(Summary::default()
+                .with_payloads(scanner.completed().len())
+                .with_output(self.output));
+        }
+
+        if let Some((index, parts)) = scanner.incomplete().partials().iter().next() {
+            let found = parts.iter().flatten().count();
+            return Err(CliError::new(
+                ErrorKind::IncompleteGroup,
+                format!(
+                    "Found only {found} of {} parts of payload {index:?}",
+                    parts.len()
+                ),
+            )
+            .into());
+        }
+
+        Err(CliError::new(ErrorKind::Decode, "No qrcloak payloads found").into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        io::{Cursor, Write},
+    };
+
+    use image::GrayImage;
+    use qrcloak_core::{
+        format::Payload,
+        generate::Generator,
+        payload::{PayloadGenerator, PayloadSplitter},
+    };
+    use tempfile::TempDir;
+
+    use super::*;
+
+    // Each part is shown for two frames, with blank frames in between
+    fn frames(text: &str, splits: u32) -> Vec<GrayImage> {
+        let payload = PayloadGenerator::default()
+            .generate(text.as_bytes().to_vec().into())
+            .expect("should build");
+
+        let payloads = PayloadSplitter::default()
+            .with_splits(splits)
+            .split(payload)
+            .map(Payload::from);
+
+        let blank = GrayImage::from_pixel(240, 240, image::Luma([255]));
+
+        Generator::default()
+            .generate(payloads)
+            .expect("should generate")
+            .into_iter()
+            .flat_map(|image| {
+                let mut frame = blank.clone();
+                image::imageops::overlay(&mut frame, &image, 10, 10);
+                [frame.clone(), frame, blank.clone()]
+            })
+            .collect()
+    }
+
+    fn run(source: &std::path::Path, extra: &[&str]) -> miette::Result<String> {
+        let mut args = QrCodeScanArgs::parse_from(
+            ["cmd"]
+                .into_iter()
+                .chain(extra.iter().copied())
+                .chain([source.to_str().unwrap()]),
+        );
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle()?;
+
+        Ok(String::from_utf8(output.into_inner()).expect("should be utf-8"))
+    }
+
+    #[test]
+    fn test_scan_y4m() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("video.y4m");
+
+        let mut file = File::create(&path).unwrap();
+        writeln!(file, "YUV4MPEG2 W240 H240 F10:1 Ip A1:1 Cmono").unwrap();
+        for frame in frames("hello world", 3)
+            .into_iter()
+            .chain(frames("goodbye", 2))
+        {
+            file.write_all(b"FRAME\n").unwrap();
+            file.write_all(&frame).unwrap();
+        }
+        drop(file);
+
+        assert_eq!(run(&path, &[]).unwrap(), "hello world");
+        assert_eq!(
+            run(&path, &["--all", "--as-text"]).unwrap(),
+            "hello world\ngoodbye\n"
+        );
+    }
+
+    #[test]
+    fn test_scan_mjpeg() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("video.mjpeg");
+
+        let mut data = Vec::new();
+        for frame in frames("hello world", 2) {
+            let mut jpeg = Vec::new();
+            frame
+                .write_to(&mut Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+                .unwrap();
+            data.extend(jpeg);
+        }
+        std::fs::write(&path, &data).unwrap();
+
+        assert_eq!(run(&path, &[]).unwrap(), "hello world");
+    }
+
+    #[test]
+    fn test_scan_directory() {
+        let dir = TempDir::new().expect("should create temp dir");
+
+        let frames = frames("hello world", 3);
+        for (i, frame) in frames.iter().enumerate() {
+            frame
+                .save(dir.path().join(format!("frame{i:03}.png")))
+                .unwrap();
+        }
+
+        assert_eq!(run(dir.path(), &[]).unwrap(), "hello world");
+
+        // Without the last part the payload stays incomplete
+        std::fs::remove_file(dir.path().join(format!("frame{:03}.png", frames.len() - 2))).unwrap();
+        std::fs::remove_file(dir.path().join(format!("frame{:03}.png", frames.len() - 3))).unwrap();
+        let error = run(dir.path(), &[]).unwrap_err();
+        assert!(error.to_string().starts_with("Found only 2 of 3 parts"));
+    }
+}
@@ -0,0 +1,111 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::io::Write;
+
+use clap::Parser;
+use qrcloak_core::{generate::TerminalRenderer, payload::PayloadGenerator};
+
+use crate::{
+    compression::CompressionOptions, encryption::EncryptionOptions, error::IntoCliDiagnostic,
+    input::BinaryInput, report::Summary, FileOrStdout,
+};
+
+use super::options::{generate_splits, splits_or_configured, Splits, SymbolOptions};
+
+#[derive(Parser, Debug)]
+pub struct QrCodeShowArgs {
+    /// Split the payload into this many parts, or with 'auto' into as few as fit into a QR Code each
+    /// [default: 1]
+    #[arg(short, long, value_name = "N|auto")]
+    splits: Option<Splits>,
+
+    #[command(flatten)]
+    encryption: EncryptionOptions,
+
+    #[command(flatten)]
+    compression: CompressionOptions,
+
+    #[command(flatten)]
+    input: BinaryInput,
+
+    #[command(flatten)]
+    symbol: SymbolOptions,
+
+    /// Draw the modules with the terminal colors instead of black and white
+    #[arg(long)]
+    no_color: bool,
+
+    /// Swap dark and light modules, for terminals with light text on a dark background
+    #[arg(long)]
+    invert: bool,
+
+    #[arg(default_value_t = FileOrStdout::Stdout)]
+    output: FileOrStdout,
+}
+
+impl QrCodeShowArgs {
+    pub fn handle(self) -> miette::Result<Summary> {
+        let input = self.input.contents().into_diagnostic()?;
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(self.encryption.encryption()?)
+            .with_compression(self.compression.compression()?)
+            .generate(input.into())
+            .into_diagnostic()?;
+
+        let renderer = TerminalRenderer::default()
+            .with_colors(!self.no_color)
+            .with_inverted(self.invert);
+
+        let rendered = generate_splits(
+            splits_or_configured(self.splits)?,
+            &self.symbol.generator()?,
+            payload,
+            |symbol| renderer.render(symbol),
+        )?;
+
+        let mut writer = self.output.try_get_writer().into_diagnostic()?;
+
+        let total = rendered.len();
+        for (i, symbol) in rendered.into_iter().enumerate() {
+            if total > 1 {
+                writeln!(writer, "{}/{}", i + 1, total).into_diagnostic()?;
+            }
+            write!(writer, "{}", symbol).into_diagnostic()?;
+        }
+
+        Ok(Summary::default().with_payloads(1).with_output(self.output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_show_qrcodes() {
+        let mut args = QrCodeShowArgs::parse_from([
+            "cmd",
+            "--splits",
+            "2",
+            "--no-color",
+            "--text",
+            "hello world",
+        ]);
+
+        let output = FileOrStdout::new_testing();
+        args.output = output.clone();
+
+        args.handle().unwrap();
+
+        let output = String::from_utf8(output.into_inner()).expect("should be utf-8");
+
+        assert!(output.starts_with("1/2\n"));
+        assert!(output.contains("\n2/2\n"));
+        assert!(!output.contains('\x1b'));
+        assert!(output.contains('\u{2588}'));
+    }
+}
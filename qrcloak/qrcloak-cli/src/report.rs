@@ -0,0 +1,277 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! What a command reports besides its output, written to stderr as messages for
+//! people or, with `--output-format json`, as one JSON object per line for scripts.
+//! The last line in JSON is an `ok` or `error` event.
+
+use std::{ffi::OsString, fmt::Display, path::Path, sync::OnceLock};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::error::ErrorKind;
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl OutputFormat {
+    /// Finds the format in the raw arguments, for the errors of parsing them.
+    pub fn from_args(args: impl IntoIterator<Item = OsString>) -> OutputFormat {
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let value = match arg.to_str() {
+                Some("--") | None => break,
+                Some("--output-format") => args.next(),
+                Some(arg) => arg.strip_prefix("--output-format=").map(OsString::from),
+            };
+
+            if let Some(format) = value
+                .as_ref()
+                .and_then(|value| value.to_str())
+                .and_then(|value| OutputFormat::from_str(value, false).ok())
+            {
+                return format;
+            }
+        }
+
+        OutputFormat::default()
+    }
+}
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+
+/// Sets the format of the reports, once the arguments are parsed.
+pub fn init(format: OutputFormat) {
+    let _ = FORMAT.set(format);
+}
+
+/// Returns the format of the reports, which is text unless it was set, as in tests.
+pub fn format() -> OutputFormat {
+    *FORMAT.get_or_init(OutputFormat::default)
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    /// A problem that did not stop the command, such as an unreadable symbol.
+    Warning { kind: ErrorKind, message: String },
+
+    /// A payload was decoded and written to the file, or to the output.
+    Decoded {
+        payload: usize,
+        path: Option<&'a Path>,
+    },
+
+    /// An item of a batch was generated, skipped or failed.
+    Item {
+        name: &'a str,
+        status: ItemStatus,
+        outputs: Vec<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<ErrorReport>,
+    },
+
+    /// The public key of a generated identity.
+    PublicKey { key: String },
+
+    /// The command succeeded.
+    Ok(Summary),
+
+    /// The command failed.
+    Error(ErrorReport),
+}
+
+/// What a command produced, reported with the `ok` event.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summary {
+    /// The number of payloads that were generated, decoded or encrypted again.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payloads: Option<usize>,
+    /// The files that were written, '-' for stdout.
+    pub outputs: Vec<String>,
+}
+
+impl Summary {
+    pub fn with_payloads(mut self, payloads: usize) -> Self {
+        self.payloads = Some(payloads);
+        self
+    }
+
+    pub fn with_output(mut self, output: impl Display) -> Self {
+        self.outputs.push(output.to_string());
+        self
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ItemStatus {
+    Created,
+    Unchanged,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ErrorReport {
+    pub kind: ErrorKind,
+    pub exit_code: u8,
+    pub message: String,
+    /// The messages of the errors that caused this one, outermost first.
+    pub causes: Vec<String>,
+}
+
+impl ErrorReport {
+    pub fn new(report: &miette::Report) -> Self {
+        let kind = ErrorKind::of_report(report);
+
+        Self {
+            kind,
+            exit_code: kind.exit_code(),
+            message: report.to_string(),
+            causes: report
+                .chain()
+                .skip(1)
+                .map(|cause| cause.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl Event<'_> {
+    // The message for people, if there is anything to tell them
+    fn text(&self) -> Option<String> {
+        match self {
+            Event::Warning { message, .. } => Some(message.clone()),
+            Event::Decoded {
+                payload,
+                path: Some(path),
+            } => Some(format!("Wrote payload {payload} to {}", path.display())),
+            Event::Decoded { path: None, .. } => None,
+            Event::Item {
+                name,
+                status,
+                outputs,
+                error,
+            } => Some(match (status, error) {
+                (ItemStatus::Created, _) => format!("{name}: created {}", outputs.join(", ")),
+                (ItemStatus::Unchanged, _) => format!("{name}: unchanged, skipped"),
+                (ItemStatus::Failed, Some(error)) => {
+                    let causes = std::iter::once(&error.message).chain(&error.causes);
+                    format!("{name}: {}", causes.cloned().collect::<Vec<_>>().join(": "))
+                }
+                (ItemStatus::Failed, None) => format!("{name}: failed"),
+            }),
+            Event::PublicKey { key } => Some(format!("Public key: {key}")),
+            Event::Ok(_) => None,
+            Event::Error(error) => Some(error.message.clone()),
+        }
+    }
+}
+
+/// Writes the event to stderr in the chosen format.
+pub fn emit(event: Event) {
+    match format() {
+        OutputFormat::Text => {
+            if let Some(text) = event.text() {
+                eprintln!("{text}");
+            }
+        }
+        OutputFormat::Json => match serde_json::to_string(&event) {
+            Ok(json) => eprintln!("{json}"),
+            Err(error) => eprintln!("Unable to report {event:?}: {error}"),
+        },
+    }
+}
+
+/// Reports a problem that the command skipped over.
+pub fn warning(kind: ErrorKind, message: impl Into<String>) {
+    emit(Event::Warning {
+        kind,
+        message: message.into(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Context;
+
+    use crate::error::{CliError, IntoCliDiagnostic};
+
+    use super::*;
+
+    #[test]
+    fn test_events_json() {
+        let report = std::fs::read("/nonexistent/qrcloak")
+            .into_diagnostic()
+            .wrap_err("Unable to read the payloads")
+            .unwrap_err();
+
+        let json = serde_json::to_value(Event::Error(ErrorReport::new(&report))).unwrap();
+        assert_eq!(json["event"], "error");
+        assert_eq!(json["kind"], "io");
+        assert_eq!(json["exit-code"], 3);
+        assert_eq!(json["message"], "Unable to read the payloads");
+        assert_eq!(json["causes"][0], "reading or writing a file failed");
+        assert_eq!(json["causes"].as_array().unwrap().len(), 2);
+
+        let report: miette::Report =
+            CliError::new(ErrorKind::IncompleteGroup, "missing part 2 of 2").into();
+        let error = ErrorReport::new(&report);
+        let item = Event::Item {
+            name: "wifi",
+            status: ItemStatus::Failed,
+            outputs: vec![],
+            error: Some(error),
+        };
+        assert_eq!(item.text().unwrap(), "wifi: missing part 2 of 2");
+
+        let json = serde_json::to_value(item).unwrap();
+        assert_eq!(json["event"], "item");
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"]["kind"], "incomplete-group");
+        assert_eq!(json["error"]["exit-code"], 5);
+
+        let json = serde_json::to_value(Event::Decoded {
+            payload: 1,
+            path: None,
+        })
+        .unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"event": "decoded", "payload": 1, "path": null})
+        );
+
+        let summary = Summary::default().with_payloads(2).with_output("-");
+        assert_eq!(
+            serde_json::to_value(Event::Ok(summary)).unwrap(),
+            serde_json::json!({"event": "ok", "payloads": 2, "outputs": ["-"]})
+        );
+    }
+
+    #[test]
+    fn test_format_from_args() {
+        let format = |args: &[&str]| OutputFormat::from_args(args.iter().map(OsString::from));
+
+        assert_eq!(format(&["qrcloak", "decode"]), OutputFormat::Text);
+        assert_eq!(
+            format(&["qrcloak", "decode", "--output-format", "json", "-i"]),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format(&["qrcloak", "--output-format=json", "--bogus"]),
+            OutputFormat::Json
+        );
+        assert_eq!(
+            format(&["qrcloak", "decode", "--", "--output-format=json"]),
+            OutputFormat::Text
+        );
+    }
+}
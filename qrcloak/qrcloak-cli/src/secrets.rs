@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{
+    io::{self, BufRead, BufReader},
+    path::Path,
+    str::FromStr,
+};
+
+use age::{
+    secrecy::{ExposeSecret, SecretString},
+    x25519, IdentityFile, IdentityFileEntry,
+};
+use console::Term;
+use miette::Context;
+
+use crate::error::{CliError, ErrorKind, IntoCliDiagnostic};
+
+/// Reads a recipients file in the format of age, with one public key per line.
+pub fn read_recipients_file(path: &Path) -> miette::Result<Vec<x25519::Recipient>> {
+    std::fs::File::open(path)
+        .and_then(|file| parse_recipients(BufReader::new(file)))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read {}", path.display()))
+}
+
+/// Parses public keys in the format of age, see [`read_recipients_file`].
+///
+/// Empty lines and lines starting with `#` are ignored.
+pub fn parse_recipients(reader: impl BufRead) -> io::Result<Vec<x25519::Recipient>> {
+    let mut recipients = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let recipient = x25519::Recipient::from_str(line).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("line {}: {err}", number + 1),
+            )
+        })?;
+        recipients.push(recipient);
+    }
+
+    if recipients.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no recipients found",
+        ));
+    }
+
+    Ok(recipients)
+}
+
+/// Reads an identity file in the format of age, which may hold several private keys
+/// and comments.
+pub fn read_identity_file(path: &Path) -> miette::Result<Vec<x25519::Identity>> {
+    std::fs::File::open(path)
+        .and_then(|file| parse_identities(BufReader::new(file)))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Unable to read {}", path.display()))
+}
+
+/// Parses identities in the format of age, see [`read_identity_file`].
+pub fn parse_identities(reader: impl BufRead) -> io::Result<Vec<x25519::Identity>> {
+    let identities = IdentityFile::from_buffer(reader)?.into_identities();
+
+    if identities.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "no identities found",
+        ));
+    }
+
+    // Plugin identities only exist with the `plugin` feature of age, which is not enabled
+    Ok(identities
+        .into_iter()
+        .map(|entry| match entry {
+            IdentityFileEntry::Native(identity) => identity,
+        })
+        .collect())
+}
+
+/// Formats an identity as an entry of an identity file, preceded by its public key.
+pub fn format_identity(identity: &x25519::Identity) -> String {
+    format!(
+        "# public key: {}\n{}\n",
+        identity.to_public(),
+        identity.to_string().expose_secret()
+    )
+}
+
+/// Reads a passphrase from the terminal without echoing it, asking a second time
+/// to confirm it if requested.
+pub fn prompt_passphrase(confirm: bool) -> miette::Result<SecretString> {
+    let term = Term::stderr();
+    if !term.is_term() {
+        return Err(
+            CliError::new(ErrorKind::Usage, "--passphrase-prompt requires a terminal").into(),
+        );
+    }
+
+    term.write_str("Enter passphrase: ").into_diagnostic()?;
+    let passphrase = term.read_secure_line().into_diagnostic()?;
+
+    if passphrase.is_empty() {
+        return Err(CliError::new(ErrorKind::Usage, "The passphrase must not be empty").into());
+    }
+
+    if confirm {
+        term.write_str("Confirm passphrase: ").into_diagnostic()?;
+        if term.read_secure_line().into_diagnostic()? != passphrase {
+            return Err(CliError::new(ErrorKind::Usage, "The passphrases do not match").into());
+        }
+    }
+
+    Ok(SecretString::new(passphrase))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn test_read_recipients_file() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("recipients.txt");
+
+        let first = x25519::Identity::generate().to_public();
+        let second = x25519::Identity::generate().to_public();
+        std::fs::write(
+            &path,
+            format!("# alice\n{first}\n\n  # bob\n  {second}  \n"),
+        )
+        .unwrap();
+
+        let recipients = read_recipients_file(&path).expect("should read");
+        assert_eq!(
+            recipients.iter().map(|r| r.to_string()).collect::<Vec<_>>(),
+            [first.to_string(), second.to_string()]
+        );
+
+        std::fs::write(&path, format!("{first}\nnot-a-key\n")).unwrap();
+        let error = read_recipients_file(&path).unwrap_err();
+        assert!(error
+            .chain()
+            .any(|cause| cause.to_string().contains("line 2")));
+
+        std::fs::write(&path, "# nobody\n").unwrap();
+        assert!(read_recipients_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_read_identity_file() {
+        let dir = TempDir::new().expect("should create temp dir");
+        let path = dir.path().join("keys.txt");
+
+        let first = x25519::Identity::generate();
+        let second = x25519::Identity::generate();
+        std::fs::write(
+            &path,
+            format!(
+                "# created: 2024-05-01T12:00:00Z\n# public key: {}\n{}\n\n# public key: {}\n{}\n",
+                first.to_public(),
+                first.to_string().expose_secret(),
+                second.to_public(),
+                second.to_string().expose_secret(),
+            ),
+        )
+        .unwrap();
+
+        let identities = read_identity_file(&path).expect("should read");
+        assert_eq!(
+            identities
+                .iter()
+                .map(|identity| identity.to_public().to_string())
+                .collect::<Vec<_>>(),
+            [
+                first.to_public().to_string(),
+                second.to_public().to_string()
+            ]
+        );
+
+        let Err(error) = read_identity_file(&dir.path().join("missing.txt")) else {
+            panic!("should fail for a missing file");
+        };
+        assert_eq!(ErrorKind::of_report(&error), ErrorKind::Io);
+    }
+}
@@ -5,21 +5,44 @@
 
 use std::{
     env,
+    io::Write,
+    path::Path,
     process::{Command, Stdio},
 };
 
 use age::secrecy::ExposeSecret;
+use rand::RngCore;
+use tempfile::TempDir;
+
+// Runs the command line tool in the directory, which is also its home, so that only
+// the configuration that a test writes there is read
+fn qrcloak_cli(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_qrcloak-cli"));
+    command
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir);
+    command
+}
+
+// Random bytes that are not valid UTF-8 and end in whitespace, which must survive as is
+fn random_data(len: usize) -> Vec<u8> {
+    let mut data = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut data);
+    data.extend_from_slice(&[0xff, 0xfe, b' ', b'\n', b'\n']);
+    data
+}
 
 #[test]
 fn test_filter() {
-    let qrcloak_cli = env!("CARGO_BIN_EXE_qrcloak-cli");
+    let dir = TempDir::new().expect("failed to create temp dir");
 
     let id = age::x25519::Identity::generate();
 
     env::set_var("AGE_PRIVATE_KEY", id.to_string().expose_secret());
     env::set_var("AGE_KEY", id.to_public().to_string());
 
-    let mut generate = Command::new(qrcloak_cli)
+    let mut generate = qrcloak_cli(dir.path())
         .args([
             "payload",
             "generate",
@@ -33,15 +56,22 @@ fn test_filter() {
         .spawn()
         .expect("failed to spawn qrcloak-cli generate");
 
-    let mut merge = Command::new(qrcloak_cli)
+    let mut merge = qrcloak_cli(dir.path())
         .args(["payload", "merge", "--file", "-"])
         .stdin(Stdio::from(generate.stdout.take().unwrap()))
         .stdout(Stdio::piped())
         .spawn()
         .expect("failed to spawn qrcloak-cli merge");
 
-    let extract = Command::new(qrcloak_cli)
-        .args(["payload", "extract", "--age-key", "--file", "-"])
+    let extract = qrcloak_cli(dir.path())
+        .args([
+            "payload",
+            "extract",
+            "--age-key",
+            "--as-text",
+            "--file",
+            "-",
+        ])
         .stdin(Stdio::from(merge.stdout.take().unwrap()))
         .stdout(Stdio::piped())
         .spawn()
@@ -62,3 +92,475 @@ fn test_filter() {
 
     assert_eq!(String::from_utf8_lossy(&generate.stdout), "hello world\n");
 }
+
+#[test]
+fn test_binary_payload_roundtrip() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+
+    let data = random_data(1024);
+
+    let mut generate = qrcloak_cli(dir.path())
+        .args(["payload", "generate", "--splits", "3", "--file", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn qrcloak-cli generate");
+
+    let mut stdin = generate.stdin.take().unwrap();
+    stdin.write_all(&data).expect("failed to write input");
+    drop(stdin);
+
+    let mut merge = qrcloak_cli(dir.path())
+        .args(["payload", "merge", "--file", "-"])
+        .stdin(Stdio::from(generate.stdout.take().unwrap()))
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn qrcloak-cli merge");
+
+    let extract = qrcloak_cli(dir.path())
+        .args(["payload", "extract", "--file", "-"])
+        .stdin(Stdio::from(merge.stdout.take().unwrap()))
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to spawn qrcloak-cli extract");
+
+    assert!(generate.wait().unwrap().success());
+    assert!(merge.wait().unwrap().success());
+
+    let output = extract.wait_with_output().unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, data);
+}
+
+#[test]
+fn test_binary_qrcode_roundtrip() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let input = dir.path().join("secret.bin");
+    let output = dir.path().join("extracted.bin");
+    let images = [dir.path().join("first.png"), dir.path().join("second.png")];
+
+    let data = random_data(512);
+    std::fs::write(&input, &data).unwrap();
+
+    let res = qrcloak_cli(dir.path())
+        .args(["qrcode", "generate", "--file"])
+        .args([&input, &images[0], &images[1]])
+        .status()
+        .expect("failed to run qrcloak-cli generate");
+    assert!(res.success());
+
+    let res = qrcloak_cli(dir.path())
+        .args(["qrcode", "extract", "--output"])
+        .args([&output, &images[0], &images[1]])
+        .status()
+        .expect("failed to run qrcloak-cli extract");
+    assert!(res.success());
+
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+}
+
+#[test]
+fn test_compressed_auto_split_roundtrip() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let input = dir.path().join("secret.bin");
+    let output = dir.path().join("extracted.bin");
+
+    // Random data does not shrink, so it needs more than one QR Code
+    let data = random_data(1200);
+    std::fs::write(&input, &data).unwrap();
+
+    let res = qrcloak_cli(dir.path())
+        .args([
+            "qrcode",
+            "generate",
+            "--compression",
+            "gzip",
+            "--ecl",
+            "high",
+            "--splits",
+            "auto",
+            "--file",
+        ])
+        .arg(&input)
+        .arg(dir.path().join("code-{index}.png"))
+        .status()
+        .expect("failed to run qrcloak-cli generate");
+    assert!(res.success());
+
+    let mut images: Vec<_> = std::fs::read_dir(dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "png"))
+        .collect();
+    images.sort();
+    assert!(images.len() > 1);
+    assert_eq!(images[0], dir.path().join("code-1.png"));
+
+    let res = qrcloak_cli(dir.path())
+        .args(["decode", "--output"])
+        .arg(&output)
+        .args(&images)
+        .status()
+        .expect("failed to run qrcloak-cli decode");
+    assert!(res.success());
+
+    assert_eq!(std::fs::read(&output).unwrap(), data);
+}
+
+#[test]
+fn test_recipient_and_identity_files() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let recipients = dir.path().join("recipients.txt");
+    let identities = dir.path().join("keys.txt");
+
+    let other = age::x25519::Identity::generate();
+    let first = age::x25519::Identity::generate();
+    let second = age::x25519::Identity::generate();
+
+    std::fs::write(&recipients, format!("# first\n{}\n", first.to_public())).unwrap();
+    // The matching key comes after one that cannot decrypt the payload
+    std::fs::write(
+        &identities,
+        format!(
+            "# public key: {}\n{}\n\n# public key: {}\n{}\n",
+            other.to_public(),
+            other.to_string().expose_secret(),
+            first.to_public(),
+            first.to_string().expose_secret(),
+        ),
+    )
+    .unwrap();
+
+    let generate = qrcloak_cli(dir.path())
+        .args(["payload", "generate", "--text", "hello world", "-R"])
+        .arg(&recipients)
+        .args(["-r", &second.to_public().to_string()])
+        .output()
+        .expect("failed to run qrcloak-cli generate");
+    assert!(generate.status.success());
+
+    let extract = |args: &[&std::ffi::OsStr]| {
+        let mut extract = qrcloak_cli(dir.path())
+            .args(["payload", "extract", "--file", "-"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to spawn qrcloak-cli extract");
+
+        let mut stdin = extract.stdin.take().unwrap();
+        stdin.write_all(&generate.stdout).unwrap();
+        drop(stdin);
+
+        extract.wait_with_output().unwrap()
+    };
+
+    let output = extract(&["-i".as_ref(), identities.as_os_str()]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"hello world");
+
+    let second_file = dir.path().join("second.txt");
+    std::fs::write(&second_file, second.to_string().expose_secret()).unwrap();
+    let output = extract(&["--identity".as_ref(), second_file.as_os_str()]);
+    assert_eq!(output.stdout, b"hello world");
+
+    let other_file = dir.path().join("other.txt");
+    std::fs::write(&other_file, other.to_string().expose_secret()).unwrap();
+    assert!(!extract(&["-i".as_ref(), other_file.as_os_str()])
+        .status
+        .success());
+
+    // Keys and passphrases cannot be mixed
+    let output = extract(&[
+        "-i".as_ref(),
+        identities.as_os_str(),
+        "--passphrase-prompt".as_ref(),
+    ]);
+    assert!(!output.status.success());
+}
+
+#[test]
+fn test_key_backup_roundtrip() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let identity = dir.path().join("key.txt");
+    let sheet = dir.path().join("backup.png");
+    let restored = dir.path().join("restored.txt");
+
+    let res = qrcloak_cli(dir.path())
+        .args(["key", "generate", "--output"])
+        .arg(&identity)
+        .stderr(Stdio::null())
+        .status()
+        .expect("failed to run qrcloak-cli key generate");
+    assert!(res.success());
+
+    // Backups without encryption are refused
+    let res = qrcloak_cli(dir.path())
+        .args(["key", "backup"])
+        .arg(&identity)
+        .arg("--output")
+        .arg(&sheet)
+        .stderr(Stdio::null())
+        .status()
+        .expect("failed to run qrcloak-cli key backup");
+    assert!(!res.success());
+
+    let res = qrcloak_cli(dir.path())
+        .args(["key", "backup", "--age-passphrase"])
+        .arg(&identity)
+        .arg("--output")
+        .arg(&sheet)
+        .env("AGE_PASSPHRASE", "correct horse battery staple")
+        .status()
+        .expect("failed to run qrcloak-cli key backup");
+    assert!(res.success());
+
+    let res = qrcloak_cli(dir.path())
+        .args(["qrcode", "extract", "--age-passphrase", "--output"])
+        .arg(&restored)
+        .arg(&sheet)
+        .env("AGE_PASSPHRASE", "correct horse battery staple")
+        .status()
+        .expect("failed to run qrcloak-cli qrcode extract");
+    assert!(res.success());
+
+    let public = |path: &std::path::Path| {
+        let output = qrcloak_cli(dir.path())
+            .args(["key", "public"])
+            .arg(path)
+            .output()
+            .expect("failed to run qrcloak-cli key public");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let original = public(&identity);
+    assert!(original.starts_with("age1"));
+    assert_eq!(public(&restored), original);
+}
+
+#[test]
+fn test_json_output_and_exit_codes() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+    let identity = age::x25519::Identity::generate();
+    let other = age::x25519::Identity::generate();
+
+    let key_file = dir.path().join("key.txt");
+    let other_file = dir.path().join("other.txt");
+    std::fs::write(&key_file, identity.to_string().expose_secret()).unwrap();
+    std::fs::write(&other_file, other.to_string().expose_secret()).unwrap();
+
+    let generate = |name: &str, args: &[&str]| {
+        let path = dir.path().join(name);
+        let output = qrcloak_cli(dir.path())
+            .args(["payload", "generate", "--text", "hello world"])
+            .args(args)
+            .env("AGE_PASSPHRASE", "correct horse battery staple")
+            .output()
+            .expect("failed to run qrcloak-cli generate");
+        assert!(output.status.success());
+        std::fs::write(&path, output.stdout).unwrap();
+        path
+    };
+
+    let keys = generate("keys.json", &["-r", &identity.to_public().to_string()]);
+    let passphrase = generate("passphrase.json", &["--age-passphrase"]);
+    let parts = generate(
+        "parts.json",
+        &["-r", &identity.to_public().to_string(), "--splits", "2"],
+    );
+
+    // Only the first of the two parts
+    let payloads: Vec<serde_json::Value> =
+        serde_json::from_slice(&std::fs::read(&parts).unwrap()).unwrap();
+    let part = dir.path().join("part.json");
+    std::fs::write(&part, serde_json::to_string(&payloads[..1]).unwrap()).unwrap();
+
+    let garbage = dir.path().join("garbage.json");
+    std::fs::write(&garbage, "not a payload").unwrap();
+
+    // Change one byte near the end of the ciphertext, in its authentication tag, by
+    // changing the lowest digit of the second to last group of three base45 digits
+    const BASE45: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+    let mut corrupt: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(&keys).unwrap()).unwrap();
+    let payload = match corrupt.as_array_mut() {
+        Some(payloads) => &mut payloads[0],
+        None => &mut corrupt,
+    };
+    let mut data: Vec<char> = payload["data"].as_str().unwrap().chars().collect();
+    let start = (data.len() / 3 - 2) * 3;
+    let digit = BASE45.find(data[start]).unwrap();
+    let digit = if digit > 0 { digit - 1 } else { digit + 1 };
+    data[start] = BASE45.as_bytes()[digit] as char;
+    payload["data"] = data.into_iter().collect::<String>().into();
+    let corrupt_file = dir.path().join("corrupt.json");
+    std::fs::write(&corrupt_file, corrupt.to_string()).unwrap();
+
+    // Runs decode and returns the exit code with the JSON events on stderr
+    let decode = |identity: &std::path::Path, input: &std::path::Path| {
+        let output = qrcloak_cli(dir.path())
+            .args(["--output-format", "json", "decode", "-i"])
+            .arg(identity)
+            .arg(input)
+            .output()
+            .expect("failed to run qrcloak-cli decode");
+
+        let events: Vec<serde_json::Value> = String::from_utf8(output.stderr)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("should be json"))
+            .collect();
+
+        (output.status.code(), events)
+    };
+
+    let (code, events) = decode(&key_file, &keys);
+    assert_eq!(code, Some(0));
+    assert_eq!(events[0]["event"], "decoded");
+    assert_eq!(
+        events.last().unwrap(),
+        &serde_json::json!({"event": "ok", "payloads": 1, "outputs": ["-"]})
+    );
+
+    let missing_key = dir.path().join("missing.txt");
+    let cases = [
+        (&missing_key, &keys, "io", 3),
+        (&other_file, &keys, "no-matching-key", 7),
+        (&key_file, &passphrase, "spec-mismatch", 6),
+        (&key_file, &part, "incomplete-group", 5),
+        (&key_file, &garbage, "decode", 4),
+        (&key_file, &corrupt_file, "decode", 4),
+        (&key_file, &dir.path().join("missing.json"), "io", 3),
+    ];
+
+    for (identity, input, kind, exit_code) in cases {
+        let (code, events) = decode(identity, input);
+        assert_eq!(code, Some(exit_code), "{kind}");
+
+        let error = events.last().unwrap();
+        assert_eq!(error["event"], "error");
+        assert_eq!(error["kind"], kind);
+        assert_eq!(error["exit-code"], exit_code);
+    }
+
+    // The same codes without JSON, where the error is for people
+    let output = qrcloak_cli(dir.path())
+        .args(["payload", "extract", "-i"])
+        .arg(&other_file)
+        .arg("--file")
+        .arg(&keys)
+        .output()
+        .expect("failed to run qrcloak-cli extract");
+    assert_eq!(output.status.code(), Some(7));
+    assert!(serde_json::from_slice::<serde_json::Value>(&output.stderr).is_err());
+}
+
+#[test]
+fn test_json_usage_and_config_errors() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+
+    // Runs the command in the directory and returns the exit code with the last event
+    let run = |args: &[&str]| {
+        let output = qrcloak_cli(dir.path())
+            .args(args)
+            .output()
+            .expect("failed to run qrcloak-cli");
+
+        let stderr = String::from_utf8(output.stderr).unwrap();
+        let last = stderr.lines().last().expect("should report").to_string();
+
+        (output.status.code(), last)
+    };
+
+    let (code, last) = run(&["--output-format", "json", "decode", "--bogus"]);
+    assert_eq!(code, Some(2));
+    let error: serde_json::Value = serde_json::from_str(&last).expect("should be json");
+    assert_eq!(error["event"], "error");
+    assert_eq!(error["kind"], "usage");
+    assert_eq!(error["exit-code"], 2);
+
+    // Without JSON, clap reports the usage error as usual
+    let (code, last) = run(&["decode", "--bogus"]);
+    assert_eq!(code, Some(2));
+    assert!(serde_json::from_str::<serde_json::Value>(&last).is_err());
+
+    // Inconsistent arguments that clap cannot check are usage errors as well
+    let identity = age::x25519::Identity::generate();
+    std::fs::write(
+        dir.path().join("key.txt"),
+        identity.to_string().expose_secret(),
+    )
+    .unwrap();
+
+    let usage_errors: [&[&str]; 2] = [
+        &[
+            "qrcode", "generate", "--text", "x", "--splits", "3", "a.png", "b.png",
+        ],
+        &["key", "backup", "key.txt", "--output", "sheet.png"],
+    ];
+    for args in usage_errors {
+        let (code, last) = run(&[&["--output-format", "json"], args].concat());
+        assert_eq!(code, Some(2), "{args:?}");
+        let error: serde_json::Value = serde_json::from_str(&last).expect("should be json");
+        assert_eq!(error["kind"], "usage");
+    }
+
+    std::fs::write(dir.path().join("qrcloak.toml"), "[render\nsplits = ").unwrap();
+
+    let (code, last) = run(&["--output-format=json", "payload", "generate", "--text", "x"]);
+    assert_eq!(code, Some(1));
+    let error: serde_json::Value = serde_json::from_str(&last).expect("should be json");
+    assert_eq!(error["event"], "error");
+    assert_eq!(error["message"], "Unable to load the configuration");
+
+    // Commands that do not read the configuration still work
+    for args in [&["completion", "bash"][..], &["key", "generate"]] {
+        let output = qrcloak_cli(dir.path())
+            .args(args)
+            .output()
+            .expect("failed to run qrcloak-cli");
+        assert!(output.status.success(), "{args:?}");
+    }
+}
+
+#[test]
+fn test_flags_override_config() {
+    let dir = TempDir::new().expect("failed to create temp dir");
+
+    std::fs::write(
+        dir.path().join("qrcloak.toml"),
+        "[defaults]\npretty = true\n",
+    )
+    .unwrap();
+
+    let generate = |args: &[&str]| {
+        let output = qrcloak_cli(dir.path())
+            .args(["payload", "generate", "--text", "hello world"])
+            .args(args)
+            .output()
+            .expect("failed to run qrcloak-cli generate");
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .trim_end()
+            .lines()
+            .count()
+    };
+
+    assert!(generate(&[]) > 1);
+    assert_eq!(generate(&["--no-pretty"]), 1);
+    // The last of the two flags wins
+    assert!(generate(&["--no-pretty", "--pretty"]) > 1);
+
+    // The user configuration is read from the home directory of the test
+    let user = dir.path().join("qrcloak");
+    std::fs::create_dir(&user).unwrap();
+    std::fs::write(user.join("config.toml"), "[render\n").unwrap();
+    let output = qrcloak_cli(dir.path())
+        .args(["payload", "generate", "--text", "hello world"])
+        .output()
+        .expect("failed to run qrcloak-cli generate");
+    assert!(!output.status.success());
+}
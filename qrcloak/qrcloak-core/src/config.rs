@@ -0,0 +1,436 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Settings shared by the command line tool and the pandoc filter.
+//!
+//! They are read from the user configuration in `$XDG_CONFIG_HOME/qrcloak/config.toml`
+//! and from the nearest `qrcloak.toml` in the working directory or one of its parents,
+//! whose values take precedence:
+//!
+//! ```toml
+//! [recipients]
+//! team = ["age1...", "age1..."]
+//! ops = ["age1..."]
+//!
+//! [defaults]
+//! recipients = ["team"]
+//! compression = "gzip"
+//! pretty = true
+//!
+//! [render]
+//! ecl = "quartile"
+//! structured-append = true
+//! splits = "auto"
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use age::x25519;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use thiserror::Error;
+
+use crate::payload::{Compression, GzipCompression};
+
+/// The name of the configuration file of a project.
+pub const PROJECT_FILE: &str = "qrcloak.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// Named groups of public keys.
+    #[serde(default)]
+    pub recipients: BTreeMap<String, Vec<String>>,
+
+    /// Defaults for the payloads.
+    #[serde(default)]
+    pub defaults: Defaults,
+
+    /// Defaults for the QR Codes.
+    #[serde(default)]
+    pub render: RenderConfig,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Defaults {
+    /// The recipient groups to encrypt to when no other encryption is given.
+    pub recipients: Option<Vec<String>>,
+
+    /// The compression of the data.
+    pub compression: Option<CompressionKind>,
+
+    /// Whether encoded payloads are pretty printed.
+    pub pretty: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct RenderConfig {
+    /// The error correction level.
+    pub ecl: Option<Ecl>,
+
+    /// Whether split payloads use QR Structured Append headers.
+    pub structured_append: Option<bool>,
+
+    /// Into how many parts payloads are split.
+    pub splits: Option<Splits>,
+}
+
+/// The compression of the data, by the name used in the configuration and on the
+/// command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum CompressionKind {
+    #[default]
+    None,
+    Gzip,
+}
+
+impl FromStr for CompressionKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CompressionKind::None),
+            "gzip" => Ok(CompressionKind::Gzip),
+            _ => Err(format!("expected 'none' or 'gzip', got '{s}'")),
+        }
+    }
+}
+
+impl From<CompressionKind> for Compression {
+    fn from(kind: CompressionKind) -> Self {
+        match kind {
+            CompressionKind::None => Compression::NoCompression,
+            CompressionKind::Gzip => Compression::Gzip(GzipCompression),
+        }
+    }
+}
+
+/// The error correction level, from the most data to the most damage the QR Code survives.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "clap", derive(clap::ValueEnum))]
+#[serde(rename_all = "lowercase")]
+pub enum Ecl {
+    Low,
+    Medium,
+    Quartile,
+    #[default]
+    High,
+}
+
+impl FromStr for Ecl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(Ecl::Low),
+            "medium" => Ok(Ecl::Medium),
+            "quartile" => Ok(Ecl::Quartile),
+            "high" => Ok(Ecl::High),
+            _ => Err(format!(
+                "expected 'low', 'medium', 'quartile' or 'high', got '{s}'"
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "generate")]
+impl From<Ecl> for qrcodegen::QrCodeEcc {
+    fn from(ecl: Ecl) -> Self {
+        match ecl {
+            Ecl::Low => qrcodegen::QrCodeEcc::Low,
+            Ecl::Medium => qrcodegen::QrCodeEcc::Medium,
+            Ecl::Quartile => qrcodegen::QrCodeEcc::Quartile,
+            Ecl::High => qrcodegen::QrCodeEcc::High,
+        }
+    }
+}
+
+/// A number of parts, or as few as fit into one symbol each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Splits {
+    Count(u32),
+    Auto,
+}
+
+impl FromStr for Splits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(Splits::Auto);
+        }
+
+        match s.parse() {
+            Ok(0) | Err(_) => Err(format!(
+                "expected a number of parts of at least 1 or 'auto', got '{s}'"
+            )),
+            Ok(count) => Ok(Splits::Count(count)),
+        }
+    }
+}
+
+impl Default for Splits {
+    fn default() -> Self {
+        Splits::Count(1)
+    }
+}
+
+impl Display for Splits {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Splits::Count(count) => write!(f, "{count}"),
+            Splits::Auto => write!(f, "auto"),
+        }
+    }
+}
+
+impl Serialize for Splits {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Splits::Count(count) => serializer.serialize_u32(*count),
+            Splits::Auto => serializer.serialize_str("auto"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Splits {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // A number of parts or the string 'auto'
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Count(u32),
+            Text(String),
+        }
+
+        let splits = match Repr::deserialize(deserializer)? {
+            Repr::Count(count) => count.to_string().parse(),
+            Repr::Text(text) => text.parse(),
+        };
+
+        splits.map_err(de::Error::custom)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("unable to read {}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("invalid configuration {}: {source}", .path.display())]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[error("unknown recipient group `{0}`")]
+    UnknownGroup(String),
+
+    #[error("invalid public key `{recipient}` in recipient group `{group}`: {reason}")]
+    InvalidRecipient {
+        group: String,
+        recipient: String,
+        reason: String,
+    },
+}
+
+impl Config {
+    /// Reads the user configuration and the project configuration for the directory,
+    /// where the values of the project take precedence.
+    pub fn discover(dir: &Path) -> Result<Self, ConfigError> {
+        let mut config = Config::default();
+
+        if let Some(path) = Self::user_path().filter(|path| path.is_file()) {
+            config = config.merge(Self::read(&path)?);
+        }
+
+        if let Some(path) = Self::project_path(dir) {
+            config = config.merge(Self::read(&path)?);
+        }
+
+        Ok(config)
+    }
+
+    /// Reads a single configuration file.
+    pub fn read(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    /// Returns `$XDG_CONFIG_HOME/qrcloak/config.toml`, or the same under `~/.config`.
+    pub fn user_path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+
+        Some(config_home.join("qrcloak").join("config.toml"))
+    }
+
+    /// Returns the nearest `qrcloak.toml` in the directory or one of its parents.
+    pub fn project_path(dir: &Path) -> Option<PathBuf> {
+        dir.ancestors()
+            .map(|dir| dir.join(PROJECT_FILE))
+            .find(|path| path.is_file())
+    }
+
+    /// Combines two configurations, where the values of `other` take precedence.
+    pub fn merge(mut self, other: Config) -> Config {
+        self.recipients.extend(other.recipients);
+
+        Config {
+            recipients: self.recipients,
+            defaults: Defaults {
+                recipients: other.defaults.recipients.or(self.defaults.recipients),
+                compression: other.defaults.compression.or(self.defaults.compression),
+                pretty: other.defaults.pretty.or(self.defaults.pretty),
+            },
+            render: RenderConfig {
+                ecl: other.render.ecl.or(self.render.ecl),
+                structured_append: other
+                    .render
+                    .structured_append
+                    .or(self.render.structured_append),
+                splits: other.render.splits.or(self.render.splits),
+            },
+        }
+    }
+
+    /// Returns the public keys of a recipient group.
+    pub fn group(&self, name: &str) -> Result<Vec<x25519::Recipient>, ConfigError> {
+        let recipients = self
+            .recipients
+            .get(name)
+            .ok_or_else(|| ConfigError::UnknownGroup(name.to_string()))?;
+
+        recipients
+            .iter()
+            .map(|recipient| {
+                x25519::Recipient::from_str(recipient).map_err(|reason| {
+                    ConfigError::InvalidRecipient {
+                        group: name.to_string(),
+                        recipient: recipient.clone(),
+                        reason: reason.to_string(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Returns the public keys of the default recipient groups, if there are any.
+    pub fn default_recipients(&self) -> Result<Vec<x25519::Recipient>, ConfigError> {
+        let mut recipients = Vec::new();
+        for group in self.defaults.recipients.iter().flatten() {
+            recipients.extend(self.group(group)?);
+        }
+
+        Ok(recipients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let alice = x25519::Identity::generate().to_public();
+        let bob = x25519::Identity::generate().to_public();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [recipients]
+            team = ["{alice}", "{bob}"]
+            broken = ["age1invalid"]
+
+            [defaults]
+            recipients = ["team"]
+            compression = "gzip"
+
+            [render]
+            ecl = "quartile"
+            splits = "auto"
+            "#
+        ))
+        .expect("should parse");
+
+        assert_eq!(config.defaults.compression, Some(CompressionKind::Gzip));
+        assert_eq!(config.render.ecl, Some(Ecl::Quartile));
+        assert_eq!(config.render.splits, Some(Splits::Auto));
+        assert_eq!(config.render.structured_append, None);
+
+        let team = config.default_recipients().expect("should resolve");
+        assert_eq!(
+            team.iter().map(ToString::to_string).collect::<Vec<_>>(),
+            [alice.to_string(), bob.to_string()]
+        );
+
+        assert!(matches!(
+            config.group("ops"),
+            Err(ConfigError::UnknownGroup(_))
+        ));
+        assert!(matches!(
+            config.group("broken"),
+            Err(ConfigError::InvalidRecipient { .. })
+        ));
+
+        assert!(toml::from_str::<Config>("[render]\nsplits = 0").is_err());
+        assert!(toml::from_str::<Config>("[render]\nunknown = 1").is_err());
+    }
+
+    #[test]
+    fn test_merge_and_discover() {
+        let temp = tempfile::TempDir::new().expect("should create temp dir");
+        let dir = temp.path();
+        let nested = dir.join("a").join("b");
+        std::fs::create_dir_all(&nested).unwrap();
+
+        std::fs::write(
+            dir.join(PROJECT_FILE),
+            "[recipients]\nteam = []\n\n[render]\necl = \"low\"\nsplits = 3\n",
+        )
+        .unwrap();
+
+        assert_eq!(Config::project_path(&nested), Some(dir.join(PROJECT_FILE)));
+
+        let user: Config = toml::from_str(
+            "[recipients]\nops = []\n\n[defaults]\npretty = true\n\n[render]\necl = \"high\"\n",
+        )
+        .unwrap();
+        let merged = user.merge(Config::read(&dir.join(PROJECT_FILE)).unwrap());
+
+        assert_eq!(
+            merged.recipients.keys().collect::<Vec<_>>(),
+            ["ops", "team"]
+        );
+        assert_eq!(merged.defaults.pretty, Some(true));
+        assert_eq!(merged.render.ecl, Some(Ecl::Low));
+        assert_eq!(merged.render.splits, Some(Splits::Count(3)));
+
+        std::fs::write(dir.join(PROJECT_FILE), "[render]\necl = \"none\"\n").unwrap();
+        assert!(matches!(
+            Config::read(&dir.join(PROJECT_FILE)),
+            Err(ConfigError::Parse { .. })
+        ));
+    }
+}
@@ -0,0 +1,266 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Synthetically degraded photos of generated symbols, to measure how
+//! many of them the preprocessing recovers.
+
+use image::{imageops, GrayImage, Luma};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+#[cfg(feature = "rqrr")]
+use crate::extract::RqrrDetector;
+use crate::{
+    extract::{Detector, DetectorChain, Extractor, Preprocessor, QuircDetector},
+    format::Payload,
+    generate::{Generator, Symbol},
+    payload::PayloadGenerator,
+};
+
+// A symbol with 8 pixels per module on a white sheet
+fn sheet(text: &str) -> (GrayImage, Payload) {
+    let payload: Payload = PayloadGenerator::default()
+        .generate(text.as_bytes().to_vec().into())
+        .expect("should build")
+        .into();
+
+    let image = Generator::default()
+        .generate([payload.clone()])
+        .expect("should generate")
+        .remove(0);
+    let image = imageops::resize(
+        &image,
+        image.width() * 2,
+        image.height() * 2,
+        imageops::FilterType::Nearest,
+    );
+
+    let margin = 64;
+    let mut sheet = GrayImage::from_pixel(
+        image.width() + margin * 2,
+        image.height() + margin * 2,
+        Luma([255]),
+    );
+    imageops::overlay(&mut sheet, &image, i64::from(margin), i64::from(margin));
+
+    (sheet, payload)
+}
+
+fn map(image: &GrayImage, f: impl Fn(u32, u32, f32) -> f32) -> GrayImage {
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        let p = f32::from(image.get_pixel(x, y).0[0]);
+        Luma([f(x, y, p).round().clamp(0.0, 255.0) as u8])
+    })
+}
+
+fn noise(image: &GrayImage, amplitude: f32, rng: &mut StdRng) -> GrayImage {
+    let noise: Vec<f32> = (0..image.width() * image.height())
+        .map(|_| (0..4).map(|_| rng.gen_range(-1.0..1.0)).sum::<f32>() / 2.0 * amplitude)
+        .collect();
+    map(image, |x, y, p| p + noise[(y * image.width() + x) as usize])
+}
+
+// Light that falls off from the top left to a dim bottom right corner
+fn uneven_lighting(image: &GrayImage) -> GrayImage {
+    let size = (image.width() + image.height()) as f32;
+    map(image, |x, y, p| {
+        p * (1.0 - 0.75 * (x + y) as f32 / size) + 20.0
+    })
+}
+
+fn low_contrast(image: &GrayImage) -> GrayImage {
+    map(image, |_, _, p| 110.0 + p * 40.0 / 255.0)
+}
+
+fn inverted(image: &GrayImage) -> GrayImage {
+    map(image, |_, _, p| 255.0 - p)
+}
+
+fn scaled(image: &GrayImage, factor: f32) -> GrayImage {
+    let (width, height) = (
+        (image.width() as f32 * factor) as u32,
+        (image.height() as f32 * factor) as u32,
+    );
+    imageops::resize(image, width, height, imageops::FilterType::Triangle)
+}
+
+// Samples the image through a projective transformation that tilts it away from the viewer
+fn perspective(image: &GrayImage, tilt: f32, rotation: f32) -> GrayImage {
+    let (w, h) = (image.width() as f32, image.height() as f32);
+    let (sin, cos) = rotation.to_radians().sin_cos();
+
+    GrayImage::from_fn(image.width(), image.height(), |x, y| {
+        // Coordinates relative to the center in [-1, 1]
+        let (u, v) = (x as f32 / w * 2.0 - 1.0, y as f32 / h * 2.0 - 1.0);
+        let depth = 1.0 + tilt * (u + v) / 2.0;
+        let (u, v) = (u * 1.15 / depth, v * 1.15 / depth);
+        let (u, v) = (u * cos - v * sin, u * sin + v * cos);
+
+        let (sx, sy) = ((u + 1.0) / 2.0 * w, (v + 1.0) / 2.0 * h);
+        if sx < 0.0 || sy < 0.0 || sx >= w || sy >= h {
+            Luma([255])
+        } else {
+            *image.get_pixel(sx as u32, sy as u32)
+        }
+    })
+}
+
+#[allow(clippy::type_complexity)]
+fn corpus() -> Vec<(&'static str, GrayImage, Payload)> {
+    let mut rng = StdRng::seed_from_u64(0x5eed);
+    let (image, payload) = sheet("a secret that was printed, photographed and scanned again");
+
+    let degradations: Vec<(&'static str, GrayImage)> = vec![
+        ("blur", imageops::blur(&image, 3.0)),
+        ("noise", noise(&image, 110.0, &mut rng)),
+        (
+            "blur and noise",
+            noise(&imageops::blur(&image, 2.5), 70.0, &mut rng),
+        ),
+        (
+            "uneven lighting",
+            uneven_lighting(&imageops::blur(&image, 2.0)),
+        ),
+        (
+            "low contrast noise",
+            noise(&low_contrast(&image), 40.0, &mut rng),
+        ),
+        ("inverted", inverted(&image)),
+        ("inverted blur", inverted(&imageops::blur(&image, 3.0))),
+        (
+            "large noisy photo",
+            noise(&scaled(&image, 4.0), 160.0, &mut rng),
+        ),
+        ("perspective", perspective(&image, 0.6, 0.0)),
+        (
+            "perspective blur",
+            imageops::blur(&perspective(&image, 0.3, 30.0), 2.5),
+        ),
+        (
+            "rotated blur",
+            imageops::blur(&perspective(&image, 0.0, 20.0), 3.0),
+        ),
+    ];
+
+    degradations
+        .into_iter()
+        .map(|(name, image)| (name, image, payload.clone()))
+        .collect()
+}
+
+// High version symbols with only a few pixels per module
+#[allow(clippy::type_complexity)]
+fn dense_corpus() -> Vec<(String, GrayImage, Payload)> {
+    let mut corpus = Vec::new();
+
+    for length in [250, 600, 1100] {
+        let text: String = (0..length)
+            .map(|i| (b'a' + (i * 7 % 26) as u8) as char)
+            .collect();
+        let payload: Payload = PayloadGenerator::default()
+            .generate(text.into_bytes().into())
+            .expect("should build")
+            .into();
+
+        let (version, image) = Generator::default()
+            .generate_with([payload.clone()], |symbol| {
+                let Symbol::Qr(qrcode) = symbol else {
+                    panic!("should be a QR Code");
+                };
+                (qrcode.version().value(), render(symbol, 3))
+            })
+            .expect("should generate")
+            .remove(0);
+
+        corpus.push((
+            format!("version {version} flat"),
+            image.clone(),
+            payload.clone(),
+        ));
+        corpus.push((
+            format!("version {version} rotated"),
+            imageops::blur(&perspective(&image, 0.0, 10.0), 0.8),
+            payload.clone(),
+        ));
+        corpus.push((
+            format!("version {version} tilted"),
+            perspective(&scaled(&image, 1.5), 0.25, 0.0),
+            payload,
+        ));
+    }
+
+    corpus
+}
+
+// Renders the symbol with the given number of pixels per module on a white sheet
+fn render(symbol: &Symbol, scale: i32) -> GrayImage {
+    let margin = symbol.quiet_zone() * 4;
+    let size = (symbol.width() + margin * 2) * scale;
+
+    GrayImage::from_fn(size as u32, size as u32, |x, y| {
+        let (x, y) = (x as i32 / scale - margin, y as i32 / scale - margin);
+        Luma([if symbol.get_module(x, y) { 0 } else { 255 }])
+    })
+}
+
+fn decodes<D: Detector>(extractor: &Extractor<D>, image: &GrayImage, payload: &Payload) -> bool {
+    let payloads =
+        extractor.extract_payloads(image.width() as usize, image.height() as usize, &**image);
+    payloads.contains(payload)
+}
+
+// Returns the names of the images the extractor decodes
+fn decoded<'a, D: Detector>(
+    extractor: &Extractor<D>,
+    corpus: &'a [(impl AsRef<str>, GrayImage, Payload)],
+) -> Vec<&'a str> {
+    corpus
+        .iter()
+        .filter(|(_, image, payload)| decodes(extractor, image, payload))
+        .map(|(name, _, _)| name.as_ref())
+        .collect()
+}
+
+fn chain() -> DetectorChain {
+    let chain = DetectorChain::default().with_detector(QuircDetector);
+    #[cfg(feature = "rqrr")]
+    let chain = chain.with_detector(RqrrDetector);
+    chain
+}
+
+#[test]
+fn test_degraded_corpus() {
+    let corpus = corpus();
+    let names: Vec<&str> = corpus.iter().map(|(name, _, _)| *name).collect();
+
+    // Only the mildly degraded images decode without preprocessing
+    let plain = decoded(&Extractor::default(), &corpus);
+    assert!(plain.len() < corpus.len() / 2, "plain decoded {plain:?}");
+
+    let preprocessing = Extractor::default().with_preprocessor(Some(Preprocessor::default()));
+    assert_eq!(decoded(&preprocessing, &corpus), names);
+    assert_eq!(
+        decoded(&preprocessing.with_detector(chain()), &corpus),
+        names
+    );
+}
+
+#[test]
+fn test_dense_corpus() {
+    let corpus = dense_corpus();
+    let names: Vec<&str> = corpus.iter().map(|(name, _, _)| name.as_str()).collect();
+
+    // quirc loses track of the timing patterns of rotated dense symbols
+    let quirc = decoded(&Extractor::default(), &corpus);
+    for name in names.iter().filter(|name| name.ends_with("flat")) {
+        assert!(quirc.contains(name), "quirc should decode {name}");
+    }
+    assert!(quirc.iter().all(|name| !name.ends_with("rotated")));
+
+    // The chain keeps the grids of quirc, so it decodes at least the same symbols
+    let chained = decoded(&Extractor::default().with_detector(chain()), &corpus);
+    for name in quirc {
+        assert!(chained.contains(&name), "the chain should decode {name}");
+    }
+}
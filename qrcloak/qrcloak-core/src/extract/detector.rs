@@ -0,0 +1,125 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::{fmt::Debug, sync::Arc};
+
+use super::{report::overlap, Point, SymbolError};
+
+/// Locates symbols in images and samples their modules.
+///
+/// The [`Extractor`](super::Extractor) decodes the sampled modules of every
+/// detector in the same way, so a detector only needs to find the grid.
+pub trait Detector: Debug {
+    /// Returns the symbols found in a grayscale image with one byte per pixel.
+    ///
+    /// Symbols that were located but whose modules could not be sampled are returned as errors.
+    fn detect(
+        &self,
+        width: usize,
+        height: usize,
+        image: &[u8],
+    ) -> Vec<Result<SampledGrid, SymbolError>>;
+}
+
+/// The modules of a symbol as sampled from an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampledGrid {
+    corners: [Point; 4],
+    size: i32,
+    modules: Vec<bool>,
+}
+
+impl SampledGrid {
+    /// Samples a grid of `size` by `size` modules, `is_dark` is called for every
+    /// module with coordinates in the range [0, size).
+    ///
+    /// The corners are in image coordinates, clockwise from the top left corner of the symbol.
+    pub fn new(corners: [Point; 4], size: i32, is_dark: impl Fn(i32, i32) -> bool) -> Self {
+        let modules = (0..size)
+            .flat_map(|y| (0..size).map(move |x| (x, y)))
+            .map(|(x, y)| is_dark(x, y))
+            .collect();
+
+        Self {
+            corners,
+            size,
+            modules,
+        }
+    }
+
+    /// Returns the corners of the symbol in image coordinates.
+    pub fn corners(&self) -> [Point; 4] {
+        self.corners
+    }
+
+    /// Returns the number of modules along each side.
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    /// Returns whether the module at the given coordinates is dark, false if out of bounds.
+    pub fn get_module(&self, x: i32, y: i32) -> bool {
+        (0..self.size).contains(&x)
+            && (0..self.size).contains(&y)
+            && self.modules[(y * self.size + x) as usize]
+    }
+
+    pub(crate) fn map_corners(self, f: impl Fn(i32, i32) -> (i32, i32)) -> Self {
+        Self {
+            corners: self.corners.map(|corner| {
+                let (x, y) = f(corner.x, corner.y);
+                Point { x, y }
+            }),
+            ..self
+        }
+    }
+}
+
+/// Runs several detectors on the same image and combines their results.
+///
+/// Grids that an earlier detector already sampled identically are dropped. Differing
+/// grids of the same symbol are kept, the extractor reports the one that decodes.
+#[derive(Debug, Clone, Default)]
+pub struct DetectorChain {
+    detectors: Vec<Arc<dyn Detector + Send + Sync>>,
+}
+
+impl DetectorChain {
+    /// Append a detector, detectors run in the order they were added.
+    pub fn with_detector(mut self, detector: impl Detector + Send + Sync + 'static) -> Self {
+        self.detectors.push(Arc::new(detector));
+        self
+    }
+}
+
+impl Detector for DetectorChain {
+    fn detect(
+        &self,
+        width: usize,
+        height: usize,
+        image: &[u8],
+    ) -> Vec<Result<SampledGrid, SymbolError>> {
+        let mut results: Vec<Result<SampledGrid, SymbolError>> = Vec::new();
+
+        for detector in self.detectors.iter() {
+            for result in detector.detect(width, height, image) {
+                let duplicate = results.iter().any(|existing| match (existing, &result) {
+                    (Ok(a), Ok(b)) => {
+                        a.size == b.size
+                            && a.modules == b.modules
+                            && overlap(&a.corners, &b.corners)
+                    }
+                    _ => false,
+                });
+
+                if !duplicate {
+                    results.push(result);
+                }
+            }
+        }
+
+        results
+    }
+}
@@ -3,23 +3,181 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use quircs::Quirc;
-
 use crate::format::Payload;
 
-pub struct Extractor;
+mod detector;
+mod preprocess;
+mod quirc;
+mod report;
+#[cfg(feature = "rqrr")]
+mod rqrr;
+mod stream;
+
+#[cfg(all(test, feature = "generate"))]
+mod corpus;
+
+pub use detector::{Detector, DetectorChain, SampledGrid};
+pub use preprocess::Preprocessor;
+pub use quirc::QuircDetector;
+pub use report::{DetectedSymbol, ExtractionReport, Point, SymbolError, SymbolStatus};
+#[cfg(feature = "rqrr")]
+pub use rqrr::RqrrDetector;
+pub use stream::{FrameScanner, FrameUpdate};
+
+#[derive(Debug, Clone)]
+pub struct Extractor<D = QuircDetector> {
+    preprocessor: Option<Preprocessor>,
+    detector: D,
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Self {
+            preprocessor: None,
+            detector: QuircDetector,
+        }
+    }
+}
 
 impl Extractor {
+    /// Extracts the payloads from the symbols in a grayscale image with one byte per pixel,
+    /// without preprocessing and with the default detector.
+    ///
+    /// This is the same as `Extractor::default().extract_payloads(width, height, image)`.
     pub fn extract(width: usize, height: usize, image: impl AsRef<[u8]>) -> Vec<Payload> {
-        let mut decoder = Quirc::new();
+        Self::default().extract_payloads(width, height, image)
+    }
+}
 
-        let codes = decoder.identify(width, height, image.as_ref());
+impl<D> Extractor<D> {
+    /// Preprocess images in which not all symbols could be decoded and retry.
+    ///
+    /// Without a preprocessor, the image is only scanned as is.
+    pub fn with_preprocessor(self, preprocessor: Option<Preprocessor>) -> Self {
+        Self {
+            preprocessor,
+            ..self
+        }
+    }
 
-        codes
+    /// Locate symbols with another detector, [`QuircDetector`] by default.
+    pub fn with_detector<E: Detector>(self, detector: E) -> Extractor<E> {
+        Extractor {
+            preprocessor: self.preprocessor,
+            detector,
+        }
+    }
+}
+
+impl<D: Detector> Extractor<D> {
+    /// Extracts the payloads from the symbols in a grayscale image with one byte per pixel.
+    ///
+    /// Symbols that cannot be decoded are skipped, see [`Extractor::extract_report`] to find them.
+    pub fn extract_payloads(
+        &self,
+        width: usize,
+        height: usize,
+        image: impl AsRef<[u8]>,
+    ) -> Vec<Payload> {
+        self.extract_report(width, height, image).into_payloads()
+    }
+
+    /// Scans a grayscale image with one byte per pixel and reports every detected
+    /// symbol with its position and either its payload or why it could not be decoded.
+    pub fn extract_report(
+        &self,
+        width: usize,
+        height: usize,
+        image: impl AsRef<[u8]>,
+    ) -> ExtractionReport {
+        let image = image.as_ref();
+
+        let Some(preprocessor) = &self.preprocessor else {
+            let mut report = ExtractionReport::default();
+            for symbol in self.scan(width, height, image, |x, y| (x, y)) {
+                report.merge(symbol);
+            }
+            return report;
+        };
+
+        let mut report = ExtractionReport::default();
+        preprocessor.run(width, height, image, |image| {
+            let symbols = self.scan(image.width, image.height, &image.pixels, |x, y| {
+                image.to_original(x, y)
+            });
+            let complete = !symbols.is_empty()
+                && symbols
+                    .iter()
+                    .all(|symbol| !matches!(symbol.status, SymbolStatus::Failed { .. }));
+
+            // Earlier stages may have decoded symbols that later ones miss
+            for symbol in symbols {
+                report.merge(symbol);
+            }
+
+            complete
+        });
+
+        report
+    }
+
+    // Returns the symbols found in the image, with their corners mapped by the given function.
+    fn scan(
+        &self,
+        width: usize,
+        height: usize,
+        image: &[u8],
+        to_original: impl Fn(i32, i32) -> (i32, i32),
+    ) -> Vec<DetectedSymbol> {
+        self.detector
+            .detect(width, height, image)
             .into_iter()
-            .filter_map(|code| code.ok())
-            .filter_map(|code| code.decode().ok())
-            .filter_map(|code| serde_json::from_slice::<Payload>(&code.payload).ok())
+            .map(|grid| match grid {
+                Ok(grid) => {
+                    let grid = grid.map_corners(&to_original);
+                    DetectedSymbol {
+                        corners: Some(grid.corners()),
+                        version: u8::try_from((grid.size() - 17) / 4).ok(),
+                        status: match decode(&grid) {
+                            Ok(payload) => SymbolStatus::Decoded { payload },
+                            Err(error) => SymbolStatus::Failed { error },
+                        },
+                    }
+                }
+                Err(error) => DetectedSymbol {
+                    corners: None,
+                    version: None,
+                    status: SymbolStatus::Failed { error },
+                },
+            })
             .collect()
     }
 }
+
+// Decodes the modules with the decoder of quirc, whichever detector sampled them.
+//
+// Quirc stops at the segment modes it does not know, such as the structured append
+// header that the generator puts after the JSON of a part.
+fn decode(grid: &SampledGrid) -> Result<Payload, SymbolError> {
+    let size = grid.size();
+    if size <= 0 {
+        return Err(SymbolError::Sampling);
+    }
+
+    let code = quircs::Code {
+        corners: grid.corners().map(|corner| quircs::Point {
+            x: corner.x,
+            y: corner.y,
+        }),
+        size,
+        cell_bitmap: std::array::from_fn(|byte| {
+            (0..8)
+                .map(|bit| byte as i32 * 8 + bit)
+                .filter(|&p| grid.get_module(p % size, p / size))
+                .fold(0, |bits, p| bits | 1 << (p & 7))
+        }),
+    };
+
+    let data = code.decode()?;
+    serde_json::from_slice(&data.payload).map_err(|_| SymbolError::InvalidPayload)
+}
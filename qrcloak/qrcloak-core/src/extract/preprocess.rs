@@ -0,0 +1,438 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+/// Image preprocessing that the [`Extractor`](super::Extractor) falls back to
+/// when the plain image does not yield all symbols.
+///
+/// The stages are tried one after the other, each on the result of the previous one,
+/// until the symbols found in an image all decode: the downscaled image, the image with
+/// normalized contrast, the image after adaptive thresholding, its inversion and finally
+/// the thresholded image rotated by each of the configured angles.
+#[derive(Debug, Clone)]
+pub struct Preprocessor {
+    max_size: Option<usize>,
+    normalize_contrast: bool,
+    adaptive_threshold: bool,
+    detect_inverted: bool,
+    rotations: Vec<f32>,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self {
+            max_size: Some(1600),
+            normalize_contrast: true,
+            adaptive_threshold: true,
+            detect_inverted: true,
+            rotations: vec![45.0, 22.5, 67.5],
+        }
+    }
+}
+
+impl Preprocessor {
+    /// Downscale images whose width or height exceeds the given number of pixels.
+    ///
+    /// Photos are often much larger than needed, which makes detection slow and
+    /// lets sensor noise break up the modules.
+    pub fn with_max_size(self, max_size: Option<usize>) -> Self {
+        Self { max_size, ..self }
+    }
+
+    /// Stretch the brightness so that the darkest pixels become black and the brightest white.
+    pub fn with_contrast_normalization(self, normalize_contrast: bool) -> Self {
+        Self {
+            normalize_contrast,
+            ..self
+        }
+    }
+
+    /// Binarize every pixel against the mean of its neighborhood, which
+    /// evens out shadows, uneven lighting, blur and noise.
+    pub fn with_adaptive_threshold(self, adaptive_threshold: bool) -> Self {
+        Self {
+            adaptive_threshold,
+            ..self
+        }
+    }
+
+    /// Also look for light symbols on a dark background.
+    pub fn with_inverted_detection(self, detect_inverted: bool) -> Self {
+        Self {
+            detect_inverted,
+            ..self
+        }
+    }
+
+    /// Retry with the image rotated by each of the given angles in degrees.
+    ///
+    /// Skewed symbols with blurry or distorted edges are often only found at a different angle.
+    pub fn with_rotations(self, rotations: impl IntoIterator<Item = f32>) -> Self {
+        Self {
+            rotations: rotations.into_iter().collect(),
+            ..self
+        }
+    }
+
+    // Passes the image of every stage to the given function until it returns true.
+    pub(crate) fn run(
+        &self,
+        width: usize,
+        height: usize,
+        pixels: &[u8],
+        mut detect: impl FnMut(&GrayImage) -> bool,
+    ) {
+        let mut image = GrayImage {
+            width,
+            height,
+            pixels: pixels.to_vec(),
+            factor: 1,
+            rotation: None,
+        };
+
+        if let Some(max_size) = self.max_size {
+            image = image.downscale(max_size);
+        }
+        if detect(&image) {
+            return;
+        }
+
+        if self.normalize_contrast {
+            image = image.normalize_contrast();
+            if detect(&image) {
+                return;
+            }
+        }
+
+        if self.adaptive_threshold {
+            image = image.adaptive_threshold();
+            if detect(&image) {
+                return;
+            }
+        }
+
+        if self.detect_inverted && detect(&image.invert()) {
+            return;
+        }
+
+        for &angle in &self.rotations {
+            let rotated = image.rotate(angle);
+            if detect(&rotated) {
+                return;
+            }
+            if self.detect_inverted && detect(&rotated.invert()) {
+                return;
+            }
+        }
+    }
+}
+
+const DENOISE_RADIUS: usize = 1;
+const THRESHOLD_PERCENT: u64 = 95;
+
+// An 8-bit grayscale image with its pixels stored row by row.
+#[derive(Debug, Clone)]
+pub(crate) struct GrayImage {
+    pub(crate) width: usize,
+    pub(crate) height: usize,
+    pub(crate) pixels: Vec<u8>,
+
+    // The factor by which the original image was downscaled
+    factor: usize,
+
+    // The rotation applied after downscaling
+    rotation: Option<Rotation>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rotation {
+    sin: f32,
+    cos: f32,
+
+    // The centers before and after the rotation
+    center: (f32, f32),
+    rotated_center: (f32, f32),
+}
+
+impl GrayImage {
+    // Maps a position in this image to the corresponding position in the original image.
+    pub(crate) fn to_original(&self, x: i32, y: i32) -> (i32, i32) {
+        let (mut x, mut y) = (x as f32, y as f32);
+        if let Some(Rotation {
+            sin,
+            cos,
+            center: (cx, cy),
+            rotated_center: (ox, oy),
+        }) = self.rotation
+        {
+            // The same mapping as when sampling the rotated image, between pixel centers
+            let (dx, dy) = (x + 0.5 - ox, y + 0.5 - oy);
+            (x, y) = (
+                dx * cos + dy * sin + cx - 0.5,
+                -dx * sin + dy * cos + cy - 0.5,
+            );
+        }
+
+        let factor = self.factor as f32;
+        (
+            ((x + 0.5) * factor).round() as i32,
+            ((y + 0.5) * factor).round() as i32,
+        )
+    }
+
+    // Shrinks the image by the smallest integer factor that makes it fit the given size,
+    // averaging the pixels of each block.
+    fn downscale(self, max_size: usize) -> Self {
+        let factor = self.width.max(self.height).div_ceil(max_size.max(1));
+        if factor <= 1 {
+            return self;
+        }
+
+        let (width, height) = (self.width / factor, self.height / factor);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0usize;
+                for yy in y * factor..(y + 1) * factor {
+                    let row = &self.pixels[yy * self.width..][..self.width];
+                    sum += row[x * factor..(x + 1) * factor]
+                        .iter()
+                        .map(|&p| usize::from(p))
+                        .sum::<usize>();
+                }
+                pixels.push((sum / (factor * factor)) as u8);
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            factor: self.factor * factor,
+            ..self
+        }
+    }
+
+    // Maps the 1st percentile of the brightness to black and the 99th to white.
+    fn normalize_contrast(self) -> Self {
+        let mut histogram = [0usize; 256];
+        for &p in &self.pixels {
+            histogram[usize::from(p)] += 1;
+        }
+
+        let cutoff = self.pixels.len() / 100;
+        let percentile = |levels: &mut dyn Iterator<Item = usize>| {
+            let mut count = 0;
+            for level in levels {
+                count += histogram[level];
+                if count > cutoff {
+                    return level;
+                }
+            }
+            0
+        };
+        let low = percentile(&mut (0..256));
+        let high = percentile(&mut (0..256).rev());
+        if high <= low {
+            return self;
+        }
+
+        let pixels = self
+            .pixels
+            .iter()
+            .map(|&p| ((usize::from(p).clamp(low, high) - low) * 255 / (high - low)) as u8)
+            .collect();
+
+        Self { pixels, ..self }
+    }
+
+    // Makes every pixel black that is darker than the mean of the surrounding window,
+    // whose size is an eighth of the image, and every other pixel white.
+    fn adaptive_threshold(self) -> Self {
+        let (width, height) = (self.width, self.height);
+        let radius = (width.max(height) / 16).max(7);
+
+        // Sums of all pixels above and to the left, with an extra row and column of zeros
+        let mut integral = vec![0u64; (width + 1) * (height + 1)];
+        for y in 0..height {
+            let mut row = 0u64;
+            for x in 0..width {
+                row += u64::from(self.pixels[y * width + x]);
+                integral[(y + 1) * (width + 1) + x + 1] = integral[y * (width + 1) + x + 1] + row;
+            }
+        }
+
+        // The sum and number of the pixels within the radius around a pixel
+        let window = |x: usize, y: usize, radius: usize| {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(width));
+            let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(height));
+            let sum = integral[y1 * (width + 1) + x1] + integral[y0 * (width + 1) + x0]
+                - integral[y0 * (width + 1) + x1]
+                - integral[y1 * (width + 1) + x0];
+            (sum, ((x1 - x0) * (y1 - y0)) as u64)
+        };
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // The pixel is averaged with its direct neighbors against noise
+                let (sum, count) = window(x, y, DENOISE_RADIUS);
+                let (mean_sum, mean_count) = window(x, y, radius);
+
+                // A small margin keeps flat areas from turning into noise
+                let dark = sum * mean_count * 100 < mean_sum * count * THRESHOLD_PERCENT;
+                pixels.push(if dark { 0 } else { 255 });
+            }
+        }
+
+        Self { pixels, ..self }
+    }
+
+    fn invert(&self) -> Self {
+        Self {
+            pixels: self.pixels.iter().map(|&p| 255 - p).collect(),
+            ..*self
+        }
+    }
+
+    // Rotates the image around its center by the given angle in degrees. The result is
+    // large enough to hold the whole image, the uncovered corners are white.
+    fn rotate(&self, degrees: f32) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (w, h) = (self.width as f32, self.height as f32);
+        // The tolerance keeps rounding errors from adding a pixel at right angles
+        let width = (w * cos.abs() + h * sin.abs() - 1e-3).ceil() as usize;
+        let height = (w * sin.abs() + h * cos.abs() - 1e-3).ceil() as usize;
+
+        let (cx, cy) = (w / 2.0, h / 2.0);
+        let (ox, oy) = (width as f32 / 2.0, height as f32 / 2.0);
+
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                // Sample the source pixel that lands here, bilinearly interpolated
+                let (dx, dy) = (x as f32 + 0.5 - ox, y as f32 + 0.5 - oy);
+                let sx = dx * cos + dy * sin + cx - 0.5;
+                let sy = -dx * sin + dy * cos + cy - 0.5;
+                pixels.push(self.sample(sx, sy));
+            }
+        }
+
+        Self {
+            width,
+            height,
+            pixels,
+            factor: self.factor,
+            rotation: Some(Rotation {
+                sin,
+                cos,
+                center: (cx, cy),
+                rotated_center: (ox, oy),
+            }),
+        }
+    }
+
+    // Returns the bilinearly interpolated brightness at the given position, white outside.
+    fn sample(&self, x: f32, y: f32) -> u8 {
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let pixel = |x: f32, y: f32| {
+            if x < 0.0 || y < 0.0 || x >= self.width as f32 || y >= self.height as f32 {
+                255.0
+            } else {
+                f32::from(self.pixels[y as usize * self.width + x as usize])
+            }
+        };
+
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+        let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+        (top * (1.0 - fy) + bottom * fy).round() as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: usize, height: usize, pixels: impl Fn(usize, usize) -> u8) -> GrayImage {
+        GrayImage {
+            width,
+            height,
+            pixels: (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| pixels(x, y))
+                .collect(),
+            factor: 1,
+            rotation: None,
+        }
+    }
+
+    #[test]
+    fn test_downscale() {
+        let result = image(10, 6, |x, _| if x < 4 { 0 } else { 200 }).downscale(5);
+
+        assert_eq!((result.width, result.height), (5, 3));
+        assert_eq!(&result.pixels[..5], &[0, 0, 200, 200, 200]);
+
+        let result = image(10, 6, |_, _| 0).downscale(10);
+        assert_eq!((result.width, result.height), (10, 6));
+    }
+
+    #[test]
+    fn test_normalize_contrast() {
+        let result = image(10, 10, |x, _| 100 + x as u8 * 10).normalize_contrast();
+
+        assert_eq!(result.pixels[0], 0);
+        assert_eq!(result.pixels[9], 255);
+    }
+
+    #[test]
+    fn test_adaptive_threshold_uneven_lighting() {
+        // A dark square on a background that gets brighter from left to right,
+        // where the square on the right is brighter than the background on the left
+        let result = image(64, 64, |x, y| {
+            let light = 80 + x as u8 * 2;
+            let square = (8..16).contains(&y) && (x % 16) < 8;
+            if square {
+                light / 2
+            } else {
+                light
+            }
+        })
+        .adaptive_threshold();
+
+        assert_eq!(result.pixels[10 * 64 + 2], 0);
+        assert_eq!(result.pixels[10 * 64 + 50], 0);
+        assert_eq!(result.pixels[10 * 64 + 60], 255);
+        assert_eq!(result.pixels[40 * 64 + 30], 255);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let source = image(20, 10, |x, _| if x < 10 { 0 } else { 255 });
+
+        let result = source.rotate(90.0);
+        assert_eq!((result.width, result.height), (10, 20));
+        // The dark left half ends up at the top
+        assert_eq!(result.pixels[2 * 10 + 5], 0);
+        assert_eq!(result.pixels[17 * 10 + 5], 255);
+
+        let result = source.rotate(45.0);
+        assert_eq!((result.width, result.height), (22, 22));
+        assert_eq!(result.pixels[0], 255);
+    }
+
+    #[test]
+    fn test_to_original() {
+        let source = image(40, 20, |_, _| 255);
+
+        let downscaled = source.clone().downscale(20);
+        assert_eq!(downscaled.to_original(5, 5), (11, 11));
+
+        // The top left corner turns to the top right
+        let rotated = downscaled.rotate(90.0);
+        assert_eq!(rotated.to_original(9, 0), (1, 1));
+        assert_eq!(rotated.to_original(0, 19), (39, 19));
+    }
+}
@@ -0,0 +1,38 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use quircs::Quirc;
+
+use super::{Detector, Point, SampledGrid, SymbolError};
+
+/// Locates symbols with quirc, which follows the timing patterns to size the grid
+/// and corrects for perspective with the alignment pattern. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct QuircDetector;
+
+impl Detector for QuircDetector {
+    fn detect(
+        &self,
+        width: usize,
+        height: usize,
+        image: &[u8],
+    ) -> Vec<Result<SampledGrid, SymbolError>> {
+        Quirc::new()
+            .identify(width, height, image)
+            .map(|code| {
+                let code = code?;
+                let corners = code.corners.map(|corner| Point {
+                    x: corner.x,
+                    y: corner.y,
+                });
+
+                Ok(SampledGrid::new(corners, code.size, |x, y| {
+                    let p = (y * code.size + x) as usize;
+                    code.cell_bitmap[p >> 3] & (1 << (p & 7)) != 0
+                }))
+            })
+            .collect()
+    }
+}
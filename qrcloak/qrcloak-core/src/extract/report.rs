@@ -0,0 +1,186 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::format::Payload;
+
+/// Everything that was detected in an image, including the symbols that could not be read.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractionReport {
+    /// The detected symbols, in the order they were found.
+    pub symbols: Vec<DetectedSymbol>,
+}
+
+impl ExtractionReport {
+    /// Returns the payloads of all decoded symbols.
+    pub fn payloads(&self) -> impl Iterator<Item = &Payload> {
+        self.symbols
+            .iter()
+            .filter_map(|symbol| match &symbol.status {
+                SymbolStatus::Decoded { payload } => Some(payload),
+                SymbolStatus::Failed { .. } => None,
+            })
+    }
+
+    /// Returns the symbols that were detected but could not be decoded.
+    pub fn failures(&self) -> impl Iterator<Item = &DetectedSymbol> {
+        self.symbols
+            .iter()
+            .filter(|symbol| matches!(symbol.status, SymbolStatus::Failed { .. }))
+    }
+
+    // Adds a symbol found in another pass over the same image, unless it was found before.
+    // A symbol that is decoded now replaces the failed entry at the same position.
+    pub(crate) fn merge(&mut self, symbol: DetectedSymbol) {
+        if let SymbolStatus::Decoded { payload } = &symbol.status {
+            if self.payloads().any(|found| found == payload) {
+                return;
+            }
+        }
+
+        let existing =
+            self.symbols
+                .iter_mut()
+                .find(|existing| match (&existing.corners, &symbol.corners) {
+                    (Some(a), Some(b)) => overlap(a, b),
+                    (None, None) => existing.status == symbol.status,
+                    _ => false,
+                });
+
+        match existing {
+            Some(existing) => {
+                if matches!(existing.status, SymbolStatus::Failed { .. })
+                    && matches!(symbol.status, SymbolStatus::Decoded { .. })
+                {
+                    *existing = symbol;
+                }
+            }
+            None => self.symbols.push(symbol),
+        }
+    }
+
+    /// Consumes the report and returns the payloads of all decoded symbols.
+    pub fn into_payloads(self) -> Vec<Payload> {
+        self.symbols
+            .into_iter()
+            .filter_map(|symbol| match symbol.status {
+                SymbolStatus::Decoded { payload } => Some(payload),
+                SymbolStatus::Failed { .. } => None,
+            })
+            .collect()
+    }
+}
+
+/// A symbol found in an image.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectedSymbol {
+    /// The corners of the symbol in image coordinates, clockwise from the top left corner
+    /// of the symbol itself, which may be anywhere if the symbol is rotated.
+    ///
+    /// Missing if the symbol reaches past the edge of the image.
+    pub corners: Option<[Point; 4]>,
+
+    /// The QR Code version, derived from the number of modules.
+    ///
+    /// Missing if the symbol reaches past the edge of the image.
+    pub version: Option<u8>,
+
+    /// Whether the symbol could be decoded, with its payload or the reason it could not.
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub status: SymbolStatus,
+}
+
+impl DetectedSymbol {
+    /// Returns the center of the symbol in image coordinates.
+    pub fn center(&self) -> Option<Point> {
+        self.corners.as_ref().map(center)
+    }
+}
+
+// Returns whether the centers are less than half a diagonal of the second symbol apart.
+pub(crate) fn overlap(a: &[Point; 4], b: &[Point; 4]) -> bool {
+    let (ca, cb) = (center(a), center(b));
+    let (dx, dy) = (i64::from(ca.x - cb.x), i64::from(ca.y - cb.y));
+    let (sx, sy) = (i64::from(b[0].x - b[2].x), i64::from(b[0].y - b[2].y));
+    (dx * dx + dy * dy) * 4 < sx * sx + sy * sy
+}
+
+fn center(corners: &[Point; 4]) -> Point {
+    Point {
+        x: corners.iter().map(|p| p.x).sum::<i32>() / 4,
+        y: corners.iter().map(|p| p.y).sum::<i32>() / 4,
+    }
+}
+
+/// A pixel position in an image.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// The result of decoding a detected symbol.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "status", rename_all = "snake_case"))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolStatus {
+    /// The symbol holds a qrcloak payload.
+    Decoded { payload: Payload },
+
+    /// The symbol could not be read, rescanning it may help.
+    Failed { error: SymbolError },
+}
+
+/// The reason a detected symbol could not be decoded.
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum SymbolError {
+    #[error("the symbol could not be sampled from the image")]
+    Sampling,
+
+    #[error("the version information is damaged")]
+    Version,
+
+    #[error("the format information is damaged")]
+    FormatEcc,
+
+    #[error("too many modules are damaged to correct the data")]
+    DataEcc,
+
+    #[error("the symbol data is malformed")]
+    Data,
+
+    #[error("the symbol does not contain a qrcloak payload")]
+    InvalidPayload,
+}
+
+impl From<quircs::ExtractError> for SymbolError {
+    fn from(_: quircs::ExtractError) -> Self {
+        SymbolError::Sampling
+    }
+}
+
+impl From<quircs::DecodeError> for SymbolError {
+    fn from(error: quircs::DecodeError) -> Self {
+        use quircs::DecodeError;
+
+        match error {
+            DecodeError::InvalidGridSize => SymbolError::Sampling,
+            DecodeError::InvalidVersion => SymbolError::Version,
+            DecodeError::FormatEcc => SymbolError::FormatEcc,
+            DecodeError::DataEcc => SymbolError::DataEcc,
+            DecodeError::UnkownDataType
+            | DecodeError::DataOverflow
+            | DecodeError::DataUnderflow => SymbolError::Data,
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use rqrr::{BitGrid, PreparedImage};
+
+use super::{Detector, Point, SampledGrid, SymbolError};
+
+/// Locates symbols with rqrr, a separate Rust port of quirc with its own thresholding
+/// and grid fitting, so that chained after [`QuircDetector`](super::QuircDetector) it
+/// gets another chance at the symbols quirc samples wrongly.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RqrrDetector;
+
+impl Detector for RqrrDetector {
+    fn detect(
+        &self,
+        width: usize,
+        height: usize,
+        image: &[u8],
+    ) -> Vec<Result<SampledGrid, SymbolError>> {
+        let mut prepared = PreparedImage::prepare_from_greyscale(width, height, |x, y| {
+            image.get(y * width + x).copied().unwrap_or(u8::MAX)
+        });
+
+        prepared
+            .detect_grids()
+            .into_iter()
+            .map(|grid| {
+                let corners = grid.bounds.map(|corner| Point {
+                    x: corner.x,
+                    y: corner.y,
+                });
+
+                Ok(SampledGrid::new(
+                    corners,
+                    grid.grid.size() as i32,
+                    |x, y| grid.grid.bit(y as usize, x as usize),
+                ))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,218 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use crate::{
+    format::{CompletePayload, Payload},
+    payload::{PayloadMerger, UnmergedPayloads},
+};
+
+use super::{Detector, Extractor, QuircDetector};
+
+/// Scans a sequence of frames, like a video of animated symbols or a webcam feed, and
+/// assembles the payloads whose parts are spread across frames.
+#[derive(Debug, Clone)]
+pub struct FrameScanner<D = QuircDetector> {
+    extractor: Extractor<D>,
+    unmerged: UnmergedPayloads,
+    previous: Vec<Payload>,
+    completed: Vec<CompletePayload>,
+}
+
+impl Default for FrameScanner {
+    fn default() -> Self {
+        Self {
+            extractor: Extractor::default(),
+            unmerged: UnmergedPayloads::default(),
+            previous: Vec::new(),
+            completed: Vec::new(),
+        }
+    }
+}
+
+/// What a single frame contributed.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameUpdate {
+    /// The payloads that were not already seen in the previous frame.
+    pub payloads: Vec<Payload>,
+
+    /// The payloads that became complete with this frame, each reported only once.
+    pub complete: Vec<CompletePayload>,
+}
+
+impl<D> FrameScanner<D> {
+    /// Scan frames with the given extractor instead of the default one.
+    pub fn with_extractor<E>(self, extractor: Extractor<E>) -> FrameScanner<E> {
+        FrameScanner {
+            extractor,
+            unmerged: self.unmerged,
+            previous: self.previous,
+            completed: self.completed,
+        }
+    }
+
+    /// Returns the parts of the payloads that are still incomplete.
+    pub fn incomplete(&self) -> &UnmergedPayloads {
+        &self.unmerged
+    }
+
+    /// Returns every payload that was completed so far.
+    pub fn completed(&self) -> &[CompletePayload] {
+        &self.completed
+    }
+}
+
+impl<D: Detector> FrameScanner<D> {
+    /// Scans a grayscale frame with one byte per pixel and merges its payloads with those of
+    /// earlier frames.
+    ///
+    /// Symbols that stay in view over consecutive frames are only merged once.
+    pub fn push_frame(
+        &mut self,
+        width: usize,
+        height: usize,
+        image: impl AsRef<[u8]>,
+    ) -> FrameUpdate {
+        let payloads = self.extractor.extract_payloads(width, height, image);
+
+        let new: Vec<Payload> = payloads
+            .iter()
+            .filter(|payload| !self.previous.contains(payload))
+            .cloned()
+            .collect();
+        self.previous = payloads;
+
+        if new.is_empty() {
+            return FrameUpdate::default();
+        }
+
+        let result = PayloadMerger::default()
+            .with_unmerged(std::mem::take(&mut self.unmerged))
+            .merge(new.clone());
+        self.unmerged = result.incomplete;
+
+        // Looping animations show the parts of a payload again after it was completed
+        let mut complete = Vec::new();
+        for payload in result.complete {
+            if !self.completed.contains(&payload) && !complete.contains(&payload) {
+                complete.push(payload);
+            }
+        }
+        self.completed.extend(complete.iter().cloned());
+
+        FrameUpdate {
+            payloads: new,
+            complete,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "generate"))]
+mod tests {
+    use image::GrayImage;
+
+    use crate::{
+        extract::Extractor,
+        format::Payload,
+        generate::Generator,
+        payload::{PayloadGenerator, PayloadSplitter},
+    };
+
+    use super::*;
+
+    fn frames(text: &str, splits: u32) -> Vec<GrayImage> {
+        frames_with(Generator::default(), text, splits)
+    }
+
+    fn frames_with(generator: Generator, text: &str, splits: u32) -> Vec<GrayImage> {
+        let payload = PayloadGenerator::default()
+            .generate(text.as_bytes().to_vec().into())
+            .expect("should build");
+
+        let payloads = PayloadSplitter::default()
+            .with_splits(splits)
+            .split(payload)
+            .map(Payload::from);
+
+        generator
+            .generate(payloads)
+            .expect("should generate")
+            .into_iter()
+            .map(|image| {
+                image::imageops::resize(
+                    &image,
+                    image.width() * 3,
+                    image.height() * 3,
+                    image::imageops::FilterType::Nearest,
+                )
+            })
+            .collect()
+    }
+
+    fn push(scanner: &mut FrameScanner, frame: &GrayImage) -> FrameUpdate {
+        scanner.push_frame(frame.width() as usize, frame.height() as usize, &**frame)
+    }
+
+    #[test]
+    fn test_frame_sequence() {
+        let parts = frames("hello world", 3);
+        let blank = GrayImage::from_pixel(100, 100, image::Luma([255]));
+
+        let mut scanner = FrameScanner::default().with_extractor(Extractor::default());
+
+        assert_eq!(push(&mut scanner, &parts[0]).payloads.len(), 1);
+        // The same symbol in the next frame is nothing new
+        assert_eq!(push(&mut scanner, &parts[0]), FrameUpdate::default());
+        assert_eq!(push(&mut scanner, &parts[1]).payloads.len(), 1);
+        assert_eq!(push(&mut scanner, &blank), FrameUpdate::default());
+        assert!(push(&mut scanner, &parts[1]).complete.is_empty());
+        assert_eq!(scanner.incomplete().partials().len(), 1);
+
+        let update = push(&mut scanner, &parts[2]);
+        assert_eq!(update.complete.len(), 1);
+        assert_eq!(&*update.complete[0].data, b"hello world");
+        assert!(scanner.incomplete().partials().is_empty());
+
+        // The animation loops, the payload is only reported once
+        for part in parts.iter() {
+            assert!(push(&mut scanner, part).complete.is_empty());
+        }
+        assert_eq!(scanner.completed().len(), 1);
+    }
+
+    #[test]
+    fn test_interleaved_payloads() {
+        let first = frames("first", 2);
+        let second = frames("second", 2);
+
+        let mut scanner = FrameScanner::default();
+        let mut complete = Vec::new();
+        for frame in [&first[0], &second[1], &second[0], &first[1]] {
+            complete.extend(push(&mut scanner, frame).complete);
+        }
+
+        assert_eq!(complete.len(), 2);
+        assert_eq!(&*complete[0].data, b"second");
+        assert_eq!(&*complete[1].data, b"first");
+    }
+
+    #[test]
+    fn test_structured_append_across_frames() {
+        let parts = frames_with(
+            Generator::default().with_structured_append(true),
+            "hello world",
+            3,
+        );
+
+        // Every symbol is a part of its own, so the progress shows before the last one
+        let mut scanner = FrameScanner::default();
+        assert_eq!(push(&mut scanner, &parts[0]).payloads.len(), 1);
+        assert_eq!(push(&mut scanner, &parts[2]).payloads.len(), 1);
+        assert_eq!(scanner.incomplete().partials().len(), 1);
+
+        let update = push(&mut scanner, &parts[1]);
+        assert_eq!(update.complete.len(), 1);
+        assert_eq!(&*update.complete[0].data, b"hello world");
+    }
+}
@@ -47,3 +47,10 @@ pub struct CompletePayload {
     )]
     pub(crate) compression: CompressionSpec,
 }
+
+impl CompletePayload {
+    /// Returns the compression the data of the payload was compressed with
+    pub fn compression(&self) -> &CompressionSpec {
+        &self.compression
+    }
+}
@@ -29,9 +29,6 @@ pub enum CompressionSpec {
 
 impl CompressionSpec {
     pub(crate) fn no_compression(&self) -> bool {
-        match self {
-            CompressionSpec::NoCompression => true,
-            _ => false,
-        }
+        matches!(self, CompressionSpec::NoCompression)
     }
 }
@@ -33,9 +33,6 @@ pub enum EncryptionSpec {
 
 impl EncryptionSpec {
     pub(crate) fn no_encryption(&self) -> bool {
-        match self {
-            EncryptionSpec::NoEncryption => true,
-            _ => false,
-        }
+        matches!(self, EncryptionSpec::NoEncryption)
     }
 }
@@ -31,6 +31,21 @@ pub struct Index {
 }
 
 impl Index {
+    /// Returns the id shared by all payloads of the group
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the position in the group, starting at zero
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    /// Returns the number of payloads in the group
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
     /// Checks whether the index for the first
     /// element in the group
     pub fn is_head(&self) -> bool {
@@ -0,0 +1,197 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Write;
+
+use image::{GrayImage, ImageBuffer, Luma};
+
+use super::Symbol;
+
+/// Renders symbols as grayscale images or as SVG documents, with a number of pixels
+/// per module and a light border around the symbol.
+#[derive(Debug, Clone)]
+pub struct ImageRenderer {
+    scale: u32,
+    border: u32,
+}
+
+impl Default for ImageRenderer {
+    fn default() -> Self {
+        Self {
+            scale: 4,
+            border: 1,
+        }
+    }
+}
+
+impl ImageRenderer {
+    /// The number of pixels per module, 4 by default. It must be at least 1.
+    pub fn with_scale(self, scale: u32) -> Self {
+        Self { scale, ..self }
+    }
+
+    /// The width of the light border in modules, 1 by default.
+    ///
+    /// Readers that are strict about the standard need [`Symbol::quiet_zone`] modules.
+    pub fn with_border(self, border: u32) -> Self {
+        Self { border, ..self }
+    }
+
+    /// Renders the symbol as an image.
+    pub fn render(&self, symbol: &Symbol) -> GrayImage {
+        let (width, height) = self.size(symbol);
+        let mut img = ImageBuffer::from_pixel(width * self.scale, height * self.scale, Luma([255]));
+
+        for (x, y) in self.dark_modules(symbol) {
+            for real_y in y * self.scale..(y + 1) * self.scale {
+                for real_x in x * self.scale..(x + 1) * self.scale {
+                    img.put_pixel(real_x, real_y, Luma([0]));
+                }
+            }
+        }
+
+        img
+    }
+
+    /// Renders the symbol as an SVG document, with one unit per module and the scale
+    /// as the size in pixels.
+    pub fn render_svg(&self, symbol: &Symbol) -> String {
+        let (width, height) = self.size(symbol);
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {width} {height}" shape-rendering="crispEdges">"#,
+            width * self.scale,
+            height * self.scale
+        )
+        .unwrap();
+        writeln!(
+            svg,
+            r##"<rect width="{width}" height="{height}" fill="#fff"/>"##
+        )
+        .unwrap();
+
+        // One rectangle per run of dark modules in a row
+        svg.push_str(r#"<path d=""#);
+        let mut run: Option<(u32, u32, u32)> = None;
+        for (x, y) in self.dark_modules(symbol) {
+            match run {
+                Some((start, row, len)) if row == y && start + len == x => {
+                    run = Some((start, row, len + 1))
+                }
+                _ => {
+                    if let Some((start, row, len)) = run {
+                        write!(svg, "M{start} {row}h{len}v1h-{len}z").unwrap();
+                    }
+                    run = Some((x, y, 1));
+                }
+            }
+        }
+        if let Some((start, row, len)) = run {
+            write!(svg, "M{start} {row}h{len}v1h-{len}z").unwrap();
+        }
+        svg.push_str("\"/>\n</svg>\n");
+
+        svg
+    }
+
+    // The size in modules, including the border
+    fn size(&self, symbol: &Symbol) -> (u32, u32) {
+        (
+            symbol.width() as u32 + self.border * 2,
+            symbol.height() as u32 + self.border * 2,
+        )
+    }
+
+    // The positions of the dark modules row by row, including the border
+    fn dark_modules<'a>(&self, symbol: &'a Symbol) -> impl Iterator<Item = (u32, u32)> + 'a {
+        let border = self.border;
+        (0..symbol.height()).flat_map(move |y| {
+            (0..symbol.width())
+                .filter(move |&x| symbol.get_module(x, y))
+                .map(move |x| (x as u32 + border, y as u32 + border))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qrcodegen::{MicroQrCode, QrCode, QrCodeEcc, RmqrCode};
+
+    use super::*;
+
+    // Reads the modules back from the runs of an SVG path
+    fn parse_svg(svg: &str, width: usize, height: usize) -> Vec<Vec<bool>> {
+        let mut modules = vec![vec![false; width]; height];
+
+        let path = svg.split(r#"<path d=""#).nth(1).unwrap();
+        let path = path.split('"').next().unwrap();
+        for run in path.split('M').skip(1) {
+            let (start, rest) = run.split_once(' ').unwrap();
+            let (row, rest) = rest.split_once('h').unwrap();
+            let (len, _) = rest.split_once('v').unwrap();
+            let (start, row, len): (usize, usize, usize) = (
+                start.parse().unwrap(),
+                row.parse().unwrap(),
+                len.parse().unwrap(),
+            );
+            modules[row][start..start + len].fill(true);
+        }
+
+        modules
+    }
+
+    fn assert_rendered(symbol: &Symbol, scale: u32, border: u32) {
+        let renderer = ImageRenderer::default()
+            .with_scale(scale)
+            .with_border(border);
+        let (width, height) = (
+            symbol.width() as u32 + border * 2,
+            symbol.height() as u32 + border * 2,
+        );
+
+        let image = renderer.render(symbol);
+        assert_eq!(image.dimensions(), (width * scale, height * scale));
+
+        let svg = renderer.render_svg(symbol);
+        assert!(svg.starts_with(&format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}""#,
+            width * scale,
+            height * scale
+        )));
+        let modules = parse_svg(&svg, width as usize, height as usize);
+
+        for y in 0..height {
+            for x in 0..width {
+                let dark = symbol.get_module(x as i32 - border as i32, y as i32 - border as i32);
+                let pixel = image.get_pixel(x * scale + scale / 2, y * scale + scale / 2);
+                assert_eq!(pixel[0] == 0, dark, "pixel of module ({x}, {y})");
+                assert_eq!(
+                    modules[y as usize][x as usize], dark,
+                    "svg module ({x}, {y})"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_image() {
+        let qrcode = QrCode::encode_text("hello world", QrCodeEcc::Low).unwrap();
+        let symbol = Symbol::Qr(qrcode);
+        assert_rendered(&symbol, 4, 1);
+        assert_rendered(&symbol, 1, 0);
+        assert_rendered(&symbol, 3, symbol.quiet_zone() as u32);
+
+        // The defaults are those of Symbol::to_image
+        assert_eq!(symbol.to_image(), ImageRenderer::default().render(&symbol));
+
+        let micro = MicroQrCode::encode_text("12345", QrCodeEcc::Low).unwrap();
+        assert_rendered(&Symbol::Micro(micro), 2, 2);
+
+        let rmqr = RmqrCode::encode_text("hello world", QrCodeEcc::Medium).unwrap();
+        assert_rendered(&Symbol::Rectangular(rmqr), 2, 2);
+    }
+}
@@ -3,11 +3,26 @@
 // SPDX-License-Identifier: Apache-2.0
 // SPDX-License-Identifier: MIT
 
-use image::{GrayImage, ImageBuffer, Luma};
-use qrcodegen::{QrCode, Version};
+use std::collections::HashMap;
+
+use ::image::GrayImage;
+use qrcodegen::{
+    DataTooLong, EncodeBuffers, Mask, MicroQrCode, MicroVersion, QrCode, QrSegment, RmqrCode,
+    RmqrVersion, StructuredAppend, Version,
+};
 use thiserror::Error;
 
-use crate::format::Payload;
+use crate::{
+    format::{CompletePayload, Index, Payload},
+    payload::PayloadSplitter,
+};
+
+mod image;
+mod terminal;
+
+pub use self::image::ImageRenderer;
+pub use qrcodegen::QrCodeEcc;
+pub use terminal::TerminalRenderer;
 
 #[derive(Debug, Clone, Copy, Default)]
 pub enum Encoding {
@@ -15,10 +30,83 @@ pub enum Encoding {
     Json,
 }
 
+/// The kind of symbol generated for each payload.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SymbolType {
+    /// A regular QR Code.
+    #[default]
+    Qr,
+    /// A Micro QR Code (M1 to M4), only fits very small payloads and no high error
+    /// correction. Anything larger fails with [`GenerateError::CapacityExceeded`].
+    Micro,
+    /// A rectangular Micro QR Code (rMQR), only medium or high error correction.
+    Rectangular,
+}
+
+/// A generated symbol of any [`SymbolType`].
+#[derive(Clone)]
+pub enum Symbol {
+    Qr(QrCode),
+    Micro(MicroQrCode),
+    Rectangular(RmqrCode),
+}
+
+impl Symbol {
+    /// Returns the number of modules in a row.
+    pub fn width(&self) -> i32 {
+        match self {
+            Symbol::Qr(qrcode) => qrcode.size(),
+            Symbol::Micro(qrcode) => qrcode.size(),
+            Symbol::Rectangular(qrcode) => qrcode.width(),
+        }
+    }
+
+    /// Returns the number of modules in a column.
+    pub fn height(&self) -> i32 {
+        match self {
+            Symbol::Qr(qrcode) => qrcode.size(),
+            Symbol::Micro(qrcode) => qrcode.size(),
+            Symbol::Rectangular(qrcode) => qrcode.height(),
+        }
+    }
+
+    /// Returns whether the module at the given coordinates is dark,
+    /// `false` if the coordinates are out of bounds.
+    pub fn get_module(&self, x: i32, y: i32) -> bool {
+        match self {
+            Symbol::Qr(qrcode) => qrcode.get_module(x, y),
+            Symbol::Micro(qrcode) => qrcode.get_module(x, y),
+            Symbol::Rectangular(qrcode) => qrcode.get_module(x, y),
+        }
+    }
+
+    /// Returns the width of the light border the standard requires around the symbol,
+    /// four modules for QR Codes and two for the Micro QR variants.
+    pub fn quiet_zone(&self) -> i32 {
+        match self {
+            Symbol::Qr(_) => 4,
+            Symbol::Micro(_) | Symbol::Rectangular(_) => 2,
+        }
+    }
+
+    /// Renders the symbol as an image with 4 pixels per module and a border of one
+    /// module, see [`ImageRenderer`] for others.
+    pub fn to_image(&self) -> GrayImage {
+        ImageRenderer::default().render(self)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Generator {
     encoding: Encoding,
     ecl: qrcodegen::QrCodeEcc,
+    structured_append: bool,
+    symbol_type: SymbolType,
+    min_version: Version,
+    max_version: Version,
+    mask: Option<Mask>,
+    boost_ecl: bool,
+    uniform_version: bool,
 }
 
 impl Default for Generator {
@@ -26,44 +114,53 @@ impl Default for Generator {
         Self {
             encoding: Encoding::default(),
             ecl: qrcodegen::QrCodeEcc::High,
+            structured_append: false,
+            symbol_type: SymbolType::default(),
+            min_version: Version::MIN,
+            max_version: Version::MAX,
+            mask: None,
+            boost_ecl: false,
+            uniform_version: false,
         }
     }
 }
 
 #[derive(Debug, Error)]
 pub enum GenerateError {
-    #[error("transparent")]
-    DataTooLong(#[from] qrcodegen::DataTooLong),
+    #[error(transparent)]
+    DataTooLong(qrcodegen::DataTooLong),
 
-    #[error("transparent")]
-    SerializationError(#[from] serde_json::Error),
-}
+    #[error("payload exceeds the symbol capacity by {0} bytes")]
+    CapacityExceeded(usize),
 
-fn qrcode_to_image(qrcode: &QrCode) -> GrayImage {
-    let scale = 4;
+    #[error("invalid version range, minimum {} is above maximum {}", .0.value(), .1.value())]
+    InvalidVersionRange(Version, Version),
 
-    let size = qrcode.size() as u32;
+    #[error(transparent)]
+    SerializationError(#[from] serde_json::Error),
 
-    let mut img = ImageBuffer::new(size * scale + scale * 2, size * scale + scale * 2);
+    #[error("structured append supports at most {max} symbols, got {0}", max = StructuredAppend::MAX_SYMBOLS)]
+    TooManySymbols(u32),
 
-    img.fill(255);
+    #[error("invalid index {} of {} in payload group {}", .0.index(), .0.size(), .0.id())]
+    InvalidIndex(Index),
 
-    for y in 0..size {
-        for x in 0..size {
-            if qrcode.get_module(x as i32, y as i32) {
-                let scaled_y_with_offset = y * scale + scale;
-                let scaled_x_with_offset = x * scale + scale;
+    #[error("structured append needs every part of payload group {0}, got {1}")]
+    IncompleteGroup(u32, usize),
 
-                for real_y in scaled_y_with_offset..scaled_y_with_offset + scale {
-                    for real_x in scaled_x_with_offset..scaled_x_with_offset + scale {
-                        img.put_pixel(real_x, real_y, Luma([0]))
-                    }
-                }
+    #[error("{0} is only supported for regular QR Codes")]
+    UnsupportedOption(&'static str),
+}
+
+impl From<DataTooLong> for GenerateError {
+    fn from(value: DataTooLong) -> Self {
+        match value {
+            DataTooLong::DataOverCapacity(datalen, maxcapacity) => {
+                GenerateError::CapacityExceeded((datalen - maxcapacity).div_ceil(8))
             }
+            value => GenerateError::DataTooLong(value),
         }
     }
-
-    img.into()
 }
 
 impl Generator {
@@ -75,43 +172,368 @@ impl Generator {
         Self { ecl, ..self }
     }
 
+    /// Encode partial payloads as a QR Structured Append sequence.
+    ///
+    /// Each symbol holds the JSON of its part, which keeps the group id and index, and
+    /// the standard header with the index as position, the number of parts and the
+    /// parity of the JSON of all parts. Every part of a group has to be generated in
+    /// the same call.
+    ///
+    /// The header follows the JSON instead of preceding it, as quirc stops reading at
+    /// the segment modes it does not know, while readers such as ZXing take the header
+    /// from anywhere in the symbol.
+    pub fn with_structured_append(self, structured_append: bool) -> Self {
+        Self {
+            structured_append,
+            ..self
+        }
+    }
+
+    /// Generate Micro QR or rectangular Micro QR symbols instead of regular QR Codes.
+    ///
+    /// Each symbol uses the smallest version that fits its payload. Structured append
+    /// only exists for regular QR Codes and is ignored for the other symbol types,
+    /// while the version, mask, boost and uniform version options make generating
+    /// fail with [`GenerateError::UnsupportedOption`].
+    /// The extractor can only read regular QR Codes.
+    pub fn with_symbol_type(self, symbol_type: SymbolType) -> Self {
+        Self {
+            symbol_type,
+            ..self
+        }
+    }
+
+    /// Use exactly the given version for every QR Code.
+    ///
+    /// This is the same as setting both the minimum and the maximum version.
+    pub fn with_version(self, version: Version) -> Self {
+        Self {
+            min_version: version,
+            max_version: version,
+            ..self
+        }
+    }
+
+    /// Use at least the given version for every QR Code.
+    pub fn with_min_version(self, min_version: Version) -> Self {
+        Self {
+            min_version,
+            ..self
+        }
+    }
+
+    /// Use at most the given version for every QR Code.
+    ///
+    /// Payloads that do not fit fail with [`GenerateError::CapacityExceeded`].
+    pub fn with_max_version(self, max_version: Version) -> Self {
+        Self {
+            max_version,
+            ..self
+        }
+    }
+
+    /// Use the given mask for every QR Code instead of choosing the best one per symbol.
+    pub fn with_mask(self, mask: Option<Mask>) -> Self {
+        Self { mask, ..self }
+    }
+
+    /// Raise the error correction level of each QR Code as long as the
+    /// payload still fits into the version that was chosen for it.
+    pub fn with_boost_ecl(self, boost_ecl: bool) -> Self {
+        Self { boost_ecl, ..self }
+    }
+
+    /// Encode all QR Codes of one call with the same version, the one needed
+    /// by the largest payload, so that the symbols of a sheet look alike.
+    ///
+    /// Without it, each symbol gets the smallest version that fits its payload,
+    /// but never a smaller one than the symbol before it.
+    pub fn with_uniform_version(self, uniform_version: bool) -> Self {
+        Self {
+            uniform_version,
+            ..self
+        }
+    }
+
     pub fn generate(
         &self,
         payload: impl IntoIterator<Item = impl Into<Payload>>,
     ) -> Result<Vec<GrayImage>, GenerateError> {
+        self.generate_with(payload, Symbol::to_image)
+    }
+
+    /// Generates the symbols for the payloads and passes each one to the given
+    /// function, such as a renderer, collecting its results.
+    pub fn generate_with<T>(
+        &self,
+        payload: impl IntoIterator<Item = impl Into<Payload>>,
+        render: impl FnMut(&Symbol) -> T,
+    ) -> Result<Vec<T>, GenerateError> {
         let iter = payload.into_iter().map(Into::into);
 
-        self.generate_many(iter)
+        self.generate_many(iter, render)
     }
 
-    fn generate_many(
+    /// Splits the payload into as few parts as needed for each of them to fit into
+    /// a symbol with the settings of the generator, and generates their symbols.
+    ///
+    /// A payload that fits into a single symbol is not split.
+    pub fn generate_split(
         &self,
-        payloads: impl Iterator<Item = Payload>,
+        payload: CompletePayload,
     ) -> Result<Vec<GrayImage>, GenerateError> {
-        match self.encoding {
-            Encoding::Json => {
-                let mut minversion = Version::MIN;
-
-                let mut result = Vec::with_capacity(payloads.size_hint().0);
-
-                for payload in payloads {
-                    let json = serde_json::to_string(&payload)?;
-                    let qrcode = qrcodegen::QrCode::encode_text_optimally_advanced(
-                        &json,
-                        self.ecl,
-                        minversion,
-                        Version::MAX,
-                        None,
-                        false,
-                    )?;
-                    let image = qrcode_to_image(&qrcode);
-                    result.push(image);
-
-                    minversion = qrcode.version()
+        self.generate_split_with(payload, Symbol::to_image)
+    }
+
+    /// Like [`Generator::generate_split`], but passes each symbol to the given function.
+    ///
+    /// The function is only called for the symbols of the parts that fit.
+    pub fn generate_split_with<T>(
+        &self,
+        payload: CompletePayload,
+        render: impl FnMut(&Symbol) -> T,
+    ) -> Result<Vec<T>, GenerateError> {
+        let len = payload.data.len().max(1);
+
+        let attempt = |splits: usize| {
+            let payloads: Vec<Payload> = if splits == 1 {
+                vec![Payload::Complete(payload.clone())]
+            } else {
+                PayloadSplitter::default()
+                    .with_splits(splits as u32)
+                    .split(payload.clone())
+                    .map(Payload::from)
+                    .collect()
+            };
+
+            self.generate_many(payloads.into_iter(), Symbol::clone)
+        };
+
+        // A part that is too long for any version fails before its capacity is known
+        let too_long = |error: &GenerateError| {
+            matches!(
+                error,
+                GenerateError::CapacityExceeded(_)
+                    | GenerateError::DataTooLong(DataTooLong::SegmentTooLong)
+            )
+        };
+
+        // Every byte of the data takes more than a byte of its symbol, so fewer
+        // parts than this cannot fit
+        let mut fail = len.div_ceil(self.symbol_capacity()).clamp(1, len) - 1;
+
+        // Double the parts until they fit, with at least one byte of the data each
+        let mut fit = fail + 1;
+        let mut symbols = loop {
+            match attempt(fit) {
+                Err(error) if too_long(&error) && fit < len => {
+                    fail = fit;
+                    fit = (fit * 2).min(len);
+                }
+                result => break result?,
+            }
+        };
+
+        // Then look for the fewest parts that fit in between
+        while fit - fail > 1 {
+            let splits = fail + (fit - fail) / 2;
+            match attempt(splits) {
+                Ok(fitting) => {
+                    fit = splits;
+                    symbols = fitting;
+                }
+                Err(error) if too_long(&error) => fail = splits,
+                Err(error) => return Err(error),
+            }
+        }
+
+        Ok(symbols.iter().map(render).collect())
+    }
+
+    // Returns the most bytes that a single symbol holds with the settings of the generator.
+    fn symbol_capacity(&self) -> usize {
+        match self.symbol_type {
+            SymbolType::Qr => QrCode::data_capacity(self.max_version, self.ecl),
+            SymbolType::Micro => MicroQrCode::data_capacity(MicroVersion::MAX, self.ecl).max(1),
+            SymbolType::Rectangular => RmqrVersion::all()
+                .map(|version| RmqrCode::data_capacity(version, self.ecl))
+                .max()
+                .unwrap_or(1),
+        }
+    }
+
+    fn generate_many<T>(
+        &self,
+        payloads: impl Iterator<Item = Payload>,
+        mut render: impl FnMut(&Symbol) -> T,
+    ) -> Result<Vec<T>, GenerateError> {
+        match (self.encoding, self.symbol_type) {
+            (Encoding::Json, SymbolType::Micro | SymbolType::Rectangular) => {
+                if let Some(option) = self.qr_only_option() {
+                    return Err(GenerateError::UnsupportedOption(option));
+                }
+
+                payloads
+                    .map(|payload| {
+                        let json = serde_json::to_string(&payload)?;
+                        let symbol = match self.symbol_type {
+                            SymbolType::Micro => {
+                                Symbol::Micro(MicroQrCode::encode_text(&json, self.ecl)?)
+                            }
+                            _ => Symbol::Rectangular(RmqrCode::encode_text(&json, self.ecl)?),
+                        };
+                        Ok(render(&symbol))
+                    })
+                    .collect()
+            }
+            (Encoding::Json, SymbolType::Qr) => {
+                if self.min_version > self.max_version {
+                    return Err(GenerateError::InvalidVersionRange(
+                        self.min_version,
+                        self.max_version,
+                    ));
+                }
+
+                let contents = self.symbol_contents(payloads)?;
+
+                let mut result = Vec::with_capacity(contents.len());
+
+                // The symbols share their encoding memory
+                let mut buffers = EncodeBuffers::new();
+
+                let mut minversion = self.min_version;
+                if self.uniform_version {
+                    // A first pass finds the version of the largest payload,
+                    // which then fits all the others as well
+                    for (header, text) in contents.iter() {
+                        let qrcode = self.encode_qrcode(*header, text, minversion, &mut buffers)?;
+                        minversion = qrcode.version();
+                        buffers.recycle(qrcode);
+                    }
+                }
+
+                for (header, text) in contents.iter() {
+                    let qrcode = self.encode_qrcode(*header, text, minversion, &mut buffers)?;
+                    minversion = qrcode.version();
+
+                    let symbol = Symbol::Qr(qrcode);
+                    result.push(render(&symbol));
+
+                    if let Symbol::Qr(qrcode) = symbol {
+                        buffers.recycle(qrcode);
+                    }
                 }
 
                 Ok(result)
             }
         }
     }
+
+    // Returns the first option that is set although only QR Codes support it.
+    fn qr_only_option(&self) -> Option<&'static str> {
+        if self.min_version != Version::MIN || self.max_version != Version::MAX {
+            Some("a version")
+        } else if self.mask.is_some() {
+            Some("a mask")
+        } else if self.boost_ecl {
+            Some("boosting the error correction level")
+        } else if self.uniform_version {
+            Some("a uniform version")
+        } else {
+            None
+        }
+    }
+
+    // Encodes the text as a QR Code of at least the given version, followed by the
+    // structured append header if the text is a part of such a sequence.
+    fn encode_qrcode(
+        &self,
+        header: Option<StructuredAppend>,
+        text: &str,
+        minversion: Version,
+        buffers: &mut EncodeBuffers,
+    ) -> Result<QrCode, GenerateError> {
+        let mut segs =
+            QrSegment::make_segments_optimally(text, self.ecl, minversion, self.max_version)?;
+        if let Some(header) = header {
+            segs.push(QrSegment::make_structured_append(header));
+        }
+
+        Ok(QrCode::encode_segments_buffered(
+            &segs,
+            self.ecl,
+            minversion,
+            self.max_version,
+            self.mask,
+            self.boost_ecl,
+            buffers,
+        )?)
+    }
+
+    // Returns the JSON of each payload, together with its structured append header
+    // if the payload is a part of such a sequence.
+    fn symbol_contents(
+        &self,
+        payloads: impl Iterator<Item = Payload>,
+    ) -> Result<Vec<(Option<StructuredAppend>, String)>, GenerateError> {
+        let contents = payloads
+            .map(|payload| {
+                let index = match &payload {
+                    Payload::Partial(partial) if self.structured_append => {
+                        if partial.is_misconfigured() {
+                            return Err(GenerateError::InvalidIndex(partial.index()));
+                        }
+                        Some(partial.index())
+                    }
+                    _ => None,
+                };
+                Ok((index, serde_json::to_string(&payload)?))
+            })
+            .collect::<Result<Vec<(Option<Index>, String)>, GenerateError>>()?;
+
+        // The JSON of the parts of each group, by their index
+        let mut groups: HashMap<u32, Vec<Option<&str>>> = HashMap::new();
+        for (index, json) in contents.iter() {
+            let Some(index) = index else {
+                continue;
+            };
+
+            if index.size > u32::from(StructuredAppend::MAX_SYMBOLS) {
+                return Err(GenerateError::TooManySymbols(index.size));
+            }
+
+            let parts = groups
+                .entry(index.id)
+                .or_insert_with(|| vec![None; index.size as usize]);
+            if parts.len() != index.size as usize {
+                return Err(GenerateError::InvalidIndex(*index));
+            }
+            match parts.get_mut(index.index as usize) {
+                Some(part) if part.is_none() => *part = Some(json),
+                _ => return Err(GenerateError::InvalidIndex(*index)),
+            }
+        }
+
+        let mut parities: HashMap<u32, u8> = HashMap::new();
+        for (id, parts) in groups {
+            let found = parts.iter().flatten().count();
+            if found != parts.len() {
+                return Err(GenerateError::IncompleteGroup(id, found));
+            }
+
+            let parity =
+                StructuredAppend::parity_of(parts.iter().flatten().map(|json| json.as_bytes()));
+            parities.insert(id, parity);
+        }
+
+        Ok(contents
+            .into_iter()
+            .map(|(index, json)| {
+                let header = index.map(|index| {
+                    StructuredAppend::new(index.index as u8, index.size as u8, parities[&index.id])
+                });
+                (header, json)
+            })
+            .collect())
+    }
 }
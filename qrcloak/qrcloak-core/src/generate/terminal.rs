@@ -0,0 +1,167 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::fmt::Write;
+
+use super::Symbol;
+
+const UPPER_HALF: char = '\u{2580}';
+const LOWER_HALF: char = '\u{2584}';
+const FULL_BLOCK: char = '\u{2588}';
+
+// ANSI foreground and background codes for black and bright white
+const DARK: (u8, u8) = (30, 40);
+const LIGHT: (u8, u8) = (97, 107);
+
+/// Renders symbols as text for terminals, two module rows per line using
+/// Unicode half-block characters, surrounded by the standard quiet zone.
+#[derive(Debug, Clone)]
+pub struct TerminalRenderer {
+    colors: bool,
+    inverted: bool,
+}
+
+impl Default for TerminalRenderer {
+    fn default() -> Self {
+        Self {
+            colors: true,
+            inverted: false,
+        }
+    }
+}
+
+impl TerminalRenderer {
+    /// Paint every module black or white with ANSI escape codes, so that the
+    /// symbol looks the same regardless of the terminal colors. This is the default.
+    ///
+    /// Without colors, blocks are drawn in the foreground color of the terminal.
+    pub fn with_colors(self, colors: bool) -> Self {
+        Self { colors, ..self }
+    }
+
+    /// Swap the colors of dark and light modules.
+    ///
+    /// Needed without colors on terminals with light text on a dark background,
+    /// where the blocks would otherwise turn the dark modules light.
+    pub fn with_inverted(self, inverted: bool) -> Self {
+        Self { inverted, ..self }
+    }
+
+    /// Renders the symbol, each line terminated by a newline.
+    pub fn render(&self, symbol: &Symbol) -> String {
+        let quiet_zone = symbol.quiet_zone();
+        let (width, height) = (
+            symbol.width() + quiet_zone * 2,
+            symbol.height() + quiet_zone * 2,
+        );
+
+        // Out of bounds modules are light, which covers the quiet zone
+        // and the padding row below a symbol of odd height
+        let is_dark = |x: i32, y: i32| {
+            let dark = symbol.get_module(x - quiet_zone, y - quiet_zone);
+            dark != self.inverted
+        };
+
+        let mut result = String::new();
+        for y in (0..height).step_by(2) {
+            let mut current = None;
+            for x in 0..width {
+                let (top, bottom) = (is_dark(x, y), is_dark(x, y + 1));
+
+                if self.colors {
+                    let color = |dark| if dark { DARK } else { LIGHT };
+                    let (fg, bg) = (color(top).0, color(bottom).1);
+                    if current != Some((fg, bg)) {
+                        write!(result, "\x1b[{fg};{bg}m").unwrap();
+                        current = Some((fg, bg));
+                    }
+                    result.push(UPPER_HALF);
+                } else {
+                    result.push(match (top, bottom) {
+                        (true, true) => FULL_BLOCK,
+                        (true, false) => UPPER_HALF,
+                        (false, true) => LOWER_HALF,
+                        (false, false) => ' ',
+                    });
+                }
+            }
+            if self.colors {
+                result.push_str("\x1b[0m");
+            }
+            result.push('\n');
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use qrcodegen::{QrCode, QrCodeEcc, RmqrCode};
+
+    use super::*;
+
+    // Reads the modules back from a rendering without colors
+    fn parse(text: &str) -> Vec<Vec<bool>> {
+        let mut rows = Vec::new();
+        for line in text.lines() {
+            let (top, bottom) = line
+                .chars()
+                .map(|c| match c {
+                    FULL_BLOCK => (true, true),
+                    UPPER_HALF => (true, false),
+                    LOWER_HALF => (false, true),
+                    _ => (false, false),
+                })
+                .unzip();
+            rows.push(top);
+            rows.push(bottom);
+        }
+        rows
+    }
+
+    fn assert_rendered(symbol: &Symbol, inverted: bool) {
+        let text = TerminalRenderer::default()
+            .with_colors(false)
+            .with_inverted(inverted)
+            .render(symbol);
+        let rows = parse(&text);
+
+        let quiet_zone = symbol.quiet_zone();
+        assert_eq!(rows[0].len() as i32, symbol.width() + quiet_zone * 2);
+        assert!(rows.len() as i32 >= symbol.height() + quiet_zone * 2);
+
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &dark) in row.iter().enumerate() {
+                let module = symbol.get_module(x as i32 - quiet_zone, y as i32 - quiet_zone);
+                assert_eq!(dark, module != inverted, "module at ({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn test_render_plain() {
+        let qrcode = QrCode::encode_text("hello world", QrCodeEcc::Low).unwrap();
+        assert_rendered(&Symbol::Qr(qrcode), false);
+
+        let rmqr = RmqrCode::encode_text("hello world", QrCodeEcc::Medium).unwrap();
+        assert_rendered(&Symbol::Rectangular(rmqr.clone()), false);
+        assert_rendered(&Symbol::Rectangular(rmqr), true);
+    }
+
+    #[test]
+    fn test_render_colors() {
+        let qrcode = QrCode::encode_text("hello world", QrCodeEcc::Low).unwrap();
+        let text = TerminalRenderer::default().render(&Symbol::Qr(qrcode));
+
+        // 21 modules and 4 on each side, an odd height padded by one row
+        assert_eq!(text.lines().count(), 15);
+        for line in text.lines() {
+            assert!(line.starts_with("\x1b[97;107m"));
+            assert!(line.ends_with("\x1b[0m"));
+            assert_eq!(line.chars().filter(|&c| c == UPPER_HALF).count(), 29);
+        }
+    }
+}
@@ -14,6 +14,9 @@ pub mod generate;
 #[cfg(feature = "extract")]
 pub mod extract;
 
+#[cfg(feature = "config")]
+pub mod config;
+
 #[cfg(feature = "uniffi")]
 uniffi::setup_scaffolding!();
 
@@ -22,22 +25,32 @@ pub use age::{secrecy, x25519};
 #[cfg(all(test, feature = "extract", feature = "generate"))]
 mod tests {
     use image::GenericImage;
+    use qrcodegen::{Mask, Version};
 
     use crate::{
-        extract::Extractor,
-        generate::Generator,
+        extract::{Extractor, SymbolError, SymbolStatus},
+        format::{PartialPayload, Payload},
+        generate::{GenerateError, Generator, SymbolType},
         payload::{PayloadGenerator, PayloadMerger, PayloadSplitter},
     };
 
-    #[test]
-    fn test_simple() {
+    fn roundtrip(generator: Generator, splits: u32) -> Vec<Payload> {
         let payload = PayloadGenerator::default()
             .generate("hello world".into())
             .expect("should build");
 
-        let payloads = PayloadSplitter::default().with_splits(4).split(payload);
+        let payloads = PayloadSplitter::default()
+            .with_splits(splits)
+            .split(payload);
 
-        let images = Generator::default()
+        roundtrip_payloads(generator, payloads)
+    }
+
+    fn roundtrip_payloads(
+        generator: Generator,
+        payloads: impl IntoIterator<Item = PartialPayload>,
+    ) -> Vec<Payload> {
+        let images = generator
             .generate(payloads)
             .expect("should generate")
             .into_iter()
@@ -66,11 +79,16 @@ mod tests {
             pos += image.width() + spacing;
         }
 
-        let payloads = Extractor::extract(
+        Extractor::extract(
             total_image.width() as usize,
             total_image.height() as usize,
             &*total_image,
-        );
+        )
+    }
+
+    #[test]
+    fn test_simple() {
+        let payloads = roundtrip(Generator::default(), 4);
 
         let complete = PayloadMerger::default().merge(payloads).complete;
 
@@ -78,4 +96,341 @@ mod tests {
 
         assert_eq!(&*complete[0].data, b"hello world");
     }
+
+    #[test]
+    fn test_structured_append() {
+        let payloads = roundtrip(Generator::default().with_structured_append(true), 4);
+
+        // Every symbol holds a part of its own
+        assert_eq!(payloads.len(), 4);
+
+        let complete = PayloadMerger::default().merge(payloads).complete;
+        assert_eq!(complete.len(), 1);
+        assert_eq!(&*complete[0].data, b"hello world");
+    }
+
+    #[test]
+    fn test_structured_append_positions() {
+        let payload = PayloadGenerator::default()
+            .generate("hello world".into())
+            .expect("should build");
+
+        let payloads = Generator::default()
+            .with_structured_append(true)
+            .generate_with(
+                PayloadSplitter::default().with_splits(3).split(payload),
+                |symbol| {
+                    let size = symbol.width();
+                    let code = quircs::Code {
+                        corners: Default::default(),
+                        size,
+                        cell_bitmap: std::array::from_fn(|byte| {
+                            (0..8)
+                                .map(|bit| byte as i32 * 8 + bit)
+                                .filter(|&p| symbol.get_module(p % size, p / size))
+                                .fold(0, |bits, p| bits | 1 << (p & 7))
+                        }),
+                    };
+                    let data = code.decode().expect("should decode");
+                    serde_json::from_slice::<PartialPayload>(&data.payload).expect("should parse")
+                },
+            )
+            .expect("should generate");
+
+        // The header follows the data, so readers that skip it still see the parts in order
+        for (position, payload) in payloads.iter().enumerate() {
+            let index = payload.index();
+            assert_eq!((index.index(), index.size()), (position as u32, 3));
+        }
+    }
+
+    #[test]
+    fn test_structured_append_sequences() {
+        let payloads = ["hello world", "goodbye world"].map(|text| {
+            PayloadGenerator::default()
+                .generate(text.into())
+                .expect("should build")
+        });
+
+        let mut partials = Vec::new();
+        for payload in payloads {
+            partials.extend(PayloadSplitter::default().with_splits(2).split(payload));
+        }
+
+        // Both sequences are in the same image and joined on their own
+        let payloads =
+            roundtrip_payloads(Generator::default().with_structured_append(true), partials);
+        let mut texts: Vec<_> = PayloadMerger::default()
+            .merge(payloads)
+            .complete
+            .into_iter()
+            .map(|complete| complete.data)
+            .collect();
+        texts.sort();
+        assert_eq!(texts, ["goodbye world", "hello world"]);
+    }
+
+    #[test]
+    fn test_structured_append_invalid_index() {
+        let payload = PayloadGenerator::default()
+            .generate("hello world".into())
+            .expect("should build");
+
+        let generator = Generator::default().with_structured_append(true);
+
+        let mut parts: Vec<PartialPayload> = PayloadSplitter::default()
+            .with_splits(2)
+            .split(payload)
+            .collect();
+        if let PartialPayload::Tail(tail) = &mut parts[1] {
+            tail.index.index = 2;
+        }
+        assert!(matches!(
+            generator.generate(parts.clone()),
+            Err(GenerateError::InvalidIndex(_))
+        ));
+
+        assert!(matches!(
+            generator.generate(parts.into_iter().take(1)),
+            Err(GenerateError::IncompleteGroup(_, 1))
+        ));
+    }
+
+    #[test]
+    fn test_structured_append_too_many_symbols() {
+        let payload = PayloadGenerator::default()
+            .generate("hello world, but split across many symbols".into())
+            .expect("should build");
+
+        let payloads = PayloadSplitter::default().with_splits(17).split(payload);
+
+        let result = Generator::default()
+            .with_structured_append(true)
+            .generate(payloads);
+
+        assert!(matches!(result, Err(GenerateError::TooManySymbols(17))));
+    }
+
+    #[test]
+    fn test_symbol_types() {
+        let payload = PayloadGenerator::default()
+            .generate("hi".into())
+            .expect("should build");
+
+        let images = Generator::default()
+            .with_symbol_type(SymbolType::Rectangular)
+            .generate([payload.clone()])
+            .expect("should generate");
+        assert!(images[0].width() > images[0].height());
+
+        // Not even M4 holds the JSON of a payload
+        let result = Generator::default()
+            .with_symbol_type(SymbolType::Micro)
+            .generate([payload.clone()]);
+        assert!(matches!(result, Err(GenerateError::CapacityExceeded(_))));
+
+        let result = Generator::default()
+            .with_symbol_type(SymbolType::Rectangular)
+            .with_mask(Some(Mask::new(1)))
+            .generate([payload]);
+        assert!(matches!(
+            result,
+            Err(GenerateError::UnsupportedOption("a mask"))
+        ));
+    }
+
+    #[test]
+    fn test_fixed_version_and_mask() {
+        let generator = Generator::default()
+            .with_version(Version::new(10))
+            .with_mask(Some(Mask::new(3)));
+
+        let payload = PayloadGenerator::default()
+            .generate("hi".into())
+            .expect("should build");
+        let images = generator.generate([payload]).expect("should generate");
+        // 57 modules of 4 pixels and a border of one module on each side
+        assert_eq!(images[0].width(), (57 + 2) * 4);
+
+        let payloads = roundtrip(generator, 2);
+        let complete = PayloadMerger::default().merge(payloads).complete;
+        assert_eq!(&*complete[0].data, b"hello world");
+    }
+
+    #[test]
+    fn test_uniform_version() {
+        let payloads = ["hi", &"long text ".repeat(20)].map(|text| {
+            PayloadGenerator::default()
+                .generate(text.as_bytes().to_vec().into())
+                .expect("should build")
+        });
+
+        let images = Generator::default()
+            .generate(payloads.clone())
+            .expect("should generate");
+        assert!(images[0].width() < images[1].width());
+
+        let images = Generator::default()
+            .with_uniform_version(true)
+            .generate(payloads)
+            .expect("should generate");
+        assert_eq!(images[0].width(), images[1].width());
+    }
+
+    #[test]
+    fn test_capacity_exceeded() {
+        let payload = PayloadGenerator::default()
+            .generate("a".repeat(400).into())
+            .expect("should build");
+
+        let generator = Generator::default().with_max_version(Version::new(10));
+        let Err(GenerateError::CapacityExceeded(excess)) = generator.generate([payload.clone()])
+        else {
+            panic!("should exceed the capacity");
+        };
+
+        assert!(excess > 0 && excess < 400);
+
+        // Leaving out that many bytes of data makes the payload fit
+        let payload = PayloadGenerator::default()
+            .generate("a".repeat(400 - excess).into())
+            .expect("should build");
+        generator.generate([payload]).expect("should fit");
+
+        let result = Generator::default()
+            .with_min_version(Version::new(5))
+            .with_max_version(Version::new(4))
+            .generate(Vec::<crate::format::Payload>::new());
+        assert!(matches!(
+            result,
+            Err(GenerateError::InvalidVersionRange(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_generate_split() {
+        let generator = Generator::default().with_max_version(Version::new(10));
+
+        let payload = PayloadGenerator::default()
+            .generate("hi".into())
+            .expect("should build");
+        assert_eq!(
+            generator.generate_split(payload).expect("should fit").len(),
+            1
+        );
+
+        let payload = PayloadGenerator::default()
+            .generate("a".repeat(400).into())
+            .expect("should build");
+        let mut rendered = 0;
+        let images = generator
+            .generate_split_with(payload.clone(), |symbol| {
+                rendered += 1;
+                symbol.to_image()
+            })
+            .expect("should split");
+        assert!(images.len() > 1);
+
+        // Only the parts that fit are rendered
+        assert_eq!(rendered, images.len());
+
+        // Unsplit it does not fit, while the group id of the parts is random and
+        // may make one part less fit or not
+        assert!(matches!(
+            generator.generate([payload]),
+            Err(GenerateError::CapacityExceeded(_))
+        ));
+
+        // Too long for any version, even before the capacity is known
+        let payload = PayloadGenerator::default()
+            .generate(
+                (0..7000)
+                    .map(|i| char::from(b'!' + (i * 37 % 90) as u8))
+                    .collect::<String>()
+                    .into(),
+            )
+            .expect("should build");
+        assert!(matches!(
+            Generator::default().generate([payload.clone()]),
+            Err(GenerateError::DataTooLong(_))
+        ));
+        assert!(
+            Generator::default()
+                .generate_split(payload)
+                .expect("should split")
+                .len()
+                > 1
+        );
+    }
+
+    #[test]
+    fn test_extraction_report() {
+        let payload = PayloadGenerator::default()
+            .generate("hello world".into())
+            .expect("should build");
+        let images = Generator::default()
+            .generate(PayloadSplitter::default().with_splits(2).split(payload))
+            .expect("should generate");
+
+        // A symbol that does not hold a qrcloak payload
+        let foreign = qrcodegen::QrCode::encode_text("hello world", qrcodegen::QrCodeEcc::High)
+            .expect("should encode");
+        let foreign = image::GrayImage::from_fn(132, 132, |x, y| {
+            let dark = foreign.get_module(x as i32 / 4 - 4, y as i32 / 4 - 4);
+            image::Luma([if dark { 0 } else { 255 }])
+        });
+
+        // Side by side with a margin of 20 pixels
+        let mut sheet = image::GrayImage::from_pixel(1000, 400, image::Luma([255]));
+        let mut x = 20;
+        for image in [&images[0], &images[1], &foreign] {
+            sheet.copy_from(image, x, 20).expect("should copy");
+            x += image.width() + 20;
+        }
+
+        // Wipe out most of the data of the second symbol
+        let (left, (width, height)) = (images[0].width() + 40, images[1].dimensions());
+        for y in 20 + height / 2..20 + height - 8 {
+            for x in left + width / 3..left + width - 8 {
+                sheet.put_pixel(x, y, image::Luma([255]));
+            }
+        }
+
+        let report = Extractor::default().extract_report(
+            sheet.width() as usize,
+            sheet.height() as usize,
+            &*sheet,
+        );
+
+        assert_eq!(report.symbols.len(), 3);
+        assert_eq!(report.payloads().count(), 1);
+
+        let mut symbols = report.symbols.clone();
+        symbols.sort_by_key(|symbol| symbol.center().expect("should have corners").x);
+
+        assert!(matches!(symbols[0].status, SymbolStatus::Decoded { .. }));
+        assert!(matches!(
+            symbols[1].status,
+            SymbolStatus::Failed {
+                error: SymbolError::DataEcc | SymbolError::FormatEcc
+            }
+        ));
+        assert_eq!(
+            symbols[2].status,
+            SymbolStatus::Failed {
+                error: SymbolError::InvalidPayload
+            }
+        );
+        assert_eq!(symbols[2].version, Some(2));
+
+        let json = serde_json::to_value(&symbols[2]).expect("should serialize");
+        assert_eq!(json["status"], "failed");
+        assert_eq!(json["error"], "invalid_payload");
+
+        // The corners of the damaged symbol lie within its image
+        for corner in symbols[1].corners.expect("should have corners") {
+            assert!((left as i32..(left + width) as i32).contains(&corner.x));
+            assert!((20..20 + height as i32).contains(&corner.y));
+        }
+    }
 }
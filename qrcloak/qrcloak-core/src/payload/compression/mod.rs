@@ -35,6 +35,15 @@ impl From<&Compression> for CompressionSpec {
     }
 }
 
+impl From<&CompressionSpec> for Decompression {
+    fn from(spec: &CompressionSpec) -> Self {
+        match spec {
+            CompressionSpec::NoCompression => Decompression::NoCompression,
+            CompressionSpec::Gzip => Decompression::Gzip(GzipCompression),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum CompressionError {}
 
@@ -16,16 +16,12 @@ use tsify_next::Tsify;
 #[cfg_attr(feature = "wasm", derive(Tsify, serde::Serialize, serde::Deserialize))]
 #[cfg_attr(feature = "wasm", tsify(into_wasm_abi, from_wasm_abi))]
 #[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+#[derive(Default)]
 pub enum DecodingOpts {
+    #[default]
     Json,
 }
 
-impl Default for DecodingOpts {
-    fn default() -> Self {
-        Self::Json
-    }
-}
-
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub struct Decoder {
     decoding_opts: DecodingOpts,
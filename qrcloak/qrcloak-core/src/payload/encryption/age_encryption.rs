@@ -8,7 +8,10 @@ use std::{
     io::{self, Write},
 };
 
-use age::{secrecy::SecretString, x25519, DecryptError, EncryptError, Identity, Recipient};
+use age::{
+    secrecy::{ExposeSecret, SecretString},
+    x25519, DecryptError, EncryptError, Identity, Recipient,
+};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 
 #[derive(Debug, Clone)]
@@ -22,16 +25,16 @@ impl From<Vec<x25519::Recipient>> for AgeKeyEncryption {
     }
 }
 
-impl Into<Vec<x25519::Recipient>> for AgeKeyEncryption {
-    fn into(self) -> Vec<x25519::Recipient> {
-        self.recipients
+impl From<AgeKeyEncryption> for Vec<x25519::Recipient> {
+    fn from(val: AgeKeyEncryption) -> Self {
+        val.recipients
     }
 }
 
 impl AgeKeyEncryption {
     // TODO one or more
     pub fn new(recipients: Vec<x25519::Recipient>) -> Self {
-        assert!(recipients.len() >= 1);
+        assert!(!recipients.is_empty());
 
         Self { recipients }
     }
@@ -67,9 +70,9 @@ impl From<Vec<x25519::Identity>> for AgeKeyDecryption {
     }
 }
 
-impl Into<Vec<x25519::Identity>> for AgeKeyDecryption {
-    fn into(self) -> Vec<x25519::Identity> {
-        self.identities
+impl From<AgeKeyDecryption> for Vec<x25519::Identity> {
+    fn from(val: AgeKeyDecryption) -> Self {
+        val.identities
     }
 }
 
@@ -112,6 +115,9 @@ impl AgeKeyDecryption {
     }
 }
 
+/// The scrypt work factor age uses for passphrase encryption.
+const DIGEST_LOG_N: u8 = 18;
+
 #[derive(Debug, Clone)]
 pub struct AgePassphrase {
     passphrase: SecretString,
@@ -123,9 +129,9 @@ impl From<SecretString> for AgePassphrase {
     }
 }
 
-impl Into<SecretString> for AgePassphrase {
-    fn into(self) -> SecretString {
-        self.passphrase
+impl From<AgePassphrase> for SecretString {
+    fn from(val: AgePassphrase) -> Self {
+        val.passphrase
     }
 }
 
@@ -161,4 +167,64 @@ impl AgePassphrase {
 
         Ok(writer.into_inner().freeze())
     }
+
+    /// Returns the scrypt hash of the passphrase, salted with the salt.
+    ///
+    /// Stored together with a salt of [`AgePassphrase::generate_salt`], the digest tells
+    /// whether the passphrase changed since. It uses the work factor age applies to
+    /// passphrase encryption, so guessing against the digest costs as much as guessing
+    /// against the encrypted data, but it still allows offline guessing: keep it as
+    /// private as the passphrase-encrypted data.
+    pub fn digest(&self, salt: &str) -> [u8; 32] {
+        self.digest_with(salt, DIGEST_LOG_N)
+    }
+
+    fn digest_with(&self, salt: &str, log_n: u8) -> [u8; 32] {
+        let params = scrypt::Params::new(log_n, 8, 1, 32).expect("parameters should be valid");
+
+        let mut digest = [0; 32];
+        scrypt::scrypt(
+            self.passphrase.expose_secret().as_bytes(),
+            salt.as_bytes(),
+            &params,
+            &mut digest,
+        )
+        .expect("output length should be valid");
+        digest
+    }
+
+    /// Returns a new random salt for [`AgePassphrase::digest`], as hex.
+    pub fn generate_salt() -> String {
+        rand::random::<[u8; 16]>()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_passphrase_digest() {
+        let passphrase = |value: &str| AgePassphrase::new(value.to_string().into());
+        let salt = AgePassphrase::generate_salt();
+
+        assert_eq!(salt.len(), 32);
+        assert_ne!(salt, AgePassphrase::generate_salt());
+
+        assert_eq!(
+            passphrase("one").digest_with(&salt, 10),
+            passphrase("one").digest_with(&salt, 10)
+        );
+        assert_ne!(
+            passphrase("one").digest_with(&salt, 10),
+            passphrase("two").digest_with(&salt, 10)
+        );
+        assert_ne!(
+            passphrase("one").digest_with(&salt, 10),
+            passphrase("one").digest_with(&AgePassphrase::generate_salt(), 10)
+        );
+    }
 }
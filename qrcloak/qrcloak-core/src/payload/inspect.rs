@@ -0,0 +1,254 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use crate::format::{CompressionSpec, EncryptionSpec, Index, PartialPayload, Payload};
+
+const AGE_MAGIC: &[u8] = b"age-encryption.org/v1\n";
+const AGE_COLUMNS: usize = 64;
+
+/// What can be learned about a payload without decrypting it.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadInfo {
+    /// Whether the payload is complete or which part of a group it is.
+    pub kind: PayloadKind,
+
+    /// The index of a partial payload.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub index: Option<Index>,
+
+    /// The encryption, which only complete payloads and heads carry.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub encryption: Option<EncryptionSpec>,
+
+    /// The compression, which only complete payloads and heads carry.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub compression: Option<CompressionSpec>,
+
+    /// The length of the data in this payload.
+    pub data_length: usize,
+
+    /// The header of age encrypted data, if the data of this payload starts with one.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub age: Option<AgeHeaderInfo>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PayloadKind {
+    /// A payload that is not split.
+    Complete,
+    /// The first part of a group of partial payloads.
+    Head,
+    /// One of the following parts of a group of partial payloads.
+    Tail,
+}
+
+/// The public part of an age header.
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AgeHeaderInfo {
+    /// The types of the recipient stanzas with how often each one occurs.
+    pub recipients: Vec<RecipientStanzas>,
+
+    /// The log2 of the scrypt work factor of a passphrase.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub scrypt_work_factor: Option<u8>,
+
+    /// The length of the encrypted data after the header, unless the header
+    /// continues in the next part of the group.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub ciphertext_length: Option<usize>,
+}
+
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecipientStanzas {
+    /// The type of the stanzas, like `X25519` or `scrypt`.
+    #[cfg_attr(feature = "serde", serde(rename = "type"))]
+    pub stanza_type: String,
+
+    /// The number of stanzas of the type.
+    pub count: usize,
+}
+
+impl From<&Payload> for PayloadInfo {
+    fn from(payload: &Payload) -> Self {
+        let (kind, index, encryption, compression, data) = match payload {
+            Payload::Complete(complete) => (
+                PayloadKind::Complete,
+                None,
+                Some(complete.encryption.clone()),
+                Some(complete.compression.clone()),
+                &complete.data,
+            ),
+            Payload::Partial(PartialPayload::Head(head)) => (
+                PayloadKind::Head,
+                Some(head.index),
+                Some(head.encryption.clone()),
+                Some(head.compression.clone()),
+                &head.data,
+            ),
+            Payload::Partial(PartialPayload::Tail(tail)) => {
+                (PayloadKind::Tail, Some(tail.index), None, None, &tail.data)
+            }
+        };
+
+        let age = match encryption {
+            Some(EncryptionSpec::NoEncryption) | None => None,
+            Some(_) => parse_age_header(data),
+        };
+
+        PayloadInfo {
+            kind,
+            index,
+            encryption,
+            compression,
+            data_length: data.len(),
+            age,
+        }
+    }
+}
+
+// Reads the stanzas of a binary age header, up to where the data ends
+fn parse_age_header(data: &[u8]) -> Option<AgeHeaderInfo> {
+    let mut rest = data.strip_prefix(AGE_MAGIC)?;
+    let mut info = AgeHeaderInfo::default();
+
+    while let Some(line) = next_line(&mut rest) {
+        if line.starts_with(b"--- ") {
+            info.ciphertext_length = Some(rest.len());
+            break;
+        }
+
+        let stanza = std::str::from_utf8(line.strip_prefix(b"-> ")?).ok()?;
+        let mut args = stanza.split(' ');
+        let stanza_type = args.next()?;
+
+        if stanza_type == "scrypt" {
+            info.scrypt_work_factor = args.nth(1).and_then(|factor| factor.parse().ok());
+        }
+
+        // age adds a stanza of a random type to keep implementations from
+        // relying on the stanzas they know, which is no recipient
+        if !stanza_type.ends_with("-grease") {
+            match info
+                .recipients
+                .iter_mut()
+                .find(|stanzas| stanzas.stanza_type == stanza_type)
+            {
+                Some(stanzas) => stanzas.count += 1,
+                None => info.recipients.push(RecipientStanzas {
+                    stanza_type: stanza_type.to_string(),
+                    count: 1,
+                }),
+            }
+        }
+
+        // The body is wrapped into full lines and ends with a shorter one
+        while next_line(&mut rest).is_some_and(|body| body.len() == AGE_COLUMNS) {}
+    }
+
+    Some(info)
+}
+
+fn next_line<'a>(rest: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let end = rest.iter().position(|&byte| byte == b'\n')?;
+    let line = &rest[..end];
+    *rest = &rest[end + 1..];
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use age::{secrecy::SecretString, x25519};
+
+    use crate::payload::{
+        AgeKeyEncryption, AgePassphrase, Compression, Encryption, GzipCompression,
+        PayloadGenerator, PayloadSplitter,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_inspect_key_encryption() {
+        let recipients = (0..3)
+            .map(|_| x25519::Identity::generate().to_public())
+            .collect();
+
+        let payload = PayloadGenerator::default()
+            .with_encryption(Encryption::AgeKey(AgeKeyEncryption::new(recipients)))
+            .with_compression(Compression::Gzip(GzipCompression))
+            .generate("hello world".into())
+            .expect("should generate");
+        let length = payload.data.len();
+
+        let info = PayloadInfo::from(&Payload::Complete(payload));
+
+        assert_eq!(info.kind, PayloadKind::Complete);
+        assert_eq!(info.index, None);
+        assert_eq!(info.encryption, Some(EncryptionSpec::AgeKey));
+        assert_eq!(info.compression, Some(CompressionSpec::Gzip));
+        assert_eq!(info.data_length, length);
+
+        let age = info.age.expect("should have an age header");
+        assert_eq!(
+            age.recipients,
+            [RecipientStanzas {
+                stanza_type: "X25519".to_string(),
+                count: 3
+            }]
+        );
+        assert_eq!(age.scrypt_work_factor, None);
+
+        // The nonce, then the encrypted gzip stream with its tag
+        let ciphertext = age.ciphertext_length.expect("should have the full header");
+        assert!(ciphertext > 16 + 16 && ciphertext < length);
+    }
+
+    #[test]
+    fn test_inspect_passphrase_splits() {
+        let payload = PayloadGenerator::default()
+            .with_encryption(Encryption::AgePassphrase(AgePassphrase::new(
+                SecretString::new("passphrase".into()),
+            )))
+            .generate("hello world".into())
+            .expect("should generate");
+
+        let infos: Vec<PayloadInfo> = PayloadSplitter::default()
+            .with_splits(2)
+            .split(payload)
+            .map(|partial| PayloadInfo::from(&Payload::Partial(partial)))
+            .collect();
+
+        assert_eq!(infos[0].kind, PayloadKind::Head);
+        assert_eq!(infos[0].encryption, Some(EncryptionSpec::AgePassphrase));
+        let age = infos[0].age.as_ref().expect("should have an age header");
+        assert_eq!(age.recipients[0].stanza_type, "scrypt");
+        assert!(age.scrypt_work_factor.is_some());
+        // Half of the data does not hold the whole header
+        assert_eq!(age.ciphertext_length, None);
+
+        assert_eq!(infos[1].kind, PayloadKind::Tail);
+        assert_eq!(infos[1].index.map(|index| index.index()), Some(1));
+        assert_eq!(infos[1].encryption, None);
+        assert_eq!(infos[1].age, None);
+    }
+
+    #[test]
+    fn test_inspect_plain() {
+        let payload = PayloadGenerator::default()
+            .generate("age-encryption.org/v1\n".into())
+            .expect("should generate");
+
+        let info = PayloadInfo::from(&Payload::Complete(payload));
+        assert_eq!(info.encryption, Some(EncryptionSpec::NoEncryption));
+        assert_eq!(info.age, None);
+    }
+}
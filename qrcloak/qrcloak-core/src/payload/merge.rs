@@ -119,7 +119,7 @@ impl PayloadMerger {
             let mut capacity = head.data.len();
             let mut tail_data = Vec::with_capacity(head.index.size as usize - 1);
 
-            for maybe_tail in val.into_iter().skip(1) {
+            for maybe_tail in val.iter_mut().skip(1) {
                 if let Some(PartialPayload::Tail(tail)) = maybe_tail {
                     capacity += tail.data.len();
                     tail_data.push(tail.data.clone());
@@ -130,7 +130,7 @@ impl PayloadMerger {
 
             let mut complete = BytesMut::with_capacity(capacity);
             complete.extend_from_slice(&head.data);
-            complete.extend(tail_data.into_iter());
+            complete.extend(tail_data);
 
             self.completes.push(CompletePayload {
                 data: complete.freeze(),
@@ -13,7 +13,9 @@ mod compression;
 mod encryption;
 mod extract;
 mod generate;
+mod inspect;
 mod merge;
+mod rekey;
 mod split;
 mod utils;
 
@@ -31,7 +33,9 @@ pub use encryption::{
 };
 pub use extract::{PayloadExtractionError, PayloadExtractor};
 pub use generate::{PayloadGenerationError, PayloadGenerator};
+pub use inspect::{AgeHeaderInfo, PayloadInfo, PayloadKind, RecipientStanzas};
 pub use merge::{MergeResult, PayloadMerger, UnmergedPayloads};
+pub use rekey::{PayloadRekeyError, PayloadRekeyer};
 pub use split::PayloadSplitter;
 
 pub enum OneOrMany<T> {
@@ -99,9 +103,9 @@ impl<T> FromIterator<T> for OneOrMany<T> {
     }
 }
 
-impl<T> Into<Vec<T>> for OneOrMany<T> {
-    fn into(self) -> Vec<T> {
-        match self {
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(val: OneOrMany<T>) -> Self {
+        match val {
             OneOrMany::Empty => vec![],
             OneOrMany::One(v) => vec![v],
             OneOrMany::Many(v) => v,
@@ -132,6 +136,7 @@ mod tests {
     };
 
     #[derive(Debug, Error)]
+    #[allow(clippy::enum_variant_names)]
     enum RoundtripError {
         #[error(transparent)]
         GenerationError(#[from] PayloadGenerationError),
@@ -336,8 +341,7 @@ mod tests {
             .with_decompression(Some(Decompression::NoCompression))
             .build()
             .test("data".into())
-            .err()
-            .expect("should have failed");
+            .expect_err("should have failed");
 
         match err {
             RoundtripError::ExtractionError(PayloadExtractionError::DecompressionError(
@@ -403,8 +407,7 @@ mod tests {
             .with_decryption(Some(Decryption::AgeKey(decryption)))
             .build()
             .test("hello world".into())
-            .err()
-            .expect("should have failed");
+            .expect_err("should have failed");
 
         match err {
             RoundtripError::ExtractionError(PayloadExtractionError::DecryptionError(
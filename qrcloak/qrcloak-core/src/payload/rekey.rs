@@ -0,0 +1,288 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::collections::{hash_map::Entry, HashMap};
+
+use thiserror::Error;
+
+use crate::format::{CompletePayload, PartialPayload, Payload};
+
+use super::{
+    Decryption, DecryptionError, Encryption, EncryptionError, PayloadMerger, PayloadSplitter,
+};
+
+/// Encrypts payloads again for other recipients, without touching their compression.
+#[derive(Default, Clone)]
+pub struct PayloadRekeyer {
+    decryption: Decryption,
+    encryption: Encryption,
+}
+
+#[derive(Debug, Error)]
+pub enum PayloadRekeyError {
+    #[error(transparent)]
+    DecryptionError(#[from] DecryptionError),
+
+    #[error(transparent)]
+    EncryptionError(#[from] EncryptionError),
+
+    #[error("payload group {id} has only {found} of {size} parts")]
+    Incomplete { id: u32, size: u32, found: usize },
+
+    #[error("invalid index {index} of {size} in payload group {id}")]
+    InvalidIndex { id: u32, size: u32, index: u32 },
+
+    #[error("payload group {id} has {len} bytes, too few to split into {size} parts")]
+    TooShort { id: u32, size: u32, len: usize },
+}
+
+// Where a payload was given, so that the rekeyed payload takes its place
+enum Slot {
+    Complete(CompletePayload),
+    Part { id: u32, size: u32, index: u32 },
+}
+
+impl PayloadRekeyer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_decryption(mut self, decryption: Decryption) -> Self {
+        self.decryption = decryption;
+        self
+    }
+
+    pub fn with_encryption(mut self, encryption: Encryption) -> Self {
+        self.encryption = encryption;
+        self
+    }
+
+    pub fn rekey(
+        &self,
+        mut payload: CompletePayload,
+    ) -> Result<CompletePayload, PayloadRekeyError> {
+        self.decryption.process(&mut payload)?;
+
+        Ok(CompletePayload {
+            data: self.encryption.process(payload.data)?,
+            encryption: self.encryption.spec(),
+            compression: payload.compression,
+        })
+    }
+
+    /// Rekeys complete payloads and groups of parts, where each group must be complete
+    /// and is split again into as many parts. The new parts get a new group id, so that
+    /// they cannot be mixed up with the old ones.
+    ///
+    /// Each rekeyed payload takes the place of the one it replaces, so a part is replaced
+    /// by the new part with the same index.
+    pub fn rekey_all(
+        &self,
+        payloads: impl IntoIterator<Item = impl Into<Payload>>,
+    ) -> Result<Vec<Payload>, PayloadRekeyError> {
+        let mut slots = Vec::new();
+        let mut parts: HashMap<(u32, u32), Vec<PartialPayload>> = HashMap::new();
+
+        for payload in payloads {
+            match payload.into() {
+                Payload::Complete(payload) => slots.push(Slot::Complete(payload)),
+                Payload::Partial(part) => {
+                    let index = part.index();
+                    if index.index() >= index.size() {
+                        return Err(PayloadRekeyError::InvalidIndex {
+                            id: index.id(),
+                            size: index.size(),
+                            index: index.index(),
+                        });
+                    }
+
+                    slots.push(Slot::Part {
+                        id: index.id(),
+                        size: index.size(),
+                        index: index.index(),
+                    });
+                    parts
+                        .entry((index.id(), index.size()))
+                        .or_default()
+                        .push(part);
+                }
+            }
+        }
+
+        let mut rekeyed_parts: HashMap<(u32, u32), Vec<PartialPayload>> = HashMap::new();
+        let mut rekeyed = Vec::with_capacity(slots.len());
+        for slot in slots {
+            match slot {
+                Slot::Complete(payload) => rekeyed.push(Payload::Complete(self.rekey(payload)?)),
+                Slot::Part { id, size, index } => {
+                    if let Entry::Vacant(entry) = rekeyed_parts.entry((id, size)) {
+                        let parts = parts.remove(&(id, size)).unwrap_or_default();
+                        let found = parts.len();
+
+                        let mut merged = PayloadMerger::default().merge(parts).complete;
+                        let (Some(payload), true) = (merged.pop(), merged.is_empty()) else {
+                            return Err(PayloadRekeyError::Incomplete { id, size, found });
+                        };
+
+                        let payload = self.rekey(payload)?;
+                        if payload.data.len() < size as usize {
+                            let len = payload.data.len();
+                            return Err(PayloadRekeyError::TooShort { id, size, len });
+                        }
+
+                        let splitter = PayloadSplitter::default().with_splits(size);
+                        entry.insert(splitter.split(payload).collect());
+                    }
+
+                    let part = rekeyed_parts[&(id, size)][index as usize].clone();
+                    rekeyed.push(Payload::Partial(part));
+                }
+            }
+        }
+
+        Ok(rekeyed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use age::x25519;
+
+    use crate::payload::{
+        AgeKeyDecryption, AgeKeyEncryption, Compression, Decompression, GzipCompression,
+        PayloadExtractor, PayloadGenerator,
+    };
+
+    use super::*;
+
+    fn keys(identity: &x25519::Identity) -> Encryption {
+        Encryption::AgeKey(AgeKeyEncryption::new(vec![identity.to_public()]))
+    }
+
+    fn extract(identity: &x25519::Identity, payloads: Vec<Payload>) -> Vec<Vec<u8>> {
+        let extractor = PayloadExtractor::default()
+            .with_decryption(Decryption::AgeKey(AgeKeyDecryption::new(vec![
+                identity.clone()
+            ])))
+            .with_decompression(Decompression::Gzip(GzipCompression));
+
+        PayloadMerger::default()
+            .merge(payloads)
+            .complete
+            .into_iter()
+            .map(|payload| extractor.extract(payload).expect("should extract").to_vec())
+            .collect()
+    }
+
+    #[test]
+    fn test_rekey_all() {
+        let old = x25519::Identity::generate();
+        let new = x25519::Identity::generate();
+
+        let generator = PayloadGenerator::default()
+            .with_encryption(keys(&old))
+            .with_compression(Compression::Gzip(GzipCompression));
+
+        let complete = generator.generate("hello".into()).unwrap();
+        let parts: Vec<Payload> = PayloadSplitter::default()
+            .with_splits(3)
+            .split(generator.generate("world".into()).unwrap())
+            .map(Payload::Partial)
+            .collect();
+        let old_id = parts[0].clone();
+
+        let rekeyer = PayloadRekeyer::default()
+            .with_decryption(Decryption::AgeKey(AgeKeyDecryption::new(vec![old.clone()])))
+            .with_encryption(keys(&new));
+
+        // The parts are given out of order, after the complete payload, and keep their places
+        let payloads = [
+            Payload::Complete(complete),
+            parts[2].clone(),
+            parts[0].clone(),
+            parts[1].clone(),
+        ];
+        let rekeyed = rekeyer.rekey_all(payloads).expect("should rekey");
+
+        assert_eq!(rekeyed.len(), 4);
+        assert!(matches!(rekeyed[0], Payload::Complete(_)));
+
+        let sizes: Vec<_> = rekeyed[1..]
+            .iter()
+            .map(|payload| match payload {
+                Payload::Partial(part) => (part.index().index(), part.index().size()),
+                Payload::Complete(_) => panic!("should be partial"),
+            })
+            .collect();
+        assert_eq!(sizes, [(2, 3), (0, 3), (1, 3)]);
+
+        let Payload::Partial(old_part) = old_id else {
+            unreachable!()
+        };
+        let Payload::Partial(new_part) = &rekeyed[1] else {
+            unreachable!()
+        };
+        assert_ne!(old_part.index().id(), new_part.index().id());
+
+        let mut data = extract(&new, rekeyed.clone());
+        data.sort();
+        assert_eq!(data, [b"hello".to_vec(), b"world".to_vec()]);
+
+        let error = rekeyer.rekey_all(rekeyed).unwrap_err();
+        assert!(matches!(error, PayloadRekeyError::DecryptionError(_)));
+
+        let error = rekeyer.rekey_all(parts[..2].to_vec()).unwrap_err();
+        assert!(matches!(
+            error,
+            PayloadRekeyError::Incomplete {
+                size: 3,
+                found: 2,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_rekey_all_invalid_groups() {
+        let split = |data: &'static str| -> Vec<PartialPayload> {
+            PayloadSplitter::default()
+                .with_splits(3)
+                .split(PayloadGenerator::default().generate(data.into()).unwrap())
+                .collect()
+        };
+        let rekeyer = PayloadRekeyer::default();
+
+        let mut parts = split("hello");
+        if let PartialPayload::Tail(tail) = &mut parts[2] {
+            tail.index.index = 3;
+        }
+        let error = rekeyer.rekey_all(parts).unwrap_err();
+        assert!(matches!(
+            error,
+            PayloadRekeyError::InvalidIndex {
+                size: 3,
+                index: 3,
+                ..
+            }
+        ));
+
+        // The group merges to fewer bytes than it has parts
+        let mut parts = split("abc");
+        for part in &mut parts[1..] {
+            if let PartialPayload::Tail(tail) = part {
+                tail.data = Default::default();
+            }
+        }
+        let error = rekeyer.rekey_all(parts).unwrap_err();
+        assert!(matches!(
+            error,
+            PayloadRekeyError::TooShort {
+                size: 3,
+                len: 1,
+                ..
+            }
+        ));
+    }
+}
@@ -38,7 +38,7 @@ impl Iterator for Splits {
 
             let data = self.data.split_to(self.quo + 1);
             Some(data)
-        } else if self.data.len() > 0 {
+        } else if !self.data.is_empty() {
             let data = self.data.split_to(self.quo);
             Some(data)
         } else {
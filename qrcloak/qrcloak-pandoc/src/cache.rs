@@ -0,0 +1,116 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Which QR codes were generated from what, so that a code block is only generated
+//! again when its data or options change. Age encryption is randomised, so every run
+//! would change every image otherwise.
+//!
+//! The cache is kept in `qrcloak-cache.json` in the working directory. It holds a hash
+//! of the data of each code block, salted with a random salt of the code block, and a
+//! passphrase only goes into the hash as its digest. The salt keeps equal data from
+//! having equal hashes and rules out precomputed tables, but a guess at the data of a
+//! code block can still be checked against its hash: keep the cache out of version
+//! control when the data could be guessed.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use miette::{Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+/// The name of the cache in the working directory.
+pub const CACHE_FILE: &str = "qrcloak-cache.json";
+
+/// What was generated for a code block by an earlier run.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct Entry {
+    sha256: String,
+    outputs: Vec<String>,
+    /// The salt of the hash
+    salt: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Cache {
+    path: PathBuf,
+    entries: BTreeMap<String, Entry>,
+    force: bool,
+}
+
+impl Cache {
+    /// Reads the cache, which is empty if it does not exist yet.
+    pub fn read(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+
+        if !path.exists() {
+            return Ok(Self {
+                path,
+                ..Default::default()
+            });
+        }
+
+        let contents = std::fs::read_to_string(&path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Unable to read {}", path.display()))?;
+
+        // A broken cache only means that everything is generated again
+        Ok(Self {
+            entries: serde_json::from_str(&contents).unwrap_or_default(),
+            path,
+            force: false,
+        })
+    }
+
+    /// Ignore the earlier runs and generate every code block again.
+    pub fn with_force(self, force: bool) -> Self {
+        Self { force, ..self }
+    }
+
+    /// Returns the images of the code block saved at the path by an earlier run, if its
+    /// hash is the same and none of them was removed.
+    pub fn get(&self, path: &str, sha256: &str) -> Option<Vec<String>> {
+        let entry = self.entries.get(path).filter(|_| !self.force)?;
+
+        if entry.sha256 != sha256 || !entry.outputs.iter().all(|output| exists(output)) {
+            return None;
+        }
+
+        Some(entry.outputs.clone())
+    }
+
+    /// Returns the salt of the code block saved at the path by an earlier run.
+    pub fn salt(&self, path: &str) -> Option<String> {
+        Some(self.entries.get(path)?.salt.clone())
+    }
+
+    pub fn insert(&mut self, path: String, sha256: String, outputs: Vec<String>, salt: String) {
+        self.entries.insert(
+            path,
+            Entry {
+                sha256,
+                outputs,
+                salt,
+            },
+        );
+    }
+
+    /// Writes the cache, without the code blocks whose images were removed. The code
+    /// blocks of other documents in the same directory are kept.
+    pub fn write(mut self) -> Result<()> {
+        self.entries
+            .retain(|_, entry| entry.outputs.iter().all(|output| exists(output)));
+
+        let json = serde_json::to_string_pretty(&self.entries).into_diagnostic()?;
+        std::fs::write(&self.path, json)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Unable to write {}", self.path.display()))
+    }
+}
+
+fn exists(path: &str) -> bool {
+    Path::new(path).exists()
+}
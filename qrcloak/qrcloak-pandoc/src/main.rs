@@ -6,17 +6,23 @@
 use std::{
     io::Write,
     ops::Range,
+    path::Path,
     process::{Command, Stdio},
     str::{from_utf8, FromStr},
 };
 
-use age::x25519::Recipient;
+use age::secrecy::SecretString;
 use miette::{miette, Diagnostic, IntoDiagnostic, LabeledSpan, NamedSource, Result, SourceSpan};
 use pandoc_ast::Pandoc;
 use qrcloak_core::{
-    generate::Generator,
-    payload::{AgeKeyEncryption, Encryption, PayloadGenerator},
+    config::{CompressionKind, Config, ConfigError, Splits},
+    generate::{GenerateError, Generator, QrCodeEcc},
+    payload::{
+        AgeKeyEncryption, AgePassphrase, Compression, Encryption, PayloadGenerator, PayloadSplitter,
+    },
 };
+use serde::Serialize;
+use sha2::{Digest, Sha256};
 use std::collections::BTreeMap;
 use thiserror::Error;
 
@@ -56,6 +62,52 @@ enum FilterError {
         #[help]
         advice: String,
     },
+
+    #[error("invalid value for metadata field `{field}`")]
+    #[diagnostic()]
+    InvalidMetadata {
+        field: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label(collection)]
+        spans: Vec<LabeledSpan>,
+
+        #[help]
+        advice: String,
+    },
+
+    #[error("invalid default recipients in the configuration")]
+    #[diagnostic()]
+    InvalidConfig {
+        #[source]
+        source: ConfigError,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("uses the default recipients")]
+        block_span: SourceSpan,
+
+        #[help]
+        advice: String,
+    },
+
+    #[error("unknown metadata field `{field}`")]
+    #[diagnostic()]
+    UnknownMetadata {
+        field: String,
+
+        #[source_code]
+        src: NamedSource<String>,
+
+        #[label("here")]
+        span: SourceSpan,
+
+        #[help]
+        advice: String,
+    },
 }
 
 #[derive(Debug)]
@@ -98,8 +150,53 @@ pub enum AttrParseError {
     #[error("missing argument `age-keys`")]
     MissingAgeKeys,
 
+    #[error("invalid default recipients")]
+    InvalidDefaultRecipients(#[source] ConfigError),
+
     #[error("invalid age key")]
     InvalidAgeKey { key_index: usize, error: String },
+
+    #[error("invalid recipient group")]
+    InvalidAgeGroup { group_index: usize, error: String },
+
+    #[error("invalid encryption")]
+    InvalidEncryption,
+
+    #[error("missing argument `passphrase-env`")]
+    MissingPassphraseEnv,
+
+    #[error("environment variable is not set")]
+    UnsetPassphraseEnv,
+
+    #[error("invalid compression")]
+    InvalidCompression,
+
+    #[error("invalid splits")]
+    InvalidSplits { error: String },
+
+    #[error("argument is not used")]
+    UnusedArgument {
+        key: &'static str,
+        encryption: &'static str,
+    },
+}
+
+// How the data of a code block is encrypted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EncryptionKind {
+    Age,
+    Passphrase,
+    None,
+}
+
+impl EncryptionKind {
+    fn name(self) -> &'static str {
+        match self {
+            EncryptionKind::Age => "age",
+            EncryptionKind::Passphrase => "passphrase",
+            EncryptionKind::None => "none",
+        }
+    }
 }
 
 pub struct MarkdownWithAttrs {
@@ -110,6 +207,37 @@ pub struct MarkdownWithAttrs {
     key_val_pairs: BTreeMap<String, Range<usize>>,
 }
 
+impl MarkdownWithAttrs {
+    // Returns the position and length of the whole value of a field
+    fn value_span_whole(&self, key: &str) -> (usize, usize) {
+        let r = self.key_val_pairs.get(key).expect("should exist");
+
+        let start = r.start + key.len() + "=\"".len();
+
+        (start, r.end - 1 - start)
+    }
+
+    // Returns the position and length of the value at the index in a comma separated field
+    fn value_span(&self, key: &str, index: usize) -> (usize, usize) {
+        let r = self.key_val_pairs.get(key).expect("should exist");
+
+        let (_, mut vals) = self.markdown[r.clone()]
+            .split_once('=')
+            .expect("should split");
+
+        vals = &vals[1..vals.len() - 1];
+
+        let mut pos = r.start + key.len() + "=\"".len();
+
+        for (_, val) in vals.split(',').enumerate().take_while(|(i, _)| *i < index) {
+            pos += val.len() + 1;
+        }
+        let invalid_value = vals.split(',').nth(index).expect("should exist");
+
+        (pos, invalid_value.len())
+    }
+}
+
 impl Code {
     pub fn new_if_marked(
         attrs: &(String, Vec<String>, Vec<(String, String)>),
@@ -144,8 +272,8 @@ impl Code {
         md
     }
 
-    pub fn parse(&self) -> Result<CodeOpts> {
-        let opts = self.attrs.parse()
+    pub fn parse(&self, defaults: &Defaults, index: usize) -> Result<CodeOpts> {
+        let opts = self.attrs.parse(defaults, index)
                     .map_err(|e| {
 
                         let code_block = self.to_markdown();
@@ -160,7 +288,7 @@ impl Code {
                             AttrParseError::MissingPath => {
                                 FilterError::MissingArgument {
                                     argument_name: "path".into(),
-                                    advice: "add a `path` field to the code block where the QR code will be saved".into(),
+                                    advice: "add a `path` field to the code block where the QR code will be saved, or a `path` template to the `qrcloak` metadata".into(),
                                     block_span,
                                     here_span,
                                     src,
@@ -169,33 +297,103 @@ impl Code {
                             AttrParseError::MissingAgeKeys => {
                                 FilterError::MissingArgument {
                                     argument_name: "age-keys".into(),
-                                    advice: "add a `age-keys` field to the code block to specify the recipients of the QR code".into(),
+                                    advice: "add a `age-keys` or `age-groups` field to the code block to specify the recipients of the QR code, or default recipients to the `qrcloak` metadata or the configuration".into(),
                                     block_span,
                                     here_span,
                                     src,
                                 }
                             }
+                            AttrParseError::InvalidDefaultRecipients(source) => {
+                                FilterError::InvalidConfig {
+                                    source,
+                                    advice: "make sure the groups of `defaults.recipients` in qrcloak.toml or the user configuration are defined in its `recipients` or in the `recipients` of the `qrcloak` metadata, and that their keys are valid".into(),
+                                    block_span,
+                                    src,
+                                }
+                            }
 
                             AttrParseError::InvalidAgeKey { key_index, error } => {
-                                let r = code_block.key_val_pairs.get("age-keys").expect("should exist");
+                                let (pos, len) = code_block.value_span("age-keys", key_index);
 
-                                let (_, mut vals) = code_block.markdown[r.clone()].split_once('=').expect("should split");
+                                FilterError::InvalidArgument {
+                                    argument_name: "age-keys".into(), 
+                                    spans: vec![LabeledSpan::new(Some(error), pos, len)],
+                                    advice: "make sure the age key is valid".into(),
+                                    block_span,
+                                    src,
+                                }
+                            }
+                            AttrParseError::InvalidAgeGroup { group_index, error } => {
+                                let (pos, len) = code_block.value_span("age-groups", group_index);
 
-                                vals = &vals[1..vals.len() - 1];
+                                FilterError::InvalidArgument {
+                                    argument_name: "age-groups".into(),
+                                    spans: vec![LabeledSpan::new(Some(error), pos, len)],
+                                    advice: "make sure the group is defined in the `recipients` of the metadata or in qrcloak.toml".into(),
+                                    block_span,
+                                    src,
+                                }
+                            }
+                            AttrParseError::InvalidEncryption => {
+                                let (pos, len) = code_block.value_span_whole("encryption");
 
-                                let mut pos = r.start + "age-keys=\"".len();
+                                FilterError::InvalidArgument {
+                                    argument_name: "encryption".into(),
+                                    spans: vec![LabeledSpan::new(Some("unknown encryption".into()), pos, len)],
+                                    advice: "use one of `age`, `passphrase` or `none`".into(),
+                                    block_span,
+                                    src,
+                                }
+                            }
+                            AttrParseError::MissingPassphraseEnv => {
+                                FilterError::MissingArgument {
+                                    argument_name: "passphrase-env".into(),
+                                    advice: "add a `passphrase-env` field with the environment variable that contains the passphrase".into(),
+                                    block_span,
+                                    here_span,
+                                    src,
+                                }
+                            }
+                            AttrParseError::UnsetPassphraseEnv => {
+                                let (pos, len) = code_block.value_span_whole("passphrase-env");
 
-                                for (_, val) in vals.split(',').enumerate().take_while(|(i, _)| *i < key_index) {
-                                    pos += val.len() + 1;
+                                FilterError::InvalidArgument {
+                                    argument_name: "passphrase-env".into(),
+                                    spans: vec![LabeledSpan::new(Some("not set".into()), pos, len)],
+                                    advice: "set the environment variable to the passphrase before running pandoc".into(),
+                                    block_span,
+                                    src,
                                 }
-                                let invalid_value = vals.split(',').nth(key_index).expect("should exist");
+                            }
+                            AttrParseError::InvalidCompression => {
+                                let (pos, len) = code_block.value_span_whole("compression");
 
+                                FilterError::InvalidArgument {
+                                    argument_name: "compression".into(),
+                                    spans: vec![LabeledSpan::new(Some("unknown compression".into()), pos, len)],
+                                    advice: "use one of `gzip` or `none`".into(),
+                                    block_span,
+                                    src,
+                                }
+                            }
+                            AttrParseError::InvalidSplits { error } => {
+                                let (pos, len) = code_block.value_span_whole("splits");
 
+                                FilterError::InvalidArgument {
+                                    argument_name: "splits".into(),
+                                    spans: vec![LabeledSpan::new(Some(error), pos, len)],
+                                    advice: "use a number of QR codes of at least 1, or `auto` for as few as the data fits into".into(),
+                                    block_span,
+                                    src,
+                                }
+                            }
+                            AttrParseError::UnusedArgument { key, encryption } => {
+                                let r = code_block.key_val_pairs.get(key).expect("should exist");
 
                                 FilterError::InvalidArgument {
-                                    argument_name: "age-keys".into(), 
-                                    spans: vec![LabeledSpan::new(Some(error), pos, invalid_value.len())],
-                                    advice: "make sure the age key is valid".into(),
+                                    argument_name: key.into(),
+                                    spans: vec![LabeledSpan::new(Some(format!("not used with `encryption=\"{encryption}\"`")), r.start, r.len())],
+                                    advice: "remove the field or change the `encryption` of the code block".into(),
                                     block_span,
                                     src,
                                 }
@@ -278,11 +476,22 @@ impl Attrs {
         markdown.attrs = attrs_start..text.len();
     }
 
-    pub fn parse(&self) -> std::result::Result<AttrOpts, AttrParseError> {
+    pub fn parse(
+        &self,
+        defaults: &Defaults,
+        index: usize,
+    ) -> std::result::Result<AttrOpts, AttrParseError> {
+        let config = &defaults.config;
+
         let mut path = None;
         let mut alt_name = None;
         let mut data_cmd = None;
         let mut age_keys = vec![];
+        let mut age_groups = vec![];
+        let mut encryption = None;
+        let mut passphrase_env = None;
+        let mut compression = None;
+        let mut splits = None;
 
         let mut leftover_key_val_pairs = vec![];
 
@@ -291,6 +500,28 @@ impl Attrs {
                 "data-cmd" => data_cmd = Some(val.to_string()),
                 "alt-name" => alt_name = Some(val.to_string()),
                 "path" => path = Some(val),
+                "encryption" => {
+                    encryption = Some(match val.as_str() {
+                        "age" => EncryptionKind::Age,
+                        "passphrase" => EncryptionKind::Passphrase,
+                        "none" => EncryptionKind::None,
+                        _ => return Err(AttrParseError::InvalidEncryption),
+                    })
+                }
+                "passphrase-env" => passphrase_env = Some(val),
+                "compression" => {
+                    compression = Some(
+                        CompressionKind::from_str(val)
+                            .map_err(|_| AttrParseError::InvalidCompression)?
+                            .into(),
+                    )
+                }
+                "splits" => {
+                    splits = Some(
+                        Splits::from_str(val)
+                            .map_err(|error| AttrParseError::InvalidSplits { error })?,
+                    )
+                }
                 "age-keys" => {
                     age_keys = val
                         .split(',')
@@ -307,23 +538,99 @@ impl Attrs {
                             Ok(acc)
                         })?
                 }
+                "age-groups" => {
+                    for (i, group) in val.split(',').enumerate() {
+                        let recipients =
+                            config
+                                .group(group)
+                                .map_err(|e| AttrParseError::InvalidAgeGroup {
+                                    error: e.to_string(),
+                                    group_index: i,
+                                })?;
+                        age_groups.extend(recipients);
+                    }
+                }
                 _ => leftover_key_val_pairs.push((key.to_string(), val.to_string())),
             }
         }
 
-        let path = if let Some(path) = path {
-            path.to_string()
-        } else {
-            return Err(AttrParseError::MissingPath);
+        let path = defaults
+            .path(path.map(String::as_str), index)
+            .ok_or(AttrParseError::MissingPath)?;
+
+        // Without an explicit encryption, a passphrase is used if there is one
+        let encryption = encryption.unwrap_or(match passphrase_env {
+            Some(_) => EncryptionKind::Passphrase,
+            None => EncryptionKind::Age,
+        });
+
+        let unused = |key: &'static str| AttrParseError::UnusedArgument {
+            key,
+            encryption: encryption.name(),
         };
 
-        if age_keys.is_empty() {
-            return Err(AttrParseError::MissingAgeKeys);
-        }
+        let has_key = |key: &str| self.key_val_pairs.iter().any(|(k, _)| k == key);
+
+        let mut recipients = vec![];
+
+        let encryption = match encryption {
+            EncryptionKind::Age => {
+                if passphrase_env.is_some() {
+                    return Err(unused("passphrase-env"));
+                }
+
+                age_keys.extend(age_groups);
+
+                if age_keys.is_empty() {
+                    age_keys = match &defaults.recipients {
+                        Some(recipients) => recipients.clone(),
+                        None => config
+                            .default_recipients()
+                            .map_err(AttrParseError::InvalidDefaultRecipients)?,
+                    };
+                }
+
+                if age_keys.is_empty() {
+                    return Err(AttrParseError::MissingAgeKeys);
+                }
+
+                recipients = age_keys.iter().map(ToString::to_string).collect();
+
+                Encryption::AgeKey(AgeKeyEncryption::new(age_keys))
+            }
+            EncryptionKind::Passphrase => {
+                for key in ["age-keys", "age-groups"] {
+                    if has_key(key) {
+                        return Err(unused(key));
+                    }
+                }
+
+                let name = passphrase_env.ok_or(AttrParseError::MissingPassphraseEnv)?;
+                let passphrase =
+                    std::env::var(name).map_err(|_| AttrParseError::UnsetPassphraseEnv)?;
+
+                Encryption::AgePassphrase(AgePassphrase::new(SecretString::new(passphrase)))
+            }
+            EncryptionKind::None => {
+                for key in ["age-keys", "age-groups", "passphrase-env"] {
+                    if has_key(key) {
+                        return Err(unused(key));
+                    }
+                }
+
+                Encryption::NoEncryption
+            }
+        };
 
         Ok(AttrOpts {
             path,
-            age_keys,
+            encryption,
+            recipients,
+            compression: compression
+                .unwrap_or_else(|| config.defaults.compression.unwrap_or_default().into()),
+            ecl: config.render.ecl.unwrap_or_default().into(),
+            splits: splits.or(config.render.splits).unwrap_or_default(),
+            structured_append: config.render.structured_append.unwrap_or_default(),
             alt_name,
             data_cmd,
             leftover_classes: self.classes.clone(),
@@ -338,65 +645,182 @@ pub struct CodeOpts {
 }
 
 impl CodeOpts {
-    pub fn to_inline(self) -> pandoc_ast::Inline {
-        pandoc_ast::Inline::Image(
-            (
-                "qrcloak".to_string(),
-                self.attr.leftover_classes,
-                self.attr.leftover_key_val_pairs,
-            ),
-            Default::default(),
-            (self.attr.path, self.attr.alt_name.unwrap_or_default()),
-        )
+    /// A hash of the data and of everything that decides how the QR codes are generated,
+    /// salted with the salt. A passphrase is included as its digest with the same salt,
+    /// see [`AgePassphrase::digest`].
+    pub fn sha256(&self, salt: &str) -> String {
+        #[derive(Serialize)]
+        struct Options<'a> {
+            encryption: &'static str,
+            recipients: &'a [String],
+            compression: &'static str,
+            ecl: String,
+            splits: String,
+            structured_append: bool,
+        }
+
+        let options = Options {
+            encryption: match self.attr.encryption {
+                Encryption::AgeKey(_) => "age",
+                Encryption::AgePassphrase(_) => "passphrase",
+                Encryption::NoEncryption => "none",
+            },
+            recipients: &self.attr.recipients,
+            compression: match self.attr.compression {
+                Compression::Gzip(_) => "gzip",
+                Compression::NoCompression => "none",
+            },
+            ecl: format!("{:?}", self.attr.ecl),
+            splits: self.attr.splits.to_string(),
+            structured_append: self.attr.structured_append,
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(salt);
+        hasher.update(serde_json::to_vec(&options).expect("should serialize"));
+        if let Encryption::AgePassphrase(passphrase) = &self.attr.encryption {
+            hasher.update(passphrase.digest(salt));
+        }
+        hasher.update(&self.data);
+        format!("{:x}", hasher.finalize())
+    }
+
+    pub fn to_inlines(self, paths: Vec<String>) -> Vec<pandoc_ast::Inline> {
+        paths
+            .into_iter()
+            .map(|path| {
+                pandoc_ast::Inline::Image(
+                    (
+                        "qrcloak".to_string(),
+                        self.attr.leftover_classes.clone(),
+                        self.attr.leftover_key_val_pairs.clone(),
+                    ),
+                    Default::default(),
+                    (path, self.attr.alt_name.clone().unwrap_or_default()),
+                )
+            })
+            .collect()
     }
-    pub fn generate_image(&self) -> Result<()> {
+
+    /// Generates the QR codes and returns the paths they are saved at, which are
+    /// numbered like `path-1.png` if there is more than one.
+    pub fn generate_images(&self) -> Result<Vec<String>> {
         let payload = PayloadGenerator::default()
-            .with_encryption(Encryption::AgeKey(AgeKeyEncryption::new(
-                self.attr.age_keys.clone(),
-            )))
+            .with_encryption(self.attr.encryption.clone())
+            .with_compression(self.attr.compression.clone())
             .generate(self.data.clone().into())
             .into_diagnostic()?;
 
-        let qrcode = Generator::default().generate([payload]).into_diagnostic()?;
+        let generator = Generator::default()
+            .with_ecl(self.attr.ecl)
+            .with_structured_append(self.attr.structured_append);
+
+        let qrcodes = match self.attr.splits {
+            Splits::Auto => generator.generate_split(payload),
+            Splits::Count(1) => generator.generate([payload]),
+            Splits::Count(splits) => generator.generate(
+                PayloadSplitter::default()
+                    .with_splits(splits)
+                    .split(payload),
+            ),
+        };
+
+        let qrcodes = qrcodes.map_err(|e| match e {
+            GenerateError::CapacityExceeded(_) | GenerateError::DataTooLong(_) => miette!(
+                help = "add `splits=\"auto\"` to the code block to spread the data over several QR codes",
+                "{e}"
+            ),
+            e => miette!(e),
+        })?;
+
+        let paths: Vec<String> = if qrcodes.len() == 1 {
+            vec![self.attr.path.clone()]
+        } else {
+            (1..=qrcodes.len())
+                .map(|index| numbered_path(&self.attr.path, index))
+                .collect()
+        };
 
-        qrcode[0].save(&self.attr.path).into_diagnostic()?;
+        for (qrcode, path) in qrcodes.iter().zip(&paths) {
+            // The output directory of the metadata may not exist yet
+            if let Some(dir) = Path::new(path).parent() {
+                std::fs::create_dir_all(dir).into_diagnostic()?;
+            }
 
-        Ok(())
+            qrcode.save(path).into_diagnostic()?;
+        }
+
+        Ok(paths)
     }
 }
 
+// Turns qrcode.png into qrcode-1.png
+fn numbered_path(path: &str, index: usize) -> String {
+    let path = Path::new(path);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match path.extension() {
+        Some(extension) => format!("{stem}-{index}.{}", extension.to_string_lossy()),
+        None => format!("{stem}-{index}"),
+    };
+
+    path.with_file_name(name).to_string_lossy().into_owned()
+}
+
 pub struct AttrOpts {
     path: String,
-    age_keys: Vec<Recipient>,
+    encryption: Encryption,
+    recipients: Vec<String>,
+    compression: Compression,
+    ecl: QrCodeEcc,
+    splits: Splits,
+    structured_append: bool,
     alt_name: Option<String>,
     data_cmd: Option<String>,
     leftover_classes: Vec<String>,
     leftover_key_val_pairs: Vec<(String, String)>,
 }
 
+mod cache;
+mod meta;
 mod recursive;
 
-fn filter_pandoc(mut pandoc: Pandoc) -> Result<Pandoc> {
+use cache::{Cache, CACHE_FILE};
+use meta::Defaults;
+
+fn filter_pandoc(mut pandoc: Pandoc, config: &Config, cache: &mut Cache) -> Result<Pandoc> {
+    let defaults = Defaults::parse(&pandoc.meta, config)?;
+
     let codes = recursive::codes(pandoc.blocks.iter_mut());
 
-    for code in codes {
-        let opts = code.code.parse()?;
+    for (index, code) in codes.into_iter().enumerate() {
+        let opts = code.code.parse(&defaults, index + 1)?;
 
-        opts.generate_image()?;
+        // The salt is kept, so that the hash only changes with the code block
+        let salt = cache
+            .salt(&opts.attr.path)
+            .unwrap_or_else(AgePassphrase::generate_salt);
+
+        let sha256 = opts.sha256(&salt);
+        let paths = match cache.get(&opts.attr.path, &sha256) {
+            Some(paths) => paths,
+            None => opts.generate_images()?,
+        };
+        cache.insert(opts.attr.path.clone(), sha256, paths.clone(), salt);
 
-        *code.inline = opts.to_inline();
+        code.replace(opts.to_inlines(paths));
     }
 
     Ok(pandoc)
 }
 
-pub fn filter(input: String) -> Result<String> {
+pub fn filter(input: String, config: &Config, cache: &mut Cache) -> Result<String> {
     let mut error = None;
 
     let output = pandoc_ast::filter(input, |pandoc| {
         let old_pandoc = pandoc.clone();
 
-        match filter_pandoc(pandoc) {
+        match filter_pandoc(pandoc, config, cache) {
             Ok(pandoc) => pandoc,
             Err(e) => {
                 error = Some(e);
@@ -430,7 +854,22 @@ fn main() -> Result<()> {
     let mut input = String::new();
     stdin().read_to_string(&mut input).into_diagnostic()?;
 
-    let output = filter(input)?;
+    // The same configuration as for qrcloak-cli, found from the working directory
+    let dir = std::env::current_dir().into_diagnostic()?;
+    let config = Config::discover(&dir).into_diagnostic()?;
+
+    // pandoc only passes the output format, so the environment can ask for it as well
+    let force = std::env::args().skip(1).any(|arg| arg == "--force")
+        || std::env::var_os("QRCLOAK_FORCE").is_some_and(|force| !force.is_empty() && force != "0");
+
+    let mut cache = Cache::read(dir.join(CACHE_FILE))?.with_force(force);
+
+    let output = filter(input, &config, &mut cache);
+
+    // The code blocks before an error are cached as well
+    cache.write()?;
+
+    let output = output?;
 
     println!("{}", output);
 
@@ -0,0 +1,403 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Defaults for the code blocks of a document, from the `qrcloak` section of its
+//! metadata. The attributes of a code block take precedence over them, and they
+//! take precedence over the configuration:
+//!
+//! ```yaml
+//! ---
+//! qrcloak:
+//!   recipients:
+//!     team: [age1..., age1...]
+//!   age-groups: [team]
+//!   age-keys: [age1...]
+//!   output-dir: images
+//!   format: png
+//!   ecl: quartile
+//!   compression: gzip
+//!   path: secret-{index}
+//! ---
+//! ```
+
+use std::{collections::BTreeMap, ops::Range, path::PathBuf, str::FromStr};
+
+use age::x25519;
+use image::ImageFormat;
+use miette::{LabeledSpan, NamedSource, Report, Result, SourceSpan};
+use pandoc_ast::{Block, Inline, Map, MetaValue};
+use qrcloak_core::config::{CompressionKind, Config, Ecl};
+
+use crate::FilterError;
+
+/// The name of the section in the metadata.
+const SECTION: &str = "qrcloak";
+
+#[derive(Debug, Default)]
+pub struct Defaults {
+    /// The configuration, with the recipient groups, compression and ECL of the metadata.
+    pub config: Config,
+
+    /// The recipients of code blocks without any, if the metadata has some.
+    pub recipients: Option<Vec<x25519::Recipient>>,
+
+    output_dir: Option<PathBuf>,
+    format: Option<String>,
+    path: Option<String>,
+}
+
+impl Defaults {
+    /// Reads the defaults from the metadata of the document on top of the configuration.
+    pub(crate) fn parse(meta: &Map<String, MetaValue>, config: &Config) -> Result<Self> {
+        let Some(section) = meta.get(SECTION) else {
+            return Ok(Self {
+                config: config.clone(),
+                ..Default::default()
+            });
+        };
+
+        let source = Source::new(section);
+
+        let MetaValue::MetaMap(fields) = section else {
+            return Err(source.invalid(
+                SECTION,
+                "expected fields",
+                "add the defaults as fields below `qrcloak`",
+            ));
+        };
+
+        let mut overlay = Config::default();
+        let mut age_keys = None;
+        let mut age_groups = None;
+        let mut defaults = Defaults::default();
+
+        for (key, value) in fields {
+            let field = format!("{SECTION}.{key}");
+
+            match key.as_str() {
+                "recipients" => {
+                    let MetaValue::MetaMap(groups) = &**value else {
+                        return Err(source.invalid(
+                            &field,
+                            "expected recipient groups",
+                            "add each group as a field with a list of age public keys",
+                        ));
+                    };
+
+                    for (name, keys) in groups {
+                        let group = format!("{field}.{name}");
+                        let keys = source.recipients(&group, keys)?;
+
+                        overlay.recipients.insert(
+                            name.clone(),
+                            keys.iter().map(|key| key.to_string()).collect(),
+                        );
+                    }
+                }
+                "age-keys" => age_keys = Some(source.recipients(&field, value)?),
+                "age-groups" => age_groups = Some(source.strings(&field, value)?),
+                "output-dir" => {
+                    defaults.output_dir = Some(source.string(&field, value)?.into());
+                }
+                "format" => {
+                    let format = source.string(&field, value)?;
+
+                    if !ImageFormat::from_extension(&format).is_some_and(|f| f.writing_enabled()) {
+                        return Err(source.invalid(
+                            &field,
+                            "unknown image format",
+                            "use an image format like `png`, `jpeg` or `webp`",
+                        ));
+                    }
+
+                    defaults.format = Some(format);
+                }
+                "ecl" => {
+                    let ecl = Ecl::from_str(&source.string(&field, value)?).map_err(|_| {
+                        source.invalid(
+                            &field,
+                            "unknown error correction level",
+                            "use one of `low`, `medium`, `quartile` or `high`",
+                        )
+                    })?;
+                    overlay.render.ecl = Some(ecl);
+                }
+                "compression" => {
+                    let compression = CompressionKind::from_str(&source.string(&field, value)?)
+                        .map_err(|_| {
+                            source.invalid(
+                                &field,
+                                "unknown compression",
+                                "use one of `gzip` or `none`",
+                            )
+                        })?;
+                    overlay.defaults.compression = Some(compression);
+                }
+                "path" => {
+                    let path = source.string(&field, value)?;
+
+                    // Otherwise every code block would be saved to the same file
+                    if !path.contains("{index}") {
+                        return Err(source.invalid(
+                            &field,
+                            "path without `{index}`",
+                            "include `{index}` where the number of the code block goes, like `secret-{index}.png`",
+                        ));
+                    }
+
+                    defaults.path = Some(path);
+                }
+                _ => return Err(source.unknown(&field)),
+            }
+        }
+
+        defaults.config = config.clone().merge(overlay);
+
+        // The groups may be defined in the metadata as well as in the configuration
+        if age_keys.is_some() || age_groups.is_some() {
+            let mut recipients = age_keys.unwrap_or_default();
+
+            let field = format!("{SECTION}.age-groups");
+            for (group, path) in age_groups.iter().flatten() {
+                let keys = defaults.config.group(group).map_err(|error| {
+                    source.invalid_at(
+                        &field,
+                        path,
+                        error.to_string(),
+                        "make sure the group is defined in the `recipients` of the metadata or in qrcloak.toml",
+                    )
+                })?;
+                recipients.extend(keys);
+            }
+
+            defaults.recipients = Some(recipients);
+        }
+
+        Ok(defaults)
+    }
+
+    /// Returns where the QR code of the code block with the number is saved, given the
+    /// `path` of the block. Without one, the `{index}` of the path template is replaced
+    /// by the number, starting at one.
+    pub fn path(&self, path: Option<&str>, index: usize) -> Option<String> {
+        let path = match path {
+            Some(path) => path.to_string(),
+            None => self.path.as_ref()?.replace("{index}", &index.to_string()),
+        };
+
+        let mut path = PathBuf::from(path);
+
+        if let (Some(format), None) = (&self.format, path.extension()) {
+            path.set_extension(format);
+        }
+
+        // Absolute paths stay as they are
+        if let Some(dir) = &self.output_dir {
+            path = dir.join(path);
+        }
+
+        Some(path.to_string_lossy().into_owned())
+    }
+}
+
+// The metadata section written as YAML, with the positions of its keys and values,
+// named by their path like `qrcloak.recipients.team.0`
+struct Source {
+    yaml: String,
+    keys: BTreeMap<String, Range<usize>>,
+    values: BTreeMap<String, Range<usize>>,
+}
+
+impl Source {
+    fn new(section: &MetaValue) -> Self {
+        let mut source = Self {
+            yaml: "---\n".into(),
+            keys: Default::default(),
+            values: Default::default(),
+        };
+
+        source.entry(0, SECTION, SECTION, section);
+        source.yaml.push_str("---\n");
+
+        source
+    }
+
+    fn entry(&mut self, indent: usize, key: &str, path: &str, value: &MetaValue) {
+        self.yaml.push_str(&" ".repeat(indent));
+
+        let start = self.yaml.len();
+        self.yaml.push_str(key);
+        self.keys.insert(path.to_string(), start..self.yaml.len());
+        self.yaml.push(':');
+
+        self.value(indent, path, value);
+    }
+
+    fn value(&mut self, indent: usize, path: &str, value: &MetaValue) {
+        match value {
+            MetaValue::MetaMap(map) => {
+                self.yaml.push('\n');
+                for (key, value) in map {
+                    self.entry(indent + 2, key, &format!("{path}.{key}"), value);
+                }
+            }
+            MetaValue::MetaList(items) => {
+                self.yaml.push('\n');
+                for (index, item) in items.iter().enumerate() {
+                    self.yaml.push_str(&" ".repeat(indent + 2));
+                    self.yaml.push('-');
+                    self.value(indent + 2, &format!("{path}.{index}"), item);
+                }
+            }
+            scalar => {
+                self.yaml.push(' ');
+
+                let start = self.yaml.len();
+                self.yaml.push_str(&text(scalar).unwrap_or_default());
+                self.values.insert(path.to_string(), start..self.yaml.len());
+                self.yaml.push('\n');
+            }
+        }
+    }
+
+    fn src(&self) -> NamedSource<String> {
+        NamedSource::new("metadata", self.yaml.clone()).with_language("YAML")
+    }
+
+    // The value at the path, or its key if it has no value of its own
+    fn span(&self, path: &str) -> Range<usize> {
+        self.values
+            .get(path)
+            .or_else(|| self.keys.get(path))
+            .cloned()
+            .unwrap_or(0..self.yaml.len())
+    }
+
+    fn invalid(&self, field: &str, label: impl Into<String>, advice: &str) -> Report {
+        self.invalid_at(field, field, label, advice)
+    }
+
+    fn invalid_at(
+        &self,
+        field: &str,
+        path: &str,
+        label: impl Into<String>,
+        advice: &str,
+    ) -> Report {
+        let span = self.span(path);
+
+        FilterError::InvalidMetadata {
+            field: field.into(),
+            src: self.src(),
+            spans: vec![LabeledSpan::new(Some(label.into()), span.start, span.len())],
+            advice: advice.into(),
+        }
+        .into()
+    }
+
+    fn unknown(&self, field: &str) -> Report {
+        let span = self.span_of_key(field);
+
+        FilterError::UnknownMetadata {
+            field: field.into(),
+            src: self.src(),
+            span: SourceSpan::new(span.start.into(), span.len()),
+            advice: "use one of `recipients`, `age-keys`, `age-groups`, `output-dir`, `format`, `ecl`, `compression` or `path`".into(),
+        }
+        .into()
+    }
+
+    fn span_of_key(&self, path: &str) -> Range<usize> {
+        self.keys.get(path).cloned().unwrap_or(0..self.yaml.len())
+    }
+
+    fn string(&self, field: &str, value: &MetaValue) -> Result<String> {
+        match text(value) {
+            Some(text) if !text.is_empty() => Ok(text),
+            Some(_) => Err(self.invalid(field, "missing value", "add a value to the field")),
+            None => Err(self.invalid(field, "expected a single value", "remove the nesting")),
+        }
+    }
+
+    // A list of values, or a single one, each with its path
+    fn strings(&self, field: &str, value: &MetaValue) -> Result<Vec<(String, String)>> {
+        let MetaValue::MetaList(items) = value else {
+            return Ok(vec![(self.string(field, value)?, field.to_string())]);
+        };
+
+        items
+            .iter()
+            .enumerate()
+            .map(|(index, item)| {
+                let path = format!("{field}.{index}");
+                match text(item) {
+                    Some(text) if !text.is_empty() => Ok((text, path)),
+                    _ => Err(self.invalid_at(
+                        field,
+                        &path,
+                        "expected a value",
+                        "make each item of the list a single value",
+                    )),
+                }
+            })
+            .collect()
+    }
+
+    fn recipients(&self, field: &str, value: &MetaValue) -> Result<Vec<x25519::Recipient>> {
+        self.strings(field, value)?
+            .into_iter()
+            .map(|(key, path)| {
+                x25519::Recipient::from_str(&key).map_err(|error| {
+                    self.invalid_at(field, &path, error, "make sure the age key is valid")
+                })
+            })
+            .collect()
+    }
+}
+
+// The text of a single value, which pandoc reads as markdown unless it is a boolean
+fn text(value: &MetaValue) -> Option<String> {
+    match value {
+        MetaValue::MetaString(text) => Some(text.clone()),
+        MetaValue::MetaBool(value) => Some(value.to_string()),
+        MetaValue::MetaInlines(inlines) => Some(inlines_text(inlines)),
+        MetaValue::MetaBlocks(blocks) => Some(
+            blocks
+                .iter()
+                .filter_map(|block| match block {
+                    Block::Plain(inlines) | Block::Para(inlines) => Some(inlines_text(inlines)),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        MetaValue::MetaMap(_) | MetaValue::MetaList(_) => None,
+    }
+}
+
+fn inlines_text(inlines: &[Inline]) -> String {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Str(text)
+            | Inline::Code(_, text)
+            | Inline::Math(_, text)
+            | Inline::RawInline(_, text) => text.clone(),
+            Inline::Space | Inline::SoftBreak | Inline::LineBreak => " ".into(),
+            Inline::Emph(inlines)
+            | Inline::Underline(inlines)
+            | Inline::Strong(inlines)
+            | Inline::Strikeout(inlines)
+            | Inline::Superscript(inlines)
+            | Inline::Subscript(inlines)
+            | Inline::SmallCaps(inlines)
+            | Inline::Cite(_, inlines)
+            | Inline::Span(_, inlines)
+            | Inline::Link(_, inlines, _) => inlines_text(inlines),
+            Inline::Quoted(_, inlines) => format!("\"{}\"", inlines_text(inlines)),
+            Inline::Image(..) | Inline::Note(_) => String::new(),
+        })
+        .collect()
+}
@@ -5,12 +5,73 @@
 
 use crate::Code;
 
+#[derive(Debug)]
+pub enum Target<'a> {
+    Block(&'a mut pandoc_ast::Block),
+    Inline(&'a mut pandoc_ast::Inline),
+}
+
 #[derive(Debug)]
 pub struct CodeBlock<'a> {
-    pub inline: &'a mut pandoc_ast::Inline,
+    pub target: Target<'a>,
     pub code: Code,
 }
 
+impl CodeBlock<'_> {
+    /// Replaces the code with the images. Several images of a code block become a `Div`
+    /// of figures captioned with their part, those of inline code a `Span`.
+    pub fn replace(self, images: Vec<pandoc_ast::Inline>) {
+        let count = images.len();
+
+        match (self.target, <[_; 1]>::try_from(images)) {
+            (Target::Block(block), Ok([image])) => {
+                *block = pandoc_ast::Block::Plain(vec![image]);
+            }
+            (Target::Inline(inline), Ok([image])) => *inline = image,
+            (Target::Block(block), Err(images)) => {
+                let figures = images
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, image)| {
+                        let caption = text(&format!("Part {} of {count}", index + 1));
+
+                        pandoc_ast::Block::Figure(
+                            Default::default(),
+                            (None, vec![pandoc_ast::Block::Plain(caption)]),
+                            vec![pandoc_ast::Block::Plain(vec![image])],
+                        )
+                    })
+                    .collect();
+
+                *block = pandoc_ast::Block::Div(Default::default(), figures);
+            }
+            (Target::Inline(inline), Err(images)) => {
+                let mut inlines = Vec::new();
+                for image in images {
+                    if !inlines.is_empty() {
+                        inlines.push(pandoc_ast::Inline::Space);
+                    }
+                    inlines.push(image);
+                }
+
+                *inline = pandoc_ast::Inline::Span(Default::default(), inlines);
+            }
+        }
+    }
+}
+
+// Words separated by spaces, as pandoc reads them
+fn text(text: &str) -> Vec<pandoc_ast::Inline> {
+    let mut inlines = Vec::new();
+    for word in text.split_whitespace() {
+        if !inlines.is_empty() {
+            inlines.push(pandoc_ast::Inline::Space);
+        }
+        inlines.push(pandoc_ast::Inline::Str(word.to_string()));
+    }
+    inlines
+}
+
 pub fn codes<'a>(blcks: impl Iterator<Item = &'a mut pandoc_ast::Block>) -> Vec<CodeBlock<'a>> {
     let flattened = blocks(blcks);
 
@@ -20,14 +81,10 @@ pub fn codes<'a>(blcks: impl Iterator<Item = &'a mut pandoc_ast::Block>) -> Vec<
         match block {
             pandoc_ast::Block::CodeBlock(attrs, data) => {
                 if let Some(code) = Code::new_if_marked(attrs, data) {
-                    *block = pandoc_ast::Block::Plain(vec![pandoc_ast::Inline::SoftBreak]);
-
-                    let inline = match block {
-                        pandoc_ast::Block::Plain(inline) => &mut inline[0],
-                        _ => panic!("should be a para"),
-                    };
-
-                    v.push(CodeBlock { inline, code });
+                    v.push(CodeBlock {
+                        target: Target::Block(block),
+                        code,
+                    });
                 }
             }
             other_block => {
@@ -36,7 +93,10 @@ pub fn codes<'a>(blcks: impl Iterator<Item = &'a mut pandoc_ast::Block>) -> Vec<
                 for inline in flattened_inlines {
                     if let pandoc_ast::Inline::Code(attrs, data) = inline {
                         if let Some(code) = Code::new_if_marked(attrs, data) {
-                            v.push(CodeBlock { inline, code });
+                            v.push(CodeBlock {
+                                target: Target::Inline(inline),
+                                code,
+                            });
                         }
                     }
                 }
@@ -82,18 +142,18 @@ pub fn blocks<'a>(
             pandoc_ast::Block::LineBlock(_) => v.push(block),
             pandoc_ast::Block::CodeBlock(_, _) => v.push(block),
             pandoc_ast::Block::RawBlock(_, _) => v.push(block),
-            pandoc_ast::Block::BlockQuote(b) => v.extend(blocks(b.into_iter())),
+            pandoc_ast::Block::BlockQuote(b) => v.extend(blocks(b.iter_mut())),
             pandoc_ast::Block::OrderedList(_, lb) => v.extend(blocks(lb.iter_mut().flatten())),
             pandoc_ast::Block::BulletList(lb) => v.extend(blocks(lb.iter_mut().flatten())),
             pandoc_ast::Block::DefinitionList(abc) => {
-                let flattened_blocks = abc.into_iter().flat_map(|(_, b)| b).flatten();
-                v.extend(flattened_blocks.into_iter());
+                let flattened_blocks = abc.iter_mut().flat_map(|(_, b)| b).flatten();
+                v.extend(flattened_blocks);
             }
-            pandoc_ast::Block::Figure(_, _, b) => v.extend(blocks(b.into_iter())),
+            pandoc_ast::Block::Figure(_, _, b) => v.extend(blocks(b.iter_mut())),
             pandoc_ast::Block::Header(_, _, _) => v.push(block),
             pandoc_ast::Block::HorizontalRule => v.push(block),
             pandoc_ast::Block::Table(_, _, _, _, _, _) => v.push(block), // TODO: handle table
-            pandoc_ast::Block::Div(_, b) => v.extend(blocks(b.into_iter())),
+            pandoc_ast::Block::Div(_, b) => v.extend(blocks(b.iter_mut())),
             pandoc_ast::Block::Null => v.push(block),
         };
         v
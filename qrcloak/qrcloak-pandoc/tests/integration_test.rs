@@ -6,14 +6,16 @@
 use core::panic;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::{env, str::FromStr};
 
 use age::x25519::Identity;
 use indoc::formatdoc;
 use miette::Result;
-use qrcloak_core::payload::{AgeKeyDecryption, Decryption, PayloadExtractor, PayloadMerger};
+use qrcloak_core::payload::{
+    AgeKeyDecryption, AgePassphrase, Decompression, Decryption, PayloadExtractor, PayloadMerger,
+};
 use tempfile::TempDir;
 
 fn block_encryption<'a>(
@@ -78,7 +80,7 @@ fn test_filter() -> Result<()> {
     let data = "Input to encrypt";
 
     let simple_block = block_encryption(
-        &[age_public_key],
+        [age_public_key],
         qrcode_path.to_string_lossy().as_ref(),
         "First Block",
         data,
@@ -94,6 +96,10 @@ fn test_filter() -> Result<()> {
         .arg(format!("{}", input_path.display()))
         .arg(format!("--output={}", output_path.display()))
         .arg(format!("--filter={}", filter_bin))
+        .current_dir(tmp_dir.path())
+        // Only the configuration of the test is read
+        .env("HOME", tmp_dir.path())
+        .env("XDG_CONFIG_HOME", tmp_dir.path())
         .output()
         .expect("could not run pandoc");
 
@@ -141,3 +147,467 @@ fn test_filter() -> Result<()> {
 
     Ok(())
 }
+
+// The JSON of a code block for the filter, as pandoc would write it
+fn code_block(attrs: &[(&str, &str)], data: &str) -> String {
+    let attrs = attrs
+        .iter()
+        .map(|(key, val)| format!(r#"["{key}","{val}"]"#))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(r#"{{"t":"CodeBlock","c":[["qrcloak",[],[{attrs}]],"{data}"]}}"#)
+}
+
+// Runs the filter on the JSON of a document in the directory, as pandoc would
+fn run_document(
+    dir: &Path,
+    meta: &str,
+    blocks: &[String],
+    envs: &[(&str, &str)],
+) -> std::process::Output {
+    let filter_bin = env!("CARGO_BIN_EXE_qrcloak-pandoc");
+
+    let document = format!(
+        r#"{{"pandoc-api-version":[1,23,1],"meta":{meta},"blocks":[{}]}}"#,
+        blocks.join(",")
+    );
+
+    let mut child = Command::new(filter_bin)
+        .current_dir(dir)
+        // Only the configuration of the test is read
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .envs(envs.iter().copied())
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .expect("could not run filter");
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(document.as_bytes())
+        .expect("could not write document");
+
+    child.wait_with_output().expect("could not wait for filter")
+}
+
+// Runs the filter on a document with a single code block
+fn run_filter(
+    dir: &Path,
+    attrs: &[(&str, &str)],
+    data: &str,
+    envs: &[(&str, &str)],
+) -> std::process::Output {
+    run_document(dir, "{}", &[code_block(attrs, data)], envs)
+}
+
+fn extract_qrcode(path: &std::path::Path, decryption: Decryption) -> String {
+    let qrcode_image = image::open(path)
+        .expect("could not open qrcode image")
+        .to_luma8();
+
+    let payload = qrcloak_core::extract::Extractor::extract(
+        qrcode_image.width() as usize,
+        qrcode_image.height() as usize,
+        &*qrcode_image,
+    );
+
+    let merged = PayloadMerger::default().merge(payload).complete;
+
+    let data = PayloadExtractor::default()
+        .with_decryption(decryption)
+        .with_decompression(Decompression::from(merged[0].compression()))
+        .extract(merged[0].clone())
+        .expect("should extract");
+
+    String::from_utf8(data.to_vec()).expect("should be utf-8")
+}
+
+#[test]
+fn test_filter_encryption_attrs() {
+    let tmp_dir = TempDir::new().expect("could not create temp dir");
+    let passphrase_path = tmp_dir.path().join("passphrase.png");
+    let plain_path = tmp_dir.path().join("plain.png");
+
+    let output = run_filter(
+        tmp_dir.path(),
+        &[
+            ("path", passphrase_path.to_str().unwrap()),
+            ("encryption", "passphrase"),
+            ("passphrase-env", "QRCLOAK_TEST_PASSPHRASE"),
+            ("compression", "gzip"),
+        ],
+        "protected",
+        &[("QRCLOAK_TEST_PASSPHRASE", "correct horse battery staple")],
+    );
+    assert!(output.status.success());
+
+    let passphrase = AgePassphrase::new("correct horse battery staple".to_string().into());
+    assert_eq!(
+        extract_qrcode(&passphrase_path, Decryption::AgePassphrase(passphrase)),
+        "protected"
+    );
+
+    let output = run_filter(
+        tmp_dir.path(),
+        &[
+            ("path", plain_path.to_str().unwrap()),
+            ("encryption", "none"),
+        ],
+        "plain",
+        &[],
+    );
+    assert!(output.status.success());
+    assert_eq!(
+        extract_qrcode(&plain_path, Decryption::NoEncryption),
+        "plain"
+    );
+
+    let invalid = [
+        (
+            vec![("encryption", "rot13")],
+            "invalid value for field `encryption`",
+        ),
+        (
+            vec![("encryption", "none"), ("passphrase-env", "VAR")],
+            "not used with `encryption=\"none\"`",
+        ),
+        (
+            vec![("passphrase-env", "QRCLOAK_TEST_UNSET")],
+            "invalid value for field `passphrase-env`",
+        ),
+        (
+            vec![("encryption", "passphrase")],
+            "missing field `passphrase-env`",
+        ),
+        (
+            vec![("encryption", "none"), ("compression", "zstd")],
+            "invalid value for field `compression`",
+        ),
+    ];
+
+    for (attrs, message) in invalid {
+        let path = tmp_dir.path().join("invalid.png");
+        let attrs: Vec<(&str, &str)> = [("path", path.to_str().unwrap())]
+            .into_iter()
+            .chain(attrs)
+            .collect();
+
+        let output = run_filter(tmp_dir.path(), &attrs, "data", &[]);
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(message),
+            "{message}"
+        );
+        assert!(!path.exists());
+    }
+}
+
+#[test]
+fn test_filter_splits() {
+    let tmp_dir = TempDir::new().expect("could not create temp dir");
+    let path = tmp_dir.path().join("qrcode.png");
+
+    let output = run_filter(
+        tmp_dir.path(),
+        &[
+            ("path", path.to_str().unwrap()),
+            ("encryption", "none"),
+            ("splits", "3"),
+        ],
+        "split into three",
+        &[],
+    );
+    assert!(output.status.success());
+
+    let document = String::from_utf8(output.stdout).expect("should be utf-8");
+    assert!(!path.exists());
+    assert_eq!(document.matches(r#""t":"Figure""#).count(), 3);
+    assert!(document.contains(r#""t":"Div""#));
+
+    let payloads = (1..=3)
+        .flat_map(|index| {
+            let path = tmp_dir.path().join(format!("qrcode-{index}.png"));
+            assert!(document.contains(path.to_str().unwrap()));
+
+            let qrcode_image = image::open(path)
+                .expect("could not open qrcode image")
+                .to_luma8();
+
+            qrcloak_core::extract::Extractor::extract(
+                qrcode_image.width() as usize,
+                qrcode_image.height() as usize,
+                &*qrcode_image,
+            )
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(payloads.len(), 3);
+
+    let merged = PayloadMerger::default().merge(payloads).complete;
+    let data = PayloadExtractor::default()
+        .with_decryption(Decryption::NoEncryption)
+        .extract(merged[0].clone())
+        .expect("should extract");
+    assert_eq!(data, "split into three");
+
+    // Data that fits into one QR code is not split with `auto`
+    let output = run_filter(
+        tmp_dir.path(),
+        &[
+            ("path", path.to_str().unwrap()),
+            ("encryption", "none"),
+            ("splits", "auto"),
+        ],
+        "fits",
+        &[],
+    );
+    assert!(output.status.success());
+    assert!(path.exists());
+
+    let output = run_filter(
+        tmp_dir.path(),
+        &[("path", path.to_str().unwrap()), ("splits", "0")],
+        "data",
+        &[],
+    );
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("invalid value for field `splits`"));
+}
+
+// A metadata value as pandoc reads it from YAML
+fn meta_str(text: &str) -> String {
+    format!(r#"{{"t":"MetaInlines","c":[{{"t":"Str","c":"{text}"}}]}}"#)
+}
+
+fn meta_list(items: &[String]) -> String {
+    format!(r#"{{"t":"MetaList","c":[{}]}}"#, items.join(","))
+}
+
+fn meta_map(fields: &[(&str, String)]) -> String {
+    let fields = fields
+        .iter()
+        .map(|(key, value)| format!(r#""{key}":{value}"#))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    format!(r#"{{"t":"MetaMap","c":{{{fields}}}}}"#)
+}
+
+#[test]
+fn test_filter_metadata() {
+    let tmp_dir = TempDir::new().expect("could not create temp dir");
+    let images = tmp_dir.path().join("images");
+    let identity = Identity::generate();
+
+    let defaults = |extra: &[(&str, String)]| {
+        let mut fields = vec![
+            (
+                "recipients",
+                meta_map(&[(
+                    "team",
+                    meta_list(&[meta_str(&identity.to_public().to_string())]),
+                )]),
+            ),
+            ("age-groups", meta_list(&[meta_str("team")])),
+            ("output-dir", meta_str(images.to_str().unwrap())),
+            ("format", meta_str("png")),
+            ("compression", meta_str("gzip")),
+            ("path", meta_str("secret-{index}")),
+        ];
+        fields.extend(extra.iter().cloned());
+
+        format!(r#"{{"qrcloak":{}}}"#, meta_map(&fields))
+    };
+
+    let output = run_document(
+        tmp_dir.path(),
+        &defaults(&[]),
+        &[
+            code_block(&[], "first"),
+            code_block(&[("path", "own.png"), ("compression", "none")], "second"),
+            code_block(&[], "third"),
+        ],
+        &[],
+    );
+    assert!(output.status.success());
+
+    let decryption = || Decryption::AgeKey(AgeKeyDecryption::new(vec![identity.clone()]));
+    assert_eq!(
+        extract_qrcode(&images.join("secret-1.png"), decryption()),
+        "first"
+    );
+    assert_eq!(
+        extract_qrcode(&images.join("own.png"), decryption()),
+        "second"
+    );
+    assert_eq!(
+        extract_qrcode(&images.join("secret-3.png"), decryption()),
+        "third"
+    );
+
+    let invalid = [
+        (
+            vec![("ecl", meta_str("highest"))],
+            "invalid value for metadata field `qrcloak.ecl`",
+        ),
+        (
+            vec![("colour", meta_str("blue"))],
+            "unknown metadata field `qrcloak.colour`",
+        ),
+        (
+            vec![("age-keys", meta_list(&[meta_str("age1invalid")]))],
+            "invalid value for metadata field `qrcloak.age-keys`",
+        ),
+        (
+            vec![("age-groups", meta_list(&[meta_str("ops")]))],
+            "invalid value for metadata field `qrcloak.age-groups`",
+        ),
+        (
+            vec![("format", meta_str("doc"))],
+            "invalid value for metadata field `qrcloak.format`",
+        ),
+        (
+            vec![("output-dir", meta_str(""))],
+            "invalid value for metadata field `qrcloak.output-dir`",
+        ),
+        (
+            vec![("path", meta_str("secret.png"))],
+            "invalid value for metadata field `qrcloak.path`",
+        ),
+    ];
+
+    for (extra, message) in invalid {
+        let output = run_document(
+            tmp_dir.path(),
+            &defaults(&extra),
+            &[code_block(&[], "data")],
+            &[],
+        );
+        assert!(!output.status.success());
+        assert!(
+            String::from_utf8_lossy(&output.stderr).contains(message),
+            "{message}"
+        );
+    }
+}
+
+#[test]
+fn test_filter_config_defaults() {
+    let tmp_dir = TempDir::new().expect("could not create temp dir");
+    let path = tmp_dir.path().join("qrcode.png");
+
+    std::fs::write(
+        tmp_dir.path().join("qrcloak.toml"),
+        "[defaults]\nrecipients = [\"ops\"]\n",
+    )
+    .unwrap();
+
+    let output = run_filter(
+        tmp_dir.path(),
+        &[("path", path.to_str().unwrap())],
+        "data",
+        &[],
+    );
+    assert!(!output.status.success());
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("invalid default recipients in the configuration"));
+    assert!(stderr.contains("unknown recipient group `ops`"));
+}
+
+#[test]
+fn test_filter_cache() {
+    let tmp_dir = TempDir::new().expect("could not create temp dir");
+    let path = tmp_dir.path().join("qrcode.png");
+    let identity = Identity::generate();
+    let public_key = identity.to_public().to_string();
+
+    let run = |data: &str, envs: &[(&str, &str)]| {
+        let attrs = [
+            ("path", path.to_str().unwrap()),
+            ("age-keys", public_key.as_str()),
+        ];
+        let output = run_filter(tmp_dir.path(), &attrs, data, envs);
+        assert!(output.status.success());
+
+        std::fs::read(&path).expect("should be generated")
+    };
+
+    // Encryption is randomised, so the same image means that it was not generated again
+    let first = run("cached", &[]);
+    assert!(tmp_dir.path().join("qrcloak-cache.json").exists());
+    assert_eq!(run("cached", &[]), first);
+
+    let changed = run("changed", &[]);
+    assert_ne!(changed, first);
+    let decryption = || Decryption::AgeKey(AgeKeyDecryption::new(vec![identity.clone()]));
+    assert_eq!(extract_qrcode(&path, decryption()), "changed");
+
+    std::fs::remove_file(&path).unwrap();
+    let removed = run("changed", &[]);
+    assert_ne!(removed, changed);
+
+    assert_ne!(run("changed", &[("QRCLOAK_FORCE", "1")]), removed);
+
+    // Code blocks with the same data have different hashes
+    let blocks = ["first.png", "second.png"].map(|name| {
+        let path = tmp_dir.path().join(name);
+        code_block(
+            &[("path", path.to_str().unwrap()), ("encryption", "none")],
+            "same",
+        )
+    });
+    let output = run_document(tmp_dir.path(), "{}", &blocks, &[]);
+    assert!(output.status.success());
+
+    let cache = std::fs::read_to_string(tmp_dir.path().join("qrcloak-cache.json")).unwrap();
+    let cache: serde_json::Value = serde_json::from_str(&cache).unwrap();
+    let hash = |name: &str| {
+        let path = tmp_dir.path().join(name);
+        cache[path.to_str().unwrap()]["sha256"].clone()
+    };
+    assert!(hash("first.png").is_string());
+    assert_ne!(hash("first.png"), hash("second.png"));
+}
+
+#[test]
+fn test_filter_cache_passphrase() {
+    let tmp_dir = TempDir::new().expect("could not create temp dir");
+    let path = tmp_dir.path().join("qrcode.png");
+
+    let run = |passphrase: &str| {
+        let attrs = [
+            ("path", path.to_str().unwrap()),
+            ("encryption", "passphrase"),
+            ("passphrase-env", "QRCLOAK_TEST_PASSPHRASE"),
+        ];
+        let output = run_filter(
+            tmp_dir.path(),
+            &attrs,
+            "data",
+            &[("QRCLOAK_TEST_PASSPHRASE", passphrase)],
+        );
+        assert!(output.status.success());
+
+        std::fs::read(&path).expect("should be generated")
+    };
+
+    let first = run("correct horse");
+    assert_eq!(run("correct horse"), first);
+
+    // A new passphrase generates the code again, without being in the cache
+    assert_ne!(run("battery staple"), first);
+    let passphrase = AgePassphrase::new("battery staple".to_string().into());
+    assert_eq!(
+        extract_qrcode(&path, Decryption::AgePassphrase(passphrase)),
+        "data"
+    );
+
+    let cache = std::fs::read_to_string(tmp_dir.path().join("qrcloak-cache.json")).unwrap();
+    assert!(!cache.contains("battery staple"));
+    assert!(cache.contains("salt"));
+}
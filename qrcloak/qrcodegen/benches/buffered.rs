@@ -0,0 +1,42 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use qrcodegen::{EncodeBuffers, QrCode, QrCodeEcc, QrSegment, Version};
+
+// Byte lengths that end up in small, medium and large versions at the medium ECC level
+const LENGTHS: [usize; 3] = [20, 300, 2000];
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+fn buffered(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for len in LENGTHS {
+        let segs = [QrSegment::make_bytes(&payload(len))];
+
+        let mut buffers = EncodeBuffers::new();
+        group.bench_with_input(BenchmarkId::new("buffered", len), &segs, |b, segs| {
+            b.iter(|| {
+                let qrcode = QrCode::encode_segments_buffered(
+                    black_box(segs),
+                    QrCodeEcc::Medium,
+                    Version::MIN,
+                    Version::MAX,
+                    None,
+                    false,
+                    &mut buffers,
+                )
+                .unwrap();
+                buffers.recycle(qrcode);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, buffered);
+criterion_main!(benches);
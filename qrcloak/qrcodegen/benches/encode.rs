@@ -0,0 +1,57 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+//! Encoding benches that only use the API of the `Vec<bool>` encoder from before the
+//! bit-packing, so that the two can be compared with a criterion baseline. From the
+//! `qrcloak` directory:
+//!
+//! ```sh
+//! git checkout f9d1586 -- qrcodegen/src
+//! cargo bench -p qrcodegen --bench encode -- --save-baseline vec-bool
+//! git checkout HEAD -- qrcodegen/src
+//! cargo bench -p qrcodegen --bench encode -- --baseline vec-bool
+//! ```
+//!
+//! The reuse of the encoding memory is benched on its own in `buffered.rs`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use qrcodegen::{Mask, QrCode, QrCodeEcc, QrSegment, Version};
+
+// Byte lengths that end up in small, medium and large versions at the medium ECC level
+const LENGTHS: [usize; 3] = [20, 300, 2000];
+
+fn payload(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+fn encode(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encode");
+    for len in LENGTHS {
+        let data = payload(len);
+
+        group.bench_with_input(BenchmarkId::new("auto_mask", len), &data, |b, data| {
+            b.iter(|| QrCode::encode_binary(black_box(data), QrCodeEcc::Medium).unwrap())
+        });
+
+        let segs = [QrSegment::make_bytes(&data)];
+        group.bench_with_input(BenchmarkId::new("fixed_mask", len), &segs, |b, segs| {
+            b.iter(|| {
+                QrCode::encode_segments_advanced(
+                    black_box(segs),
+                    QrCodeEcc::Medium,
+                    Version::MIN,
+                    Version::MAX,
+                    Some(Mask::new(0)),
+                    false,
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
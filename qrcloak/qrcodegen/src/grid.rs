@@ -0,0 +1,114 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+/*---- Bit grid functionality ----*/
+
+// A rectangular grid of bits, packed row by row into 64-bit words.
+//
+// Bit x of row y is bit (x % 64) of word (y * stride + x / 64), so that whole rows can be
+// processed a word at a time. The unused high bits of the last word of each row are always 0.
+#[derive(Clone, PartialEq, Eq, Default)]
+pub(crate) struct BitGrid {
+    // The number of columns, at least 0.
+    width: i32,
+
+    // The number of rows, at least 0.
+    height: i32,
+
+    // The number of words per row.
+    stride: usize,
+
+    // The packed bits, with a length of stride * height.
+    words: Vec<u64>,
+}
+
+impl BitGrid {
+    // Creates a grid with the given dimensions where all bits are 0.
+    pub(crate) fn new(width: i32, height: i32) -> Self {
+        let mut result = Self::default();
+        result.reset(width, height);
+        result
+    }
+
+    // Changes the dimensions of this grid and sets all bits to 0, reusing the allocation.
+    pub(crate) fn reset(&mut self, width: i32, height: i32) {
+        assert!(width >= 0 && height >= 0, "Invalid dimensions");
+        self.width = width;
+        self.height = height;
+        self.stride = (width as usize).div_ceil(64);
+        self.words.clear();
+        self.words.resize(self.stride * height as usize, 0);
+    }
+
+    // Returns the number of columns.
+    pub(crate) fn width(&self) -> i32 {
+        self.width
+    }
+
+    // Returns the number of words per row.
+    pub(crate) fn stride(&self) -> usize {
+        self.stride
+    }
+
+    // Returns the bit at the given coordinates, which must be in bounds.
+    pub(crate) fn get(&self, x: i32, y: i32) -> bool {
+        let (i, bit) = self.index(x, y);
+        (self.words[i] >> bit) & 1 != 0
+    }
+
+    // Sets the bit at the given coordinates, which must be in bounds.
+    pub(crate) fn set(&mut self, x: i32, y: i32, value: bool) {
+        let (i, bit) = self.index(x, y);
+        self.words[i] = self.words[i] & !(1 << bit) | u64::from(value) << bit;
+    }
+
+    // Inverts the bit at the given coordinates iff value is true. Coordinates must be in bounds.
+    pub(crate) fn xor(&mut self, x: i32, y: i32, value: bool) {
+        let (i, bit) = self.index(x, y);
+        self.words[i] ^= u64::from(value) << bit;
+    }
+
+    // Returns the packed words of the given row.
+    pub(crate) fn row(&self, y: i32) -> &[u64] {
+        let start: usize = y as usize * self.stride;
+        &self.words[start..start + self.stride]
+    }
+
+    // Returns the packed words of the given row for modification. The caller
+    // must keep the bits beyond the width at 0.
+    pub(crate) fn row_mut(&mut self, y: i32) -> &mut [u64] {
+        let start: usize = y as usize * self.stride;
+        &mut self.words[start..start + self.stride]
+    }
+
+    // Returns the number of bits that are 1.
+    pub(crate) fn count_ones(&self) -> usize {
+        self.words.iter().map(|w| w.count_ones() as usize).sum()
+    }
+
+    // Writes the transposition of this grid into the given grid, reusing its allocation.
+    pub(crate) fn transpose_into(&self, out: &mut BitGrid) {
+        out.reset(self.height, self.width);
+        for y in 0..self.height {
+            for (i, &word) in self.row(y).iter().enumerate() {
+                let mut word: u64 = word;
+                while word != 0 {
+                    let x = (i * 64) as i32 + word.trailing_zeros() as i32;
+                    out.set(y, x, true);
+                    word &= word - 1; // Clear lowest set bit
+                }
+            }
+        }
+    }
+
+    // Returns the word index and bit position of the given coordinates.
+    fn index(&self, x: i32, y: i32) -> (usize, u32) {
+        debug_assert!((0..self.width).contains(&x) && (0..self.height).contains(&y));
+        (
+            y as usize * self.stride + (x as usize >> 6),
+            (x & 63) as u32,
+        )
+    }
+}
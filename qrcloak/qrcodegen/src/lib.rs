@@ -30,12 +30,24 @@
 //! - User can specify mask pattern manually, otherwise library will automatically evaluate all 8 masks and select the optimal one
 //! - User can specify absolute error correction level, or allow the library to boost it if it doesn't increase the version number
 //! - User can create a list of data segments manually and add ECI segments
+//! - Optimal segmentation can use kanji mode and announce UTF-8 with an ECI segment
+//! - Encodes Micro QR Code symbols (M1 to M4) and rectangular Micro QR Code symbols (R7x43 to R17x139)
 //!
 //! More information about QR Code technology and this library's design can be found on the project home page.
 
 #![forbid(unsafe_code)]
 use std::convert::TryFrom;
 
+mod grid;
+mod kanji;
+mod micro;
+mod rmqr;
+
+use grid::BitGrid;
+
+pub use micro::{MicroQrCode, MicroVersion};
+pub use rmqr::{RmqrCode, RmqrVersion};
+
 /*---- QrCode functionality ----*/
 
 /// A QR Code symbol, which is a type of two-dimension barcode.
@@ -81,10 +93,11 @@ pub struct QrCode {
 
     // The modules of this QR Code (false = light, true = dark).
     // Immutable after constructor finishes. Accessed through get_module().
-    modules: Vec<bool>,
+    modules: BitGrid,
 
-    // Indicates function modules that are not subjected to masking. Discarded when constructor finishes.
-    isfunction: Vec<bool>,
+    // Indicates function modules that are not subjected to masking.
+    // Handed back to the encode buffers when constructor finishes.
+    isfunction: BitGrid,
 }
 
 impl QrCode {
@@ -166,12 +179,41 @@ impl QrCode {
     /// Returns a wrapped `QrCode` if successful, or `Err` if the data is too
     /// long to fit in any version in the given range at the given ECC level.
     pub fn encode_segments_advanced(
+        segs: &[QrSegment],
+        ecl: QrCodeEcc,
+        minversion: Version,
+        maxversion: Version,
+        mask: Option<Mask>,
+        boostecl: bool,
+    ) -> Result<Self, DataTooLong> {
+        QrCode::encode_segments_buffered(
+            segs,
+            ecl,
+            minversion,
+            maxversion,
+            mask,
+            boostecl,
+            &mut EncodeBuffers::new(),
+        )
+    }
+
+    /// Returns a QR Code representing the given segments with the given encoding
+    /// parameters, using the memory of the given buffers for intermediate results.
+    ///
+    /// The result is the same as with `encode_segments_advanced()`. When encoding many
+    /// QR Codes in a row, passing the same buffers every time and handing QR Codes that
+    /// are no longer needed back with `EncodeBuffers::recycle()` avoids most allocations.
+    ///
+    /// Returns a wrapped `QrCode` if successful, or `Err` if the data is too
+    /// long to fit in any version in the given range at the given ECC level.
+    pub fn encode_segments_buffered(
         segs: &[QrSegment],
         mut ecl: QrCodeEcc,
         minversion: Version,
         maxversion: Version,
         mask: Option<Mask>,
         boostecl: bool,
+        buffers: &mut EncodeBuffers,
     ) -> Result<Self, DataTooLong> {
         assert!(minversion <= maxversion, "Invalid value");
 
@@ -180,10 +222,9 @@ impl QrCode {
         let datausedbits: usize = loop {
             let datacapacitybits: usize = QrCode::get_num_data_codewords(version, ecl) * 8; // Number of data bits available
             let dataused: Option<usize> = QrSegment::get_total_bits(segs, version);
-            if dataused.map_or(false, |n| n <= datacapacitybits) {
+            if dataused.is_some_and(|n| n <= datacapacitybits) {
                 break dataused.unwrap(); // This version number is found to be suitable
             } else if version >= maxversion {
-                println!("failed here");
                 // All versions in the range could not fit the given data
                 return Err(match dataused {
                     None => DataTooLong::SegmentTooLong,
@@ -203,42 +244,39 @@ impl QrCode {
         }
 
         // Concatenate all segments to create the data bit string
-        let mut bb = BitBuffer(Vec::new());
+        let mut bb: BitBuffer = std::mem::take(&mut buffers.bits);
+        bb.clear();
         for seg in segs {
             bb.append_bits(seg.mode.mode_bits(), 4);
             bb.append_bits(
                 u32::try_from(seg.numchars).unwrap(),
                 seg.mode.num_char_count_bits(version),
             );
-            bb.0.extend_from_slice(&seg.data);
+            bb.append_buffer(&seg.data);
         }
-        debug_assert_eq!(bb.0.len(), datausedbits);
+        debug_assert_eq!(bb.len(), datausedbits);
 
         // Add terminator and pad up to a byte if applicable
         let datacapacitybits: usize = QrCode::get_num_data_codewords(version, ecl) * 8;
-        debug_assert!(bb.0.len() <= datacapacitybits);
-        let numzerobits: usize = std::cmp::min(4, datacapacitybits - bb.0.len());
+        debug_assert!(bb.len() <= datacapacitybits);
+        let numzerobits: usize = std::cmp::min(4, datacapacitybits - bb.len());
         bb.append_bits(0, u8::try_from(numzerobits).unwrap());
-        let numzerobits: usize = bb.0.len().wrapping_neg() & 7;
+        let numzerobits: usize = bb.len().wrapping_neg() & 7;
         bb.append_bits(0, u8::try_from(numzerobits).unwrap());
-        debug_assert_eq!(bb.0.len() % 8, 0);
+        debug_assert_eq!(bb.len() % 8, 0);
 
         // Pad with alternating bytes until data capacity is reached
         for &padbyte in [0xEC, 0x11].iter().cycle() {
-            if bb.0.len() >= datacapacitybits {
+            if bb.len() >= datacapacitybits {
                 break;
             }
             bb.append_bits(padbyte, 8);
         }
 
-        // Pack bits into bytes in big endian
-        let mut datacodewords = vec![0u8; bb.0.len() / 8];
-        for (i, &bit) in bb.0.iter().enumerate() {
-            datacodewords[i >> 3] |= u8::from(bit) << (7 - (i & 7));
-        }
-
-        // Create the QR Code object
-        Ok(QrCode::encode_codewords(version, ecl, &datacodewords, mask))
+        // Create the QR Code object, the bits are already packed into bytes in big endian
+        let result = QrCode::encode_codewords_buffered(version, ecl, bb.as_bytes(), mask, buffers);
+        buffers.bits = bb;
+        Ok(result)
     }
 
     /*---- Constructor (low level) ----*/
@@ -249,50 +287,55 @@ impl QrCode {
     /// This is a low-level API that most users should not use directly.
     /// A mid-level API is the `encode_segments()` function.
     pub fn encode_codewords(
+        ver: Version,
+        ecl: QrCodeEcc,
+        datacodewords: &[u8],
+        msk: Option<Mask>,
+    ) -> Self {
+        QrCode::encode_codewords_buffered(ver, ecl, datacodewords, msk, &mut EncodeBuffers::new())
+    }
+
+    // Creates a new QR Code like encode_codewords(), using the memory of the given buffers.
+    fn encode_codewords_buffered(
         ver: Version,
         ecl: QrCodeEcc,
         datacodewords: &[u8],
         mut msk: Option<Mask>,
+        buffers: &mut EncodeBuffers,
     ) -> Self {
         // Initialize fields
-        let size = usize::from(ver.value()) * 4 + 17;
+        let size = i32::from(ver.value()) * 4 + 17;
+        let mut modules: BitGrid = std::mem::take(&mut buffers.modules);
+        modules.reset(size, size); // Initially all light
+        let mut isfunction: BitGrid = std::mem::take(&mut buffers.isfunction);
+        isfunction.reset(size, size);
         let mut result = Self {
             version: ver,
-            size: size as i32,
+            size,
             mask: Mask::new(0), // Dummy value
             errorcorrectionlevel: ecl,
-            modules: vec![false; size * size], // Initially all light
-            isfunction: vec![false; size * size],
+            modules,
+            isfunction,
         };
 
         // Compute ECC, draw modules
         result.draw_function_patterns();
-        let allcodewords: Vec<u8> = result.add_ecc_and_interleave(datacodewords);
+        let mut allcodewords: Vec<u8> = std::mem::take(&mut buffers.codewords);
+        result.add_ecc_and_interleave(datacodewords, &mut allcodewords);
         result.draw_codewords(&allcodewords);
+        buffers.codewords = allcodewords;
 
         // Do masking
         if msk.is_none() {
             // Automatically choose best mask
-            let mut minpenalty = std::i32::MAX;
-            for i in 0u8..8 {
-                let i = Mask::new(i);
-                result.apply_mask(i);
-                result.draw_format_bits(i);
-                let penalty: i32 = result.get_penalty_score();
-                if penalty < minpenalty {
-                    msk = Some(i);
-                    minpenalty = penalty;
-                }
-                result.apply_mask(i); // Undoes the mask due to XOR
-            }
+            msk = Some(result.choose_mask(buffers));
         }
         let msk: Mask = msk.unwrap();
         result.mask = msk;
         result.apply_mask(msk); // Apply the final choice of mask
         result.draw_format_bits(msk); // Overwrite old format bits
 
-        result.isfunction.clear();
-        result.isfunction.shrink_to_fit();
+        buffers.isfunction = std::mem::take(&mut result.isfunction);
         result
     }
 
@@ -329,12 +372,7 @@ impl QrCode {
 
     // Returns the color of the module at the given coordinates, which must be in bounds.
     fn module(&self, x: i32, y: i32) -> bool {
-        self.modules[(y * self.size + x) as usize]
-    }
-
-    // Returns a mutable reference to the module's color at the given coordinates, which must be in bounds.
-    fn module_mut(&mut self, x: i32, y: i32) -> &mut bool {
-        &mut self.modules[(y * self.size + x) as usize]
+        self.modules.get(x, y)
     }
 
     /*---- Private helper methods for constructor: Drawing function modules ----*/
@@ -374,17 +412,7 @@ impl QrCode {
     // Draws two copies of the format bits (with its own error correction code)
     // based on the given mask and this object's error correction level field.
     fn draw_format_bits(&mut self, mask: Mask) {
-        // Calculate error correction code and pack bits
-        let bits: u32 = {
-            // errcorrlvl is uint2, mask is uint3
-            let data: u32 = u32::from(self.errorcorrectionlevel.format_bits() << 3 | mask.value());
-            let mut rem: u32 = data;
-            for _ in 0..10 {
-                rem = (rem << 1) ^ ((rem >> 9) * 0x537);
-            }
-            (data << 10 | rem) ^ 0x5412 // uint15
-        };
-        debug_assert_eq!(bits >> 15, 0);
+        let bits: u32 = QrCode::get_format_bits(self.errorcorrectionlevel, mask);
 
         // Draw first copy
         for i in 0..6 {
@@ -408,6 +436,20 @@ impl QrCode {
         self.set_function_module(8, size - 8, true); // Always dark
     }
 
+    // Calculates the 15 format bits (with their own error correction code)
+    // for the given error correction level and mask.
+    fn get_format_bits(ecl: QrCodeEcc, mask: Mask) -> u32 {
+        // errcorrlvl is uint2, mask is uint3
+        let data: u32 = u32::from(ecl.format_bits() << 3 | mask.value());
+        let mut rem: u32 = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits: u32 = (data << 10 | rem) ^ 0x5412; // uint15
+        debug_assert_eq!(bits >> 15, 0);
+        bits
+    }
+
     // Draws two copies of the version bits (with its own error correction code),
     // based on this object's version field, iff 7 <= version <= 40.
     fn draw_version(&mut self) {
@@ -464,15 +506,15 @@ impl QrCode {
     // Sets the color of a module and marks it as a function module.
     // Only used by the constructor. Coordinates must be in bounds.
     fn set_function_module(&mut self, x: i32, y: i32, isdark: bool) {
-        *self.module_mut(x, y) = isdark;
-        self.isfunction[(y * self.size + x) as usize] = true;
+        self.modules.set(x, y, isdark);
+        self.isfunction.set(x, y, true);
     }
 
     /*---- Private helper methods for constructor: Codewords and masking ----*/
 
-    // Returns a new byte string representing the given data with the appropriate error correction
-    // codewords appended to it, based on this object's version and error correction level.
-    fn add_ecc_and_interleave(&self, data: &[u8]) -> Vec<u8> {
+    // Writes the given data with the appropriate error correction codewords into the given
+    // buffer, interleaved across the blocks, based on this object's version and error correction level.
+    fn add_ecc_and_interleave(&self, data: &[u8], result: &mut Vec<u8>) {
         let ver: Version = self.version;
         let ecl: QrCodeEcc = self.errorcorrectionlevel;
         assert_eq!(
@@ -487,34 +529,31 @@ impl QrCode {
         let rawcodewords: usize = QrCode::get_num_raw_data_modules(ver) / 8;
         let numshortblocks: usize = numblocks - rawcodewords % numblocks;
         let shortblocklen: usize = rawcodewords / numblocks;
+        let shortdatalen: usize = shortblocklen - blockecclen;
 
-        // Split data into blocks and append ECC to each block
-        let mut blocks = Vec::<Vec<u8>>::with_capacity(numblocks);
+        // Split data into blocks and write the bytes of every block straight to their
+        // interleaved (not concatenated) positions. Only long blocks have a final
+        // data byte at index shortdatalen, which the short blocks skip.
+        result.clear();
+        result.resize(rawcodewords, 0);
         let rsdiv: Vec<u8> = QrCode::reed_solomon_compute_divisor(blockecclen);
         let mut k: usize = 0;
         for i in 0..numblocks {
-            let datlen: usize = shortblocklen - blockecclen + usize::from(i >= numshortblocks);
-            let mut dat = data[k..k + datlen].to_vec();
+            let datlen: usize = shortdatalen + usize::from(i >= numshortblocks);
+            let dat: &[u8] = &data[k..k + datlen];
             k += datlen;
-            let ecc: Vec<u8> = QrCode::reed_solomon_compute_remainder(&dat, &rsdiv);
-            if i < numshortblocks {
-                dat.push(0);
+            for (j, &b) in dat.iter().enumerate() {
+                result[if j < shortdatalen {
+                    j * numblocks + i
+                } else {
+                    shortdatalen * numblocks + i - numshortblocks
+                }] = b;
             }
-            dat.extend_from_slice(&ecc);
-            blocks.push(dat);
-        }
-
-        // Interleave (not concatenate) the bytes from every block into a single sequence
-        let mut result = Vec::<u8>::with_capacity(rawcodewords);
-        for i in 0..=shortblocklen {
-            for (j, block) in blocks.iter().enumerate() {
-                // Skip the padding byte in short blocks
-                if i != shortblocklen - blockecclen || j >= numshortblocks {
-                    result.push(block[i]);
-                }
+            let ecc: Vec<u8> = QrCode::reed_solomon_compute_remainder(dat, &rsdiv);
+            for (j, &b) in ecc.iter().enumerate() {
+                result[data.len() + j * numblocks + i] = b;
             }
         }
-        result
     }
 
     // Draws the given sequence of 8-bit codewords (data and error correction) onto the entire
@@ -540,9 +579,13 @@ impl QrCode {
                     let x: i32 = right - j; // Actual x coordinate
                     let upward: bool = (right + 1) & 2 == 0;
                     let y: i32 = if upward { self.size - 1 - vert } else { vert }; // Actual y coordinate
-                    if !self.isfunction[(y * self.size + x) as usize] && i < data.len() * 8 {
-                        *self.module_mut(x, y) =
-                            get_bit(u32::from(data[i >> 3]), 7 - ((i as i32) & 7));
+                    if !self.isfunction.get(x, y) && i < data.len() * 8 {
+                        // The module is still light, so only dark bits need to be written
+                        self.modules.xor(
+                            x,
+                            y,
+                            get_bit(u32::from(data[i >> 3]), 7 - ((i as i32) & 7)),
+                        );
                         i += 1;
                     }
                     // If this QR Code has any remainder bits (0 to 7), they were assigned as
@@ -560,103 +603,203 @@ impl QrCode {
     // the same mask value a second time will undo the mask. A final well-formed
     // QR Code needs exactly one (not zero, two, etc.) mask applied.
     fn apply_mask(&mut self, mask: Mask) {
-        for y in 0..self.size {
-            for x in 0..self.size {
-                let invert: bool = match mask.value() {
-                    0 => (x + y) % 2 == 0,
-                    1 => y % 2 == 0,
-                    2 => x % 3 == 0,
-                    3 => (x + y) % 3 == 0,
-                    4 => (x / 3 + y / 2) % 2 == 0,
-                    5 => x * y % 2 + x * y % 3 == 0,
-                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
-                    7 => ((x + y) % 2 + x * y % 3) % 2 == 0,
-                    _ => unreachable!(),
-                };
-                *self.module_mut(x, y) ^= invert & !self.isfunction[(y * self.size + x) as usize];
+        QrCode::xor_mask_pattern(
+            &mut self.modules,
+            &self.isfunction,
+            mask,
+            false,
+            &mut Vec::new(),
+        );
+    }
+
+    // Returns the mask whose penalty score is the lowest after trying all 8 masks. The function
+    // modules must be marked and the codeword bits must be drawn. The modules are left unmasked.
+    fn choose_mask(&mut self, buffers: &mut EncodeBuffers) -> Mask {
+        // The columns are scored as the rows of the transposition, which is masked alongside
+        let transposed: &mut BitGrid = &mut buffers.transposed;
+        self.modules.transpose_into(transposed);
+        self.isfunction
+            .transpose_into(&mut buffers.transposedfunction);
+
+        let mut result = Mask::new(0);
+        let mut minpenalty = i32::MAX;
+        for i in 0u8..8 {
+            let i = Mask::new(i);
+            for undo in [false, true] {
+                QrCode::xor_mask_pattern(
+                    &mut self.modules,
+                    &self.isfunction,
+                    i,
+                    false,
+                    &mut buffers.maskrows,
+                );
+                QrCode::xor_mask_pattern(
+                    transposed,
+                    &buffers.transposedfunction,
+                    i,
+                    true,
+                    &mut buffers.maskrows,
+                );
+                if undo {
+                    break; // The mask is undone due to XOR
+                }
+
+                // Copy the format bits in row 8 and column 8 to the transposition
+                self.draw_format_bits(i);
+                for j in 0..self.size {
+                    transposed.set(8, j, self.module(j, 8));
+                    transposed.set(j, 8, self.module(8, j));
+                }
+                let penalty: i32 = QrCode::get_penalty_score(&self.modules, transposed);
+                if penalty < minpenalty {
+                    result = i;
+                    minpenalty = penalty;
+                }
+            }
+        }
+        result
+    }
+
+    // XORs the modules of the given grid that are not function modules with the given mask
+    // pattern. Iff transposed is true, then the grids hold the transposition of the QR Code,
+    // so the pattern is transposed as well. The pattern rows vector is only used as scratch memory.
+    fn xor_mask_pattern(
+        modules: &mut BitGrid,
+        isfunction: &BitGrid,
+        mask: Mask,
+        transposed: bool,
+        patternrows: &mut Vec<u64>,
+    ) {
+        // Every mask pattern repeats after 12 rows and after 12 columns
+        // (a multiple of 2, 3 and 4), so the first 12 rows are packed once
+        let size: i32 = modules.width();
+        let stride: usize = modules.stride();
+        patternrows.clear();
+        patternrows.resize(12 * stride, 0);
+        for y in 0..12 {
+            for x in 0..size {
+                let (px, py) = if transposed { (y, x) } else { (x, y) };
+                if QrCode::get_mask_bit(mask, px, py) {
+                    patternrows[y as usize * stride + (x as usize >> 6)] |= 1 << (x & 63);
+                }
+            }
+        }
+
+        for y in 0..size {
+            let pattern: &[u64] = &patternrows[(y % 12) as usize * stride..][..stride];
+            let functions: &[u64] = isfunction.row(y);
+            for ((word, &pat), &func) in modules.row_mut(y).iter_mut().zip(pattern).zip(functions) {
+                *word ^= pat & !func;
             }
         }
     }
 
-    // Calculates and returns the penalty score based on state of this QR Code's current modules.
-    // This is used by the automatic mask choice algorithm to find the mask pattern that yields the lowest score.
-    fn get_penalty_score(&self) -> i32 {
+    // Returns true iff the given mask pattern inverts the module at the given coordinates.
+    fn get_mask_bit(mask: Mask, x: i32, y: i32) -> bool {
+        match mask.value() {
+            0 => (x + y) % 2 == 0,
+            1 => y % 2 == 0,
+            2 => x % 3 == 0,
+            3 => (x + y) % 3 == 0,
+            4 => (x / 3 + y / 2) % 2 == 0,
+            5 => x * y % 2 + x * y % 3 == 0,
+            6 => (x * y % 2 + x * y % 3) % 2 == 0,
+            7 => ((x + y) % 2 + x * y % 3) % 2 == 0,
+            _ => unreachable!(),
+        }
+    }
+
+    // Calculates and returns the penalty score based on the given modules of a QR Code and their
+    // transposition. This is used by the automatic mask choice algorithm to find the mask pattern
+    // that yields the lowest score. Whole rows are processed a word of 64 modules at a time.
+    fn get_penalty_score(modules: &BitGrid, transposed: &BitGrid) -> i32 {
         let mut result: i32 = 0;
-        let size: i32 = self.size;
+        let size: i32 = modules.width();
 
         // Adjacent modules in row having same color, and finder-like patterns
         for y in 0..size {
-            let mut runcolor = false;
-            let mut runx: i32 = 0;
-            let mut runhistory = FinderPenalty::new(size);
-            for x in 0..size {
-                if self.module(x, y) == runcolor {
-                    runx += 1;
-                    if runx == 5 {
-                        result += PENALTY_N1;
-                    } else if runx > 5 {
-                        result += 1;
-                    }
-                } else {
-                    runhistory.add_history(runx);
-                    if !runcolor {
-                        result += runhistory.count_patterns() * PENALTY_N3;
-                    }
-                    runcolor = self.module(x, y);
-                    runx = 1;
-                }
-            }
-            result += runhistory.terminate_and_count(runcolor, runx) * PENALTY_N3;
+            result += QrCode::get_line_penalty(modules.row(y), size);
         }
         // Adjacent modules in column having same color, and finder-like patterns
         for x in 0..size {
-            let mut runcolor = false;
-            let mut runy: i32 = 0;
-            let mut runhistory = FinderPenalty::new(size);
-            for y in 0..size {
-                if self.module(x, y) == runcolor {
-                    runy += 1;
-                    if runy == 5 {
-                        result += PENALTY_N1;
-                    } else if runy > 5 {
-                        result += 1;
-                    }
-                } else {
-                    runhistory.add_history(runy);
-                    if !runcolor {
-                        result += runhistory.count_patterns() * PENALTY_N3;
-                    }
-                    runcolor = self.module(x, y);
-                    runy = 1;
-                }
-            }
-            result += runhistory.terminate_and_count(runcolor, runy) * PENALTY_N3;
+            result += QrCode::get_line_penalty(transposed.row(x), size);
         }
 
         // 2*2 blocks of modules having same color
+        let stride: usize = modules.stride();
         for y in 0..size - 1 {
-            for x in 0..size - 1 {
-                let color: bool = self.module(x, y);
-                if color == self.module(x + 1, y)
-                    && color == self.module(x, y + 1)
-                    && color == self.module(x + 1, y + 1)
-                {
-                    result += PENALTY_N2;
+            let upper: &[u64] = modules.row(y);
+            let lower: &[u64] = modules.row(y + 1);
+            for i in 0..stride {
+                // Shifts the modules at x + 1 into bit x
+                let next = |words: &[u64]| words[i] >> 1 | words.get(i + 1).map_or(0, |w| w << 63);
+                let samebelow: u64 = !(upper[i] ^ lower[i]);
+                let samebelownext: u64 = !(next(upper) ^ next(lower));
+                let sameright: u64 = !(upper[i] ^ next(upper));
+                let mut blocks: u64 = samebelow & samebelownext & sameright;
+                // Only blocks with their top left corner at x < size - 1 count
+                let valid: usize = (size - 1) as usize - i * 64;
+                if valid < 64 {
+                    blocks &= (1 << valid) - 1;
                 }
+                result += blocks.count_ones() as i32 * PENALTY_N2;
             }
         }
 
         // Balance of dark and light modules
-        let dark: i32 = self.modules.iter().copied().map(i32::from).sum();
+        let dark: i32 = modules.count_ones() as i32;
         let total: i32 = size * size; // Note that size is odd, so dark/total != 1/2
                                       // Compute the smallest integer k >= 0 such that (45-5k)% <= dark/total <= (55+5k)%
         let k: i32 = ((dark * 20 - total * 10).abs() + total - 1) / total - 1;
-        debug_assert!(0 <= k && k <= 9);
+        debug_assert!((0..=9).contains(&k));
         result += k * PENALTY_N4;
-        debug_assert!(0 <= result && result <= 2568888); // Non-tight upper bound based on default values of PENALTY_N1, ..., N4
+        debug_assert!((0..=2568888).contains(&result)); // Non-tight upper bound based on default values of PENALTY_N1, ..., N4
         result
     }
 
+    // Returns the penalty score for runs of the same color and finder-like patterns
+    // in the given packed line of modules. The line is walked a run at a time.
+    fn get_line_penalty(line: &[u64], size: i32) -> i32 {
+        let mut result: i32 = 0;
+        let mut runcolor = false;
+        let mut start: i32 = 0;
+        let mut runhistory = FinderPenalty::new(size);
+        loop {
+            let end: i32 = QrCode::find_color_change(line, start, runcolor, size);
+            let runlength: i32 = end - start;
+            if runlength >= 5 {
+                result += PENALTY_N1 + runlength - 5;
+            }
+            if end == size {
+                return result + runhistory.terminate_and_count(runcolor, runlength) * PENALTY_N3;
+            }
+            runhistory.add_history(runlength);
+            if !runcolor {
+                result += runhistory.count_patterns() * PENALTY_N3;
+            }
+            runcolor = !runcolor;
+            start = end;
+        }
+    }
+
+    // Returns the position of the first module at or after start in the given packed line
+    // whose color differs from the given color, or size if there is none.
+    fn find_color_change(line: &[u64], start: i32, color: bool, size: i32) -> i32 {
+        let flip: u64 = if color { !0 } else { 0 };
+        let mut i: usize = start as usize >> 6;
+        let mut word: u64 = (line[i] ^ flip) & (!0 << (start & 63));
+        loop {
+            if word != 0 {
+                return std::cmp::min((i * 64) as i32 + word.trailing_zeros() as i32, size);
+            }
+            i += 1;
+            if i == line.len() {
+                return size;
+            }
+            word = line[i] ^ flip;
+        }
+    }
+
     /*---- Private helper functions ----*/
 
     // Returns an ascending list of positions of alignment patterns for this version number.
@@ -699,6 +842,12 @@ impl QrCode {
         result
     }
 
+    /// Returns the number of bytes that a QR Code of the given version number and error
+    /// correction level holds, including the segment headers and the terminator.
+    pub fn data_capacity(ver: Version, ecl: QrCodeEcc) -> usize {
+        QrCode::get_num_data_codewords(ver, ecl)
+    }
+
     // Returns the number of 8-bit data (i.e. not error correction) codewords contained in any
     // QR Code of the given version number and error correction level, with remainder bits discarded.
     // This stateless pure function could be implemented as a (40*4)-cell lookup table.
@@ -932,7 +1081,7 @@ pub struct QrSegment {
     numchars: usize,
 
     // The data bits of this segment. Accessed through data().
-    data: Vec<bool>,
+    data: BitBuffer,
 }
 
 impl QrSegment {
@@ -944,25 +1093,22 @@ impl QrSegment {
     ///
     /// Any text string can be converted to UTF-8 bytes and encoded as a byte mode segment.
     pub fn make_bytes(data: &[u8]) -> Self {
-        let mut bb = BitBuffer(Vec::with_capacity(data.len() * 8));
-        for &b in data {
-            bb.append_bits(u32::from(b), 8);
-        }
-        QrSegment::new(QrSegmentMode::Byte, data.len(), bb.0)
+        let bb = BitBuffer {
+            bytes: data.to_vec(),
+            len: data.len() * 8,
+        };
+        QrSegment::new(QrSegmentMode::Byte, data.len(), bb)
     }
 
     /// Returns a segment representing the given string of decimal digits encoded in numeric mode.
     ///
     /// Panics if the string contains non-digit characters.
     pub fn make_numeric(text: &str) -> Self {
-        let mut bb = BitBuffer(Vec::with_capacity(text.len() * 3 + (text.len() + 2) / 3));
+        let mut bb = BitBuffer::with_capacity(text.len() * 3 + text.len().div_ceil(3));
         let mut accumdata: u32 = 0;
         let mut accumcount: u8 = 0;
         for b in text.bytes() {
-            assert!(
-                (b'0'..=b'9').contains(&b),
-                "String contains non-numeric characters"
-            );
+            assert!(b.is_ascii_digit(), "String contains non-numeric characters");
             accumdata = accumdata * 10 + u32::from(b - b'0');
             accumcount += 1;
             if accumcount == 3 {
@@ -975,7 +1121,7 @@ impl QrSegment {
             // 1 or 2 digits remaining
             bb.append_bits(accumdata, accumcount * 3 + 1);
         }
-        QrSegment::new(QrSegmentMode::Numeric, text.len(), bb.0)
+        QrSegment::new(QrSegmentMode::Numeric, text.len(), bb)
     }
 
     /// Returns a segment representing the given text string encoded in alphanumeric mode.
@@ -985,7 +1131,7 @@ impl QrSegment {
     ///
     /// Panics if the string contains non-encodable characters.
     pub fn make_alphanumeric(text: &str) -> Self {
-        let mut bb = BitBuffer(Vec::with_capacity(text.len() * 5 + (text.len() + 1) / 2));
+        let mut bb = BitBuffer::with_capacity(text.len() * 5 + text.len().div_ceil(2));
         let mut accumdata: u32 = 0;
         let mut accumcount: u32 = 0;
         for c in text.chars() {
@@ -1004,7 +1150,26 @@ impl QrSegment {
             // 1 character remaining
             bb.append_bits(accumdata, 6);
         }
-        QrSegment::new(QrSegmentMode::Alphanumeric, text.len(), bb.0)
+        QrSegment::new(QrSegmentMode::Alphanumeric, text.len(), bb)
+    }
+
+    /// Returns a segment representing the given text string encoded in kanji mode.
+    ///
+    /// Only the characters of the JIS X 0208 set are allowed, which are the double
+    /// byte characters of Shift JIS. Each character takes 13 bits instead of the
+    /// 16 or 24 bits of its UTF-8 form in byte mode.
+    ///
+    /// Panics if the string contains non-encodable characters.
+    pub fn make_kanji(text: &str) -> Self {
+        let mut bb = BitBuffer::with_capacity(text.len() * 13);
+        let mut numchars: usize = 0;
+        for c in text.chars() {
+            let value: u16 = kanji::to_kanji_value(c)
+                .expect("String contains unencodable characters in kanji mode");
+            bb.append_bits(u32::from(value), 13);
+            numchars += 1;
+        }
+        QrSegment::new(QrSegmentMode::Kanji, numchars, bb)
     }
 
     /// Returns a list of zero or more segments to represent the given Unicode text string.
@@ -1025,18 +1190,28 @@ impl QrSegment {
         }
     }
 
-    fn compute_character_modes(text: &str, version: Version) -> Vec<QrSegmentMode> {
-        let mut char_modes = vec![vec![None; 3]; text.chars().count()];
-
-        let mode_types = [
-            QrSegmentMode::Byte,
-            QrSegmentMode::Alphanumeric,
-            QrSegmentMode::Numeric,
-        ];
+    // Returns the mode of each character for the fewest bits. Without byte mode,
+    // every character must be encodable in one of the other modes.
+    fn compute_character_modes(
+        text: &str,
+        version: Version,
+        byte: bool,
+        kanji: bool,
+    ) -> Vec<QrSegmentMode> {
+        let mut mode_types = Vec::with_capacity(4);
+        if byte {
+            mode_types.push(QrSegmentMode::Byte);
+        }
+        mode_types.push(QrSegmentMode::Alphanumeric);
+        mode_types.push(QrSegmentMode::Numeric);
+        if kanji {
+            mode_types.push(QrSegmentMode::Kanji);
+        }
 
         let num_modes = mode_types.len();
 
-        // TODO kanji and eci
+        let mut char_modes = vec![vec![None; num_modes]; text.chars().count()];
+
         let head_costs = mode_types
             .iter()
             .map(|t| t.header_cost(version) as usize)
@@ -1049,17 +1224,20 @@ impl QrSegment {
 
             let current_char_mode = &mut char_modes[i];
 
-            current_costs[0] = Some(prev_costs[0] + c.len_utf8() * 8 * 6);
-            current_char_mode[0] = Some(QrSegmentMode::Byte);
-
-            if QrSegment::is_alphanumeric_chars([c]) {
-                current_costs[1] = Some(prev_costs[1] + 33);
-                current_char_mode[1] = Some(QrSegmentMode::Alphanumeric);
-            }
-
-            if QrSegment::is_numeric_chars([c]) {
-                current_costs[2] = Some(prev_costs[2] + 20);
-                current_char_mode[2] = Some(QrSegmentMode::Numeric);
+            for (m, &mode) in mode_types.iter().enumerate() {
+                let cost: Option<usize> = match mode {
+                    QrSegmentMode::Byte => Some(c.len_utf8() * 8 * 6),
+                    QrSegmentMode::Alphanumeric => {
+                        QrSegment::is_alphanumeric_chars([c]).then_some(33)
+                    }
+                    QrSegmentMode::Numeric => QrSegment::is_numeric_chars([c]).then_some(20),
+                    QrSegmentMode::Kanji => QrSegment::is_kanji_chars([c]).then_some(78),
+                    _ => None,
+                };
+                if let Some(cost) = cost {
+                    current_costs[m] = Some(prev_costs[m] + cost);
+                    current_char_mode[m] = Some(mode);
+                }
             }
 
             for j in 0..num_modes {
@@ -1067,8 +1245,8 @@ impl QrSegment {
                 for k in 0..num_modes {
                     // From mode
 
-                    if let Some(_) = current_char_mode[k] {
-                        let new_cost = (current_costs[k].unwrap() + 5) / 6 * 6 + head_costs[j];
+                    if current_char_mode[k].is_some() {
+                        let new_cost = current_costs[k].unwrap().div_ceil(6) * 6 + head_costs[j];
 
                         if current_char_mode[j].is_none() || new_cost < current_costs[j].unwrap() {
                             current_costs[j] = Some(new_cost);
@@ -1085,7 +1263,7 @@ impl QrSegment {
                 .collect();
         }
 
-        let mut current_mode = QrSegmentMode::Byte;
+        let mut current_mode = mode_types[0];
         let mut min_cost = prev_costs[0];
 
         for i in 0..num_modes {
@@ -1113,7 +1291,7 @@ impl QrSegment {
     }
 
     fn split_into_segments(text: &str, char_modes: Vec<QrSegmentMode>) -> Vec<QrSegment> {
-        assert!(text.len() > 0);
+        assert!(!text.is_empty());
 
         let mut current_mode = char_modes[0];
         let mut start = 0;
@@ -1124,7 +1302,8 @@ impl QrSegment {
             QrSegmentMode::Byte => result.push(QrSegment::make_bytes(slice.as_bytes())),
             QrSegmentMode::Alphanumeric => result.push(QrSegment::make_alphanumeric(slice)),
             QrSegmentMode::Numeric => result.push(QrSegment::make_numeric(slice)),
-            _ => panic!("only implemented for byte, alphanumeric and numeric"),
+            QrSegmentMode::Kanji => result.push(QrSegment::make_kanji(slice)),
+            _ => panic!("only implemented for byte, alphanumeric, numeric and kanji"),
         };
 
         for (char_index, (byte_index, _)) in text.char_indices().enumerate() {
@@ -1145,8 +1324,39 @@ impl QrSegment {
         result
     }
 
-    fn make_segments_optimally_for_version(text: &str, version: Version) -> Vec<QrSegment> {
-        Self::split_into_segments(text, Self::compute_character_modes(text, version))
+    fn make_segments_optimally_for_version(
+        text: &str,
+        version: Version,
+        kanji: bool,
+        utf8eci: bool,
+    ) -> Vec<QrSegment> {
+        let segments = |byte: bool| {
+            Self::split_into_segments(
+                text,
+                Self::compute_character_modes(text, version, byte, kanji),
+            )
+        };
+
+        let mut segs = segments(true);
+        if utf8eci && segs.iter().any(|seg| seg.mode == QrSegmentMode::Byte) {
+            segs.insert(0, QrSegment::make_eci(UTF8_ECI_ASSIGNMENT));
+
+            // The designator is only needed with byte mode, so the text may take
+            // fewer bits without it if all characters fit the other modes
+            let encodable = |c: char| {
+                QrSegment::is_alphanumeric_chars([c]) || (kanji && QrSegment::is_kanji_chars([c]))
+            };
+            if text.chars().all(encodable) {
+                let bits = |segs: &[QrSegment]| {
+                    QrSegment::get_total_bits(segs, version).unwrap_or(usize::MAX)
+                };
+                let without = segments(false);
+                if bits(&without) <= bits(&segs) {
+                    segs = without;
+                }
+            }
+        }
+        segs
     }
 
     pub fn make_segments_optimally(
@@ -1154,10 +1364,32 @@ impl QrSegment {
         ecl: QrCodeEcc,
         minversion: Version,
         maxversion: Version,
+    ) -> Result<Vec<QrSegment>, DataTooLong> {
+        Self::make_segments_optimally_advanced(text, ecl, minversion, maxversion, false, false)
+    }
+
+    /// Returns a list of segments that represent the given text string in the fewest bits
+    /// for the smallest version within the given range that fits the text.
+    ///
+    /// Iff kanji is `true`, then characters of the JIS X 0208 set may be encoded in kanji
+    /// mode. Readers commonly return such segments as Shift JIS bytes, so this should only
+    /// be enabled when the text is meant to be displayed rather than processed as bytes.
+    /// Iff utf8eci is `true` and any byte mode segment is used, then the list starts
+    /// with an ECI designator for UTF-8, so that readers don't have to guess the
+    /// character set of the bytes.
+    ///
+    /// Returns `Err` if the text is too long to fit in any version in the given range.
+    pub fn make_segments_optimally_advanced(
+        text: &str,
+        ecl: QrCodeEcc,
+        minversion: Version,
+        maxversion: Version,
+        kanji: bool,
+        utf8eci: bool,
     ) -> Result<Vec<QrSegment>, DataTooLong> {
         assert!(minversion <= maxversion, "Invalid value");
 
-        let mut segs = Self::make_segments_optimally_for_version(text, minversion);
+        let mut segs = Self::make_segments_optimally_for_version(text, minversion, kanji, utf8eci);
 
         let mut data_capacity_bits;
         let mut data_used_bits;
@@ -1166,7 +1398,7 @@ impl QrSegment {
             let version = Version::new(version);
 
             if version.value() == 10 || version.value() == 27 {
-                segs = Self::make_segments_optimally_for_version(text, version);
+                segs = Self::make_segments_optimally_for_version(text, version, kanji, utf8eci);
             }
 
             data_capacity_bits = QrCode::get_num_data_codewords(version, ecl) * 8;
@@ -1194,7 +1426,7 @@ impl QrSegment {
     /// Returns a segment representing an Extended Channel Interpretation
     /// (ECI) designator with the given assignment value.
     pub fn make_eci(assignval: u32) -> Self {
-        let mut bb = BitBuffer(Vec::with_capacity(24));
+        let mut bb = BitBuffer::with_capacity(24);
         if assignval < (1 << 7) {
             bb.append_bits(assignval, 8);
         } else if assignval < (1 << 14) {
@@ -1206,7 +1438,22 @@ impl QrSegment {
         } else {
             panic!("ECI assignment value out of range");
         }
-        QrSegment::new(QrSegmentMode::Eci, 0, bb.0)
+        QrSegment::new(QrSegmentMode::Eci, 0, bb)
+    }
+
+    /// Returns a segment representing a Structured Append header with the given
+    /// symbol position, total symbol count and parity.
+    ///
+    /// The standard puts the header in front of the other segments, but readers that
+    /// do not know the mode stop at it, so it may also be the last one. All symbols of one message
+    /// carry the same total and parity, so that readers can reassemble the message
+    /// from up to 16 symbols in any scanning order.
+    pub fn make_structured_append(header: StructuredAppend) -> Self {
+        let mut bb = BitBuffer::with_capacity(16);
+        bb.append_bits(u32::from(header.position()), 4);
+        bb.append_bits(u32::from(header.total() - 1), 4);
+        bb.append_bits(u32::from(header.parity()), 8);
+        QrSegment::new(QrSegmentMode::StructuredAppend, 0, bb)
     }
 
     /*---- Constructor (low level) ----*/
@@ -1215,7 +1462,7 @@ impl QrSegment {
     ///
     /// The character count (numchars) must agree with the mode and
     /// the bit buffer length, but the constraint isn't checked.
-    pub fn new(mode: QrSegmentMode, numchars: usize, data: Vec<bool>) -> Self {
+    pub fn new(mode: QrSegmentMode, numchars: usize, data: BitBuffer) -> Self {
         Self {
             mode,
             numchars,
@@ -1236,7 +1483,7 @@ impl QrSegment {
     }
 
     /// Returns the data bits of this segment.
-    pub fn data(&self) -> &Vec<bool> {
+    pub fn data(&self) -> &BitBuffer {
         &self.data
     }
 
@@ -1269,7 +1516,7 @@ impl QrSegment {
     }
 
     fn is_numeric_chars(chars: impl IntoIterator<Item = char>) -> bool {
-        chars.into_iter().all(|c| ('0'..='9').contains(&c))
+        chars.into_iter().all(|c: char| c.is_ascii_digit())
     }
 
     /// Tests whether the given string can be encoded as a segment in alphanumeric mode.
@@ -1283,12 +1530,29 @@ impl QrSegment {
     fn is_alphanumeric_chars(chars: impl IntoIterator<Item = char>) -> bool {
         chars.into_iter().all(|c| ALPHANUMERIC_CHARSET.contains(c))
     }
+
+    /// Tests whether the given string can be encoded as a segment in kanji mode.
+    ///
+    /// A string is encodable iff each character is in the JIS X 0208 set,
+    /// which are the double byte characters of Shift JIS.
+    pub fn is_kanji(text: &str) -> bool {
+        Self::is_kanji_chars(text.chars())
+    }
+
+    fn is_kanji_chars(chars: impl IntoIterator<Item = char>) -> bool {
+        chars
+            .into_iter()
+            .all(|c| kanji::to_kanji_value(c).is_some())
+    }
 }
 
 // The set of all legal characters in alphanumeric mode,
 // where each character value maps to the index in the string.
 static ALPHANUMERIC_CHARSET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
 
+// The ECI assignment value of the UTF-8 character set.
+const UTF8_ECI_ASSIGNMENT: u32 = 26;
+
 /*---- QrSegmentMode functionality ----*/
 
 /// Describes how a segment's data bits are interpreted.
@@ -1299,6 +1563,7 @@ pub enum QrSegmentMode {
     Byte,
     Kanji,
     Eci,
+    StructuredAppend,
 }
 
 impl QrSegmentMode {
@@ -1312,6 +1577,7 @@ impl QrSegmentMode {
             Byte => 0x4,
             Kanji => 0x8,
             Eci => 0x7,
+            StructuredAppend => 0x3,
         }
     }
 
@@ -1325,6 +1591,7 @@ impl QrSegmentMode {
             Byte => [8, 16, 16],
             Kanji => [8, 10, 12],
             Eci => [0, 0, 0],
+            StructuredAppend => [0, 0, 0],
         })[usize::from((ver.value() + 7) / 17)]
     }
 
@@ -1343,17 +1610,143 @@ impl QrSegmentMode {
 
 /// An appendable sequence of bits (0s and 1s).
 ///
-/// Mainly used by QrSegment.
-pub struct BitBuffer(pub Vec<bool>);
+/// Mainly used by QrSegment. The bits are packed into bytes in big endian
+/// order, so a buffer of whole bytes can be used as codewords directly.
+#[derive(Clone, PartialEq, Eq, Default, Debug)]
+pub struct BitBuffer {
+    // The packed bits. The unused low bits of the last byte are always 0.
+    bytes: Vec<u8>,
+
+    // The number of bits.
+    len: usize,
+}
 
 impl BitBuffer {
+    /// Creates an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty buffer with space for at least the given number of bits.
+    pub fn with_capacity(bits: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(bits.div_ceil(8)),
+            len: 0,
+        }
+    }
+
+    /// Returns the number of bits in this buffer.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Tests whether this buffer holds no bits.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the bit at the given index.
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn get(&self, index: usize) -> bool {
+        assert!(index < self.len, "Index out of bounds");
+        (self.bytes[index >> 3] >> (7 - (index & 7))) & 1 != 0
+    }
+
+    /// Returns an iterator over the bits of this buffer.
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).map(|i| self.get(i))
+    }
+
+    /// Returns the packed bytes of this buffer. The last byte
+    /// is padded with 0 bits if the length is not a multiple of 8.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Removes all bits, keeping the allocated memory.
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.len = 0;
+    }
+
     /// Appends the given number of low-order bits of the given value to this buffer.
     ///
     /// Requires len &#x2264; 31 and val &lt; 2<sup>len</sup>.
     pub fn append_bits(&mut self, val: u32, len: u8) {
         assert!(len <= 31 && val >> len == 0, "Value out of range");
-        self.0
-            .extend((0..i32::from(len)).rev().map(|i| get_bit(val, i))); // Append bit by bit
+        let mut remaining: u8 = len;
+        while remaining > 0 {
+            // Fill the free low bits of the last byte, starting a new byte if it is full
+            let used = (self.len & 7) as u8;
+            if used == 0 {
+                self.bytes.push(0);
+            }
+            let n: u8 = std::cmp::min(8 - used, remaining);
+            let chunk: u32 = (val >> (remaining - n)) & ((1 << n) - 1);
+            *self.bytes.last_mut().unwrap() |= (chunk << (8 - used - n)) as u8;
+            remaining -= n;
+            self.len += usize::from(n);
+        }
+    }
+
+    /// Appends all bits of the given buffer to this buffer.
+    pub fn append_buffer(&mut self, other: &BitBuffer) {
+        if self.len & 7 == 0 {
+            self.bytes.extend_from_slice(&other.bytes);
+            self.len += other.len;
+            return;
+        }
+        let wholebytes: usize = other.len / 8;
+        for &b in &other.bytes[..wholebytes] {
+            self.append_bits(u32::from(b), 8);
+        }
+        let restlen = (other.len % 8) as u8;
+        if restlen > 0 {
+            self.append_bits(u32::from(other.bytes[wholebytes] >> (8 - restlen)), restlen);
+        }
+    }
+}
+
+/*---- Encode buffers functionality ----*/
+
+/// Memory that is reused across QR Code encodings.
+///
+/// Encoding a QR Code needs a few intermediate buffers whose size depends on the version.
+/// When many QR Codes are encoded in a row, such as for printing a batch of sheets, passing
+/// the same buffers to `QrCode::encode_segments_buffered()` and handing QR Codes that are no
+/// longer needed back with `recycle()` makes these allocations happen only once.
+#[derive(Clone, Default)]
+pub struct EncodeBuffers {
+    // The data bit string, which becomes the data codewords.
+    bits: BitBuffer,
+
+    // The data and error correction codewords after interleaving.
+    codewords: Vec<u8>,
+
+    // The modules of a recycled QR Code, reused for the next one.
+    modules: BitGrid,
+
+    // The function modules of the QR Code being encoded.
+    isfunction: BitGrid,
+
+    // The transposed modules and function modules, used to score columns while choosing a mask.
+    transposed: BitGrid,
+    transposedfunction: BitGrid,
+
+    // The packed rows of a mask pattern.
+    maskrows: Vec<u64>,
+}
+
+impl EncodeBuffers {
+    /// Creates empty buffers, which allocate on first use.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes back the module memory of the given QR Code for the next encoding.
+    pub fn recycle(&mut self, qrcode: QrCode) {
+        self.modules = qrcode.modules;
     }
 }
 
@@ -1439,6 +1832,60 @@ impl Mask {
     }
 }
 
+/// The header of a symbol that is part of a Structured Append sequence.
+///
+/// A message can be spread across up to 16 symbols. Each symbol stores its
+/// position in the sequence, the total number of symbols and a parity byte,
+/// which is the XOR of all bytes of the whole message.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct StructuredAppend {
+    position: u8,
+    total: u8,
+    parity: u8,
+}
+
+impl StructuredAppend {
+    /// The maximum number of symbols in a Structured Append sequence.
+    pub const MAX_SYMBOLS: u8 = 16;
+
+    /// Creates a header for the symbol at the given position.
+    ///
+    /// Panics if the total is outside the range [1, 16] or the
+    /// position is not smaller than the total.
+    pub const fn new(position: u8, total: u8, parity: u8) -> Self {
+        assert!(
+            1 <= total && total <= Self::MAX_SYMBOLS,
+            "Symbol count out of range"
+        );
+        assert!(position < total, "Symbol position out of range");
+        Self {
+            position,
+            total,
+            parity,
+        }
+    }
+
+    /// Computes the parity byte of the given message data.
+    pub fn parity_of<'a>(data: impl IntoIterator<Item = &'a [u8]>) -> u8 {
+        data.into_iter().flatten().fold(0, |acc, b| acc ^ b)
+    }
+
+    /// Returns the zero-based position of the symbol, which is in the range [0, 15].
+    pub const fn position(self) -> u8 {
+        self.position
+    }
+
+    /// Returns the number of symbols in the sequence, which is in the range [1, 16].
+    pub const fn total(self) -> u8 {
+        self.total
+    }
+
+    /// Returns the parity byte of the whole message.
+    pub const fn parity(self) -> u8 {
+        self.parity
+    }
+}
+
 // Returns true iff the i'th bit of x is set to 1.
 fn get_bit(x: u32, i: i32) -> bool {
     (x >> i) & 1 != 0
@@ -1456,7 +1903,10 @@ mod tests {
     use proptest::{prelude::prop, prop_oneof, proptest, strategy::Strategy};
     use quircs::Quirc;
 
-    use crate::{QrCode, QrCodeEcc};
+    use crate::{
+        kanji, BitBuffer, BitGrid, EncodeBuffers, FinderPenalty, Mask, QrCode, QrCodeEcc,
+        QrSegment, QrSegmentMode, Version, PENALTY_N1, PENALTY_N2, PENALTY_N3, PENALTY_N4,
+    };
 
     fn qrcode_to_image(qrcode: &QrCode, scale: u32) -> image::ImageBuffer<Luma<u8>, Vec<u8>> {
         let size = qrcode.size() as u32;
@@ -1480,7 +1930,7 @@ mod tests {
             }
         }
 
-        img.into()
+        img
     }
 
     fn text_in_range(truncate: usize) -> impl Strategy<Value = String> {
@@ -1519,6 +1969,247 @@ mod tests {
         }
     }
 
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(50))]
+        #[test]
+        fn kanji_roundtrip(
+            // Starts with a byte mode character, so that the symbol announces UTF-8
+            text in "é[ぁ-んァ-ン一-龥ａ-ｚa-z0-9 ]{1,100}",
+            ecl: QrCodeEcc
+        ) {
+            let segs = QrSegment::make_segments_optimally_advanced(
+                &text, ecl, Version::MIN, Version::MAX, true, true,
+            ).unwrap();
+
+            // Kanji mode never makes the bit stream longer
+            let version = Version::new(10);
+            let bits = |kanji: bool| QrSegment::get_total_bits(
+                &QrSegment::make_segments_optimally_for_version(&text, version, kanji, false),
+                version,
+            ).unwrap();
+            assert!(bits(true) <= bits(false));
+
+            let qrcode = QrCode::encode_segments(&segs, ecl).unwrap();
+            let img = qrcode_to_image(&qrcode, 8);
+            let code = Quirc::new()
+                .identify(img.width() as usize, img.height() as usize, &img)
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(code.decode().unwrap().payload, expected_payload(&text, &segs));
+        }
+    }
+
+    // The bytes a reader returns for the segments of the text, which are Shift JIS for
+    // kanji mode whatever character set the symbol announces
+    fn expected_payload(text: &str, segs: &[QrSegment]) -> Vec<u8> {
+        let mut chars = text.chars();
+        let mut result = Vec::new();
+        for seg in segs {
+            match seg.mode() {
+                QrSegmentMode::Eci => {}
+                QrSegmentMode::Kanji => {
+                    for c in chars.by_ref().take(seg.num_chars()) {
+                        let value = kanji::to_kanji_value(c).unwrap();
+                        result.extend_from_slice(&kanji::to_shift_jis(value));
+                    }
+                }
+                // The count of byte mode segments is in bytes rather than characters
+                QrSegmentMode::Byte => {
+                    let end = result.len() + seg.num_chars();
+                    while result.len() < end {
+                        let c = chars.next().unwrap();
+                        result.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                    }
+                }
+                _ => result.extend(chars.by_ref().take(seg.num_chars()).map(|c| c as u8)),
+            }
+        }
+        result
+    }
+
+    #[test]
+    fn utf8_eci_cost() {
+        // Byte mode takes 36 bits, but 48 with the designator, which
+        // alphanumeric and kanji mode do without in 44 bits
+        let segs = QrSegment::make_segments_optimally_for_version("Aα", Version::MIN, true, true);
+        assert_eq!(
+            segs.iter().map(QrSegment::mode).collect::<Vec<_>>(),
+            [QrSegmentMode::Alphanumeric, QrSegmentMode::Kanji]
+        );
+        assert_eq!(QrSegment::get_total_bits(&segs, Version::MIN), Some(44));
+
+        // Without kanji mode there is no way around the designator
+        let segs = QrSegment::make_segments_optimally_for_version("Aα", Version::MIN, false, true);
+        assert_eq!(segs[0].mode(), QrSegmentMode::Eci);
+        assert_eq!(QrSegment::get_total_bits(&segs, Version::MIN), Some(48));
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(50))]
+        #[test]
+        fn utf8_eci_roundtrip(
+            text in text_in_range(100),
+            ecl: QrCodeEcc
+        ) {
+            let segs = QrSegment::make_segments_optimally_advanced(
+                &text, ecl, Version::MIN, Version::MAX, false, true,
+            ).unwrap();
+
+            let hasbytes = segs.iter().any(|seg| seg.mode() == QrSegmentMode::Byte);
+            assert_eq!(segs[0].mode() == QrSegmentMode::Eci, hasbytes);
+
+            let mut decoder = Quirc::new();
+            let qrcode = QrCode::encode_segments(&segs, ecl).unwrap();
+            let img = qrcode_to_image(&qrcode, 40);
+            let mut results = decoder.identify(img.width() as usize, img.height() as usize, &img);
+            let decoded_code = results.next().unwrap().unwrap().decode().unwrap();
+
+            assert_eq!(from_utf8(&decoded_code.payload).unwrap(), text);
+            assert_eq!(decoded_code.eci.map(|eci| eci as u32), hasbytes.then_some(26));
+        }
+    }
+
+    #[test]
+    fn kanji_characters() {
+        assert!(QrSegment::is_kanji("漢字テスト"));
+        assert!(!QrSegment::is_kanji("漢字 test"));
+
+        let seg = QrSegment::make_kanji("漢字テスト");
+        assert_eq!(seg.num_chars(), 5);
+        assert_eq!(seg.data().len(), 5 * 13);
+    }
+
+    // The penalty score computed module by module, as the specification describes it.
+    fn reference_penalty_score(qrcode: &QrCode) -> i32 {
+        let mut result: i32 = 0;
+        let size: i32 = qrcode.size;
+        let module = |x: i32, y: i32, transposed: bool| {
+            if transposed {
+                qrcode.module(y, x)
+            } else {
+                qrcode.module(x, y)
+            }
+        };
+
+        // Adjacent modules in row and column having same color, and finder-like patterns
+        for transposed in [false, true] {
+            for y in 0..size {
+                let mut runcolor = false;
+                let mut runx: i32 = 0;
+                let mut runhistory = FinderPenalty::new(size);
+                for x in 0..size {
+                    if module(x, y, transposed) == runcolor {
+                        runx += 1;
+                        if runx == 5 {
+                            result += PENALTY_N1;
+                        } else if runx > 5 {
+                            result += 1;
+                        }
+                    } else {
+                        runhistory.add_history(runx);
+                        if !runcolor {
+                            result += runhistory.count_patterns() * PENALTY_N3;
+                        }
+                        runcolor = module(x, y, transposed);
+                        runx = 1;
+                    }
+                }
+                result += runhistory.terminate_and_count(runcolor, runx) * PENALTY_N3;
+            }
+        }
+
+        // 2*2 blocks of modules having same color
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color: bool = qrcode.module(x, y);
+                if color == qrcode.module(x + 1, y)
+                    && color == qrcode.module(x, y + 1)
+                    && color == qrcode.module(x + 1, y + 1)
+                {
+                    result += PENALTY_N2;
+                }
+            }
+        }
+
+        // Balance of dark and light modules
+        let dark: i32 = (0..size * size)
+            .map(|i| i32::from(qrcode.module(i % size, i / size)))
+            .sum();
+        let total: i32 = size * size;
+        let k: i32 = ((dark * 20 - total * 10).abs() + total - 1) / total - 1;
+        result + k * PENALTY_N4
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(50))]
+        #[test]
+        fn penalty_matches_reference(
+            data in prop::collection::vec(0u8.., 1..2300),
+            mask in 0u8..8
+        ) {
+            let segs = [QrSegment::make_bytes(&data)];
+            let qrcode = QrCode::encode_segments_advanced(
+                &segs, QrCodeEcc::Low, Version::MIN, Version::MAX, Some(Mask::new(mask)), false,
+            ).unwrap();
+
+            let mut transposed = BitGrid::default();
+            qrcode.modules.transpose_into(&mut transposed);
+            assert_eq!(
+                QrCode::get_penalty_score(&qrcode.modules, &transposed),
+                reference_penalty_score(&qrcode)
+            );
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(100))]
+        #[test]
+        fn bit_buffer_append(
+            parts in prop::collection::vec(prop::collection::vec(proptest::bool::ANY, 0..20), 0..10)
+        ) {
+            let mut bb = BitBuffer::new();
+            for part in &parts {
+                let mut other = BitBuffer::new();
+                for &bit in part {
+                    other.append_bits(u32::from(bit), 1);
+                }
+                bb.append_buffer(&other);
+            }
+
+            let expected: Vec<bool> = parts.concat();
+            assert_eq!(bb.len(), expected.len());
+            assert_eq!(bb.iter().collect::<Vec<_>>(), expected);
+            for (i, &b) in bb.as_bytes().iter().enumerate() {
+                let byte = (0..8)
+                    .map(|j| expected.get(i * 8 + j).copied().unwrap_or(false))
+                    .fold(0u8, |acc, bit| acc << 1 | u8::from(bit));
+                assert_eq!(b, byte);
+            }
+        }
+    }
+
+    #[test]
+    fn encode_buffers_reuse() {
+        let mut buffers = EncodeBuffers::new();
+        for len in [2000, 10, 500, 10] {
+            let segs = [QrSegment::make_bytes(&vec![len as u8; len])];
+            let expected = QrCode::encode_segments(&segs, QrCodeEcc::Medium).unwrap();
+            let actual = QrCode::encode_segments_buffered(
+                &segs,
+                QrCodeEcc::Medium,
+                Version::MIN,
+                Version::MAX,
+                None,
+                true,
+                &mut buffers,
+            )
+            .unwrap();
+            assert!(actual == expected);
+            buffers.recycle(actual);
+        }
+    }
+
     impl Arbitrary for QrCodeEcc {
         type Parameters = ();
 
@@ -1546,7 +2237,7 @@ mod tests {
     fn roundtrip(text: &str, ecl: QrCodeEcc) {
         let mut decoder = Quirc::new();
 
-        let qrcode = QrCode::encode_text_optimally(&text, ecl).unwrap();
+        let qrcode = QrCode::encode_text_optimally(text, ecl).unwrap();
 
         let img = qrcode_to_image(&qrcode, 40);
 
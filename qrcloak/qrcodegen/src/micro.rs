@@ -0,0 +1,714 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+
+use crate::{
+    get_bit, BitBuffer, BitGrid, DataTooLong, Mask, QrCode, QrCodeEcc, QrSegment, QrSegmentMode,
+};
+
+/*---- MicroQrCode functionality ----*/
+
+/// A Micro QR Code symbol, the small variant of the QR Code with a single finder pattern.
+///
+/// Described in the ISO/IEC 18004 standard alongside the regular QR Code.
+///
+/// Micro QR Codes come in 4 versions (M1 to M4) with sizes from 11 to 17 modules,
+/// so they need far less space than a version 1 QR Code for short data like PINs.
+/// Version M1 only holds numeric data and only detects errors, which is represented
+/// by `QrCodeEcc::Low`. M2 and M3 support the low and medium error correction levels,
+/// M4 additionally supports the quartile level. No version supports `QrCodeEcc::High`.
+/// Extended Channel Interpretation and Structured Append segments do not exist in Micro QR.
+#[derive(Clone, PartialEq, Eq)]
+pub struct MicroQrCode {
+    // Scalar parameters:
+
+    // The version number of this Micro QR Code, which is between 1 and 4 (inclusive).
+    version: MicroVersion,
+
+    // The width and height of this Micro QR Code, measured in modules, between
+    // 11 and 17 (inclusive). This is equal to version * 2 + 9.
+    size: i32,
+
+    // The error correction level used in this Micro QR Code.
+    errorcorrectionlevel: QrCodeEcc,
+
+    // The index of the mask pattern used in this Micro QR Code, which is between 0 and 3 (inclusive).
+    mask: Mask,
+
+    // Grids of modules/pixels, with dimensions of size*size:
+
+    // The modules of this Micro QR Code (false = light, true = dark).
+    modules: BitGrid,
+
+    // Indicates function modules that are not subjected to masking. Discarded when constructor finishes.
+    isfunction: BitGrid,
+}
+
+impl MicroQrCode {
+    /*---- Static factory functions (high level) ----*/
+
+    /// Returns a Micro QR Code representing the given Unicode text string at the given error correction level.
+    ///
+    /// The smallest possible version is automatically chosen for the output. The ECC level
+    /// of the result may be higher than the ecl argument if it can be done without increasing the version.
+    ///
+    /// Returns a wrapped `MicroQrCode` if successful, or `Err` if the
+    /// data is too long to fit in any version at the given ECC level.
+    pub fn encode_text(text: &str, ecl: QrCodeEcc) -> Result<Self, DataTooLong> {
+        let segs: Vec<QrSegment> = QrSegment::make_segments(text);
+        MicroQrCode::encode_segments(&segs, ecl)
+    }
+
+    /// Returns a Micro QR Code representing the given binary data at the given error correction level.
+    ///
+    /// Byte mode is only available from version M3 on, which can hold at most 15 bytes.
+    ///
+    /// Returns a wrapped `MicroQrCode` if successful, or `Err` if the
+    /// data is too long to fit in any version at the given ECC level.
+    pub fn encode_binary(data: &[u8], ecl: QrCodeEcc) -> Result<Self, DataTooLong> {
+        let segs: [QrSegment; 1] = [QrSegment::make_bytes(data)];
+        MicroQrCode::encode_segments(&segs, ecl)
+    }
+
+    /*---- Static factory functions (mid level) ----*/
+
+    /// Returns a Micro QR Code representing the given segments at the given error correction level.
+    ///
+    /// The smallest possible version is automatically chosen for the output. The ECC level
+    /// of the result may be higher than the ecl argument if it can be done without increasing the version.
+    ///
+    /// Returns a wrapped `MicroQrCode` if successful, or `Err` if the
+    /// data is too long to fit in any version at the given ECC level.
+    pub fn encode_segments(segs: &[QrSegment], ecl: QrCodeEcc) -> Result<Self, DataTooLong> {
+        MicroQrCode::encode_segments_advanced(
+            segs,
+            ecl,
+            MicroVersion::MIN,
+            MicroVersion::MAX,
+            None,
+            true,
+        )
+    }
+
+    /// Returns a Micro QR Code representing the given segments with the given encoding parameters.
+    ///
+    /// The smallest possible version within the given range is automatically
+    /// chosen for the output. Iff boostecl is `true`, then the ECC level of the result
+    /// may be higher than the ecl argument if it can be done without increasing the
+    /// version. The mask number is either between 0 to 3 (inclusive) to force that
+    /// mask, or `None` to automatically choose an appropriate mask.
+    ///
+    /// Returns a wrapped `MicroQrCode` if successful, or `Err` if the data is too long
+    /// to fit in any version in the given range at the given ECC level. A level that no
+    /// version in the range supports is reported as a capacity of 0 bits.
+    pub fn encode_segments_advanced(
+        segs: &[QrSegment],
+        mut ecl: QrCodeEcc,
+        minversion: MicroVersion,
+        maxversion: MicroVersion,
+        mask: Option<Mask>,
+        boostecl: bool,
+    ) -> Result<Self, DataTooLong> {
+        assert!(minversion <= maxversion, "Invalid value");
+        assert!(
+            mask.is_none_or(|mask| mask.value() < 4),
+            "Mask value out of range"
+        );
+
+        // Find the minimal version number to use
+        let mut version: MicroVersion = minversion;
+        let datausedbits: usize = loop {
+            let datacapacitybits: Option<usize> = MicroQrCode::get_num_data_bits(version, ecl);
+            let dataused: Option<usize> = QrSegment::get_total_bits_micro(segs, version);
+            if let (Some(n), Some(capacity)) = (dataused, datacapacitybits) {
+                if n <= capacity {
+                    break n; // This version number is found to be suitable
+                }
+            }
+            if version >= maxversion {
+                // All versions in the range could not fit the given data
+                return Err(match dataused {
+                    None => DataTooLong::SegmentTooLong,
+                    Some(n) => DataTooLong::DataOverCapacity(n, datacapacitybits.unwrap_or(0)),
+                });
+            }
+            version = MicroVersion::new(version.value() + 1);
+        };
+
+        // Increase the error correction level while the data still fits in the current version number
+        for &newecl in &[QrCodeEcc::Medium, QrCodeEcc::Quartile] {
+            // From low to high
+            if boostecl
+                && newecl > ecl
+                && MicroQrCode::get_num_data_bits(version, newecl)
+                    .is_some_and(|capacity| datausedbits <= capacity)
+            {
+                ecl = newecl;
+            }
+        }
+
+        // Concatenate all segments to create the data bit string
+        let mut bb = BitBuffer::new();
+        for seg in segs {
+            let (modebits, ccbits) = seg.mode.micro_header(version).unwrap();
+            bb.append_bits(modebits, version.value() - 1);
+            bb.append_bits(u32::try_from(seg.numchars).unwrap(), ccbits);
+            bb.append_buffer(&seg.data);
+        }
+        debug_assert_eq!(bb.len(), datausedbits);
+
+        // Add terminator and pad up to a byte if applicable
+        let datacapacitybits: usize = MicroQrCode::get_num_data_bits(version, ecl).unwrap();
+        debug_assert!(bb.len() <= datacapacitybits);
+        let terminatorlen = usize::from(version.value()) * 2 + 1;
+        let numzerobits: usize = std::cmp::min(terminatorlen, datacapacitybits - bb.len());
+        bb.append_bits(0, u8::try_from(numzerobits).unwrap());
+        let numzerobits: usize =
+            std::cmp::min(bb.len().wrapping_neg() & 7, datacapacitybits - bb.len());
+        bb.append_bits(0, u8::try_from(numzerobits).unwrap());
+
+        // Pad with alternating bytes until data capacity is reached. The final
+        // data codeword of versions M1 and M3 is only 4 bits long and stays zero.
+        for &padbyte in [0xEC, 0x11].iter().cycle() {
+            if bb.len() + 8 > datacapacitybits {
+                break;
+            }
+            bb.append_bits(padbyte, 8);
+        }
+        let numzerobits: usize = datacapacitybits - bb.len();
+        bb.append_bits(0, u8::try_from(numzerobits).unwrap());
+        debug_assert_eq!(bb.len(), datacapacitybits);
+
+        // Create the Micro QR Code object, the bits are already packed into bytes in big endian
+        Ok(MicroQrCode::encode_codewords(
+            version,
+            ecl,
+            bb.as_bytes(),
+            mask,
+        ))
+    }
+
+    /*---- Constructor (low level) ----*/
+
+    /// Creates a new Micro QR Code with the given version number,
+    /// error correction level, data codeword bytes, and mask number.
+    ///
+    /// For versions M1 and M3 the final data codeword only has 4 bits, which
+    /// are the high bits of the last byte. The low bits of that byte must be zero.
+    ///
+    /// This is a low-level API that most users should not use directly.
+    /// A mid-level API is the `encode_segments()` function.
+    pub fn encode_codewords(
+        ver: MicroVersion,
+        ecl: QrCodeEcc,
+        datacodewords: &[u8],
+        mut msk: Option<Mask>,
+    ) -> Self {
+        // Initialize fields
+        let size = usize::from(ver.value()) * 2 + 9;
+        let mut result = Self {
+            version: ver,
+            size: size as i32,
+            mask: Mask::new(0), // Dummy value
+            errorcorrectionlevel: ecl,
+            modules: BitGrid::new(size as i32, size as i32), // Initially all light
+            isfunction: BitGrid::new(size as i32, size as i32),
+        };
+
+        // Compute ECC, draw modules
+        result.draw_function_patterns();
+        let allbits: BitBuffer = result.add_ecc(datacodewords);
+        result.draw_codewords(&allbits);
+
+        // Do masking
+        if msk.is_none() {
+            // Automatically choose best mask, which has the highest score
+            let mut maxscore = -1;
+            for i in 0u8..4 {
+                let i = Mask::new(i);
+                result.apply_mask(i);
+                result.draw_format_bits(i);
+                let score: i32 = result.get_mask_score();
+                if score > maxscore {
+                    msk = Some(i);
+                    maxscore = score;
+                }
+                result.apply_mask(i); // Undoes the mask due to XOR
+            }
+        }
+        let msk: Mask = msk.unwrap();
+        result.mask = msk;
+        result.apply_mask(msk); // Apply the final choice of mask
+        result.draw_format_bits(msk); // Overwrite old format bits
+
+        result.isfunction = BitGrid::default();
+        result
+    }
+
+    /*---- Public methods ----*/
+
+    /// Returns this Micro QR Code's version, in the range [1, 4].
+    pub fn version(&self) -> MicroVersion {
+        self.version
+    }
+
+    /// Returns this Micro QR Code's size, in the range [11, 17].
+    pub fn size(&self) -> i32 {
+        self.size
+    }
+
+    /// Returns this Micro QR Code's error correction level.
+    pub fn error_correction_level(&self) -> QrCodeEcc {
+        self.errorcorrectionlevel
+    }
+
+    /// Returns this Micro QR Code's mask, in the range [0, 3].
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    /// Returns the color of the module (pixel) at the given coordinates,
+    /// which is `false` for light or `true` for dark.
+    ///
+    /// The top left corner has the coordinates (x=0, y=0). If the given
+    /// coordinates are out of bounds, then `false` (light) is returned.
+    pub fn get_module(&self, x: i32, y: i32) -> bool {
+        (0..self.size).contains(&x) && (0..self.size).contains(&y) && self.module(x, y)
+    }
+
+    // Returns the color of the module at the given coordinates, which must be in bounds.
+    fn module(&self, x: i32, y: i32) -> bool {
+        self.modules.get(x, y)
+    }
+
+    /*---- Private helper methods for constructor: Drawing function modules ----*/
+
+    // Reads this object's version field, and draws and marks all function modules.
+    fn draw_function_patterns(&mut self) {
+        // Draw horizontal and vertical timing patterns along the top and left edges
+        let size: i32 = self.size;
+        for i in 0..size {
+            self.set_function_module(i, 0, i % 2 == 0);
+            self.set_function_module(0, i, i % 2 == 0);
+        }
+
+        // Draw the single finder pattern (overwrites some timing modules)
+        self.draw_finder_pattern(3, 3);
+
+        // Draw configuration data
+        self.draw_format_bits(Mask::new(0)); // Dummy mask value; overwritten later in the constructor
+    }
+
+    // Draws the single copy of the format bits (with its own error correction code)
+    // based on the given mask and this object's version and error correction level field.
+    fn draw_format_bits(&mut self, mask: Mask) {
+        let bits: u32 = MicroQrCode::get_format_bits(self.version, self.errorcorrectionlevel, mask);
+
+        for i in 0..8 {
+            self.set_function_module(8, i + 1, get_bit(bits, i));
+        }
+        for i in 8..15 {
+            self.set_function_module(15 - i, 8, get_bit(bits, i));
+        }
+    }
+
+    // Calculates the 15 format bits (with their own error correction code)
+    // for the given version, error correction level and mask.
+    fn get_format_bits(ver: MicroVersion, ecl: QrCodeEcc, mask: Mask) -> u32 {
+        // The symbol number identifies the combination of version and error correction level
+        let symbolnumber: u32 = match (ver.value(), ecl) {
+            (1, _) => 0,
+            (2, QrCodeEcc::Low) => 1,
+            (2, QrCodeEcc::Medium) => 2,
+            (3, QrCodeEcc::Low) => 3,
+            (3, QrCodeEcc::Medium) => 4,
+            (4, QrCodeEcc::Low) => 5,
+            (4, QrCodeEcc::Medium) => 6,
+            (4, QrCodeEcc::Quartile) => 7,
+            _ => unreachable!(),
+        };
+
+        // symbolnumber is uint3, mask is uint2
+        let data: u32 = symbolnumber << 2 | u32::from(mask.value());
+        let mut rem: u32 = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits: u32 = (data << 10 | rem) ^ 0x4445; // uint15
+        debug_assert_eq!(bits >> 15, 0);
+        bits
+    }
+
+    // Draws a 9*9 finder pattern including the border separator,
+    // with the center module at (x, y). Modules can be out of bounds.
+    fn draw_finder_pattern(&mut self, x: i32, y: i32) {
+        for dy in -4..=4 {
+            for dx in -4..=4 {
+                let xx: i32 = x + dx;
+                let yy: i32 = y + dy;
+                if (0..self.size).contains(&xx) && (0..self.size).contains(&yy) {
+                    let dist: i32 = std::cmp::max(dx.abs(), dy.abs()); // Chebyshev/infinity norm
+                    self.set_function_module(xx, yy, dist != 2 && dist != 4);
+                }
+            }
+        }
+    }
+
+    // Sets the color of a module and marks it as a function module.
+    // Only used by the constructor. Coordinates must be in bounds.
+    fn set_function_module(&mut self, x: i32, y: i32, isdark: bool) {
+        self.modules.set(x, y, isdark);
+        self.isfunction.set(x, y, true);
+    }
+
+    /*---- Private helper methods for constructor: Codewords and masking ----*/
+
+    // Returns the bits of the given data codewords with the error correction codewords
+    // appended, based on this object's version and error correction level. Micro QR Codes
+    // only have a single block, but the final data codeword of M1 and M3 is only 4 bits long.
+    fn add_ecc(&self, data: &[u8]) -> BitBuffer {
+        let ver: MicroVersion = self.version;
+        let ecl: QrCodeEcc = self.errorcorrectionlevel;
+        let databits: usize = MicroQrCode::get_num_data_bits(ver, ecl).expect("Illegal argument");
+        assert_eq!(data.len(), databits.div_ceil(8), "Illegal argument");
+
+        let ecclen: usize = MicroQrCode::table_get(&MICRO_ECC_CODEWORDS, ver, ecl);
+        let rsdiv: Vec<u8> = QrCode::reed_solomon_compute_divisor(ecclen);
+        let ecc: Vec<u8> = QrCode::reed_solomon_compute_remainder(data, &rsdiv);
+
+        let mut bb = BitBuffer::with_capacity(databits + ecclen * 8);
+        for (i, &b) in data.iter().enumerate() {
+            let len: usize = std::cmp::min(8, databits - i * 8);
+            bb.append_bits(u32::from(b) >> (8 - len), len as u8);
+        }
+        for &b in &ecc {
+            bb.append_bits(u32::from(b), 8);
+        }
+        bb
+    }
+
+    // Draws the given sequence of bits (data and error correction) onto the entire
+    // data area of this Micro QR Code. Function modules need to be marked off before this is called.
+    fn draw_codewords(&mut self, data: &BitBuffer) {
+        let mut i: usize = 0; // Bit index into the data
+        let mut upward = true;
+        // Do the zigzag scan, the vertical timing pattern is in the leftmost column
+        let mut right: i32 = self.size - 1;
+        while right >= 1 {
+            // Index of right column in each column pair
+            for vert in 0..self.size {
+                // Vertical counter
+                for j in 0..2 {
+                    let x: i32 = right - j; // Actual x coordinate
+                    let y: i32 = if upward { self.size - 1 - vert } else { vert }; // Actual y coordinate
+                    if !self.isfunction.get(x, y) && i < data.len() {
+                        self.modules.set(x, y, data.get(i));
+                        i += 1;
+                    }
+                }
+            }
+            upward = !upward;
+            right -= 2;
+        }
+        debug_assert_eq!(i, data.len());
+    }
+
+    // XORs the codeword modules in this Micro QR Code with the given mask pattern.
+    // Calling apply_mask() with the same mask value a second time will undo the mask.
+    fn apply_mask(&mut self, mask: Mask) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert: bool = match mask.value() {
+                    0 => y % 2 == 0,
+                    1 => (x / 3 + y / 2) % 2 == 0,
+                    2 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    3 => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                    _ => unreachable!(),
+                };
+                self.modules.xor(x, y, invert & !self.isfunction.get(x, y));
+            }
+        }
+    }
+
+    // Calculates the score based on the dark modules along the right and bottom edges.
+    // Unlike with QR Codes, the mask pattern with the highest score is the best one.
+    fn get_mask_score(&self) -> i32 {
+        let size: i32 = self.size;
+        let right: i32 = (1..size).map(|y| i32::from(self.module(size - 1, y))).sum();
+        let bottom: i32 = (1..size).map(|x| i32::from(self.module(x, size - 1))).sum();
+        if right <= bottom {
+            right * 16 + bottom
+        } else {
+            bottom * 16 + right
+        }
+    }
+
+    /// Returns the number of whole bytes that a Micro QR Code of the given version and error
+    /// correction level holds, including the segment headers and the terminator, or 0 if the
+    /// version does not support the level.
+    pub fn data_capacity(ver: MicroVersion, ecl: QrCodeEcc) -> usize {
+        MicroQrCode::get_num_data_bits(ver, ecl).unwrap_or(0) / 8
+    }
+
+    /*---- Private helper functions ----*/
+
+    // Returns the number of data bits that can be stored in a Micro QR Code of the given
+    // version number and error correction level, or None if the version does not support the level.
+    fn get_num_data_bits(ver: MicroVersion, ecl: QrCodeEcc) -> Option<usize> {
+        let bits: i16 = MICRO_DATA_BITS[ecl.ordinal()][usize::from(ver.value())];
+        usize::try_from(bits).ok()
+    }
+
+    // Returns an entry from the given table based on the given values.
+    fn table_get(table: &'static [[i8; 5]; 4], ver: MicroVersion, ecl: QrCodeEcc) -> usize {
+        table[ecl.ordinal()][usize::from(ver.value())] as usize
+    }
+}
+
+impl QrSegment {
+    // Calculates and returns the number of bits needed to encode the given segments
+    // in a Micro QR Code of the given version. The result is None if a segment's mode is
+    // not available in the version or a segment has too many characters to fit its length field.
+    fn get_total_bits_micro(segs: &[Self], version: MicroVersion) -> Option<usize> {
+        let mut result: usize = 0;
+        for seg in segs {
+            let (_, ccbits) = seg.mode.micro_header(version)?;
+            if seg.numchars >= 1usize << ccbits {
+                return None; // The segment's length doesn't fit the field's bit width
+            }
+            result = result.checked_add(usize::from(version.value() - 1 + ccbits))?;
+            result = result.checked_add(seg.data.len())?;
+        }
+        Some(result)
+    }
+}
+
+impl QrSegmentMode {
+    // Returns the mode indicator value and the bit width of the character count field for a
+    // segment in this mode in a Micro QR Code at the given version number, or None if the
+    // version does not support the mode. The mode indicator is version - 1 bits long.
+    fn micro_header(self, ver: MicroVersion) -> Option<(u32, u8)> {
+        use QrSegmentMode::*;
+        let (modebits, ccbits): (u32, [u8; 4]) = match self {
+            Numeric => (0, [3, 4, 5, 6]),
+            Alphanumeric => (1, [0, 3, 4, 5]),
+            Byte => (2, [0, 0, 4, 5]),
+            Kanji => (3, [0, 0, 3, 4]),
+            Eci | StructuredAppend => return None,
+        };
+        let ccbits: u8 = ccbits[usize::from(ver.value() - 1)];
+        (ccbits > 0).then_some((modebits, ccbits))
+    }
+}
+
+/*---- Constants and tables ----*/
+
+// Number of data bits per version and error correction level, where -1 marks unsupported levels.
+// The capacity of M1 and M3 ends with a 4 bit codeword.
+static MICRO_DATA_BITS: [[i16; 5]; 4] = [
+    // Version: (note that index 0 is for padding, and is set to an illegal value)
+    //0,  M1,  M2,  M3,  M4    Error correction level
+    [-1, 20, 40, 84, 128], // Low (error detection only for M1)
+    [-1, -1, 32, 68, 112], // Medium
+    [-1, -1, -1, -1, 80],  // Quartile
+    [-1, -1, -1, -1, -1],  // High
+];
+
+static MICRO_ECC_CODEWORDS: [[i8; 5]; 4] = [
+    // Version: (note that index 0 is for padding, and is set to an illegal value)
+    //0, M1, M2, M3, M4    Error correction level
+    [-1, 2, 5, 6, 8],     // Low
+    [-1, -1, 6, 8, 10],   // Medium
+    [-1, -1, -1, -1, 14], // Quartile
+    [-1, -1, -1, -1, -1], // High
+];
+
+/*---- Miscellaneous values ----*/
+
+/// A Micro QR Code version number between 1 and 4 (inclusive), standing for M1 to M4.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct MicroVersion(u8);
+
+impl MicroVersion {
+    /// The minimum version number, M1.
+    pub const MIN: MicroVersion = MicroVersion(1);
+
+    /// The maximum version number, M4.
+    pub const MAX: MicroVersion = MicroVersion(4);
+
+    /// Creates a version object from the given number.
+    ///
+    /// Panics if the number is outside the range [1, 4].
+    pub const fn new(ver: u8) -> Self {
+        assert!(
+            MicroVersion::MIN.value() <= ver && ver <= MicroVersion::MAX.value(),
+            "Version number out of range"
+        );
+        Self(ver)
+    }
+
+    /// Returns the value, which is in the range [1, 4].
+    pub const fn value(self) -> u8 {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::ProptestConfig;
+    use proptest::proptest;
+
+    use crate::{BitGrid, DataTooLong, Mask, MicroQrCode, MicroVersion, QrCode, QrCodeEcc};
+
+    // Reads the data bits back from the modules by unmasking and following the zigzag scan.
+    fn read_bits(qrcode: &MicroQrCode) -> Vec<bool> {
+        let mut unmasked = qrcode.clone();
+        unmasked.isfunction = BitGrid::new(qrcode.size(), qrcode.size());
+        unmasked.draw_function_patterns();
+        unmasked.apply_mask(qrcode.mask());
+
+        let size = qrcode.size();
+        let mut result = Vec::new();
+        let mut upward = true;
+        let mut right = size - 1;
+        while right >= 1 {
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    if !unmasked.isfunction.get(x, y) {
+                        result.push(unmasked.module(x, y));
+                    }
+                }
+            }
+            upward = !upward;
+            right -= 2;
+        }
+        result
+    }
+
+    fn to_bytes(bits: &[bool]) -> Vec<u8> {
+        bits.chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0u8, |acc, (i, &bit)| acc | u8::from(bit) << (7 - i))
+            })
+            .collect()
+    }
+
+    fn check_codewords(qrcode: &MicroQrCode) {
+        let ver = qrcode.version();
+        let ecl = qrcode.error_correction_level();
+        let databits = MicroQrCode::get_num_data_bits(ver, ecl).unwrap();
+        let ecclen = MicroQrCode::table_get(&super::MICRO_ECC_CODEWORDS, ver, ecl);
+
+        let bits = read_bits(qrcode);
+        assert_eq!(bits.len(), databits + ecclen * 8);
+
+        let data = to_bytes(&bits[..databits]);
+        let ecc = to_bytes(&bits[databits..]);
+
+        let rsdiv = QrCode::reed_solomon_compute_divisor(ecclen);
+        assert_eq!(QrCode::reed_solomon_compute_remainder(&data, &rsdiv), ecc);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(100))]
+        #[test]
+        fn micro_numeric(text in "[0-9]{1,35}") {
+            let qrcode = MicroQrCode::encode_text(&text, QrCodeEcc::Low).unwrap();
+            assert_eq!(qrcode.size(), i32::from(qrcode.version().value()) * 2 + 9);
+            check_codewords(&qrcode);
+        }
+
+        #[test]
+        fn micro_binary(data in proptest::collection::vec(0u8.., 1..=9), mask in 0u8..4) {
+            let qrcode = MicroQrCode::encode_segments_advanced(
+                &[crate::QrSegment::make_bytes(&data)],
+                QrCodeEcc::Medium,
+                MicroVersion::MIN,
+                MicroVersion::MAX,
+                Some(Mask::new(mask)),
+                false,
+            )
+            .unwrap();
+            assert_eq!(qrcode.mask(), Mask::new(mask));
+            assert!(qrcode.version() >= MicroVersion::new(3));
+            check_codewords(&qrcode);
+        }
+    }
+
+    #[test]
+    fn micro_versions() {
+        let m1 = MicroQrCode::encode_text("12345", QrCodeEcc::Low).unwrap();
+        assert_eq!(m1.version(), MicroVersion::new(1));
+        assert_eq!(m1.size(), 11);
+
+        let m2 = MicroQrCode::encode_text("HELLO", QrCodeEcc::Low).unwrap();
+        assert_eq!(m2.version(), MicroVersion::new(2));
+
+        let m4 = MicroQrCode::encode_text("HELLO WORLD", QrCodeEcc::Quartile).unwrap();
+        assert_eq!(m4.version(), MicroVersion::new(4));
+        assert_eq!(m4.error_correction_level(), QrCodeEcc::Quartile);
+    }
+
+    #[test]
+    fn micro_known_answer() {
+        // The Micro QR Code encoding example of ISO/IEC 18004, Annex I
+        let qrcode = MicroQrCode::encode_segments_advanced(
+            &crate::QrSegment::make_segments("01234567"),
+            QrCodeEcc::Low,
+            MicroVersion::new(2),
+            MicroVersion::new(2),
+            None,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            to_bytes(&read_bits(&qrcode)),
+            [0x40, 0x18, 0xAC, 0xC3, 0x00, 0x86, 0x0D, 0x22, 0xAE, 0x30]
+        );
+    }
+
+    #[test]
+    fn micro_too_long() {
+        assert!(matches!(
+            MicroQrCode::encode_text("text too long for m4", QrCodeEcc::Low),
+            Err(DataTooLong::DataOverCapacity(168, 128))
+        ));
+        assert!(matches!(
+            MicroQrCode::encode_text("1", QrCodeEcc::High),
+            Err(DataTooLong::DataOverCapacity(_, 0))
+        ));
+    }
+
+    #[test]
+    fn micro_data_capacity() {
+        assert_eq!(
+            MicroQrCode::data_capacity(MicroVersion::new(1), QrCodeEcc::Low),
+            2
+        );
+        assert_eq!(
+            MicroQrCode::data_capacity(MicroVersion::MAX, QrCodeEcc::Low),
+            16
+        );
+        assert_eq!(
+            MicroQrCode::data_capacity(MicroVersion::MAX, QrCodeEcc::High),
+            0
+        );
+    }
+
+    #[test]
+    fn micro_format_bits() {
+        // Format information of M1 with mask 0 from the standard
+        assert_eq!(
+            MicroQrCode::get_format_bits(MicroVersion::new(1), QrCodeEcc::Low, Mask::new(0)),
+            0x4445
+        );
+    }
+}
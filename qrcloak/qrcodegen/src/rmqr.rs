@@ -0,0 +1,807 @@
+// SPDX-FileCopyrightText: 2024 Felix Hilgers <contact@fhilgers.com>
+//
+// SPDX-License-Identifier: Apache-2.0
+// SPDX-License-Identifier: MIT
+
+use std::convert::TryFrom;
+
+use crate::{
+    get_bit, BitBuffer, BitGrid, DataTooLong, QrCode, QrCodeEcc, QrSegment, QrSegmentMode,
+};
+
+/*---- RmqrCode functionality ----*/
+
+/// A rectangular Micro QR Code (rMQR) symbol.
+///
+/// Described in the ISO/IEC 23941 standard.
+///
+/// rMQR Codes come in 32 versions with heights from 7 to 17 and widths from 27 to 139
+/// modules, which suits narrow print areas like labels. They have a finder pattern on the
+/// left, a smaller finder sub pattern in the bottom right corner and a single fixed mask.
+/// Only the medium and high error correction levels exist: `QrCodeEcc::Low` is raised to
+/// medium and `QrCodeEcc::Quartile` to high. Structured Append segments do not exist in rMQR.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RmqrCode {
+    // Scalar parameters:
+
+    // The version of this rMQR Code, which determines its width and height.
+    version: RmqrVersion,
+
+    // The width of this rMQR Code, measured in modules, between 27 and 139 (inclusive).
+    width: i32,
+
+    // The height of this rMQR Code, measured in modules, between 7 and 17 (inclusive).
+    height: i32,
+
+    // The error correction level used in this rMQR Code, either medium or high.
+    errorcorrectionlevel: QrCodeEcc,
+
+    // Grids of modules/pixels, with dimensions of width*height:
+
+    // The modules of this rMQR Code (false = light, true = dark).
+    modules: BitGrid,
+
+    // Indicates function modules that are not subjected to masking. Discarded when constructor finishes.
+    isfunction: BitGrid,
+}
+
+impl RmqrCode {
+    /*---- Static factory functions (high level) ----*/
+
+    /// Returns an rMQR Code representing the given Unicode text string at the given error correction level.
+    ///
+    /// The version with the smallest area is automatically chosen for the output. The ECC level
+    /// of the result may be higher than the ecl argument if it can be done without changing the version.
+    ///
+    /// Returns a wrapped `RmqrCode` if successful, or `Err` if the
+    /// data is too long to fit in any version at the given ECC level.
+    pub fn encode_text(text: &str, ecl: QrCodeEcc) -> Result<Self, DataTooLong> {
+        let segs: Vec<QrSegment> = QrSegment::make_segments(text);
+        RmqrCode::encode_segments(&segs, ecl)
+    }
+
+    /// Returns an rMQR Code representing the given binary data at the given error correction level.
+    ///
+    /// Returns a wrapped `RmqrCode` if successful, or `Err` if the
+    /// data is too long to fit in any version at the given ECC level.
+    pub fn encode_binary(data: &[u8], ecl: QrCodeEcc) -> Result<Self, DataTooLong> {
+        let segs: [QrSegment; 1] = [QrSegment::make_bytes(data)];
+        RmqrCode::encode_segments(&segs, ecl)
+    }
+
+    /*---- Static factory functions (mid level) ----*/
+
+    /// Returns an rMQR Code representing the given segments at the given error correction level.
+    ///
+    /// The version with the smallest area is automatically chosen for the output. The ECC level
+    /// of the result may be higher than the ecl argument if it can be done without changing the version.
+    ///
+    /// Returns a wrapped `RmqrCode` if successful, or `Err` if the
+    /// data is too long to fit in any version at the given ECC level.
+    pub fn encode_segments(segs: &[QrSegment], ecl: QrCodeEcc) -> Result<Self, DataTooLong> {
+        RmqrCode::encode_segments_advanced(segs, ecl, RmqrVersion::all(), true)
+    }
+
+    /// Returns an rMQR Code representing the given segments with the given encoding parameters.
+    ///
+    /// Out of the given candidate versions, the one with the smallest area that fits the data
+    /// is chosen for the output, preferring the lower one on ties. This allows restricting the
+    /// symbol to a fixed height, for example with `RmqrVersion::all().filter(|v| v.height() == 11)`.
+    /// Iff boostecl is `true`, then the ECC level of the result may be higher than the ecl
+    /// argument if it can be done without changing the version.
+    ///
+    /// Returns a wrapped `RmqrCode` if successful, or `Err` if the data is
+    /// too long to fit in any candidate version at the given ECC level.
+    pub fn encode_segments_advanced(
+        segs: &[QrSegment],
+        ecl: QrCodeEcc,
+        versions: impl IntoIterator<Item = RmqrVersion>,
+        boostecl: bool,
+    ) -> Result<Self, DataTooLong> {
+        let mut ecl: QrCodeEcc = RmqrCode::supported_ecl(ecl);
+
+        // Find the version with the smallest area to use
+        let mut best: Option<(RmqrVersion, usize)> = None;
+        let mut error = DataTooLong::SegmentTooLong;
+        let mut errorcapacity: usize = 0;
+        for version in versions {
+            let datacapacitybits: usize = RmqrCode::get_num_data_codewords(version, ecl) * 8;
+            let dataused: Option<usize> = QrSegment::get_total_bits_rmqr(segs, version);
+            match dataused {
+                Some(n) if n <= datacapacitybits => {
+                    let key = |v: RmqrVersion| (v.width() * v.height(), v.height());
+                    if best.is_none_or(|(b, _)| key(version) < key(b)) {
+                        best = Some((version, n));
+                    }
+                }
+                Some(n) if datacapacitybits >= errorcapacity => {
+                    error = DataTooLong::DataOverCapacity(n, datacapacitybits);
+                    errorcapacity = datacapacitybits;
+                }
+                _ => {}
+            }
+        }
+        let (version, datausedbits) = best.ok_or(error)?;
+
+        // Increase the error correction level while the data still fits in the current version
+        if boostecl
+            && datausedbits <= RmqrCode::get_num_data_codewords(version, QrCodeEcc::High) * 8
+        {
+            ecl = QrCodeEcc::High;
+        }
+
+        // Concatenate all segments to create the data bit string
+        let mut bb = BitBuffer::new();
+        for seg in segs {
+            let (modebits, ccbits) = seg.mode.rmqr_header(version).unwrap();
+            bb.append_bits(modebits, 3);
+            bb.append_bits(u32::try_from(seg.numchars).unwrap(), ccbits);
+            bb.append_buffer(&seg.data);
+        }
+        debug_assert_eq!(bb.len(), datausedbits);
+
+        // Add terminator and pad up to a byte if applicable
+        let datacapacitybits: usize = RmqrCode::get_num_data_codewords(version, ecl) * 8;
+        debug_assert!(bb.len() <= datacapacitybits);
+        let numzerobits: usize = std::cmp::min(3, datacapacitybits - bb.len());
+        bb.append_bits(0, u8::try_from(numzerobits).unwrap());
+        let numzerobits: usize = bb.len().wrapping_neg() & 7;
+        bb.append_bits(0, u8::try_from(numzerobits).unwrap());
+        debug_assert_eq!(bb.len() % 8, 0);
+
+        // Pad with alternating bytes until data capacity is reached
+        for &padbyte in [0xEC, 0x11].iter().cycle() {
+            if bb.len() >= datacapacitybits {
+                break;
+            }
+            bb.append_bits(padbyte, 8);
+        }
+
+        // Create the rMQR Code object, the bits are already packed into bytes in big endian
+        Ok(RmqrCode::encode_codewords(version, ecl, bb.as_bytes()))
+    }
+
+    /*---- Constructor (low level) ----*/
+
+    /// Creates a new rMQR Code with the given version,
+    /// error correction level and data codeword bytes.
+    ///
+    /// This is a low-level API that most users should not use directly.
+    /// A mid-level API is the `encode_segments()` function.
+    pub fn encode_codewords(ver: RmqrVersion, ecl: QrCodeEcc, datacodewords: &[u8]) -> Self {
+        // Initialize fields
+        let width: i32 = ver.width();
+        let height: i32 = ver.height();
+        let ecl: QrCodeEcc = RmqrCode::supported_ecl(ecl);
+        let mut result = Self {
+            version: ver,
+            width,
+            height,
+            errorcorrectionlevel: ecl,
+            modules: BitGrid::new(width, height), // Initially all light
+            isfunction: BitGrid::new(width, height),
+        };
+
+        // Compute ECC, draw modules
+        result.draw_function_patterns();
+        let allcodewords: Vec<u8> = result.add_ecc_and_interleave(datacodewords);
+        result.draw_codewords(&allcodewords);
+
+        // There is only a single mask pattern
+        result.apply_mask();
+
+        result.isfunction = BitGrid::default();
+        result
+    }
+
+    /*---- Public methods ----*/
+
+    /// Returns this rMQR Code's version.
+    pub fn version(&self) -> RmqrVersion {
+        self.version
+    }
+
+    /// Returns this rMQR Code's width, in the range [27, 139].
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Returns this rMQR Code's height, in the range [7, 17].
+    pub fn height(&self) -> i32 {
+        self.height
+    }
+
+    /// Returns this rMQR Code's error correction level, which is medium or high.
+    pub fn error_correction_level(&self) -> QrCodeEcc {
+        self.errorcorrectionlevel
+    }
+
+    /// Returns the color of the module (pixel) at the given coordinates,
+    /// which is `false` for light or `true` for dark.
+    ///
+    /// The top left corner has the coordinates (x=0, y=0). If the given
+    /// coordinates are out of bounds, then `false` (light) is returned.
+    pub fn get_module(&self, x: i32, y: i32) -> bool {
+        (0..self.width).contains(&x) && (0..self.height).contains(&y) && self.module(x, y)
+    }
+
+    // Returns the color of the module at the given coordinates, which must be in bounds.
+    fn module(&self, x: i32, y: i32) -> bool {
+        self.modules.get(x, y)
+    }
+
+    /*---- Private helper methods for constructor: Drawing function modules ----*/
+
+    // Reads this object's version field, and draws and marks all function modules.
+    fn draw_function_patterns(&mut self) {
+        let width: i32 = self.width;
+        let height: i32 = self.height;
+        let alignpatpos: &[i32] = self.version.alignment_pattern_positions();
+
+        // Draw timing patterns along all edges and through the alignment patterns
+        for x in 0..width {
+            self.set_function_module(x, 0, x % 2 == 0);
+            self.set_function_module(x, height - 1, x % 2 == 0);
+        }
+        for y in 0..height {
+            self.set_function_module(0, y, y % 2 == 0);
+            self.set_function_module(width - 1, y, y % 2 == 0);
+            for &x in alignpatpos {
+                self.set_function_module(x, y, y % 2 == 0);
+            }
+        }
+
+        // Draw the finder pattern and the finder sub pattern (overwrite some timing modules)
+        self.draw_finder_pattern(3, 3);
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                let dist: i32 = std::cmp::max(dx.abs(), dy.abs()); // Chebyshev/infinity norm
+                self.set_function_module(width - 3 + dx, height - 3 + dy, dist != 1);
+            }
+        }
+
+        // Draw the corner finder patterns
+        self.set_function_module(width - 2, 0, true);
+        self.set_function_module(width - 2, 1, false);
+        self.set_function_module(width - 1, 1, true);
+        if height >= 11 {
+            self.set_function_module(1, height - 1, true);
+            self.set_function_module(2, height - 1, true);
+            self.set_function_module(1, height - 2, false);
+        }
+
+        // Draw the alignment patterns at the top and bottom edges
+        for &x in alignpatpos {
+            self.draw_alignment_pattern(x, 1);
+            self.draw_alignment_pattern(x, height - 2);
+        }
+
+        // Draw configuration data
+        self.draw_format_bits();
+    }
+
+    // Draws the two copies of the format bits (with their own error correction code)
+    // based on this object's version and error correction level field.
+    fn draw_format_bits(&mut self) {
+        // Calculate error correction code and pack bits
+        let bits: u32 = {
+            // errcorrlvl is uint1, version is uint5
+            let data: u32 = u32::from(self.errorcorrectionlevel == QrCodeEcc::High) << 5
+                | u32::from(self.version.value());
+            let mut rem: u32 = data;
+            for _ in 0..12 {
+                rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+            }
+            data << 12 | rem // uint18
+        };
+        debug_assert_eq!(bits >> 18, 0);
+
+        // Draw the copy next to the finder pattern
+        let first: u32 = bits ^ 0x1FAB2;
+        for i in 0..18 {
+            self.set_function_module(8 + i / 5, 1 + i % 5, get_bit(first, i));
+        }
+
+        // Draw the copy next to the finder sub pattern
+        let second: u32 = bits ^ 0x20A7B;
+        let (width, height) = (self.width, self.height);
+        for i in 0..15 {
+            self.set_function_module(width - 8 + i / 5, height - 6 + i % 5, get_bit(second, i));
+        }
+        for i in 15..18 {
+            self.set_function_module(width - 20 + i, height - 6, get_bit(second, i));
+        }
+    }
+
+    // Draws a 7*7 finder pattern including the border separator, with the center
+    // module at (x, y). Modules can be out of bounds.
+    fn draw_finder_pattern(&mut self, x: i32, y: i32) {
+        for dy in -4..=4 {
+            for dx in -4..=4 {
+                let xx: i32 = x + dx;
+                let yy: i32 = y + dy;
+                if (0..self.width).contains(&xx) && (0..self.height).contains(&yy) {
+                    let dist: i32 = std::cmp::max(dx.abs(), dy.abs()); // Chebyshev/infinity norm
+                    self.set_function_module(xx, yy, dist != 2 && dist != 4);
+                }
+            }
+        }
+    }
+
+    // Draws a 3*3 alignment pattern with a light center module
+    // at (x, y). All modules must be in bounds.
+    fn draw_alignment_pattern(&mut self, x: i32, y: i32) {
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                self.set_function_module(x + dx, y + dy, dx != 0 || dy != 0);
+            }
+        }
+    }
+
+    // Sets the color of a module and marks it as a function module.
+    // Only used by the constructor. Coordinates must be in bounds.
+    fn set_function_module(&mut self, x: i32, y: i32, isdark: bool) {
+        self.modules.set(x, y, isdark);
+        self.isfunction.set(x, y, true);
+    }
+
+    /*---- Private helper methods for constructor: Codewords and masking ----*/
+
+    // Returns a new byte string representing the given data with the appropriate error correction
+    // codewords appended to it, based on this object's version and error correction level.
+    fn add_ecc_and_interleave(&self, data: &[u8]) -> Vec<u8> {
+        let ver: RmqrVersion = self.version;
+        let ecl: QrCodeEcc = self.errorcorrectionlevel;
+        assert_eq!(
+            data.len(),
+            RmqrCode::get_num_data_codewords(ver, ecl),
+            "Illegal argument"
+        );
+
+        // Calculate parameter numbers
+        let numblocks: usize = RmqrCode::table_get(&RMQR_NUM_ERROR_CORRECTION_BLOCKS, ver, ecl);
+        let blockecclen: usize = RmqrCode::table_get(&RMQR_ECC_CODEWORDS_PER_BLOCK, ver, ecl);
+        let rawcodewords: usize = usize::from(RMQR_NUM_RAW_CODEWORDS[usize::from(ver.value())]);
+        let numshortblocks: usize = numblocks - rawcodewords % numblocks;
+        let shortblocklen: usize = rawcodewords / numblocks;
+
+        // Split data into blocks and append ECC to each block
+        let mut blocks = Vec::<Vec<u8>>::with_capacity(numblocks);
+        let rsdiv: Vec<u8> = QrCode::reed_solomon_compute_divisor(blockecclen);
+        let mut k: usize = 0;
+        for i in 0..numblocks {
+            let datlen: usize = shortblocklen - blockecclen + usize::from(i >= numshortblocks);
+            let mut dat = data[k..k + datlen].to_vec();
+            k += datlen;
+            let ecc: Vec<u8> = QrCode::reed_solomon_compute_remainder(&dat, &rsdiv);
+            if i < numshortblocks {
+                dat.push(0);
+            }
+            dat.extend_from_slice(&ecc);
+            blocks.push(dat);
+        }
+
+        // Interleave (not concatenate) the bytes from every block into a single sequence
+        let mut result = Vec::<u8>::with_capacity(rawcodewords);
+        for i in 0..=shortblocklen {
+            for (j, block) in blocks.iter().enumerate() {
+                // Skip the padding byte in short blocks
+                if i != shortblocklen - blockecclen || j >= numshortblocks {
+                    result.push(block[i]);
+                }
+            }
+        }
+        result
+    }
+
+    // Draws the given sequence of 8-bit codewords (data and error correction) onto the entire
+    // data area of this rMQR Code. Function modules need to be marked off before this is called.
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let mut i: usize = 0; // Bit index into the data
+        let mut upward = true;
+        // Do the zigzag scan, the rightmost column only holds function modules
+        let mut right: i32 = self.width - 2;
+        while right >= 1 {
+            // Index of right column in each column pair
+            for vert in 0..self.height {
+                // Vertical counter
+                for j in 0..2 {
+                    let x: i32 = right - j; // Actual x coordinate
+                    let y: i32 = if upward { self.height - 1 - vert } else { vert }; // Actual y coordinate
+                    if !self.isfunction.get(x, y) && i < data.len() * 8 {
+                        self.modules.set(
+                            x,
+                            y,
+                            get_bit(u32::from(data[i >> 3]), 7 - ((i as i32) & 7)),
+                        );
+                        i += 1;
+                    }
+                    // If this rMQR Code has any remainder bits (0 to 7), they were assigned as
+                    // 0/false/light by the constructor and are left unchanged by this method
+                }
+            }
+            upward = !upward;
+            right -= 2;
+        }
+        debug_assert_eq!(i, data.len() * 8);
+    }
+
+    // XORs the codeword modules in this rMQR Code with the only mask pattern.
+    fn apply_mask(&mut self) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let invert: bool = (y / 2 + x / 3) % 2 == 0;
+                self.modules.xor(x, y, invert & !self.isfunction.get(x, y));
+            }
+        }
+    }
+
+    /*---- Private helper functions ----*/
+
+    // Returns the error correction level that is actually used for the given one.
+    fn supported_ecl(ecl: QrCodeEcc) -> QrCodeEcc {
+        match ecl {
+            QrCodeEcc::Low | QrCodeEcc::Medium => QrCodeEcc::Medium,
+            QrCodeEcc::Quartile | QrCodeEcc::High => QrCodeEcc::High,
+        }
+    }
+
+    /// Returns the number of bytes that an rMQR Code of the given version and error
+    /// correction level holds, including the segment headers and the terminator.
+    pub fn data_capacity(ver: RmqrVersion, ecl: QrCodeEcc) -> usize {
+        RmqrCode::get_num_data_codewords(ver, ecl)
+    }
+
+    // Returns the number of 8-bit data (i.e. not error correction) codewords contained in any
+    // rMQR Code of the given version and error correction level, with remainder bits discarded.
+    fn get_num_data_codewords(ver: RmqrVersion, ecl: QrCodeEcc) -> usize {
+        usize::from(RMQR_NUM_RAW_CODEWORDS[usize::from(ver.value())])
+            - RmqrCode::table_get(&RMQR_ECC_CODEWORDS_PER_BLOCK, ver, ecl)
+                * RmqrCode::table_get(&RMQR_NUM_ERROR_CORRECTION_BLOCKS, ver, ecl)
+    }
+
+    // Returns an entry from the given table based on the given values.
+    fn table_get(table: &'static [[u8; 32]; 2], ver: RmqrVersion, ecl: QrCodeEcc) -> usize {
+        let ecl = RmqrCode::supported_ecl(ecl);
+        usize::from(table[usize::from(ecl == QrCodeEcc::High)][usize::from(ver.value())])
+    }
+}
+
+impl QrSegment {
+    // Calculates and returns the number of bits needed to encode the given segments
+    // in an rMQR Code of the given version. The result is None if a segment's mode is
+    // not available in rMQR or a segment has too many characters to fit its length field.
+    fn get_total_bits_rmqr(segs: &[Self], version: RmqrVersion) -> Option<usize> {
+        let mut result: usize = 0;
+        for seg in segs {
+            let (_, ccbits) = seg.mode.rmqr_header(version)?;
+            if let Some(limit) = 1usize.checked_shl(ccbits.into()) {
+                if seg.numchars >= limit {
+                    return None; // The segment's length doesn't fit the field's bit width
+                }
+            }
+            result = result.checked_add(3 + usize::from(ccbits))?;
+            result = result.checked_add(seg.data.len())?;
+        }
+        Some(result)
+    }
+}
+
+impl QrSegmentMode {
+    // Returns the 3-bit mode indicator value and the bit width of the character count field
+    // for a segment in this mode in an rMQR Code at the given version, or None if rMQR
+    // does not support the mode.
+    fn rmqr_header(self, ver: RmqrVersion) -> Option<(u32, u8)> {
+        use QrSegmentMode::*;
+        let ccbits: [u8; 4] = RMQR_CHAR_COUNT_BITS[usize::from(ver.value())];
+        match self {
+            Numeric => Some((0x1, ccbits[0])),
+            Alphanumeric => Some((0x2, ccbits[1])),
+            Byte => Some((0x3, ccbits[2])),
+            Kanji => Some((0x4, ccbits[3])),
+            Eci => Some((0x7, 0)),
+            StructuredAppend => None,
+        }
+    }
+}
+
+/*---- Constants and tables ----*/
+
+// The versions in the order of their version indicator.
+static RMQR_HEIGHTS: [u8; 32] = [
+    7, 7, 7, 7, 7, 9, 9, 9, 9, 9, 11, 11, 11, 11, 11, 11, 13, 13, 13, 13, 13, 13, 15, 15, 15, 15,
+    15, 17, 17, 17, 17, 17,
+];
+
+static RMQR_WIDTHS: [u8; 32] = [
+    43, 59, 77, 99, 139, 43, 59, 77, 99, 139, 27, 43, 59, 77, 99, 139, 27, 43, 59, 77, 99, 139, 43,
+    59, 77, 99, 139, 43, 59, 77, 99, 139,
+];
+
+// Number of codewords after all function modules are excluded, with remainder bits discarded.
+static RMQR_NUM_RAW_CODEWORDS: [u8; 32] = [
+    13, 21, 32, 44, 68, 21, 33, 49, 66, 99, 15, 31, 47, 67, 89, 132, 21, 41, 60, 85, 113, 166, 51,
+    74, 103, 136, 199, 61, 88, 122, 160, 232,
+];
+
+static RMQR_ECC_CODEWORDS_PER_BLOCK: [[u8; 32]; 2] = [
+    // R7x43 ... R7x139, R9x43 ... R9x139, R11x27 ... R11x139, R13x27 ... R13x139, R15x43 ... R15x139, R17x43 ... R17x139
+    [
+        7, 9, 12, 16, 24, 9, 12, 18, 24, 18, 8, 12, 16, 24, 16, 24, 9, 14, 22, 16, 20, 20, 18, 26,
+        18, 24, 24, 22, 16, 22, 20, 20,
+    ], // Medium
+    [
+        10, 14, 22, 30, 22, 14, 22, 16, 22, 22, 10, 20, 16, 22, 30, 30, 14, 28, 20, 28, 26, 26, 18,
+        24, 24, 22, 26, 20, 30, 28, 26, 26,
+    ], // High
+];
+
+static RMQR_NUM_ERROR_CORRECTION_BLOCKS: [[u8; 32]; 2] = [
+    // R7x43 ... R7x139, R9x43 ... R9x139, R11x27 ... R11x139, R13x27 ... R13x139, R15x43 ... R15x139, R17x43 ... R17x139
+    [
+        1, 1, 1, 1, 1, 1, 1, 1, 1, 2, 1, 1, 1, 1, 2, 2, 1, 1, 1, 2, 2, 3, 1, 1, 2, 2, 3, 1, 2, 2,
+        3, 4,
+    ], // Medium
+    [
+        1, 1, 1, 1, 2, 1, 1, 2, 2, 3, 1, 1, 2, 2, 2, 3, 1, 1, 2, 2, 3, 4, 2, 2, 3, 4, 5, 2, 2, 3,
+        4, 6,
+    ], // High
+];
+
+// Character count bits for the numeric, alphanumeric, byte and kanji modes.
+static RMQR_CHAR_COUNT_BITS: [[u8; 4]; 32] = [
+    [4, 3, 3, 2],
+    [5, 5, 4, 3],
+    [6, 5, 5, 4],
+    [7, 6, 5, 5],
+    [7, 6, 6, 5],
+    [5, 5, 4, 3],
+    [6, 5, 5, 4],
+    [7, 6, 5, 5],
+    [7, 6, 6, 5],
+    [8, 7, 6, 6],
+    [4, 4, 3, 2],
+    [6, 5, 5, 4],
+    [7, 6, 5, 5],
+    [7, 6, 6, 5],
+    [8, 7, 6, 6],
+    [8, 7, 7, 6],
+    [5, 5, 4, 3],
+    [6, 6, 5, 5],
+    [7, 6, 6, 5],
+    [7, 7, 6, 5],
+    [8, 7, 7, 6],
+    [8, 8, 7, 7],
+    [7, 6, 6, 5],
+    [7, 7, 6, 5],
+    [8, 7, 7, 6],
+    [8, 7, 7, 6],
+    [9, 8, 7, 7],
+    [7, 6, 6, 5],
+    [8, 7, 6, 6],
+    [8, 7, 7, 6],
+    [8, 8, 7, 6],
+    [9, 8, 8, 7],
+];
+
+/*---- Miscellaneous values ----*/
+
+/// One of the 32 rMQR Code versions, from R7x43 to R17x139.
+///
+/// The value is the version indicator stored in the format information, in the range [0, 31].
+/// Versions are ordered by height first and width second.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub struct RmqrVersion(u8);
+
+impl RmqrVersion {
+    /// The smallest version by value, R7x43.
+    pub const MIN: RmqrVersion = RmqrVersion(0);
+
+    /// The largest version by value, R17x139.
+    pub const MAX: RmqrVersion = RmqrVersion(31);
+
+    /// Creates a version object from the given version indicator.
+    ///
+    /// Panics if the number is outside the range [0, 31].
+    pub const fn new(ver: u8) -> Self {
+        assert!(
+            ver <= RmqrVersion::MAX.value(),
+            "Version number out of range"
+        );
+        Self(ver)
+    }
+
+    /// Returns the version with the given width and height, if it exists.
+    pub fn from_size(width: i32, height: i32) -> Option<Self> {
+        RmqrVersion::all().find(|v| v.width() == width && v.height() == height)
+    }
+
+    /// Returns all versions in ascending order.
+    pub fn all() -> impl Iterator<Item = Self> + Clone {
+        (RmqrVersion::MIN.value()..=RmqrVersion::MAX.value()).map(RmqrVersion)
+    }
+
+    /// Returns the value, which is in the range [0, 31].
+    pub const fn value(self) -> u8 {
+        self.0
+    }
+
+    /// Returns the width of symbols of this version, in the range [27, 139].
+    pub fn width(self) -> i32 {
+        i32::from(RMQR_WIDTHS[usize::from(self.0)])
+    }
+
+    /// Returns the height of symbols of this version, in the range [7, 17].
+    pub fn height(self) -> i32 {
+        i32::from(RMQR_HEIGHTS[usize::from(self.0)])
+    }
+
+    // Returns the x coordinates of the alignment pattern centers, which only depend on the width.
+    fn alignment_pattern_positions(self) -> &'static [i32] {
+        match self.width() {
+            27 => &[],
+            43 => &[21],
+            59 => &[19, 39],
+            77 => &[25, 51],
+            99 => &[23, 49, 75],
+            139 => &[27, 55, 83, 111],
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl std::fmt::Display for RmqrVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "R{}x{}", self.height(), self.width())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::ProptestConfig;
+    use proptest::proptest;
+
+    use crate::{get_bit, BitGrid, DataTooLong, QrCodeEcc, RmqrCode, RmqrVersion};
+
+    use super::{
+        RMQR_ECC_CODEWORDS_PER_BLOCK, RMQR_NUM_ERROR_CORRECTION_BLOCKS, RMQR_NUM_RAW_CODEWORDS,
+    };
+
+    // Reads the codewords back from the modules by unmasking and following the zigzag scan.
+    fn read_codewords(qrcode: &RmqrCode) -> Vec<u8> {
+        let mut unmasked = qrcode.clone();
+        unmasked.isfunction = BitGrid::new(qrcode.width(), qrcode.height());
+        unmasked.draw_function_patterns();
+        unmasked.apply_mask();
+
+        let (width, height) = (qrcode.width(), qrcode.height());
+        let mut bits = Vec::new();
+        let mut upward = true;
+        let mut right = width - 2;
+        while right >= 1 {
+            for vert in 0..height {
+                for j in 0..2 {
+                    let x = right - j;
+                    let y = if upward { height - 1 - vert } else { vert };
+                    if !unmasked.isfunction.get(x, y) {
+                        bits.push(unmasked.module(x, y));
+                    }
+                }
+            }
+            upward = !upward;
+            right -= 2;
+        }
+
+        bits.chunks_exact(8)
+            .map(|chunk| chunk.iter().fold(0u8, |acc, &bit| acc << 1 | u8::from(bit)))
+            .collect()
+    }
+
+    #[test]
+    fn raw_codewords_match_layout() {
+        for version in RmqrVersion::all() {
+            let qrcode = RmqrCode::encode_codewords(
+                version,
+                QrCodeEcc::Medium,
+                &vec![0; RmqrCode::get_num_data_codewords(version, QrCodeEcc::Medium)],
+            );
+
+            let mut skeleton = qrcode.clone();
+            skeleton.isfunction = BitGrid::new(version.width(), version.height());
+            skeleton.draw_function_patterns();
+            let datamodules =
+                (version.width() * version.height()) as usize - skeleton.isfunction.count_ones();
+
+            assert_eq!(
+                datamodules / 8,
+                usize::from(RMQR_NUM_RAW_CODEWORDS[usize::from(version.value())]),
+                "{version}"
+            );
+        }
+    }
+
+    #[test]
+    fn block_tables() {
+        for version in RmqrVersion::all() {
+            for ecl in [QrCodeEcc::Medium, QrCodeEcc::High] {
+                let blocks = RmqrCode::table_get(&RMQR_NUM_ERROR_CORRECTION_BLOCKS, version, ecl);
+                let ecc = RmqrCode::table_get(&RMQR_ECC_CODEWORDS_PER_BLOCK, version, ecl);
+                assert!(blocks >= 1 && ecc <= 30);
+                assert!(RmqrCode::get_num_data_codewords(version, ecl) > blocks);
+            }
+            assert!(
+                RmqrCode::get_num_data_codewords(version, QrCodeEcc::High)
+                    < RmqrCode::get_num_data_codewords(version, QrCodeEcc::Medium)
+            );
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(100))]
+        #[test]
+        fn rmqr_fits(text in "[ -~]{1,60}", high: bool) {
+            let ecl = if high { QrCodeEcc::High } else { QrCodeEcc::Medium };
+            let qrcode = RmqrCode::encode_text(&text, ecl).unwrap();
+            assert_eq!(qrcode.width(), qrcode.version().width());
+            assert_eq!(qrcode.height(), qrcode.version().height());
+            assert!(qrcode.error_correction_level() >= ecl);
+        }
+    }
+
+    #[test]
+    fn rmqr_fixed_height() {
+        let qrcode = RmqrCode::encode_segments_advanced(
+            &crate::QrSegment::make_segments("hello world"),
+            QrCodeEcc::Medium,
+            RmqrVersion::all().filter(|v| v.height() == 7),
+            false,
+        )
+        .unwrap();
+        assert_eq!(qrcode.version(), RmqrVersion::from_size(59, 7).unwrap());
+        assert_eq!(qrcode.version().to_string(), "R7x59");
+    }
+
+    #[test]
+    fn rmqr_known_answer() {
+        let qrcode = RmqrCode::encode_segments_advanced(
+            &crate::QrSegment::make_segments("12345"),
+            QrCodeEcc::Medium,
+            RmqrVersion::all().take(1),
+            false,
+        )
+        .unwrap();
+        assert_eq!(qrcode.version().to_string(), "R7x43");
+
+        // Worked out from the encoding rules of ISO/IEC 23941: numeric mode 001, a count
+        // of 0101 in four bits, 123 and 45 in ten and seven bits, the terminator 000,
+        // the pad codewords and seven error correction codewords in a single block
+        assert_eq!(
+            read_codewords(&qrcode),
+            [0x2A, 0x3D, 0xAD, 0x00, 0xEC, 0x11, 0x40, 0x38, 0x29, 0x3F, 0x91, 0xA1, 0x19]
+        );
+
+        // Both copies of the format information of R7x43 with medium error correction
+        // are only the masks of the standard
+        for i in 0..18 {
+            assert_eq!(qrcode.module(8 + i / 5, 1 + i % 5), get_bit(0x1FAB2, i));
+        }
+        for i in 0..15 {
+            assert_eq!(
+                qrcode.module(43 - 8 + i / 5, 7 - 6 + i % 5),
+                get_bit(0x20A7B, i)
+            );
+        }
+        for i in 15..18 {
+            assert_eq!(qrcode.module(43 - 20 + i, 7 - 6), get_bit(0x20A7B, i));
+        }
+    }
+
+    #[test]
+    fn rmqr_too_long() {
+        let text = "x".repeat(200);
+        assert!(matches!(
+            RmqrCode::encode_text(&text, QrCodeEcc::Medium),
+            Err(DataTooLong::DataOverCapacity(_, 1216))
+        ));
+    }
+}